@@ -1,8 +1,12 @@
 use crate::SharedOptions;
 
 use probe_rs::{
-    architecture::arm::ap::AccessPortError, config::TargetSelector, flashing::FileDownloadError,
-    DebugProbeError, Error, Probe, Session,
+    architecture::arm::ap::AccessPortError,
+    config::TargetSelector,
+    debug::DebugError,
+    flashing::FileDownloadError,
+    recording::{RecordingProbe, ReplayProbe},
+    DebugProbeError, DebugProbeInfo, Error, Probe, Session,
 };
 
 use std::fmt;
@@ -37,6 +41,11 @@ pub enum CliError {
         #[from]
         Error,
     ),
+    Debug(
+        #[source]
+        #[from]
+        DebugError,
+    ),
 }
 
 impl fmt::Display for CliError {
@@ -54,14 +63,32 @@ impl fmt::Display for CliError {
                 Some(details) => write!(f, "Unable to open probe: {}", details),
             },
             ProbeRs(ref e) => e.fmt(f),
+            Debug(ref e) => e.fmt(f),
         }
     }
 }
 
-pub(crate) fn open_probe(index: Option<usize>) -> Result<Probe, CliError> {
+pub(crate) fn open_probe(shared_options: &SharedOptions) -> Result<Probe, CliError> {
+    if let Some(path) = &shared_options.replay {
+        return Ok(Probe::new(ReplayProbe::open(path)?));
+    }
+
+    let probe = open_connected_probe(shared_options)?;
+
+    match &shared_options.record {
+        Some(path) => Ok(Probe::new(RecordingProbe::create(probe, path)?)),
+        None => Ok(probe),
+    }
+}
+
+fn open_connected_probe(shared_options: &SharedOptions) -> Result<Probe, CliError> {
+    if let Some(selector) = &shared_options.remote {
+        return Ok(DebugProbeInfo::remote(selector)?.open()?);
+    }
+
     let available_probes = Probe::list_all();
 
-    let device = match index {
+    let device = match shared_options.n {
         Some(index) => available_probes
             .get(index)
             .ok_or(CliError::UnableToOpenProbe(Some("Unable to open the specified probe. Use the 'list' subcommand to see all available probes.")))?,
@@ -90,7 +117,7 @@ pub(crate) fn with_device<F>(shared_options: &SharedOptions, f: F) -> Result<(),
 where
     F: FnOnce(Session) -> Result<(), CliError>,
 {
-    let mut probe = open_probe(shared_options.n)?;
+    let mut probe = open_probe(shared_options)?;
 
     let target_selector = match &shared_options.chip {
         Some(identifier) => identifier.into(),
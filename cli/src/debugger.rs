@@ -2,7 +2,7 @@ use crate::common::CliError;
 
 use capstone::Capstone;
 use probe_rs::architecture::arm::CortexDump;
-use probe_rs::debug::DebugInfo;
+use probe_rs::debug::{DebugInfo, SteppingMode};
 use probe_rs::{Core, CoreRegisterAddress, MemoryInterface};
 use std::fs::File;
 use std::io::prelude::*;
@@ -29,6 +29,27 @@ impl DebugCli {
             },
         });
 
+        cli.add_command(Command {
+            name: "next",
+            help_text: "Step to the next source line, stepping over function calls",
+
+            function: |cli_data, _args| step_source(cli_data, SteppingMode::StepOver),
+        });
+
+        cli.add_command(Command {
+            name: "step_into",
+            help_text: "Step to the next source line, entering function calls",
+
+            function: |cli_data, _args| step_source(cli_data, SteppingMode::StepInto),
+        });
+
+        cli.add_command(Command {
+            name: "finish",
+            help_text: "Run until the current function returns",
+
+            function: |cli_data, _args| step_source(cli_data, SteppingMode::StepOut),
+        });
+
         cli.add_command(Command {
             name: "halt",
             help_text: "Stop the CPU",
@@ -99,7 +120,12 @@ impl DebugCli {
             name: "quit",
             help_text: "Exit the program",
 
-            function: |_cli_data, _args| Ok(CliState::Stop),
+            function: |cli_data, _args| {
+                // Restore the instructions replaced by software breakpoints.
+                cli_data.core.clear_all_breakpoints()?;
+
+                Ok(CliState::Stop)
+            },
         });
 
         cli.add_command(Command {
@@ -153,7 +179,7 @@ impl DebugCli {
                 let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
                 let address = u32::from_str_radix(address_str, 16).unwrap();
 
-                cli_data.core.set_hw_breakpoint(address)?;
+                cli_data.core.set_breakpoint(address)?;
 
                 println!("Set new breakpoint at address {:#08x}", address);
 
@@ -169,7 +195,7 @@ impl DebugCli {
                 let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
                 let address = u32::from_str_radix(address_str, 16).unwrap();
 
-                cli_data.core.clear_hw_breakpoint(address)?;
+                cli_data.core.clear_breakpoint(address)?;
 
                 Ok(CliState::Continue)
             },
@@ -312,6 +338,33 @@ impl DebugCli {
     }
 }
 
+fn step_source(cli_data: &mut CliData, mode: SteppingMode) -> Result<CliState, CliError> {
+    let debug_info = match &cli_data.debug_info {
+        Some(debug_info) => debug_info,
+        None => {
+            println!("Stepping by source lines requires debug information.");
+            return Ok(CliState::Continue);
+        }
+    };
+
+    let pc = debug_info.step(&mut cli_data.core, mode)?;
+
+    match debug_info.get_source_location(pc) {
+        Some(location) => println!(
+            "Core stopped at address {:#010x} ({}:{})",
+            pc,
+            location.file.as_deref().unwrap_or("<unknown file>"),
+            location
+                .line
+                .map(|line| line.to_string())
+                .unwrap_or_else(|| "?".to_owned())
+        ),
+        None => println!("Core stopped at address {:#010x}", pc),
+    }
+
+    Ok(CliState::Continue)
+}
+
 pub struct CliData<'p> {
     pub core: Core<'p>,
     pub debug_info: Option<DebugInfo>,
@@ -13,7 +13,7 @@ use probe_rs::{
 };
 
 pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<(), CliError> {
-    let mut probe = open_probe(shared_options.n)?;
+    let mut probe = open_probe(shared_options)?;
     probe.attach_to_unspecified()?;
 
     /*
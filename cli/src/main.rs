@@ -8,13 +8,14 @@ use debugger::CliState;
 use probe_rs::{
     debug::DebugInfo,
     flashing::{download_file, Format},
-    MemoryInterface, Probe, Session,
+    remote, MemoryInterface, Probe, Session,
 };
 
 use capstone::{arch::arm::ArchMode, prelude::*, Capstone, Endian};
 use rustyline::Editor;
 use structopt::StructOpt;
 
+use std::net::TcpListener;
 use std::num::ParseIntError;
 use std::path::PathBuf;
 use std::time::Instant;
@@ -56,6 +57,10 @@ enum CLI {
         #[structopt(long, parse(from_os_str))]
         /// Binary to debug
         exe: Option<PathBuf>,
+
+        /// The number of the core to debug
+        #[structopt(long, default_value = "0")]
+        core: usize,
     },
     /// Dump memory from attached target
     #[structopt(name = "dump")]
@@ -87,6 +92,13 @@ enum CLI {
         #[structopt(parse(try_from_str = parse_hex))]
         loc: u32,
     },
+    /// Serve the connected debug probes to other machines, which select them with `--remote`
+    #[structopt(name = "serve")]
+    Serve {
+        /// The address to listen on
+        #[structopt(long, default_value = "0.0.0.0:4321")]
+        address: String,
+    },
 }
 
 /// Shared options for all commands which use a specific probe
@@ -96,6 +108,18 @@ struct SharedOptions {
     #[structopt(long = "probe-index")]
     n: Option<usize>,
 
+    /// Use a debug probe of a probe server, selected with `host:port/serial`
+    #[structopt(long, conflicts_with = "n")]
+    remote: Option<String>,
+
+    /// Record the operations of the debug probe to a file
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Replay a recording made with `--record` instead of using a debug probe
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["n", "remote"])]
+    replay: Option<PathBuf>,
+
     /// The target to be selected.
     #[structopt(short, long)]
     chip: Option<String>,
@@ -115,10 +139,11 @@ fn main() {
         CLI::List {} => list_connected_devices(),
         CLI::Info { shared } => crate::info::show_info_of_device(&shared),
         CLI::Reset { shared, assert } => reset_target_of_device(&shared, assert),
-        CLI::Debug { shared, exe } => debug(&shared, exe),
+        CLI::Debug { shared, exe, core } => debug(&shared, exe, core),
         CLI::Dump { shared, loc, words } => dump_memory(&shared, loc, words),
         CLI::Download { shared, path } => download_program_fast(&shared, &path),
         CLI::Trace { shared, loc } => trace_u32_on_target(&shared, loc),
+        CLI::Serve { address } => serve_probes(&address),
     };
 
     if let Err(e) = cli_result {
@@ -143,6 +168,16 @@ fn list_connected_devices() -> Result<(), CliError> {
     Ok(())
 }
 
+fn serve_probes(address: &str) -> Result<(), CliError> {
+    let listener = TcpListener::bind(address)?;
+
+    println!("Serving the debug probes at {}", listener.local_addr()?);
+
+    remote::serve(listener)?;
+
+    Ok(())
+}
+
 fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(), CliError> {
     with_device(shared_options, |mut session| {
         let mut data = vec![0 as u32; words as usize];
@@ -237,7 +272,11 @@ fn trace_u32_on_target(shared_options: &SharedOptions, loc: u32) -> Result<(), C
     })
 }
 
-fn debug(shared_options: &SharedOptions, exe: Option<PathBuf>) -> Result<(), CliError> {
+fn debug(
+    shared_options: &SharedOptions,
+    exe: Option<PathBuf>,
+    core_index: usize,
+) -> Result<(), CliError> {
     let runner = |mut session: Session| {
         let cs = Capstone::new()
             .arm()
@@ -252,7 +291,7 @@ fn debug(shared_options: &SharedOptions, exe: Option<PathBuf>) -> Result<(), Cli
 
         let cli = debugger::DebugCli::new();
 
-        let core = session.core(0)?;
+        let core = session.core(core_index)?;
 
         let mut cli_data = debugger::CliData {
             core,
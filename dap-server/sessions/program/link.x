@@ -0,0 +1,15 @@
+/*
+ * The program of the `launch.json` session, for the simulated Cortex-M4. It is built with
+ * a nightly compiler, as `main.rs` has no dependencies, not even `core`:
+ *
+ * rustc +nightly --target thumbv7em-none-eabi --crate-type bin -C opt-level=0 -g \
+ *     -C overflow-checks=off -C panic=abort -C linker=rust-lld -C linker-flavor=ld.lld \
+ *     -C link-arg=-Tlink.x --remap-path-prefix=$PWD=/src -o program.elf main.rs
+ */
+MEMORY { FLASH : ORIGIN = 0x0, LENGTH = 64K RAM : ORIGIN = 0x20000000, LENGTH = 64K }
+ENTRY(main)
+SECTIONS {
+  .vector_table ORIGIN(FLASH) : { LONG(ORIGIN(RAM) + LENGTH(RAM)); LONG(main); } > FLASH
+  .text : { *(.text .text.*) } > FLASH
+  /DISCARD/ : { *(.ARM.exidx .ARM.exidx.*) }
+}
@@ -0,0 +1,100 @@
+use colored::*;
+use std::process::{self};
+use structopt::StructOpt;
+
+use probe_rs::{config::TargetSelector, Probe};
+use probe_rs_dap_server::Transport;
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(name = "chip", long = "chip")]
+    chip: Option<String>,
+    #[structopt(
+        name = "port",
+        long = "port",
+        help = "Use this flag to serve the client on a TCP port, instead of stdin and stdout."
+    )]
+    port: Option<u16>,
+    #[cfg(unix)]
+    #[structopt(
+        name = "unix-socket",
+        long = "unix-socket",
+        conflicts_with = "port",
+        help = "Use this flag to serve the client on a Unix domain socket at the given path."
+    )]
+    unix_socket: Option<PathBuf>,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    match main_try() {
+        Ok(_) => (),
+        Err(e) => {
+            eprintln!("{}: {}", "error".red().bold(), e);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn open_probe(index: Option<usize>) -> Result<Probe, failure::Error> {
+    let available_probes = Probe::list_all();
+
+    let device = match index {
+        Some(index) => available_probes
+            .get(index)
+            .ok_or_else(|| failure::err_msg("Unable to open the specified probe. Use the 'list' subcommand to see all available probes."))?,
+        None => {
+            // open the default probe, if only one probe was found
+            if available_probes.len() == 1 {
+                &available_probes[0]
+            } else {
+                return Err(failure::err_msg("Multiple probes found. Please specify which probe to use using the -n parameter."));
+            }
+        }
+    };
+
+    let probe = Probe::from_probe_info(device)?;
+
+    Ok(probe)
+}
+
+fn main_try() -> Result<(), failure::Error> {
+    // Get commandline options.
+    let opt = Opt::from_iter(std::env::args());
+    let transport = transport(&opt);
+
+    let probe = open_probe(None)?;
+
+    let target_selector = match opt.chip {
+        Some(identifier) => identifier.into(),
+        None => TargetSelector::Auto,
+    };
+    let session = probe.attach(target_selector)?;
+
+    // Stdout is used by the client when serving on stdio, so the message goes to stderr.
+    eprintln!("Firing up the debug adapter at {}", transport);
+
+    if let Err(e) = probe_rs_dap_server::serve(transport, session) {
+        eprintln!("During the execution of the debug adapter an error was encountered:");
+        eprintln!("{:?}", e);
+    }
+
+    Ok(())
+}
+
+fn transport(opt: &Opt) -> Transport {
+    #[cfg(unix)]
+    {
+        if let Some(path) = &opt.unix_socket {
+            return Transport::Unix(path.clone());
+        }
+    }
+
+    match opt.port {
+        Some(port) => Transport::Tcp(format!("localhost:{}", port)),
+        None => Transport::Stdio,
+    }
+}
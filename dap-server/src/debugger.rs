@@ -0,0 +1,997 @@
+use crate::protocol::{Message, Request};
+use crate::target::{thread_id, DebugTarget};
+
+use capstone::arch::arm::{ArchExtraMode, ArchMode};
+use capstone::prelude::*;
+use capstone::Capstone;
+use probe_rs::debug::{ColumnType, DebugError, DebugInfo, SourceLocation, SteppingMode, Value};
+use probe_rs::flashing::FileDownloadError;
+use probe_rs::server::{first_halted, halt_others};
+use probe_rs::{Architecture, Core, CoreStatus, HaltReason, MemoryInterface};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum RequestError {
+    #[error("Invalid arguments: {0}")]
+    Arguments(#[from] serde_json::Error),
+    #[error("{0}")]
+    Probe(#[from] probe_rs::Error),
+    #[error("{0}")]
+    Debug(#[from] DebugError),
+    #[error("Unable to program the flash: {0}")]
+    Download(#[from] FileDownloadError),
+    #[error("Unable to disassemble: {0}")]
+    Disassembly(capstone::Error),
+    #[error("Unknown thread {0}")]
+    UnknownThread(i64),
+    #[error("Unknown stack frame {0}")]
+    UnknownFrame(usize),
+    #[error("Unknown variables reference {0}")]
+    UnknownReference(usize),
+    #[error("Invalid memory reference '{0}'")]
+    InvalidMemoryReference(String),
+    #[error("Stepping out of a function requires debug information")]
+    MissingDebugInfo,
+    #[error(
+        "At most {} instructions can be disassembled at once",
+        MAX_INSTRUCTIONS
+    )]
+    TooManyInstructions,
+    #[error("Unsupported request '{0}'")]
+    Unsupported(String),
+}
+
+/// The instructions which are disassembled at most by one request.
+const MAX_INSTRUCTIONS: usize = 0x1000;
+
+/// The body of a successful response.
+type RequestResult = Result<Option<serde_json::Value>, RequestError>;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchArguments {
+    /// The ELF file which is programmed, and whose debug information is used.
+    program: PathBuf,
+    /// Skips programming the flash, if the program is already on the target.
+    #[serde(default)]
+    no_flash: bool,
+    #[serde(default)]
+    stop_on_entry: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachArguments {
+    /// The ELF file which runs on the target, for its debug information.
+    program: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Source {
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SourceBreakpoint {
+    line: u64,
+    column: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetBreakpointsArguments {
+    source: Source,
+    #[serde(default)]
+    breakpoints: Vec<SourceBreakpoint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstructionBreakpoint {
+    instruction_reference: String,
+    #[serde(default)]
+    offset: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetInstructionBreakpointsArguments {
+    breakpoints: Vec<InstructionBreakpoint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ThreadArguments {
+    thread_id: i64,
+    /// Steps by `instruction` instead of by source line.
+    granularity: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StackTraceArguments {
+    thread_id: i64,
+    #[serde(default)]
+    start_frame: usize,
+    /// The number of frames to return, or all frames if it is 0.
+    #[serde(default)]
+    levels: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopesArguments {
+    frame_id: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VariablesArguments {
+    variables_reference: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DisassembleArguments {
+    memory_reference: String,
+    #[serde(default)]
+    offset: i64,
+    #[serde(default)]
+    instruction_offset: i64,
+    instruction_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DisconnectArguments {
+    /// Halts the cores, instead of resuming them.
+    #[serde(default)]
+    terminate_debuggee: bool,
+}
+
+/// A stack frame which was reported to the client, until the cores are resumed.
+struct Frame {
+    core: usize,
+    /// Whether this is the innermost frame, whose registers are the registers of the core.
+    top: bool,
+    variables: Vec<(String, String, Value)>,
+}
+
+/// What a variables reference refers to, until the cores are resumed.
+enum Container {
+    /// The variables of a stack frame.
+    Locals(usize),
+    /// The registers of a core.
+    Registers(usize),
+    /// The fields of a struct, or the elements of an array.
+    Children(Vec<(String, Value)>),
+}
+
+/// Handles the requests of a client, and reports when the cores halt.
+///
+/// The cores are debugged all-stop: when one core halts, the other cores are halted as well.
+pub(crate) struct Debugger<'a> {
+    target: &'a mut dyn DebugTarget,
+    debug_info: Option<DebugInfo>,
+    /// The reason of the stop which is reported once the configuration is done,
+    /// or `None` if the cores are resumed then.
+    start: Option<&'static str>,
+    /// The resumed cores.
+    running: Vec<usize>,
+    /// The addresses of the breakpoints of each source file.
+    source_breakpoints: HashMap<PathBuf, Vec<u32>>,
+    instruction_breakpoints: Vec<u32>,
+    next_breakpoint_id: i64,
+    frames: Vec<Frame>,
+    references: Vec<Container>,
+    /// Events which are sent after the response of the current request.
+    events: Vec<Message>,
+    disconnected: bool,
+}
+
+impl<'a> Debugger<'a> {
+    pub(crate) fn new(target: &'a mut dyn DebugTarget) -> Self {
+        Debugger {
+            target,
+            debug_info: None,
+            start: Some("pause"),
+            running: vec![],
+            source_breakpoints: HashMap::new(),
+            instruction_breakpoints: vec![],
+            next_breakpoint_id: 1,
+            frames: vec![],
+            references: vec![],
+            events: vec![],
+            disconnected: false,
+        }
+    }
+
+    /// Whether the client disconnected, after which no more requests are handled.
+    pub(crate) fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    /// Handles a request, and returns its response followed by the events it caused.
+    pub(crate) fn handle(&mut self, request: Request) -> Vec<Message> {
+        let arguments = request.arguments;
+
+        let result =
+            match request.command.as_str() {
+                "initialize" => self.initialize(),
+                "launch" => parse(arguments).and_then(|arguments| self.launch(arguments)),
+                "attach" => parse(arguments).and_then(|arguments| self.attach(arguments)),
+                "setBreakpoints" => {
+                    parse(arguments).and_then(|arguments| self.set_breakpoints(arguments))
+                }
+                "setInstructionBreakpoints" => parse(arguments)
+                    .and_then(|arguments| self.set_instruction_breakpoints(arguments)),
+                // Exceptions of the target are always reported.
+                "setExceptionBreakpoints" => Ok(None),
+                "configurationDone" => self.configuration_done(),
+                "threads" => self.threads(),
+                "stackTrace" => parse(arguments).and_then(|arguments| self.stack_trace(arguments)),
+                "scopes" => parse(arguments).and_then(|arguments| self.scopes(arguments)),
+                "variables" => parse(arguments).and_then(|arguments| self.variables(arguments)),
+                "continue" => self.resume(),
+                "pause" => parse(arguments).and_then(|arguments| self.pause(arguments)),
+                "next" => parse(arguments)
+                    .and_then(|arguments| self.step(arguments, SteppingMode::StepOver)),
+                "stepIn" => parse(arguments)
+                    .and_then(|arguments| self.step(arguments, SteppingMode::StepInto)),
+                "stepOut" => parse(arguments)
+                    .and_then(|arguments| self.step(arguments, SteppingMode::StepOut)),
+                "disassemble" => parse(arguments).and_then(|arguments| self.disassemble(arguments)),
+                "disconnect" => parse(arguments).and_then(|arguments| self.disconnect(arguments)),
+                command => Err(RequestError::Unsupported(command.to_owned())),
+            };
+
+        let response = match result {
+            Ok(body) => Message::Response {
+                request_seq: request.seq,
+                success: true,
+                command: request.command,
+                message: None,
+                body,
+            },
+            Err(e) => {
+                log::warn!("Failed to handle request '{}': {}", request.command, e);
+
+                Message::Response {
+                    request_seq: request.seq,
+                    success: false,
+                    command: request.command,
+                    message: Some(e.to_string()),
+                    body: None,
+                }
+            }
+        };
+
+        let mut messages = vec![response];
+        messages.append(&mut self.events);
+        messages
+    }
+
+    /// Polls the resumed cores. Once one of them halts, the others are halted as well,
+    /// and the stop is reported.
+    pub(crate) fn poll(&mut self) -> Vec<Message> {
+        let halted = match first_halted(&mut *self.target, &self.running) {
+            Some(halted) => halted,
+            None => return vec![],
+        };
+
+        halt_others(
+            &mut *self.target,
+            &std::mem::take(&mut self.running),
+            halted,
+        );
+
+        let reason = match self.target.core(halted).and_then(|mut core| core.status()) {
+            Ok(CoreStatus::Halted(reason)) => stop_reason(reason),
+            Ok(_) => "pause",
+            Err(e) => {
+                log::warn!("Unable to read the halt reason: {}", e);
+                "pause"
+            }
+        };
+
+        vec![stopped(reason, halted)]
+    }
+
+    fn initialize(&mut self) -> RequestResult {
+        self.events.push(Message::event("initialized", None));
+
+        Ok(Some(json!({
+            "supportsConfigurationDoneRequest": true,
+            "supportsDisassembleRequest": true,
+            "supportsInstructionBreakpoints": true,
+            "supportsSteppingGranularity": true,
+            "supportsTerminateDebuggee": true,
+        })))
+    }
+
+    fn launch(&mut self, arguments: LaunchArguments) -> RequestResult {
+        if !arguments.no_flash {
+            self.target.download(&arguments.program)?;
+        }
+
+        self.debug_info = Some(DebugInfo::from_file(&arguments.program)?);
+
+        // Resetting the first core resets the whole chip.
+        self.target.core(0)?.reset_and_halt()?;
+
+        for n in 1..self.target.count() {
+            halt(&mut *self.target, n)?;
+        }
+
+        self.start = if arguments.stop_on_entry {
+            Some("entry")
+        } else {
+            None
+        };
+
+        Ok(None)
+    }
+
+    fn attach(&mut self, arguments: AttachArguments) -> RequestResult {
+        if let Some(program) = &arguments.program {
+            self.debug_info = Some(DebugInfo::from_file(program)?);
+        }
+
+        // Cores which are running keep running, until one of them halts.
+        for n in 0..self.target.count() {
+            if !self.target.core(n)?.core_halted()? {
+                self.running.push(n);
+            }
+        }
+
+        self.start = Some("pause");
+
+        Ok(None)
+    }
+
+    fn configuration_done(&mut self) -> RequestResult {
+        match self.start {
+            Some(reason) if self.running.is_empty() => self.events.push(stopped(reason, 0)),
+            Some(_) => (),
+            None => self.run_all()?,
+        }
+
+        Ok(None)
+    }
+
+    fn set_breakpoints(&mut self, arguments: SetBreakpointsArguments) -> RequestResult {
+        let path = match arguments.source.path {
+            Some(path) => path,
+            None => {
+                let breakpoints: Vec<_> = arguments
+                    .breakpoints
+                    .iter()
+                    .map(|_| unverified("The source has no path"))
+                    .collect();
+
+                return Ok(Some(json!({ "breakpoints": breakpoints })));
+            }
+        };
+
+        // The breakpoints of the source replace all of its previous breakpoints.
+        for address in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            self.clear_breakpoint(address)?;
+        }
+
+        let mut breakpoints = vec![];
+        let mut addresses = vec![];
+
+        for breakpoint in arguments.breakpoints {
+            let location = match &self.debug_info {
+                Some(debug_info) => {
+                    debug_info.get_breakpoint_location(&path, breakpoint.line, breakpoint.column)
+                }
+                None => {
+                    breakpoints.push(unverified("No debug information is loaded"));
+                    continue;
+                }
+            };
+
+            let address = match location {
+                Ok(Some(address)) => address as u32,
+                Ok(None) => {
+                    breakpoints.push(unverified("There is no code at this line"));
+                    continue;
+                }
+                Err(e) => {
+                    breakpoints.push(unverified(e));
+                    continue;
+                }
+            };
+
+            match self.set_breakpoint(address) {
+                Ok(id) => {
+                    addresses.push(address);
+                    breakpoints.push(json!({
+                        "id": id,
+                        "verified": true,
+                        "line": breakpoint.line,
+                        "instructionReference": format!("{:#010x}", address),
+                    }));
+                }
+                Err(e) => breakpoints.push(unverified(e)),
+            }
+        }
+
+        self.source_breakpoints.insert(path, addresses);
+
+        Ok(Some(json!({ "breakpoints": breakpoints })))
+    }
+
+    fn set_instruction_breakpoints(
+        &mut self,
+        arguments: SetInstructionBreakpointsArguments,
+    ) -> RequestResult {
+        for address in std::mem::take(&mut self.instruction_breakpoints) {
+            self.clear_breakpoint(address)?;
+        }
+
+        let mut breakpoints = vec![];
+
+        for breakpoint in arguments.breakpoints {
+            let address = match parse_address(&breakpoint.instruction_reference) {
+                Ok(address) => (address as i64 + breakpoint.offset) as u32,
+                Err(e) => {
+                    breakpoints.push(unverified(e));
+                    continue;
+                }
+            };
+
+            match self.set_breakpoint(address) {
+                Ok(id) => {
+                    self.instruction_breakpoints.push(address);
+                    breakpoints.push(json!({
+                        "id": id,
+                        "verified": true,
+                        "instructionReference": format!("{:#010x}", address),
+                    }));
+                }
+                Err(e) => breakpoints.push(unverified(e)),
+            }
+        }
+
+        Ok(Some(json!({ "breakpoints": breakpoints })))
+    }
+
+    fn threads(&mut self) -> RequestResult {
+        let threads: Vec<_> = (0..self.target.count())
+            .map(|n| json!({ "id": thread_id(n), "name": format!("Core {}", n) }))
+            .collect();
+
+        Ok(Some(json!({ "threads": threads })))
+    }
+
+    fn stack_trace(&mut self, arguments: StackTraceArguments) -> RequestResult {
+        let n = self.core_of_thread(arguments.thread_id)?;
+
+        let mut core = self.target.core(n)?;
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+
+        let frames: Vec<_> = match &self.debug_info {
+            Some(debug_info) => debug_info
+                .try_unwind(&mut core, u64::from(pc))
+                .map(|frame| {
+                    let pc = frame.pc();
+                    let variables = frame
+                        .variables
+                        .into_iter()
+                        .map(|variable| (variable.name, variable.typ.name, variable.value))
+                        .collect();
+
+                    (frame.function_name, frame.source_location, pc, variables)
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        // Without debug information, only the current location is known.
+        let frames = if frames.is_empty() {
+            vec![(format!("{:#010x}", pc), None, pc, vec![])]
+        } else {
+            frames
+        };
+
+        let total_frames = frames.len();
+        let levels = match arguments.levels {
+            0 => total_frames,
+            levels => levels,
+        };
+
+        let mut stack_frames = vec![];
+
+        for (i, (name, location, pc, variables)) in frames
+            .into_iter()
+            .enumerate()
+            .skip(arguments.start_frame)
+            .take(levels)
+        {
+            self.frames.push(Frame {
+                core: n,
+                top: i == 0,
+                variables,
+            });
+
+            let mut frame = json!({
+                "id": self.frames.len(),
+                "name": name,
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("{:#010x}", pc),
+            });
+
+            if let Some(location) = location {
+                frame["line"] = location.line.unwrap_or(0).into();
+                frame["column"] = match location.column {
+                    Some(ColumnType::Column(column)) => column,
+                    _ => 1,
+                }
+                .into();
+
+                if let Some(source) = source(&location) {
+                    frame["source"] = source;
+                }
+            }
+
+            stack_frames.push(frame);
+        }
+
+        Ok(Some(json!({
+            "stackFrames": stack_frames,
+            "totalFrames": total_frames,
+        })))
+    }
+
+    fn scopes(&mut self, arguments: ScopesArguments) -> RequestResult {
+        let index = arguments.frame_id.wrapping_sub(1);
+        let frame = self
+            .frames
+            .get(index)
+            .ok_or(RequestError::UnknownFrame(arguments.frame_id))?;
+        let (core, top) = (frame.core, frame.top);
+
+        let mut scopes = vec![json!({
+            "name": "Locals",
+            "presentationHint": "locals",
+            "variablesReference": self.add_reference(Container::Locals(index)),
+            "expensive": false,
+        })];
+
+        // The registers of the outer frames are not restored.
+        if top {
+            scopes.push(json!({
+                "name": "Registers",
+                "presentationHint": "registers",
+                "variablesReference": self.add_reference(Container::Registers(core)),
+                "expensive": false,
+            }));
+        }
+
+        Ok(Some(json!({ "scopes": scopes })))
+    }
+
+    fn variables(&mut self, arguments: VariablesArguments) -> RequestResult {
+        let reference = arguments.variables_reference;
+
+        let values: Vec<(String, Option<String>, Value)> = match self
+            .references
+            .get(reference.wrapping_sub(1))
+            .ok_or(RequestError::UnknownReference(reference))?
+        {
+            Container::Locals(index) => self.frames[*index]
+                .variables
+                .iter()
+                .map(|(name, typ, value)| (name.clone(), Some(typ.clone()), value.clone()))
+                .collect(),
+            Container::Children(children) => children
+                .iter()
+                .map(|(name, value)| (name.clone(), None, value.clone()))
+                .collect(),
+            Container::Registers(n) => {
+                let variables = registers(&mut self.target.core(*n)?)?;
+
+                return Ok(Some(json!({ "variables": variables })));
+            }
+        };
+
+        let mut variables = vec![];
+
+        for (name, typ, value) in values {
+            let children: Vec<(String, Value)> = match &value {
+                Value::Struct { fields, .. } => fields.clone(),
+                Value::Array(elements) => elements
+                    .iter()
+                    .enumerate()
+                    .map(|(index, element)| (format!("[{}]", index), element.clone()))
+                    .collect(),
+                _ => vec![],
+            };
+
+            let variables_reference = if children.is_empty() {
+                0
+            } else {
+                self.add_reference(Container::Children(children))
+            };
+
+            let mut variable = json!({
+                "name": name,
+                "value": value.to_string(),
+                "variablesReference": variables_reference,
+            });
+
+            if let Some(typ) = typ {
+                variable["type"] = typ.into();
+            }
+
+            variables.push(variable);
+        }
+
+        Ok(Some(json!({ "variables": variables })))
+    }
+
+    /// Resumes all cores.
+    fn resume(&mut self) -> RequestResult {
+        self.run_all()?;
+
+        Ok(Some(json!({ "allThreadsContinued": true })))
+    }
+
+    fn pause(&mut self, arguments: ThreadArguments) -> RequestResult {
+        let n = self.core_of_thread(arguments.thread_id)?;
+
+        for n in std::mem::take(&mut self.running) {
+            halt(&mut *self.target, n)?;
+        }
+
+        self.events.push(stopped("pause", n));
+
+        Ok(None)
+    }
+
+    /// Steps a single core. The other cores stay halted.
+    fn step(&mut self, arguments: ThreadArguments, mode: SteppingMode) -> RequestResult {
+        let n = self.core_of_thread(arguments.thread_id)?;
+        let by_instruction = arguments.granularity.as_deref() == Some("instruction");
+
+        self.invalidate();
+
+        let mut core = self.target.core(n)?;
+
+        match &self.debug_info {
+            Some(debug_info) if !by_instruction => {
+                step_over_hw_breakpoint(&mut core)?;
+                debug_info.step(&mut core, mode)?;
+            }
+            None if mode == SteppingMode::StepOut && !by_instruction => {
+                return Err(RequestError::MissingDebugInfo)
+            }
+            _ => {
+                if !step_over_hw_breakpoint(&mut core)? {
+                    core.step()?;
+                }
+            }
+        }
+
+        self.events.push(stopped("step", n));
+
+        Ok(None)
+    }
+
+    fn disassemble(&mut self, arguments: DisassembleArguments) -> RequestResult {
+        let address = parse_address(&arguments.memory_reference)? as i64 + arguments.offset;
+        let count = arguments.instruction_count;
+
+        if count > MAX_INSTRUCTIONS {
+            return Err(RequestError::TooManyInstructions);
+        }
+
+        let architecture = self.target.core(0)?.architecture();
+
+        // Instructions before the address are assumed to have the smallest size,
+        // as the instructions have a variable length.
+        let (instruction_size, max_size) = match architecture {
+            Architecture::Arm => (2, 4),
+            Architecture::Riscv => (4, 4),
+        };
+
+        let start = (address + arguments.instruction_offset * instruction_size).max(0) as u32;
+
+        let mut data = vec![0; count * max_size];
+        let readable = self.target.core(0)?.read_8(start, &mut data).is_ok();
+
+        let mut instructions = vec![];
+
+        if readable {
+            match architecture {
+                Architecture::Arm => {
+                    let capstone = Capstone::new()
+                        .arm()
+                        .mode(ArchMode::Thumb)
+                        .extra_mode(std::iter::once(ArchExtraMode::MClass))
+                        .build()
+                        .map_err(RequestError::Disassembly)?;
+
+                    for instruction in capstone
+                        .disasm_all(&data, u64::from(start))
+                        .map_err(RequestError::Disassembly)?
+                        .iter()
+                    {
+                        let text = format!(
+                            "{} {}",
+                            instruction.mnemonic().unwrap_or_default(),
+                            instruction.op_str().unwrap_or_default()
+                        );
+
+                        instructions.push((
+                            instruction.address() as u32,
+                            instruction.bytes().to_vec(),
+                            text.trim().to_owned(),
+                        ));
+                    }
+                }
+                // The instructions are shown as raw words, as they are not decoded.
+                Architecture::Riscv => {
+                    for (i, word) in data.chunks(4).enumerate() {
+                        let value = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+                        instructions.push((
+                            start + 4 * i as u32,
+                            word.to_vec(),
+                            format!(".word {:#010x}", value),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let mut disassembly: Vec<_> = instructions
+            .into_iter()
+            .take(count)
+            .map(|(address, bytes, text)| {
+                let mut instruction = json!({
+                    "address": format!("{:#010x}", address),
+                    "instructionBytes": bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>(),
+                    "instruction": text,
+                });
+
+                let location = self
+                    .debug_info
+                    .as_ref()
+                    .and_then(|debug_info| debug_info.get_source_location(u64::from(address)));
+
+                if let Some(location) = location {
+                    if let Some(source) = source(&location) {
+                        instruction["location"] = source;
+                    }
+                    if let Some(line) = location.line {
+                        instruction["line"] = line.into();
+                    }
+                }
+
+                instruction
+            })
+            .collect();
+
+        // The client expects exactly the requested number of instructions.
+        while disassembly.len() < count {
+            let address = start as usize + disassembly.len() * instruction_size as usize;
+
+            disassembly.push(json!({
+                "address": format!("{:#010x}", address),
+                "instruction": "??",
+                "presentationHint": "invalid",
+            }));
+        }
+
+        Ok(Some(json!({ "instructions": disassembly })))
+    }
+
+    fn disconnect(&mut self, arguments: DisconnectArguments) -> RequestResult {
+        self.disconnected = true;
+
+        for n in 0..self.target.count() {
+            let mut core = self.target.core(n)?;
+
+            // Don't leave software breakpoints behind in the target memory.
+            if let Err(e) = core.clear_all_breakpoints() {
+                log::warn!("Failed to clear the breakpoints of core {}: {}", n, e);
+            }
+
+            if arguments.terminate_debuggee {
+                core.halt()?;
+            } else if core.core_halted()? {
+                core.run()?;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn core_of_thread(&self, thread_id: i64) -> Result<usize, RequestError> {
+        match thread_id {
+            id if id >= 1 && id as usize <= self.target.count() => Ok(id as usize - 1),
+            id => Err(RequestError::UnknownThread(id)),
+        }
+    }
+
+    fn run_all(&mut self) -> Result<(), RequestError> {
+        self.invalidate();
+
+        for n in 0..self.target.count() {
+            let mut core = self.target.core(n)?;
+
+            step_over_hw_breakpoint(&mut core)?;
+            core.run()?;
+
+            self.running.push(n);
+        }
+
+        Ok(())
+    }
+
+    /// Sets a breakpoint on all cores, and returns its id.
+    fn set_breakpoint(&mut self, address: u32) -> Result<i64, RequestError> {
+        for n in 0..self.target.count() {
+            self.target.core(n)?.set_breakpoint(address)?;
+        }
+
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+
+        Ok(id)
+    }
+
+    fn clear_breakpoint(&mut self, address: u32) -> Result<(), RequestError> {
+        // Software breakpoints are cleared in reverse, so the original instruction is restored last.
+        for n in (0..self.target.count()).rev() {
+            self.target.core(n)?.clear_breakpoint(address)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_reference(&mut self, container: Container) -> usize {
+        self.references.push(container);
+        self.references.len()
+    }
+
+    /// Forgets the stack frames and variables, as they change once the cores are resumed.
+    fn invalidate(&mut self) {
+        self.frames.clear();
+        self.references.clear();
+    }
+}
+
+/// Parses the arguments of a request. Requests without arguments are treated like
+/// requests with empty arguments.
+fn parse<T: DeserializeOwned>(arguments: serde_json::Value) -> Result<T, RequestError> {
+    let arguments = match arguments {
+        serde_json::Value::Null => json!({}),
+        arguments => arguments,
+    };
+
+    Ok(serde_json::from_value(arguments)?)
+}
+
+/// Parses an address, which is hex if it starts with `0x` and decimal otherwise.
+fn parse_address(reference: &str) -> Result<u32, RequestError> {
+    let address = match reference.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => reference.parse(),
+    };
+
+    address.map_err(|_| RequestError::InvalidMemoryReference(reference.to_owned()))
+}
+
+fn halt(target: &mut dyn DebugTarget, n: usize) -> Result<(), probe_rs::Error> {
+    let mut core = target.core(n)?;
+    core.halt()?;
+    core.wait_for_core_halted()
+}
+
+/// Executes the instruction at a hardware breakpoint, which would halt the core again
+/// when it is resumed. Returns whether the core was stepped.
+fn step_over_hw_breakpoint(core: &mut Core) -> Result<bool, probe_rs::Error> {
+    let pc = core.read_core_reg(core.registers().program_counter())?;
+
+    if !core.has_hw_breakpoint(pc) {
+        return Ok(false);
+    }
+
+    core.clear_hw_breakpoint(pc)?;
+    let result = core.step();
+    core.set_hw_breakpoint(pc)?;
+
+    result.map(|_| true)
+}
+
+/// Reads the registers of a core, as variables.
+fn registers(core: &mut Core) -> Result<Vec<serde_json::Value>, probe_rs::Error> {
+    let register_file = core.registers();
+    let program_counter = register_file.program_counter();
+
+    let mut registers: Vec<_> = register_file.registers().collect();
+
+    // The program counter is not a platform register on every architecture.
+    if register_file
+        .dwarf_register_number(program_counter)
+        .is_none()
+    {
+        registers.push(program_counter);
+    }
+
+    registers
+        .into_iter()
+        .map(|register| {
+            let value = core.read_core_reg(register)?;
+
+            Ok(json!({
+                "name": register.name(),
+                "value": format!("{:#010x}", value),
+                "variablesReference": 0,
+            }))
+        })
+        .collect()
+}
+
+/// The source of a location, if its file is known.
+fn source(location: &SourceLocation) -> Option<serde_json::Value> {
+    let file = location.file.as_ref()?;
+
+    let path = match &location.directory {
+        Some(directory) => directory.join(file),
+        None => PathBuf::from(file),
+    };
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| file.clone());
+
+    Some(json!({
+        "name": name,
+        "path": path,
+    }))
+}
+
+fn unverified(message: impl ToString) -> serde_json::Value {
+    json!({
+        "verified": false,
+        "message": message.to_string(),
+    })
+}
+
+fn stopped(reason: &str, n: usize) -> Message {
+    Message::event(
+        "stopped",
+        Some(json!({
+            "reason": reason,
+            "threadId": thread_id(n),
+            "allThreadsStopped": true,
+        })),
+    )
+}
+
+fn stop_reason(reason: HaltReason) -> &'static str {
+    match reason {
+        HaltReason::Breakpoint => "breakpoint",
+        HaltReason::Watchpoint => "data breakpoint",
+        HaltReason::Step => "step",
+        HaltReason::Exception | HaltReason::Semihosting(_) => "exception",
+        HaltReason::Request | HaltReason::External | HaltReason::Unknown => "pause",
+    }
+}
+
+#[cfg(test)]
+mod test;
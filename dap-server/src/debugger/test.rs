@@ -0,0 +1,186 @@
+//! Replays recorded DAP sessions against simulated chips.
+
+use super::Debugger;
+use crate::protocol::Request;
+use crate::target::DebugTarget;
+
+use probe_rs::config::MemoryRegion;
+use probe_rs::flashing::{download_file, FileDownloadError, Format};
+use probe_rs::server::Cores;
+use probe_rs::simulator::SimulatedCortexM;
+use probe_rs::{Core, Error, Probe, Session};
+use serde_json::Value;
+use std::path::Path;
+
+/// A vector table, with the stack at the end of the RAM and the reset handler at 0x40.
+const VECTORS: [u8; 8] = [0x00, 0x00, 0x01, 0x20, 0x41, 0x00, 0x00, 0x00];
+
+/// Sixteen `NOP`s at 0x40, followed by `B .`.
+fn program() -> Vec<u8> {
+    let mut program = [0x00, 0xbf].repeat(16);
+    program.extend_from_slice(&[0xfe, 0xe7]);
+    program
+}
+
+fn programmed_chip() -> SimulatedCortexM {
+    let chip = SimulatedCortexM::new();
+    chip.write_memory(0x0, &VECTORS);
+    chip.write_memory(0x40, &program());
+    chip
+}
+
+fn session(chip: &SimulatedCortexM) -> Session {
+    Probe::new(chip.clone())
+        .attach(SimulatedCortexM::target())
+        .unwrap()
+}
+
+/// Starts [`program`], and halts the core once it loops behind the `NOP`s.
+fn halt_in_loop(mut core: Core) {
+    core.reset().unwrap();
+    assert!(!core.core_halted().unwrap());
+    core.halt().unwrap();
+}
+
+/// Simulated chips, whose cores are debugged like the cores of a single chip.
+struct SimulatedChips(Vec<Session>);
+
+impl Cores for SimulatedChips {
+    fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn core(&mut self, n: usize) -> Result<Core<'_>, Error> {
+        self.0.get_mut(n).ok_or(Error::CoreNotFound(n))?.core(0)
+    }
+
+    fn memory_map(&self) -> &[MemoryRegion] {
+        self.0[0].memory_map()
+    }
+}
+
+impl DebugTarget for SimulatedChips {
+    fn download(&mut self, path: &Path) -> Result<(), FileDownloadError> {
+        download_file(&mut self.0[0], path, Format::Elf)
+    }
+}
+
+/// Replays a recorded session. Each request of the recording is handled, and the messages
+/// which the debugger sends until the next request have to match the recorded messages.
+///
+/// The sequence numbers are not compared, and fields which are missing in the recording
+/// are ignored.
+fn replay(target: &mut dyn DebugTarget, recording: &str) {
+    let recording: Vec<Value> = serde_json::from_str(recording).expect("Invalid recording");
+
+    let mut debugger = Debugger::new(target);
+
+    let mut expected = recording.into_iter().peekable();
+
+    while let Some(request) = expected.next() {
+        assert_eq!(request["type"], "request", "Expected a request");
+
+        let command = request["command"].clone();
+        let request: Request = serde_json::from_value(request).expect("Invalid request");
+
+        let mut messages = debugger.handle(request);
+        messages.extend(debugger.poll());
+
+        let mut messages = messages
+            .into_iter()
+            .map(|message| serde_json::to_value(message).unwrap());
+
+        while let Some(message) = expected.next_if(|message| message["type"] != "request") {
+            let actual = messages.next();
+
+            assert!(
+                actual
+                    .as_ref()
+                    .is_some_and(|actual| matches(&message, actual)),
+                "After {}, expected {} but got {:?}",
+                command,
+                message,
+                actual
+            );
+        }
+
+        if let Some(message) = messages.next() {
+            panic!("After {}, got the unexpected {}", command, message);
+        }
+    }
+}
+
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected.iter().all(|(key, value)| {
+            key == "seq" || actual.get(key).is_some_and(|actual| matches(value, actual))
+        }),
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual)
+                    .all(|(expected, actual)| matches(expected, actual))
+        }
+        (expected, actual) => expected == actual,
+    }
+}
+
+#[test]
+fn attached_core_is_inspected() {
+    let chip = programmed_chip();
+    let mut session = session(&chip);
+    halt_in_loop(session.core(0).unwrap());
+
+    replay(&mut session, include_str!("../../sessions/attach.json"));
+}
+
+#[test]
+fn instruction_breakpoints_stop_all_cores() {
+    let chips = [programmed_chip(), programmed_chip()];
+    let mut target = SimulatedChips(chips.iter().map(session).collect());
+
+    // The first core starts at the `NOP`s, while the second one already loops.
+    target.core(0).unwrap().reset_and_halt().unwrap();
+    halt_in_loop(target.core(1).unwrap());
+
+    replay(&mut target, include_str!("../../sessions/breakpoints.json"));
+
+    assert_eq!(chips[0].core_register(15), 0x60);
+    assert!(chips.iter().all(|chip| chip.is_halted()));
+    assert!(!target.core(0).unwrap().has_hw_breakpoint(0x50));
+}
+
+#[test]
+fn memory_is_disassembled() {
+    let chip = programmed_chip();
+
+    // `nop` and `bkpt #0`.
+    chip.write_memory(0x2000_0000, &[0x00, 0xbf, 0x00, 0xbe]);
+
+    let mut session = session(&chip);
+    halt_in_loop(session.core(0).unwrap());
+
+    replay(
+        &mut session,
+        include_str!("../../sessions/disassemble.json"),
+    );
+}
+
+/// The program of `sessions/program`, which calls `add` twice, is flashed and debugged
+/// by source line.
+#[test]
+fn launched_program_is_debugged_by_source_line() {
+    let chip = SimulatedCortexM::new();
+    let mut session = session(&chip);
+
+    let recording = include_str!("../../sessions/launch.json").replace(
+        "$PROGRAM",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/sessions/program/program.elf"),
+    );
+
+    replay(&mut session, &recording);
+
+    // `main` loops at the end.
+    assert_eq!(chip.core_register(15), 0x38);
+}
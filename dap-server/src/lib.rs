@@ -0,0 +1,8 @@
+mod debugger;
+mod protocol;
+mod server;
+mod target;
+
+pub use probe_rs::server::Transport;
+pub use protocol::ProtocolError;
+pub use server::serve;
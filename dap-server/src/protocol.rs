@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("Unable to access the connection: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The message has no Content-Length header")]
+    MissingContentLength,
+}
+
+/// A request of the client.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Request {
+    pub(crate) seq: i64,
+    pub(crate) command: String,
+    #[serde(default)]
+    pub(crate) arguments: Value,
+}
+
+/// A message which is sent to the client.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Message {
+    Response {
+        request_seq: i64,
+        success: bool,
+        command: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        body: Option<Value>,
+    },
+    Event {
+        event: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        body: Option<Value>,
+    },
+}
+
+impl Message {
+    pub(crate) fn event(event: &str, body: Option<Value>) -> Self {
+        Message::Event {
+            event: event.to_owned(),
+            body,
+        }
+    }
+}
+
+/// Reads the next request of the client. `None` is returned once the connection is closed.
+///
+/// Other messages, like responses to requests of the server, are skipped.
+pub(crate) fn read_request(reader: &mut impl BufRead) -> Result<Option<Request>, ProtocolError> {
+    loop {
+        let mut content_length = None;
+
+        // The header ends with an empty line.
+        loop {
+            let mut line = String::new();
+
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+
+            let line = line.trim_end();
+
+            if line.is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("Content-Length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+
+        let mut content = vec![0; content_length.ok_or(ProtocolError::MissingContentLength)?];
+        reader.read_exact(&mut content)?;
+
+        let message: Value = serde_json::from_slice(&content)?;
+
+        if message["type"] == "request" {
+            return Ok(Some(serde_json::from_value(message)?));
+        }
+
+        log::debug!("Skipping message {}", message);
+    }
+}
+
+/// Writes a message with the sequence number `seq`.
+pub(crate) fn write_message(
+    writer: &mut impl Write,
+    seq: i64,
+    message: &Message,
+) -> Result<(), ProtocolError> {
+    let mut content = serde_json::to_value(message)?;
+    content["seq"] = seq.into();
+
+    let content = content.to_string();
+
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_request, write_message, Message};
+    use serde_json::json;
+
+    #[test]
+    fn requests_are_read_from_their_header() {
+        let content = r#"{"seq":1,"type":"request","command":"threads"}"#;
+        let response = r#"{"seq":2,"type":"response","request_seq":1,"success":true,"command":"runInTerminal"}"#;
+        let data = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: {}\r\n\r\n{}",
+            response.len(),
+            response,
+            content.len(),
+            content
+        );
+
+        let mut reader = data.as_bytes();
+
+        let request = read_request(&mut reader).unwrap().unwrap();
+        assert_eq!(request.seq, 1);
+        assert_eq!(request.command, "threads");
+
+        assert!(read_request(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn messages_are_written_with_their_sequence_number() {
+        let mut data = vec![];
+
+        write_message(
+            &mut data,
+            7,
+            &Message::event("stopped", Some(json!({ "reason": "pause" }))),
+        )
+        .unwrap();
+
+        let content = r#"{"body":{"reason":"pause"},"event":"stopped","seq":7,"type":"event"}"#;
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::debugger::Debugger;
+use crate::protocol::{read_request, write_message, ProtocolError, Request};
+use crate::target::DebugTarget;
+
+#[cfg(unix)]
+use probe_rs::server::SocketFile;
+use probe_rs::server::Transport;
+use probe_rs::Session;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// How often the resumed cores are polled, while there are no requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Serves debug sessions of the client on `transport`.
+///
+/// The sessions on a socket are served one after another.
+pub fn serve(transport: Transport, mut session: Session) -> Result<(), ProtocolError> {
+    match transport {
+        Transport::Tcp(address) => {
+            let listener = TcpListener::bind(address)?;
+
+            for stream in listener.incoming() {
+                let stream = stream?;
+                log::info!("Accepted a connection from {}", stream.peer_addr()?);
+
+                let reader = stream.try_clone()?;
+                run(&mut session, reader, stream)?;
+            }
+
+            Ok(())
+        }
+        #[cfg(unix)]
+        Transport::Unix(path) => {
+            SocketFile::remove_stale(&path)?;
+            let listener = UnixListener::bind(&path)?;
+            let _socket = SocketFile::new(path.clone());
+
+            for stream in listener.incoming() {
+                let stream = stream?;
+                log::info!("Accepted a connection on {}", path.display());
+
+                let reader = stream.try_clone()?;
+                run(&mut session, reader, stream)?;
+            }
+
+            Ok(())
+        }
+        Transport::Stdio => run(&mut session, io::stdin(), io::stdout()),
+    }
+}
+
+/// Runs a single debug session, until the client disconnects.
+fn run(
+    target: &mut dyn DebugTarget,
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write,
+) -> Result<(), ProtocolError> {
+    let requests = spawn_reader(BufReader::new(reader));
+
+    let mut debugger = Debugger::new(target);
+    let mut seq = 1;
+
+    while !debugger.is_disconnected() {
+        let messages = match requests.recv_timeout(POLL_INTERVAL) {
+            Ok(request) => {
+                log::debug!("Handling request '{}'", request.command);
+                debugger.handle(request)
+            }
+            Err(RecvTimeoutError::Timeout) => debugger.poll(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        for message in messages {
+            write_message(&mut writer, seq, &message)?;
+            seq += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the requests on a separate thread, so the cores are polled while the client is idle.
+fn spawn_reader(mut reader: impl BufRead + Send + 'static) -> Receiver<Request> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || loop {
+        match read_request(&mut reader) {
+            Ok(Some(request)) => {
+                if sender.send(request).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("Unable to read a request: {}", e);
+                break;
+            }
+        }
+    });
+
+    receiver
+}
@@ -0,0 +1,22 @@
+use probe_rs::flashing::{download_file, FileDownloadError, Format};
+use probe_rs::server::Cores;
+use probe_rs::Session;
+use std::path::Path;
+
+/// The target which is debugged. Core `n` is presented to the client as the thread `n + 1`,
+/// as the protocol does not use 0 as a thread id.
+pub(crate) trait DebugTarget: Cores {
+    /// Programs the flash with the ELF file at `path`.
+    fn download(&mut self, path: &Path) -> Result<(), FileDownloadError>;
+}
+
+impl DebugTarget for Session {
+    fn download(&mut self, path: &Path) -> Result<(), FileDownloadError> {
+        download_file(self, path, Format::Elf)
+    }
+}
+
+/// The thread id of core `n`.
+pub(crate) fn thread_id(n: usize) -> i64 {
+    n as i64 + 1
+}
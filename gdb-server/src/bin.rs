@@ -3,6 +3,10 @@ use std::process::{self};
 use structopt::StructOpt;
 
 use probe_rs::{config::TargetSelector, Probe};
+use probe_rs_gdb_server::Transport;
+
+#[cfg(unix)]
+use std::path::PathBuf;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
@@ -26,6 +30,21 @@ struct Opt {
         help = "Use this flag to override the default GDB connection string (localhost:1337)."
     )]
     gdb_connection_string: Option<String>,
+    #[structopt(
+        name = "pipe",
+        long = "pipe",
+        conflicts_with_all = &["gdb-connection-string", "unix-socket"],
+        help = "Use this flag to serve GDB on stdin and stdout, for `target remote | gdb-server --pipe`."
+    )]
+    pipe: bool,
+    #[cfg(unix)]
+    #[structopt(
+        name = "unix-socket",
+        long = "unix-socket",
+        conflicts_with = "gdb-connection-string",
+        help = "Use this flag to serve GDB on a Unix domain socket at the given path."
+    )]
+    unix_socket: Option<PathBuf>,
 }
 
 fn main() {
@@ -64,6 +83,7 @@ pub fn open_probe(index: Option<usize>) -> Result<Probe, failure::Error> {
 fn main_try() -> Result<(), failure::Error> {
     // Get commandline options.
     let opt = Opt::from_iter(std::env::args());
+    let transport = transport(&opt);
 
     let probe = open_probe(None)?;
 
@@ -73,18 +93,35 @@ fn main_try() -> Result<(), failure::Error> {
     };
     let session = probe.attach(target_selector)?;
 
-    let gdb_connection_string = opt
-        .gdb_connection_string
-        .or_else(|| Some("localhost:1337".to_string()));
-    // This next unwrap will always resolve as the connection string is always Some(T).
-    println!(
-        "Firing up GDB stub at {}",
-        gdb_connection_string.as_ref().unwrap()
-    );
-    if let Err(e) = probe_rs_gdb_server::run(gdb_connection_string, session) {
+    // Stdout is used by GDB when piping, so the message goes to stderr.
+    match transport {
+        Transport::Stdio => eprintln!("Firing up GDB stub at {}", transport),
+        _ => println!("Firing up GDB stub at {}", transport),
+    }
+
+    if let Err(e) = probe_rs_gdb_server::serve(transport, session, opt.reset_halt) {
         eprintln!("During the execution of GDB an error was encountered:");
         eprintln!("{:?}", e);
     }
 
     Ok(())
 }
+
+fn transport(opt: &Opt) -> Transport {
+    if opt.pipe {
+        return Transport::Stdio;
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(path) = &opt.unix_socket {
+            return Transport::Unix(path.clone());
+        }
+    }
+
+    Transport::Tcp(
+        opt.gdb_connection_string
+            .clone()
+            .unwrap_or_else(|| "localhost:1337".to_string()),
+    )
+}
@@ -1,12 +1,17 @@
-use async_std::{
-    net::{TcpListener, TcpStream, ToSocketAddrs},
-    prelude::*,
-    task,
-};
+use crate::reader::AckMode;
+use crate::transport::{Stdio, Stream};
+use probe_rs::server::Transport;
+
+use async_std::{net::TcpListener, prelude::*, task};
 use futures::channel::mpsc;
 use gdb_protocol::packet::CheckedPacket;
 use probe_rs::Session;
 
+#[cfg(unix)]
+use async_std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use probe_rs::server::SocketFile;
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
 type Receiver<T> = mpsc::UnboundedReceiver<T>;
@@ -14,46 +19,90 @@ type Receiver<T> = mpsc::UnboundedReceiver<T>;
 const CONNECTION_STRING: &str = "127.0.0.1:1337";
 
 /// This is the main entrypoint which we will call to start the GDB stub.
-pub fn run(connection_string: Option<impl Into<String>>, session: Session) -> Result<()> {
+///
+/// If `reset_halt` is set, the core is halted after it was reset when GDB programmed the flash.
+pub fn run(
+    connection_string: Option<impl Into<String>>,
+    session: Session,
+    reset_halt: bool,
+) -> Result<()> {
     let connection_string = connection_string
         .map(|cs| cs.into())
         .unwrap_or_else(|| CONNECTION_STRING.to_owned());
-    println!("GDB stub listening on {}", connection_string);
-    task::block_on(accept_loop(connection_string, session))
+
+    serve(Transport::Tcp(connection_string), session, reset_halt)
 }
 
-/// This function accepts any incomming connection.
-async fn accept_loop(addr: impl ToSocketAddrs, session: Session) -> Result<()> {
-    let listener = TcpListener::bind(addr).await?;
+/// Starts the GDB stub on the given transport.
+///
+/// Sockets accept one connection after the other, while stdio serves a single connection.
+/// Stdout carries the packets then, so all other output goes to stderr.
+pub fn serve(transport: Transport, session: Session, reset_halt: bool) -> Result<()> {
+    match transport {
+        Transport::Stdio => eprintln!("GDB stub listening on {}", transport),
+        _ => println!("GDB stub listening on {}", transport),
+    }
+
+    task::block_on(accept_loop(transport, session, reset_halt))
+}
 
+/// This function accepts any incomming connection.
+async fn accept_loop(transport: Transport, session: Session, reset_halt: bool) -> Result<()> {
     let mut session = session;
 
-    let mut incoming = listener.incoming();
-    while let Some(stream) = incoming.next().await {
-        if let Err(e) = handle_connection(stream?, &mut session).await {
-            eprintln!(
-                "An error with the current connection has been encountered. It has been closed."
-            );
-            eprintln!("{:?}", e);
+    match transport {
+        Transport::Tcp(address) => {
+            let listener = TcpListener::bind(address).await?;
+
+            let mut incoming = listener.incoming();
+            while let Some(stream) = incoming.next().await {
+                let stream = stream?;
+                println!("Accepted a new connection from: {}", stream.peer_addr()?);
+                serve_connection(stream, &mut session, reset_halt).await;
+            }
+        }
+        #[cfg(unix)]
+        Transport::Unix(path) => {
+            SocketFile::remove_stale(&path)?;
+            let listener = UnixListener::bind(&path).await?;
+            let _socket = SocketFile::new(path.clone());
+
+            let mut incoming = listener.incoming();
+            while let Some(stream) = incoming.next().await {
+                println!("Accepted a new connection on: {}", path.display());
+                serve_connection(stream?, &mut session, reset_halt).await;
+            }
         }
+        Transport::Stdio => serve_connection(Stdio::new(), &mut session, reset_halt).await,
     }
+
     Ok(())
 }
 
+/// Serves a connection until it is closed, reporting errors on stderr.
+async fn serve_connection(stream: impl Stream, session: &mut Session, reset_halt: bool) {
+    if let Err(e) = handle_connection(stream, session, reset_halt).await {
+        eprintln!("An error with the current connection has been encountered. It has been closed.");
+        eprintln!("{:?}", e);
+    }
+}
+
 /// Handle a single connection of a client
-async fn handle_connection(stream: TcpStream, session: &mut Session) -> Result<()> {
+async fn handle_connection(
+    stream: impl Stream,
+    session: &mut Session,
+    reset_halt: bool,
+) -> Result<()> {
     let (packet_stream_sender, packet_stream_receiver) = mpsc::unbounded();
     let (tbd_sender, tbd_receiver) = mpsc::unbounded();
 
-    println!("Accepted a new connection from: {}", stream.peer_addr()?);
-
     let inbound_broker_handle = task::spawn(inbound_broker_loop(
         stream,
         tbd_sender,
         packet_stream_receiver,
     ));
 
-    super::worker::worker(tbd_receiver, packet_stream_sender, session).await?;
+    super::worker::worker(tbd_receiver, packet_stream_sender, session, reset_halt).await?;
 
     inbound_broker_handle.await?;
 
@@ -62,7 +111,7 @@ async fn handle_connection(stream: TcpStream, session: &mut Session) -> Result<(
 
 /// The receiver loop handles any messages that are inbound.
 async fn inbound_broker_loop(
-    stream: TcpStream,
+    stream: impl Stream,
     packet_stream: Sender<CheckedPacket>,
     mut packet_stream_2: Receiver<CheckedPacket>,
 ) -> Result<()> {
@@ -70,6 +119,7 @@ async fn inbound_broker_loop(
 
     let mut buffer = vec![];
     let mut tmp_buf = [0; 1024];
+    let mut ack_mode = AckMode::Enabled;
 
     let mut stream = stream;
 
@@ -80,19 +130,19 @@ async fn inbound_broker_loop(
         futures::select! {
             packet = packet_stream_2 => {
                 if let Some(packet) = packet {
-                    super::writer::writer(packet, &mut stream, &packet_stream, &mut buffer).await?
+                    super::writer::writer(packet, &mut stream, &packet_stream, &mut buffer, &mut ack_mode).await?
                 }
             },
             n = read => {
                 match n {
                     Ok(0) => {
-                        println!("GDB connection closed.");
+                        eprintln!("GDB connection closed.");
                         break Ok(());
                     }
                     Ok(n) => {
                         buffer.extend(&tmp_buf[0..n]);
                         log::info!("Current buf {}", String::from_utf8_lossy(&buffer));
-                        super::reader::reader(&mut stream, &packet_stream, &mut buffer).await?
+                        super::reader::reader(&mut stream, &packet_stream, &mut buffer, &mut ack_mode).await?
                     },
                     Err(e) => {
 
@@ -1,61 +1,239 @@
-use probe_rs::{Core, MemoryInterface};
+use crate::memory_map;
+use crate::monitor::MonitorCommand;
+use crate::semihosting::FileIo;
+use crate::target_description::TargetDescription;
+use crate::threads::{thread_id, ThreadId, Threads};
+use probe_rs::server::Cores;
+
+use probe_rs::flashing::{FlashError, FlashLoader, FlashProgress};
+use probe_rs::{Core, CoreStatus, HaltReason, MemoryInterface, Session, WatchpointKind};
 use recap::Recap;
 use serde::Deserialize;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The reason why a packet could not be handled.
+///
+/// Each error is answered with an `Exx` reply, so the connection stays usable.
+#[derive(Debug, Error)]
+pub(crate) enum HandlerError {
+    #[error("Malformed packet '{0}'")]
+    MalformedPacket(String),
+    #[error("Unknown register {0}")]
+    UnknownRegister(usize),
+    #[error("{0}")]
+    Core(#[from] probe_rs::Error),
+    #[error("Memory access failed: {0}")]
+    MemoryAccess(#[source] probe_rs::Error),
+    #[error("{0}")]
+    Flash(#[from] FlashError),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
 
-pub(crate) fn q_supported() -> Option<String> {
-    Some("PacketSize=2048;swbreak-;hwbreak+;vContSupported+;qXfer:memory-map:read+".into())
+impl HandlerError {
+    /// The error reply which is sent to GDB.
+    pub(crate) fn reply(&self) -> String {
+        match self {
+            HandlerError::MalformedPacket(_) | HandlerError::UnknownRegister(_) => "E00",
+            HandlerError::Core(_) => "E01",
+            HandlerError::Flash(_) => "E02",
+            HandlerError::Io(_) => "E05",
+            // We have no clue if this is the right error code since GDB doesn't feel like docs.
+            // We just assume Linux ERRNOs and pick a fitting one: https://gist.github.com/greggyNapalm/2413028#file-gistfile1-txt-L138
+            // This seems to work in practice and seems to be the way to do stuff around GDB.
+            HandlerError::MemoryAccess(_) => "E79",
+        }
+        .into()
+    }
 }
 
-pub(crate) fn reply_empty() -> Option<String> {
-    Some("".into())
+/// The reply to a packet, where `None` means that no reply is sent.
+pub(crate) type HandlerResult = Result<Option<String>, HandlerError>;
+
+/// The largest packet which GDB may send, and which the replies are bounded by.
+const PACKET_SIZE: usize = 2048;
+
+pub(crate) fn q_supported() -> HandlerResult {
+    Ok(Some(format!(
+        "PacketSize={};swbreak+;hwbreak+;vContSupported+;qXfer:memory-map:read+;qXfer:features:read+;QStartNoAckMode+",
+        PACKET_SIZE
+    )))
 }
 
-pub(crate) fn q_attached() -> Option<String> {
-    Some("1".into())
+pub(crate) fn reply_empty() -> HandlerResult {
+    Ok(Some("".into()))
 }
 
-pub(crate) fn halt_reason() -> Option<String> {
-    Some("S05".into())
+pub(crate) fn q_attached() -> HandlerResult {
+    Ok(Some("1".into()))
 }
 
-pub(crate) fn read_general_registers() -> Option<String> {
-    Some("xxxxxxxx".into())
+pub(crate) fn halt_reason(cores: &mut dyn Cores, threads: &Threads) -> HandlerResult {
+    let mut core = cores.core(threads.general)?;
+
+    Ok(Some(stop_reply(&mut core, threads.general)))
 }
 
-pub(crate) fn read_register(packet_string: String, core: &mut Core) -> Option<String> {
+pub(crate) fn thread_info_first(cores: &mut dyn Cores) -> HandlerResult {
+    let ids: Vec<String> = (0..cores.count())
+        .map(|n| format!("{:x}", thread_id(n)))
+        .collect();
+
+    Ok(Some(format!("m{}", ids.join(","))))
+}
+
+pub(crate) fn thread_info_subsequent() -> HandlerResult {
+    Ok(Some("l".into()))
+}
+
+pub(crate) fn current_thread(threads: &Threads) -> HandlerResult {
+    Ok(Some(format!("QC{:x}", thread_id(threads.general))))
+}
+
+pub(crate) fn thread_extra_info(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    let n = packet_string
+        .split_once(',')
+        .and_then(|(_, id)| parse_thread(id, cores))
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
+
+    Ok(Some(to_hex(format!("Core {}", n).as_bytes())))
+}
+
+pub(crate) fn thread_alive(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    match parse_thread(&packet_string[1..], cores) {
+        Some(_) => Ok(Some("OK".into())),
+        None => Err(HandlerError::MalformedPacket(packet_string)),
+    }
+}
+
+pub(crate) fn set_thread(
+    packet_string: String,
+    cores: &mut dyn Cores,
+    threads: &mut Threads,
+) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"H(?P<op>[gc])(?P<thread>-?\w+)"#)]
+    struct H {
+        op: String,
+        thread: String,
+    }
+
+    let h = parse::<H>(&packet_string)?;
+
+    // All and any thread select the first core, as only one core can be accessed at a time.
+    let n = match ThreadId::parse(&h.thread) {
+        Some(ThreadId::Core(n)) if n < cores.count() => n,
+        Some(ThreadId::All) | Some(ThreadId::Any) => 0,
+        _ => return Err(HandlerError::MalformedPacket(packet_string)),
+    };
+
+    if h.op == "g" {
+        threads.general = n;
+    } else {
+        threads.step = n;
+    }
+
+    Ok(Some("OK".into()))
+}
+
+/// Returns the core of a thread id which refers to a single, existing core.
+fn parse_thread(id: &str, cores: &mut dyn Cores) -> Option<usize> {
+    match ThreadId::parse(id)? {
+        ThreadId::Core(n) if n < cores.count() => Some(n),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_general_registers(
+    core: &mut Core,
+    description: &TargetDescription,
+) -> HandlerResult {
+    Ok(Some(
+        description
+            .registers()
+            .map(|register| register.read(core))
+            .collect(),
+    ))
+}
+
+pub(crate) fn write_general_registers(
+    packet_string: String,
+    core: &mut Core,
+    description: &TargetDescription,
+) -> HandlerResult {
+    let data = parse_hex(&packet_string[1..])
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
+
+    let mut remaining = &data[..];
+
+    for register in description.registers() {
+        if remaining.len() < register.size() {
+            break;
+        }
+
+        let (value, rest) = remaining.split_at(register.size());
+        remaining = rest;
+
+        register.write(core, value)?;
+    }
+
+    Ok(Some("OK".into()))
+}
+
+pub(crate) fn read_register(
+    packet_string: String,
+    core: &mut Core,
+    description: &TargetDescription,
+) -> HandlerResult {
     #[derive(Debug, Deserialize, PartialEq, Recap)]
     #[recap(regex = r#"p(?P<reg>\w+)"#)]
     struct P {
         reg: String,
     }
 
-    let p = packet_string.parse::<P>().unwrap();
+    let p = parse::<P>(&packet_string)?;
+    let number = parse_number(&p.reg, &packet_string)? as usize;
 
     let _ = core.halt();
-    core.wait_for_core_halted().unwrap();
-
-    let value = core
-        .read_core_reg(u16::from_str_radix(&p.reg, 16).unwrap())
-        .unwrap();
-
-    format!(
-        "{}{}{}{}",
-        value as u8,
-        (value >> 8) as u8,
-        (value >> 16) as u8,
-        (value >> 24) as u8
-    );
-
-    Some(format!(
-        "{:02x}{:02x}{:02x}{:02x}",
-        value as u8,
-        (value >> 8) as u8,
-        (value >> 16) as u8,
-        (value >> 24) as u8
-    ))
+    core.wait_for_core_halted()?;
+
+    let register = description
+        .register(number)
+        .ok_or(HandlerError::UnknownRegister(number))?;
+
+    Ok(Some(register.read(core)))
 }
 
-pub(crate) fn read_memory(packet_string: String, core: &mut Core) -> Option<String> {
+pub(crate) fn write_register(
+    packet_string: String,
+    core: &mut Core,
+    description: &TargetDescription,
+) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"P(?P<reg>\w+)=(?P<value>\w+)"#)]
+    struct P {
+        reg: String,
+        value: String,
+    }
+
+    let p = parse::<P>(&packet_string)?;
+    let number = parse_number(&p.reg, &packet_string)? as usize;
+
+    let register = description
+        .register(number)
+        .ok_or(HandlerError::UnknownRegister(number))?;
+
+    match parse_hex(&p.value) {
+        Some(value) if value.len() == register.size() => {
+            register.write(core, &value)?;
+            Ok(Some("OK".into()))
+        }
+        _ => Err(HandlerError::MalformedPacket(packet_string)),
+    }
+}
+
+pub(crate) fn read_memory(packet_string: String, core: &mut Core) -> HandlerResult {
     #[derive(Debug, Deserialize, PartialEq, Recap)]
     #[recap(regex = r#"m(?P<addr>\w+),(?P<length>\w+)"#)]
     struct M {
@@ -63,51 +241,175 @@ pub(crate) fn read_memory(packet_string: String, core: &mut Core) -> Option<Stri
         length: String,
     }
 
-    let m = packet_string.parse::<M>().unwrap();
+    let m = parse::<M>(&packet_string)?;
+    let addr = parse_number(&m.addr, &packet_string)?;
+    let length = parse_number(&m.length, &packet_string)?;
+
+    // Each byte takes two characters of the reply, and GDB reads the rest with another packet.
+    let length = (length as usize).min(PACKET_SIZE / 2);
+
+    let mut readback_data = vec![0u8; length];
+    core.read_8(addr, &mut readback_data)
+        .map_err(HandlerError::MemoryAccess)?;
+
+    Ok(Some(
+        readback_data
+            .iter()
+            .map(|s| format!("{:02x?}", s))
+            .collect::<Vec<String>>()
+            .join(""),
+    ))
+}
+
+pub(crate) fn vcont_supported() -> HandlerResult {
+    Ok(Some("vCont;c;C;s;S;t".into()))
+}
 
-    let mut readback_data = vec![0u8; usize::from_str_radix(&m.length, 16).unwrap()];
-    match core.read_8(
-        u32::from_str_radix(&m.addr, 16).unwrap(),
-        &mut readback_data,
-    ) {
-        Ok(_) => Some(
-            readback_data
-                .iter()
-                .map(|s| format!("{:02x?}", s))
-                .collect::<Vec<String>>()
-                .join(""),
-        ),
-        // We have no clue if this is the right error code since GDB doesn't feel like docs.
-        // We just assume Linux ERRNOs and pick a fitting one: https://gist.github.com/greggyNapalm/2413028#file-gistfile1-txt-L138
-        // This seems to work in practice and seems to be the way to do stuff around GDB.
-        Err(_e) => Some("E79".to_string()),
+/// Resumes all cores.
+pub(crate) fn run(cores: &mut dyn Cores, threads: &mut Threads) -> HandlerResult {
+    for n in 0..cores.count() {
+        cores.core(n)?.run()?;
+        threads.resumed(n);
     }
+
+    Ok(None)
 }
 
-pub(crate) fn vcont_supported() -> Option<String> {
-    Some("vCont;c;t;s".into())
+pub(crate) fn step(cores: &mut dyn Cores, threads: &mut Threads) -> HandlerResult {
+    step_core(cores, threads, threads.step)
 }
 
-pub(crate) fn run(core: &mut Core, awaits_halt: &mut bool) -> Option<String> {
-    core.run().unwrap();
-    *awaits_halt = true;
-    None
+/// Steps a core, while the other cores stay halted.
+fn step_core(cores: &mut dyn Cores, threads: &mut Threads, n: usize) -> HandlerResult {
+    cores.core(n)?.step()?;
+    threads.running.clear();
+
+    Ok(Some(format!("T05thread:{:x};", thread_id(n))))
+}
+
+/// Applies the action for each core given by a `vCont` packet.
+///
+/// If a core is stepped, the other cores stay halted.
+pub(crate) fn vcont(
+    packet_string: String,
+    cores: &mut dyn Cores,
+    threads: &mut Threads,
+) -> HandlerResult {
+    let malformed = || HandlerError::MalformedPacket(packet_string.clone());
+
+    let mut actions = vec![];
+
+    for action in packet_string.split(';').skip(1) {
+        let mut parts = action.splitn(2, ':');
+
+        // The signal of the `C` and `S` actions is not delivered to the core.
+        let action = match parts.next().and_then(|action| action.chars().next()) {
+            Some('c') | Some('C') => 'c',
+            Some('s') | Some('S') => 's',
+            Some('t') => 't',
+            _ => return Err(malformed()),
+        };
+
+        let thread = match parts.next() {
+            Some(thread) => ThreadId::parse(thread).ok_or_else(malformed)?,
+            None => ThreadId::All,
+        };
+
+        actions.push((action, thread));
+    }
+
+    // The first action which matches a core is used for it.
+    let cores_with = |action: char| -> Vec<usize> {
+        (0..cores.count())
+            .filter(|&n| {
+                actions
+                    .iter()
+                    .find(|(_, thread)| thread.matches(n))
+                    .map(|(a, _)| *a == action)
+                    .unwrap_or(false)
+            })
+            .collect()
+    };
+
+    let stepped = cores_with('s');
+    let resumed = cores_with('c');
+    let stopped = cores_with('t');
+
+    if let Some(&n) = stepped.first() {
+        return step_core(cores, threads, n);
+    }
+
+    for n in stopped {
+        let mut core = cores.core(n)?;
+        core.halt()?;
+        core.wait_for_core_halted()?;
+        threads.running.retain(|&running| running != n);
+    }
+
+    if resumed.is_empty() {
+        return Ok(Some("OK".into()));
+    }
+
+    for n in resumed {
+        cores.core(n)?.run()?;
+        threads.resumed(n);
+    }
+
+    Ok(None)
 }
 
-pub(crate) fn stop(core: &mut Core, awaits_halt: &mut bool) -> Option<String> {
-    core.halt().unwrap();
-    core.wait_for_core_halted().unwrap();
-    *awaits_halt = false;
-    Some("OK".into())
+pub(crate) fn insert_software_break(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"Z0,(?P<addr>\w+),(?P<kind>\w+)"#)]
+    struct Z0 {
+        addr: String,
+        kind: String,
+    }
+
+    let z0 = parse::<Z0>(&packet_string)?;
+    let addr = parse_number(&z0.addr, &packet_string)?;
+
+    // The instruction is replaced by a breakpoint instruction, as GDB expects. Only where
+    // that is not possible, like in flash, a hardware breakpoint takes its place.
+    if memory_map::is_ram(cores.memory_map(), addr) {
+        set_on_all_cores(cores, |core| core.set_sw_breakpoint(addr))?;
+    } else {
+        set_on_all_cores(cores, |core| {
+            if core.has_hw_breakpoint(addr) {
+                Ok(())
+            } else {
+                core.set_hw_breakpoint(addr)
+            }
+        })?;
+    }
+
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn step(core: &mut Core, awaits_halt: &mut bool) -> Option<String> {
-    core.step().unwrap();
-    *awaits_halt = false;
-    Some("S05".into())
+pub(crate) fn remove_software_break(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"z0,(?P<addr>\w+),(?P<kind>\w+)"#)]
+    struct Z0 {
+        addr: String,
+        kind: String,
+    }
+
+    let z0 = parse::<Z0>(&packet_string)?;
+    let addr = parse_number(&z0.addr, &packet_string)?;
+
+    // A software breakpoint is cleared by the first core, as it is shared by all of them.
+    clear_on_all_cores(cores, |core| {
+        if core.has_breakpoint(addr) {
+            core.clear_breakpoint(addr)
+        } else {
+            Ok(())
+        }
+    })?;
+
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn insert_hardware_break(packet_string: String, core: &mut Core) -> Option<String> {
+pub(crate) fn insert_hardware_break(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
     #[derive(Debug, Deserialize, PartialEq, Recap)]
     #[recap(regex = r#"Z1,(?P<addr>\w+),(?P<size>\w+)"#)]
     struct Z1 {
@@ -115,18 +417,15 @@ pub(crate) fn insert_hardware_break(packet_string: String, core: &mut Core) -> O
         size: String,
     }
 
-    let z1 = packet_string.parse::<Z1>().unwrap();
+    let z1 = parse::<Z1>(&packet_string)?;
+    let addr = parse_number(&z1.addr, &packet_string)?;
 
-    let addr = u32::from_str_radix(&z1.addr, 16).unwrap();
+    set_on_all_cores(cores, |core| core.set_hw_breakpoint(addr))?;
 
-    core.reset_and_halt().unwrap();
-    core.wait_for_core_halted().unwrap();
-    core.set_hw_breakpoint(addr).unwrap();
-    core.run().unwrap();
-    Some("OK".into())
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn remove_hardware_break(packet_string: String, core: &mut Core) -> Option<String> {
+pub(crate) fn remove_hardware_break(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
     #[derive(Debug, Deserialize, PartialEq, Recap)]
     #[recap(regex = r#"z1,(?P<addr>\w+),(?P<size>\w+)"#)]
     struct Z1 {
@@ -134,18 +433,120 @@ pub(crate) fn remove_hardware_break(packet_string: String, core: &mut Core) -> O
         size: String,
     }
 
-    let z1 = packet_string.parse::<Z1>().unwrap();
+    let z1 = parse::<Z1>(&packet_string)?;
+    let addr = parse_number(&z1.addr, &packet_string)?;
+
+    clear_on_all_cores(cores, |core| core.clear_hw_breakpoint(addr))?;
+
+    Ok(Some("OK".into()))
+}
+
+#[derive(Debug, Deserialize, PartialEq, Recap)]
+#[recap(regex = r#"[Zz](?P<typ>[234]),(?P<addr>\w+),(?P<length>\w+)"#)]
+struct Watch {
+    typ: String,
+    addr: String,
+    length: String,
+}
+
+impl Watch {
+    fn kind(&self) -> WatchpointKind {
+        match self.typ.as_str() {
+            "2" => WatchpointKind::Write,
+            "3" => WatchpointKind::Read,
+            _ => WatchpointKind::Access,
+        }
+    }
+
+    /// The aligned region of 2^mask bytes which contains all watched bytes.
+    fn region(&self) -> Option<(u32, u32)> {
+        let addr = u32::from_str_radix(&self.addr, 16).ok()?;
+        let length = u32::from_str_radix(&self.length, 16).ok()?;
+
+        let last = addr.checked_add(length.max(1) - 1)?;
+        let mask = 32 - (addr ^ last).leading_zeros();
+
+        let base = if mask == 32 {
+            0
+        } else {
+            addr & !((1 << mask) - 1)
+        };
+
+        Some((base, mask))
+    }
+}
+
+pub(crate) fn insert_watchpoint(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    let watch = parse::<Watch>(&packet_string)?;
+
+    let (addr, mask) = watch
+        .region()
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
 
-    let addr = u32::from_str_radix(&z1.addr, 16).unwrap();
+    set_on_all_cores(cores, |core| {
+        core.set_hw_watchpoint(addr, mask, watch.kind())
+    })?;
 
-    core.reset_and_halt().unwrap();
-    core.wait_for_core_halted().unwrap();
-    core.clear_hw_breakpoint(addr).unwrap();
-    core.run().unwrap();
-    Some("OK".into())
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn write_memory(packet_string: String, data: &[u8], core: &mut Core) -> Option<String> {
+pub(crate) fn remove_watchpoint(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    let watch = parse::<Watch>(&packet_string)?;
+
+    let (addr, _) = watch
+        .region()
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
+
+    clear_on_all_cores(cores, |core| core.clear_hw_watchpoint(addr))?;
+
+    Ok(Some("OK".into()))
+}
+
+/// The stop reply which tells GDB why core `n` halted.
+pub(crate) fn stop_reply(core: &mut Core, n: usize) -> String {
+    let thread = format!("thread:{:x};", thread_id(n));
+
+    let reason = match core.status() {
+        Ok(CoreStatus::Halted(reason)) => reason,
+        Ok(_) => return format!("T05{}", thread),
+        Err(e) => {
+            log::warn!("Unable to read the halt reason: {}", e);
+            return format!("T05{}", thread);
+        }
+    };
+
+    match reason {
+        HaltReason::Breakpoint => {
+            let pc = core.read_core_reg(core.registers().program_counter());
+
+            match pc {
+                Ok(pc) if core.has_hw_breakpoint(pc) => format!("T05{}hwbreak:;", thread),
+                // Either a breakpoint placed in RAM, or one compiled into the program.
+                _ => format!("T05{}swbreak:;", thread),
+            }
+        }
+        HaltReason::Watchpoint => match core.matched_watchpoint() {
+            Ok(Some(watchpoint)) => {
+                let kind = match watchpoint.kind() {
+                    WatchpointKind::Write => "watch",
+                    WatchpointKind::Read => "rwatch",
+                    WatchpointKind::Access => "awatch",
+                };
+
+                format!("T05{}{}:{:x};", thread, kind, watchpoint.address())
+            }
+            Ok(None) => format!("T05{}", thread),
+            Err(e) => {
+                log::warn!("Unable to read the matched watchpoint: {}", e);
+                format!("T05{}", thread)
+            }
+        },
+        HaltReason::Request | HaltReason::External => format!("T02{}", thread),
+        _ => format!("T05{}", thread),
+    }
+}
+
+pub(crate) fn write_memory(packet_string: String, data: &[u8], core: &mut Core) -> HandlerResult {
     #[derive(Debug, Deserialize, PartialEq, Recap)]
     #[recap(regex = r#"X(?P<addr>\w+),(?P<length>\w+):(?P<data>[01]*)"#)]
     struct X {
@@ -154,62 +555,410 @@ pub(crate) fn write_memory(packet_string: String, data: &[u8], core: &mut Core)
         data: String,
     }
 
-    let x = packet_string.parse::<X>().unwrap();
+    let x = parse::<X>(&packet_string)?;
+    let addr = parse_number(&x.addr, &packet_string)?;
+    let length = parse_number(&x.length, &packet_string)?;
+
+    // The data is binary, so it is taken from the packet after the first colon.
+    let data = data
+        .iter()
+        .position(|&byte| byte == b':')
+        .map(|separator| &data[separator + 1..])
+        .filter(|data| data.len() == length as usize)
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
+
+    core.write_8(addr, data)
+        .map_err(HandlerError::MemoryAccess)?;
 
-    let length = usize::from_str_radix(&x.length, 16).unwrap();
-    let data = &data[data.len() - length..];
+    Ok(Some("OK".into()))
+}
 
-    core.write_8(u32::from_str_radix(&x.addr, 16).unwrap(), data)
-        .unwrap();
+pub(crate) fn flash_erase(packet_string: String) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"vFlashErase:(?P<addr>\w+),(?P<length>\w+)"#)]
+    struct VFlashErase {
+        addr: String,
+        length: String,
+    }
+
+    // The sectors are erased by the flash algorithm when the data is programmed on `vFlashDone`.
+    parse::<VFlashErase>(&packet_string)?;
 
-    Some("OK".into())
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn get_memory_map() -> Option<String> {
-    let xml = r#"<?xml version="1.0"?>
-<!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd">
-<memory-map>
-<memory type="ram" start="0x20000000" length="0x4000"/>
-<memory type="rom" start="0x00000000" length="0x40000"/>
-</memory-map>"#;
-    Some(String::from_utf8(gdb_sanitize_file(xml.as_bytes(), 0, 1000)).unwrap())
+pub(crate) fn flash_write(data: &[u8], flash_data: &mut Vec<(u32, Vec<u8>)>) -> HandlerResult {
+    let malformed = || HandlerError::MalformedPacket(String::from_utf8_lossy(data).into_owned());
+
+    // The data is binary, so the packet is split at the colon after the address.
+    let packet = data.get(b"vFlashWrite:".len()..).ok_or_else(malformed)?;
+    let separator = packet
+        .iter()
+        .position(|&byte| byte == b':')
+        .ok_or_else(malformed)?;
+
+    let address = std::str::from_utf8(&packet[..separator])
+        .ok()
+        .and_then(|address| u32::from_str_radix(address, 16).ok())
+        .ok_or_else(malformed)?;
+
+    flash_data.push((address, packet[separator + 1..].to_vec()));
+
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn user_halt(core: &mut Core, awaits_halt: &mut bool) -> Option<String> {
-    let _ = core.halt();
-    core.wait_for_core_halted().unwrap();
-    *awaits_halt = false;
-    Some("T02".into())
+/// Programs the data collected from `vFlashWrite` packets, and resets the target afterwards.
+///
+/// Then all cores are halted with `reset_halt`, or else all of them are running.
+pub(crate) fn flash_done(
+    session: &mut Session,
+    flash_data: &mut Vec<(u32, Vec<u8>)>,
+    threads: &mut Threads,
+    reset_halt: bool,
+) -> HandlerResult {
+    let data = std::mem::take(flash_data);
+
+    let memory_map = session.memory_map().to_vec();
+    let mut loader = FlashLoader::new(&memory_map, false);
+
+    for (address, data) in &data {
+        loader.add_data(*address, data)?;
+    }
+
+    loader.commit(session, &FlashProgress::new(|_| {}), false)?;
+
+    // Resetting the first core resets the whole chip.
+    if reset_halt {
+        session.core(0)?.reset_and_halt()?;
+
+        for n in 1..session.list_cores().len() {
+            let mut core = session.core(n)?;
+            core.halt()?;
+            core.wait_for_core_halted()?;
+        }
+
+        threads.running.clear();
+    } else {
+        session.core(0)?.reset()?;
+
+        for n in 0..session.list_cores().len() {
+            threads.resumed(n);
+        }
+    }
+
+    Ok(Some("OK".into()))
+}
+
+pub(crate) fn get_memory_map(packet_string: String, memory_map: &str) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"qXfer:memory-map:read::(?P<offset>\w+),(?P<length>\w+)"#)]
+    struct QXfer {
+        offset: String,
+        length: String,
+    }
+
+    let q = parse::<QXfer>(&packet_string)?;
+    let offset = parse_number(&q.offset, &packet_string)?;
+    let length = parse_number(&q.length, &packet_string)?;
+
+    Ok(Some(gdb_sanitize_file(
+        memory_map.as_bytes(),
+        offset as usize,
+        length as usize,
+    )))
+}
+
+pub(crate) fn read_target_description(
+    packet_string: String,
+    description: &TargetDescription,
+) -> HandlerResult {
+    #[derive(Debug, Deserialize, PartialEq, Recap)]
+    #[recap(regex = r#"qXfer:features:read:(?P<annex>[^:]+):(?P<offset>\w+),(?P<length>\w+)"#)]
+    struct QXfer {
+        annex: String,
+        offset: String,
+        length: String,
+    }
+
+    let q = parse::<QXfer>(&packet_string)?;
+
+    if q.annex != "target.xml" {
+        return Err(HandlerError::MalformedPacket(packet_string));
+    }
+
+    let offset = parse_number(&q.offset, &packet_string)?;
+    let length = parse_number(&q.length, &packet_string)?;
+
+    let xml = description.to_xml();
+    Ok(Some(gdb_sanitize_file(
+        xml.as_bytes(),
+        offset as usize,
+        length as usize,
+    )))
+}
+
+/// Turns off the acknowledgement of packets, once the reply was acknowledged.
+pub(crate) fn start_no_ack_mode() -> HandlerResult {
+    Ok(Some("OK".into()))
+}
+
+pub(crate) fn host_info(description: &TargetDescription) -> HandlerResult {
+    Ok(Some(format!(
+        "triple:{};endian:little;ptrsize:4;",
+        to_hex(description.triple().as_bytes())
+    )))
+}
+
+/// The target is presented to LLDB as a single process.
+pub(crate) fn process_info(description: &TargetDescription) -> HandlerResult {
+    Ok(Some(format!(
+        "pid:1;endian:little;ptrsize:4;triple:{};",
+        to_hex(description.triple().as_bytes())
+    )))
+}
+
+/// Describes a register to LLDB, which asks for one register after the other until
+/// it receives an error.
+pub(crate) fn register_info(
+    packet_string: String,
+    description: &TargetDescription,
+) -> HandlerResult {
+    let number = parse_number(&packet_string["qRegisterInfo".len()..], &packet_string)?;
+
+    match description.register_info(number as usize) {
+        Some(info) => Ok(Some(info)),
+        None => Ok(Some("E45".into())),
+    }
+}
+
+pub(crate) fn memory_region_info(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    let address = parse_number(&packet_string["qMemoryRegionInfo:".len()..], &packet_string)?;
+
+    Ok(Some(memory_map::memory_region_info(
+        cores.memory_map(),
+        address,
+    )))
+}
+
+/// The stop reply of a single thread, which LLDB reads for every thread once the target halted.
+pub(crate) fn thread_stop_info(packet_string: String, cores: &mut dyn Cores) -> HandlerResult {
+    let n = parse_thread(&packet_string["qThreadStopInfo".len()..], cores)
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
+
+    Ok(Some(stop_reply(&mut cores.core(n)?, n)))
+}
+
+/// Describes all threads as JSON, including their program counter and stack pointer,
+/// so LLDB does not have to read them separately.
+pub(crate) fn threads_info(
+    cores: &mut dyn Cores,
+    description: &TargetDescription,
+) -> HandlerResult {
+    let expedited: Vec<usize> = ["pc", "sp"]
+        .iter()
+        .filter_map(|name| description.generic_register(name))
+        .collect();
+
+    let mut threads = vec![];
+
+    for n in 0..cores.count() {
+        let mut core = cores.core(n)?;
+
+        let (reason, signal) = match core.status()? {
+            CoreStatus::Halted(HaltReason::Breakpoint) => ("breakpoint", 5),
+            CoreStatus::Halted(HaltReason::Watchpoint) => ("watchpoint", 5),
+            CoreStatus::Halted(HaltReason::Step) => ("trace", 5),
+            CoreStatus::Halted(HaltReason::Request) | CoreStatus::Halted(HaltReason::External) => {
+                ("signal", 2)
+            }
+            _ => ("signal", 5),
+        };
+
+        let registers: Vec<String> = expedited
+            .iter()
+            .filter_map(|&number| {
+                let register = description.register(number)?;
+                Some(format!(r#""{}":"{}""#, number, register.read(&mut core)))
+            })
+            .collect();
+
+        threads.push(format!(
+            r#"{{"tid":{},"name":"Core {}","reason":"{}","signal":{},"registers":{{{}}}}}"#,
+            thread_id(n),
+            n,
+            reason,
+            signal,
+            registers.join(",")
+        ));
+    }
+
+    Ok(Some(format!("[{}]", threads.join(","))))
+}
+
+/// Resumes the core which made a semihosting request, once GDB executed it.
+///
+/// If GDB was interrupted with Ctrl-C meanwhile, all cores are halted instead.
+pub(crate) fn file_io_reply(
+    packet_string: String,
+    cores: &mut dyn Cores,
+    threads: &mut Threads,
+    file_io: &mut FileIo,
+) -> HandlerResult {
+    let (n, interrupted) = file_io.complete(&packet_string, cores)?;
+
+    if interrupted {
+        threads.general = n;
+        return user_halt(cores, threads);
+    }
+
+    cores.core(n)?.run()?;
+    threads.resumed(n);
+
+    Ok(None)
 }
 
-pub(crate) fn detach(break_due: &mut bool) -> Option<String> {
+/// Halts all cores.
+pub(crate) fn user_halt(cores: &mut dyn Cores, threads: &mut Threads) -> HandlerResult {
+    for n in 0..cores.count() {
+        let mut core = cores.core(n)?;
+        let _ = core.halt();
+        core.wait_for_core_halted()?;
+    }
+
+    threads.running.clear();
+
+    Ok(Some(format!("T02thread:{:x};", thread_id(threads.general))))
+}
+
+pub(crate) fn detach(cores: &mut dyn Cores, break_due: &mut bool) -> HandlerResult {
+    // Don't leave software breakpoints behind in the target memory.
+    if let Err(e) = clear_on_all_cores(cores, |core| core.clear_all_breakpoints()) {
+        log::warn!("Failed to clear breakpoints on detach: {}", e);
+    }
+
     *break_due = true;
-    Some("OK".into())
+    Ok(Some("OK".into()))
 }
 
-pub(crate) fn reset_halt(core: &mut Core) -> Option<String> {
-    let _cpu_info = core.reset();
-    let _cpu_info = core.halt();
-    Some("OK".into())
+/// Runs a `monitor` command. Its output for the console is appended to `console`.
+pub(crate) fn monitor(
+    packet_string: String,
+    session: &mut Session,
+    threads: &mut Threads,
+    console: &mut String,
+) -> HandlerResult {
+    let command = packet_string
+        .split_once(',')
+        .and_then(|(_, command)| parse_hex(command))
+        .and_then(|command| String::from_utf8(command).ok())
+        .ok_or_else(|| HandlerError::MalformedPacket(packet_string.clone()))?;
+
+    let command = match MonitorCommand::parse(&command) {
+        Ok(command) => command,
+        Err(message) => {
+            console.push_str(&message);
+            console.push('\n');
+            return Err(HandlerError::MalformedPacket(packet_string));
+        }
+    };
+
+    if let Err(e) = command.execute(session, threads, console) {
+        console.push_str(&format!("Error: {}\n", e));
+        return Err(e);
+    }
+
+    Ok(Some("OK".into()))
+}
+
+/// The `O` packet which prints `output` on the console of GDB.
+pub(crate) fn console_output(output: &str) -> String {
+    format!("O{}", to_hex(output.as_bytes()))
+}
+
+/// Sets a breakpoint or watchpoint on all cores, as GDB expects them to apply to all threads.
+fn set_on_all_cores(
+    cores: &mut dyn Cores,
+    mut set: impl FnMut(&mut Core) -> Result<(), probe_rs::Error>,
+) -> Result<(), HandlerError> {
+    for n in 0..cores.count() {
+        set(&mut cores.core(n)?)?;
+    }
+
+    Ok(())
+}
+
+/// Clears a breakpoint or watchpoint on all cores.
+fn clear_on_all_cores(
+    cores: &mut dyn Cores,
+    mut clear: impl FnMut(&mut Core) -> Result<(), probe_rs::Error>,
+) -> Result<(), HandlerError> {
+    for n in 0..cores.count() {
+        clear(&mut cores.core(n)?)?;
+    }
+
+    Ok(())
 }
 
-fn gdb_sanitize_file(data: &[u8], offset: u32, len: u32) -> Vec<u8> {
-    let offset = offset as usize;
-    let len = len as usize;
-    let mut end = offset + len;
+/// Parses a packet into one of the recap structs above.
+fn parse<T: FromStr>(packet_string: &str) -> Result<T, HandlerError> {
+    packet_string
+        .parse::<T>()
+        .map_err(|_| HandlerError::MalformedPacket(packet_string.to_owned()))
+}
+
+/// Parses a hex encoded number of a packet.
+fn parse_number(value: &str, packet_string: &str) -> Result<u32, HandlerError> {
+    u32::from_str_radix(value, 16)
+        .map_err(|_| HandlerError::MalformedPacket(packet_string.to_owned()))
+}
+
+/// Parses a string of hex encoded bytes.
+fn parse_hex(data: &str) -> Option<Vec<u8>> {
+    data.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn gdb_sanitize_file(data: &[u8], offset: usize, len: usize) -> String {
+    let mut end = offset.saturating_add(len);
     if offset > data.len() {
-        b"l".to_vec()
+        "l".to_owned()
     } else {
         if end > data.len() {
             end = data.len();
         }
-        let mut trimmed_data = Vec::from(&data[offset..end]);
+        let trimmed_data = String::from_utf8_lossy(&data[offset..end]);
         if trimmed_data.len() >= len {
             // XXX should this be <= or < ?
-            trimmed_data.insert(0, b'm');
+            format!("m{}", trimmed_data)
         } else {
-            trimmed_data.insert(0, b'l');
+            format!("l{}", trimmed_data)
         }
-        trimmed_data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Watch;
+
+    fn region(packet: &str) -> Option<(u32, u32)> {
+        packet.parse::<Watch>().unwrap().region()
+    }
+
+    #[test]
+    fn watched_region_is_aligned() {
+        assert_eq!(region("Z2,20000004,4"), Some((0x2000_0004, 2)));
+        assert_eq!(region("Z3,20000001,1"), Some((0x2000_0001, 0)));
+        // Two bytes crossing a word boundary need an 8 byte region.
+        assert_eq!(region("Z4,20000003,2"), Some((0x2000_0000, 3)));
+        assert_eq!(region("z2,20000000,8"), Some((0x2000_0000, 3)));
     }
 }
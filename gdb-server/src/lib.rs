@@ -2,8 +2,15 @@
 
 mod gdb_server_async;
 mod handlers;
+mod memory_map;
+mod monitor;
 mod reader;
+mod semihosting;
+mod target_description;
+mod threads;
+mod transport;
 mod worker;
 mod writer;
 
-pub use gdb_server_async::run;
+pub use gdb_server_async::{run, serve};
+pub use probe_rs::server::Transport;
@@ -0,0 +1,243 @@
+use probe_rs::config::{MemoryRegion, RawFlashAlgorithm};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Builds the memory map which is sent to GDB from the memory regions of the target.
+///
+/// Flash regions are split where the sector size changes, as GDB expects a single
+/// block size per region. Flash without a matching flash algorithm is reported as ROM.
+pub(crate) fn gdb_memory_map(
+    memory_map: &[MemoryRegion],
+    flash_algorithms: &[RawFlashAlgorithm],
+) -> String {
+    let mut xml = String::new();
+
+    let _ = writeln!(xml, r#"<?xml version="1.0"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<!DOCTYPE memory-map PUBLIC "+//IDN gnu.org//DTD GDB Memory Map V1.0//EN" "http://sourceware.org/gdb/gdb-memory-map.dtd">"#
+    );
+    let _ = writeln!(xml, "<memory-map>");
+
+    for region in memory_map {
+        match region {
+            MemoryRegion::Ram(region) => write_region(&mut xml, "ram", &region.range),
+            MemoryRegion::Generic(region) => write_region(&mut xml, "ram", &region.range),
+            MemoryRegion::Flash(region) => {
+                let mut algorithms = flash_algorithms.iter().filter(|algorithm| {
+                    algorithm
+                        .flash_properties
+                        .address_range
+                        .contains(&region.range.start)
+                });
+
+                // The sectors of the default algorithm are used if several match.
+                let algorithm = algorithms
+                    .clone()
+                    .find(|algorithm| algorithm.default)
+                    .or_else(|| algorithms.next());
+
+                match algorithm {
+                    Some(algorithm) => {
+                        for (range, block_size) in flash_blocks(algorithm, &region.range) {
+                            let _ = writeln!(
+                                xml,
+                                r#"<memory type="flash" start="{:#x}" length="{:#x}"><property name="blocksize">{:#x}</property></memory>"#,
+                                range.start,
+                                range.end - range.start,
+                                block_size
+                            );
+                        }
+                    }
+                    None => write_region(&mut xml, "rom", &region.range),
+                }
+            }
+        }
+    }
+
+    let _ = writeln!(xml, "</memory-map>");
+
+    xml
+}
+
+/// Describes the memory region which contains `address` in the format of the
+/// `qMemoryRegionInfo` packet of LLDB.
+///
+/// An address outside of all regions is reported as an unmapped region without permissions,
+/// which extends up to the next region.
+pub(crate) fn memory_region_info(memory_map: &[MemoryRegion], address: u32) -> String {
+    let region = memory_map
+        .iter()
+        .map(permissions)
+        .find(|(range, _)| range.contains(&address));
+
+    match region {
+        Some((range, permissions)) => format!(
+            "start:{:x};size:{:x};permissions:{};",
+            range.start,
+            range.end - range.start,
+            permissions
+        ),
+        None => {
+            let end = memory_map
+                .iter()
+                .map(|region| permissions(region).0.start)
+                .filter(|&start| start > address)
+                .min()
+                .map(u64::from)
+                .unwrap_or(1 << 32);
+
+            format!("start:{:x};size:{:x};", address, end - u64::from(address))
+        }
+    }
+}
+
+/// Whether `address` is located in RAM, where instructions can be replaced by breakpoints.
+pub(crate) fn is_ram(memory_map: &[MemoryRegion], address: u32) -> bool {
+    memory_map.iter().any(|region| match region {
+        MemoryRegion::Ram(ram) => ram.range.contains(&address),
+        _ => false,
+    })
+}
+
+/// The address range of a region, and how it can be accessed.
+fn permissions(region: &MemoryRegion) -> (&Range<u32>, &'static str) {
+    match region {
+        MemoryRegion::Ram(region) => (&region.range, "rwx"),
+        MemoryRegion::Generic(region) => (&region.range, "rw"),
+        MemoryRegion::Flash(region) => (&region.range, "rx"),
+    }
+}
+
+fn write_region(xml: &mut String, kind: &str, range: &Range<u32>) {
+    let _ = writeln!(
+        xml,
+        r#"<memory type="{}" start="{:#x}" length="{:#x}"/>"#,
+        kind,
+        range.start,
+        range.end - range.start
+    );
+}
+
+/// Splits `range` into parts with the same sector size.
+fn flash_blocks(algorithm: &RawFlashAlgorithm, range: &Range<u32>) -> Vec<(Range<u32>, u32)> {
+    let properties = &algorithm.flash_properties;
+    let flash_start = properties.address_range.start;
+
+    let mut blocks = vec![];
+
+    for (i, sector) in properties.sectors.iter().enumerate() {
+        // The sector size applies up to the next sector description.
+        let end = properties
+            .sectors
+            .get(i + 1)
+            .map(|next| flash_start + next.address)
+            .unwrap_or(properties.address_range.end);
+
+        let start = (flash_start + sector.address).max(range.start);
+        let end = end.min(range.end);
+
+        if start < end {
+            blocks.push((start..end, sector.size));
+        }
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod test {
+    use super::{gdb_memory_map, memory_region_info};
+    use probe_rs::config::{
+        FlashProperties, FlashRegion, MemoryRegion, RamRegion, RawFlashAlgorithm, SectorDescription,
+    };
+
+    #[test]
+    fn flash_is_split_by_sector_size() {
+        let memory_map = vec![
+            MemoryRegion::Flash(FlashRegion {
+                range: 0x0800_0000..0x0810_0000,
+                is_boot_memory: true,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2002_0000,
+                is_boot_memory: false,
+            }),
+        ];
+
+        let algorithm = RawFlashAlgorithm {
+            flash_properties: FlashProperties {
+                address_range: 0x0800_0000..0x0810_0000,
+                sectors: vec![
+                    SectorDescription {
+                        address: 0,
+                        size: 0x4000,
+                    },
+                    SectorDescription {
+                        address: 0x1_0000,
+                        size: 0x1_0000,
+                    },
+                    SectorDescription {
+                        address: 0x2_0000,
+                        size: 0x2_0000,
+                    },
+                ]
+                .into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let xml = gdb_memory_map(&memory_map, &[algorithm]);
+
+        assert!(xml.contains(r#"<memory type="flash" start="0x8000000" length="0x10000"><property name="blocksize">0x4000</property></memory>"#));
+        assert!(xml.contains(r#"<memory type="flash" start="0x8010000" length="0x10000"><property name="blocksize">0x10000</property></memory>"#));
+        assert!(xml.contains(r#"<memory type="flash" start="0x8020000" length="0xe0000"><property name="blocksize">0x20000</property></memory>"#));
+        assert!(xml.contains(r#"<memory type="ram" start="0x20000000" length="0x20000"/>"#));
+    }
+
+    #[test]
+    fn flash_without_algorithm_is_rom() {
+        let memory_map = vec![MemoryRegion::Flash(FlashRegion {
+            range: 0..0x4_0000,
+            is_boot_memory: true,
+        })];
+
+        let xml = gdb_memory_map(&memory_map, &[]);
+
+        assert!(xml.contains(r#"<memory type="rom" start="0x0" length="0x40000"/>"#));
+    }
+
+    #[test]
+    fn memory_regions_are_described() {
+        let memory_map = vec![
+            MemoryRegion::Flash(FlashRegion {
+                range: 0x0800_0000..0x0810_0000,
+                is_boot_memory: true,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2002_0000,
+                is_boot_memory: false,
+            }),
+        ];
+
+        assert_eq!(
+            memory_region_info(&memory_map, 0x0800_1000),
+            "start:8000000;size:100000;permissions:rx;"
+        );
+        assert_eq!(
+            memory_region_info(&memory_map, 0x2000_0000),
+            "start:20000000;size:20000;permissions:rwx;"
+        );
+
+        // The gaps between the regions are unmapped.
+        assert_eq!(
+            memory_region_info(&memory_map, 0x1000_0000),
+            "start:10000000;size:10000000;"
+        );
+        assert_eq!(
+            memory_region_info(&memory_map, 0x2002_0000),
+            "start:20020000;size:dffe0000;"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::handlers::HandlerError;
+use crate::threads::Threads;
+
+use probe_rs::{flashing, MemoryInterface, Session};
+use std::fmt::Write;
+use std::io::Write as _;
+use std::path::PathBuf;
+
+/// The bytes which `dump_image` reads at once.
+const DUMP_CHUNK_SIZE: usize = 0x1000;
+
+/// The usage and description of each command, as listed by `monitor help`.
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "Lists the available commands"),
+    (
+        "reset [run|halt|init]",
+        "Resets the target, and halts it with 'halt' or 'init'",
+    ),
+    ("halt", "Halts all cores"),
+    (
+        "speed [khz]",
+        "Shows or changes the speed of the probe (also 'adapter speed')",
+    ),
+    ("flash erase_all", "Erases the entire flash"),
+    (
+        "flash erase_sector <address>",
+        "Erases the flash sector which contains the address",
+    ),
+    (
+        "dump_image <file> <address> <size>",
+        "Writes a memory region of the current thread to a file",
+    ),
+];
+
+/// A command which is sent with `monitor` from GDB.
+///
+/// The commands follow the names of OpenOCD, so existing GDB scripts keep working.
+#[derive(Debug, PartialEq)]
+pub(crate) enum MonitorCommand {
+    Help,
+    Reset {
+        halt: bool,
+    },
+    Halt,
+    Speed(Option<u32>),
+    EraseAll,
+    EraseSector(u32),
+    DumpImage {
+        path: PathBuf,
+        address: u32,
+        size: u32,
+    },
+}
+
+impl MonitorCommand {
+    /// Parses a command. If it is not understood, the message for the console is returned.
+    pub(crate) fn parse(command: &str) -> Result<Self, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+
+        let command = match words.as_slice() {
+            ["help"] => MonitorCommand::Help,
+            ["reset"] | ["reset", "run"] => MonitorCommand::Reset { halt: false },
+            ["reset", "halt"] | ["reset", "init"] => MonitorCommand::Reset { halt: true },
+            ["halt"] => MonitorCommand::Halt,
+            ["speed"] | ["adapter", "speed"] => MonitorCommand::Speed(None),
+            ["speed", khz] | ["adapter", "speed", khz] => {
+                MonitorCommand::Speed(Some(parse_number(khz)?))
+            }
+            ["flash", "erase_all"] => MonitorCommand::EraseAll,
+            ["flash", "erase_sector", address] => {
+                MonitorCommand::EraseSector(parse_number(address)?)
+            }
+            ["dump_image", path, address, size] => MonitorCommand::DumpImage {
+                path: PathBuf::from(path),
+                address: parse_number(address)?,
+                size: parse_number(size)?,
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown command '{}', see 'monitor help'",
+                    command.trim()
+                ))
+            }
+        };
+
+        Ok(command)
+    }
+
+    /// Runs the command. Its output is appended to `console`.
+    pub(crate) fn execute(
+        self,
+        session: &mut Session,
+        threads: &mut Threads,
+        console: &mut String,
+    ) -> Result<(), HandlerError> {
+        match self {
+            MonitorCommand::Help => {
+                for (usage, description) in COMMANDS {
+                    let _ = writeln!(console, "{:<36} {}", usage, description);
+                }
+            }
+            MonitorCommand::Reset { halt } => {
+                // Resetting the first core resets the whole chip.
+                let mut core = session.core(0)?;
+
+                if halt {
+                    core.reset_and_halt()?;
+                    threads.running.clear();
+                    let _ = writeln!(console, "Target reset and halted");
+                } else {
+                    core.reset()?;
+                    let _ = writeln!(console, "Target reset");
+                }
+            }
+            MonitorCommand::Halt => {
+                for n in 0..session.list_cores().len() {
+                    let mut core = session.core(n)?;
+                    core.halt()?;
+                    core.wait_for_core_halted()?;
+                }
+
+                threads.running.clear();
+                let _ = writeln!(console, "Target halted");
+            }
+            MonitorCommand::Speed(None) => {
+                let _ = writeln!(console, "Probe speed is {} kHz", session.speed_khz());
+            }
+            MonitorCommand::Speed(Some(speed_khz)) => {
+                let speed_khz = session
+                    .set_speed(speed_khz)
+                    .map_err(probe_rs::Error::from)?;
+                let _ = writeln!(console, "Probe speed set to {} kHz", speed_khz);
+            }
+            MonitorCommand::EraseAll => {
+                flashing::erase_all(session)?;
+                let _ = writeln!(console, "Flash erased");
+            }
+            MonitorCommand::EraseSector(address) => {
+                flashing::erase_sector(session, address)?;
+                let _ = writeln!(console, "Flash sector at {:#010x} erased", address);
+            }
+            MonitorCommand::DumpImage {
+                path,
+                address,
+                size,
+            } => {
+                let mut core = session.core(threads.general)?;
+                let mut file = std::fs::File::create(&path)?;
+
+                // The image is copied in chunks, so its size is not bounded by the host memory.
+                let mut data = [0; DUMP_CHUNK_SIZE];
+
+                for offset in (0..size).step_by(DUMP_CHUNK_SIZE) {
+                    let chunk = &mut data[..(size - offset).min(DUMP_CHUNK_SIZE as u32) as usize];
+
+                    core.read_8(address.wrapping_add(offset), chunk)
+                        .map_err(HandlerError::MemoryAccess)?;
+                    file.write_all(chunk)?;
+                }
+
+                let _ = writeln!(
+                    console,
+                    "Wrote {} bytes from {:#010x} to {}",
+                    size,
+                    address,
+                    path.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a number, which is hex if it starts with `0x` and decimal otherwise.
+fn parse_number(value: &str) -> Result<u32, String> {
+    let number = if value.starts_with("0x") || value.starts_with("0X") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+
+    number.map_err(|_| format!("'{}' is not a number", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::MonitorCommand;
+    use std::path::PathBuf;
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(
+            MonitorCommand::parse("reset halt"),
+            Ok(MonitorCommand::Reset { halt: true })
+        );
+        assert_eq!(
+            MonitorCommand::parse("  reset "),
+            Ok(MonitorCommand::Reset { halt: false })
+        );
+        assert_eq!(
+            MonitorCommand::parse("adapter speed 4000"),
+            Ok(MonitorCommand::Speed(Some(4000)))
+        );
+        assert_eq!(
+            MonitorCommand::parse("flash erase_sector 0x08004000"),
+            Ok(MonitorCommand::EraseSector(0x0800_4000))
+        );
+        assert_eq!(
+            MonitorCommand::parse("dump_image ram.bin 0x20000000 1024"),
+            Ok(MonitorCommand::DumpImage {
+                path: PathBuf::from("ram.bin"),
+                address: 0x2000_0000,
+                size: 1024,
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_commands_are_explained() {
+        assert_eq!(
+            MonitorCommand::parse("reset sideways"),
+            Err("Unknown command 'reset sideways', see 'monitor help'".into())
+        );
+        assert_eq!(
+            MonitorCommand::parse("speed fast"),
+            Err("'fast' is not a number".into())
+        );
+        assert!(MonitorCommand::parse("").is_err());
+    }
+}
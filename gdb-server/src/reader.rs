@@ -1,4 +1,6 @@
-use async_std::{net::TcpStream, prelude::*};
+use crate::transport::Stream;
+
+use async_std::prelude::*;
 use futures::channel::mpsc;
 use gdb_protocol::{
     packet::{CheckedPacket, Kind as PacketKind},
@@ -8,10 +10,20 @@ use gdb_protocol::{
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
 
-pub async fn reader(
-    stream: &mut TcpStream,
+/// Whether packets are acknowledged with `+` and `-`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum AckMode {
+    Enabled,
+    /// `QStartNoAckMode` was received. Its reply is still acknowledged, the packets after it are not.
+    Disabling,
+    Disabled,
+}
+
+pub(crate) async fn reader(
+    stream: &mut impl Stream,
     packet_stream: &Sender<CheckedPacket>,
     buffer: &mut Vec<u8>,
+    ack_mode: &mut AckMode,
 ) -> Result<()> {
     log::debug!("READ WIN");
     let mut parser = Parser::default();
@@ -27,7 +39,7 @@ pub async fn reader(
         );
 
         // TODO: Fix this later on. I am sure there is a better way to handle this in the parser maybe.
-        if drained[0] == 0x03 {
+        if drained.first() == Some(&0x03) {
             packet_stream
                 .unbounded_send(CheckedPacket::from_data(PacketKind::Packet, vec![0x03]))?;
         }
@@ -36,13 +48,24 @@ pub async fn reader(
             match packet.kind {
                 PacketKind::Packet => match packet.check() {
                     Some(checked) => {
-                        log::debug!("Sending ACK");
-                        stream.write_all(&[b'+']).await?;
+                        if *ack_mode != AckMode::Disabled {
+                            log::debug!("Sending ACK");
+                            stream.write_all(&[b'+']).await?;
+                        }
+
+                        if *ack_mode == AckMode::Enabled && checked.data == b"QStartNoAckMode" {
+                            *ack_mode = AckMode::Disabling;
+                        }
+
                         packet_stream.unbounded_send(checked)?;
                     }
+                    // Without acknowledgements, the packet can not be requested again.
+                    None if *ack_mode == AckMode::Disabled => {
+                        log::warn!("Dropping a packet with an invalid checksum");
+                    }
                     None => {
                         log::debug!("Sending nACK");
-                        (&*stream).write_all(&[b'-']).await?;
+                        stream.write_all(&[b'-']).await?;
                     }
                 },
                 // Protocol specifies notifications should not be checked
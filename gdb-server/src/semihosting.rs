@@ -0,0 +1,332 @@
+use crate::handlers::HandlerError;
+use probe_rs::server::Cores;
+
+use probe_rs::semihosting::{
+    complete_request, read_c_string, read_exit, OpenMode, SemihostingRequest, CONSOLE_PATH,
+    SYS_CLOCK, SYS_CLOSE, SYS_ERRNO, SYS_EXIT, SYS_EXIT_EXTENDED, SYS_ISERROR, SYS_ISTTY, SYS_OPEN,
+    SYS_READ, SYS_REMOVE, SYS_SEEK, SYS_TIME, SYS_WRITE, SYS_WRITE0, SYS_WRITEC,
+};
+use probe_rs::{Core, CoreStatus, Error, HaltReason, MemoryInterface};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Returned to the target when an operation failed.
+const FAILURE: u32 = u32::MAX;
+
+/// The errno of GDB for operations which are not supported.
+const EIO: i32 = 5;
+
+/// The flags of the `open` call of GDB.
+const O_RDONLY: u32 = 0x0;
+const O_WRONLY: u32 = 0x1;
+const O_RDWR: u32 = 0x2;
+const O_APPEND: u32 = 0x8;
+const O_CREAT: u32 = 0x200;
+const O_TRUNC: u32 = 0x400;
+
+/// Files are created with the mode `0644`.
+const CREATE_MODE: u32 = 0o644;
+
+/// The file descriptors of the GDB console.
+const STDIN: u32 = 0;
+const STDOUT: u32 = 1;
+const STDERR: u32 = 2;
+
+/// How the result of a `F` reply is converted into the result of the semihosting operation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Conversion {
+    /// The result is returned unchanged.
+    Value,
+    /// The number of bytes which were *not* transferred is returned.
+    Remaining(u32),
+    /// `0` is returned on success.
+    Success,
+    /// The errno is returned on failure.
+    Errno,
+    /// The operation has no result.
+    Done,
+}
+
+/// How a semihosting request is serviced.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Service {
+    /// GDB executes the request once it received this `F` packet.
+    Gdb(String),
+    /// The request was serviced without GDB, and the core was resumed.
+    Done,
+    /// The request is not serviced, and the core stays halted.
+    Stop,
+    /// The application exited with the exit code, and the core stays halted.
+    Exit(i32),
+}
+
+/// Services the semihosting requests of the target using the File-I/O protocol of GDB,
+/// so that GDB accesses the files and the console on behalf of the target.
+pub(crate) struct FileIo {
+    /// The core which waits for the `F` reply of GDB, with the conversion of its result.
+    pending: Option<(usize, Conversion)>,
+    errno: i32,
+    start: Instant,
+}
+
+impl FileIo {
+    pub(crate) fn new() -> Self {
+        FileIo {
+            pending: None,
+            errno: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Whether a request is executed by GDB.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Services the semihosting request core `n` is halted at, if there is one.
+    pub(crate) fn serve(&mut self, cores: &mut dyn Cores, n: usize) -> Result<Service, Error> {
+        let mut core = cores.core(n)?;
+
+        let request = match core.status()? {
+            CoreStatus::Halted(HaltReason::Semihosting(request)) => request,
+            _ => return Ok(Service::Stop),
+        };
+
+        match self.translate(&mut core, request)? {
+            Translation::Gdb(packet, conversion) => {
+                self.pending = Some((n, conversion));
+                Ok(Service::Gdb(packet))
+            }
+            Translation::Value(value) => {
+                complete_request(&mut core, value)?;
+                core.run()?;
+                Ok(Service::Done)
+            }
+            Translation::Exit(code) => Ok(Service::Exit(code)),
+        }
+    }
+
+    /// Completes the pending request with the `F` reply of GDB.
+    ///
+    /// Returns the core which made the request, and whether GDB was interrupted with Ctrl-C,
+    /// in which case the core has to stay halted.
+    pub(crate) fn complete(
+        &mut self,
+        packet_string: &str,
+        cores: &mut dyn Cores,
+    ) -> Result<(usize, bool), HandlerError> {
+        let malformed = || HandlerError::MalformedPacket(packet_string.to_owned());
+
+        // The call specific attachment after `;` is not used by any of the calls.
+        let mut fields = packet_string[1..]
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .split(',');
+
+        let retcode = fields.next().and_then(parse_signed).ok_or_else(malformed)?;
+        let errno = match fields.next() {
+            Some(errno) => parse_signed(errno).ok_or_else(malformed)?,
+            None => 0,
+        };
+        let interrupted = fields.next() == Some("C");
+
+        let (n, conversion) = self.pending.take().ok_or_else(malformed)?;
+
+        if retcode < 0 {
+            self.errno = errno;
+        }
+
+        let value = match conversion {
+            Conversion::Value => Some(retcode as u32),
+            Conversion::Remaining(len) if retcode < 0 => Some(len),
+            Conversion::Remaining(len) => Some(len.saturating_sub(retcode as u32)),
+            Conversion::Success if retcode < 0 => Some(FAILURE),
+            Conversion::Success => Some(0),
+            Conversion::Errno if retcode < 0 => Some(errno as u32),
+            Conversion::Errno => Some(0),
+            Conversion::Done => None,
+        };
+
+        complete_request(&mut cores.core(n)?, value)?;
+
+        Ok((n, interrupted))
+    }
+
+    fn translate(
+        &mut self,
+        core: &mut Core,
+        request: SemihostingRequest,
+    ) -> Result<Translation, Error> {
+        let parameter = request.parameter();
+
+        let translation = match request.operation() {
+            SYS_OPEN => {
+                let mut block = [0u32; 3];
+                core.read_32(parameter, &mut block)?;
+                let [path, mode, len] = block;
+
+                let mode = match OpenMode::from_raw(mode) {
+                    Some(mode) => mode,
+                    None => return Ok(self.failure()),
+                };
+
+                // Only the console is opened here, GDB reads the path of other files itself.
+                let is_console = len as usize == CONSOLE_PATH.len() && {
+                    let mut name = [0; CONSOLE_PATH.len()];
+                    core.read_8(path, &mut name)?;
+                    name == CONSOLE_PATH.as_bytes()
+                };
+
+                if is_console {
+                    // The console is already open in GDB.
+                    let fd = match mode {
+                        OpenMode::Read | OpenMode::ReadWrite => STDIN,
+                        OpenMode::Write | OpenMode::WriteRead => STDOUT,
+                        OpenMode::Append | OpenMode::AppendRead => STDERR,
+                    };
+
+                    return Ok(Translation::Value(Some(fd)));
+                }
+
+                let flags = match mode {
+                    OpenMode::Read => O_RDONLY,
+                    OpenMode::ReadWrite => O_RDWR,
+                    OpenMode::Write => O_WRONLY | O_CREAT | O_TRUNC,
+                    OpenMode::WriteRead => O_RDWR | O_CREAT | O_TRUNC,
+                    OpenMode::Append => O_WRONLY | O_CREAT | O_APPEND,
+                    OpenMode::AppendRead => O_RDWR | O_CREAT | O_APPEND,
+                };
+
+                // The length which is sent to GDB includes the null terminator.
+                Translation::Gdb(
+                    format!(
+                        "Fopen,{:x}/{:x},{:x},{:x}",
+                        path,
+                        len + 1,
+                        flags,
+                        CREATE_MODE
+                    ),
+                    Conversion::Value,
+                )
+            }
+            SYS_CLOSE => {
+                let fd = core.read_word_32(parameter)?;
+
+                // The console of GDB stays open.
+                if fd <= STDERR {
+                    return Ok(Translation::Value(Some(0)));
+                }
+
+                Translation::Gdb(format!("Fclose,{:x}", fd), Conversion::Value)
+            }
+            SYS_WRITEC => Translation::Gdb(
+                format!("Fwrite,{:x},{:x},1", STDOUT, parameter),
+                Conversion::Done,
+            ),
+            SYS_WRITE0 => {
+                let len = read_c_string(core, parameter)?.len();
+
+                Translation::Gdb(
+                    format!("Fwrite,{:x},{:x},{:x}", STDOUT, parameter, len),
+                    Conversion::Done,
+                )
+            }
+            SYS_WRITE | SYS_READ => {
+                let mut block = [0u32; 3];
+                core.read_32(parameter, &mut block)?;
+                let [fd, data, len] = block;
+
+                let call = if request.operation() == SYS_WRITE {
+                    "Fwrite"
+                } else {
+                    "Fread"
+                };
+
+                Translation::Gdb(
+                    format!("{},{:x},{:x},{:x}", call, fd, data, len),
+                    Conversion::Remaining(len),
+                )
+            }
+            SYS_ISTTY => {
+                let fd = core.read_word_32(parameter)?;
+
+                Translation::Gdb(format!("Fisatty,{:x}", fd), Conversion::Value)
+            }
+            SYS_SEEK => {
+                let mut block = [0u32; 2];
+                core.read_32(parameter, &mut block)?;
+                let [fd, position] = block;
+
+                // The position is absolute, `SEEK_SET`.
+                Translation::Gdb(
+                    format!("Flseek,{:x},{:x},0", fd, position),
+                    Conversion::Success,
+                )
+            }
+            SYS_REMOVE => {
+                let mut block = [0u32; 2];
+                core.read_32(parameter, &mut block)?;
+                let [path, len] = block;
+
+                Translation::Gdb(
+                    format!("Funlink,{:x}/{:x}", path, len + 1),
+                    Conversion::Errno,
+                )
+            }
+            SYS_ISERROR => {
+                let status = core.read_word_32(parameter)?;
+
+                Translation::Value(Some(((status as i32) < 0) as u32))
+            }
+            SYS_ERRNO => Translation::Value(Some(self.errno as u32)),
+            SYS_CLOCK => {
+                let centiseconds = self.start.elapsed().as_millis() / 10;
+
+                Translation::Value(Some(centiseconds as u32))
+            }
+            SYS_TIME => {
+                let seconds = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs() as u32)
+                    .unwrap_or(0);
+
+                Translation::Value(Some(seconds))
+            }
+            SYS_EXIT | SYS_EXIT_EXTENDED => match read_exit(core, request)? {
+                Some(exit) => Translation::Exit(exit.code()),
+                None => self.failure(),
+            },
+            operation => {
+                log::warn!("Unsupported semihosting operation {:#04x}", operation);
+
+                self.failure()
+            }
+        };
+
+        Ok(translation)
+    }
+
+    fn failure(&mut self) -> Translation {
+        self.errno = EIO;
+
+        Translation::Value(Some(FAILURE))
+    }
+}
+
+/// What a semihosting request is translated to.
+enum Translation {
+    /// A `F` packet for GDB, and how its result is converted.
+    Gdb(String, Conversion),
+    /// The request is serviced without GDB, with this result.
+    Value(Option<u32>),
+    /// The application exited with the exit code.
+    Exit(i32),
+}
+
+/// Parses a hex number, which may be negative.
+fn parse_signed(value: &str) -> Option<i32> {
+    match value.strip_prefix('-') {
+        Some(value) => i32::from_str_radix(value, 16).ok().map(|value| -value),
+        None => i32::from_str_radix(value, 16).ok(),
+    }
+}
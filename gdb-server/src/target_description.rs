@@ -0,0 +1,360 @@
+use probe_rs::{Core, CoreRegisterAddress, MemoryInterface, RegisterDescription, RegisterFile};
+use std::fmt::Write;
+
+/// Media and VFP Feature Register 0, which reads as zero if no FPU is present.
+const MVFR0: u32 = 0xE000_EF40;
+
+/// Core register address of the xPSR on ARM.
+const XPSR: CoreRegisterAddress = CoreRegisterAddress(0b1_0000);
+
+/// Core register address of the FPSCR on ARM.
+const FPSCR: CoreRegisterAddress = CoreRegisterAddress(0b010_0001);
+
+/// Core register address of `s0` on ARM, the other single precision registers follow it.
+const S0: u16 = 0b100_0000;
+
+/// Number of double precision registers of the ARM FPU.
+const FPU_DOUBLE_REGISTERS: u16 = 16;
+
+/// DWARF register number of `d0` on ARM, the other double precision registers follow it.
+const DWARF_D0: usize = 256;
+
+/// The generic register names of LLDB for the argument registers, in order.
+const ARGUMENTS: [&str; 8] = [
+    "arg1", "arg2", "arg3", "arg4", "arg5", "arg6", "arg7", "arg8",
+];
+
+/// A register as it is presented to GDB.
+pub(crate) struct GdbRegister {
+    name: String,
+    bitsize: usize,
+    typ: &'static str,
+    group: &'static str,
+    /// The core registers holding the value, starting with the least significant 32 bits.
+    parts: Vec<CoreRegisterAddress>,
+    /// The register number used in the debug information.
+    dwarf: Option<usize>,
+    /// The role of the register, which LLDB knows by a generic name like `pc` or `arg1`.
+    generic: Option<&'static str>,
+}
+
+impl GdbRegister {
+    fn new(name: impl Into<String>, typ: &'static str, group: &'static str) -> Self {
+        GdbRegister {
+            name: name.into(),
+            bitsize: 32,
+            typ,
+            group,
+            parts: vec![],
+            dwarf: None,
+            generic: None,
+        }
+    }
+
+    fn dwarf(mut self, number: Option<usize>) -> Self {
+        self.dwarf = number;
+        self
+    }
+
+    fn generic(mut self, name: Option<&'static str>) -> Self {
+        self.generic = name;
+        self
+    }
+
+    fn at(mut self, address: CoreRegisterAddress) -> Self {
+        self.bitsize = 32 * (self.parts.len() + 1);
+        self.parts.push(address);
+        self
+    }
+
+    /// Size of the register in bytes.
+    pub(crate) fn size(&self) -> usize {
+        self.bitsize / 8
+    }
+
+    /// Reads the register, formatted as hex bytes in target byte order.
+    ///
+    /// Registers which can not be read are reported as unavailable.
+    pub(crate) fn read(&self, core: &mut Core) -> String {
+        let mut value = String::with_capacity(self.size() * 2);
+
+        for &part in &self.parts {
+            match core.read_core_reg(part) {
+                Ok(part) => {
+                    for byte in &part.to_le_bytes() {
+                        let _ = write!(value, "{:02x}", byte);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Failed to read register {}: {}", self.name, e);
+                    value.push_str("xxxxxxxx");
+                }
+            }
+        }
+
+        value
+    }
+
+    /// Writes the register from its bytes in target byte order.
+    pub(crate) fn write(&self, core: &mut Core, bytes: &[u8]) -> Result<(), probe_rs::Error> {
+        for (&part, chunk) in self.parts.iter().zip(bytes.chunks(4)) {
+            let mut value = [0u8; 4];
+            value[..chunk.len()].copy_from_slice(chunk);
+
+            core.write_core_reg(part, u32::from_le_bytes(value))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The generic name of a register of the register file, if it has a special role.
+fn generic_name(
+    register_file: &RegisterFile,
+    register: &RegisterDescription,
+) -> Option<&'static str> {
+    let address = CoreRegisterAddress::from(register).0;
+    let is = |other: &RegisterDescription| CoreRegisterAddress::from(other).0 == address;
+
+    if is(register_file.program_counter()) {
+        Some("pc")
+    } else if is(register_file.stack_pointer()) {
+        Some("sp")
+    } else if is(register_file.return_address()) {
+        Some("ra")
+    } else {
+        (0..ARGUMENTS.len())
+            .find(|&i| register_file.get_argument_register(i).is_some_and(is))
+            .map(|i| ARGUMENTS[i])
+    }
+}
+
+/// A group of registers which GDB knows by its name.
+struct Feature {
+    name: &'static str,
+    registers: Vec<GdbRegister>,
+}
+
+/// Describes the registers of a core to GDB.
+///
+/// The registers are numbered in the order in which they appear in the description,
+/// which is also the order used by the `g` and `G` packets.
+pub(crate) struct TargetDescription {
+    architecture: &'static str,
+    /// The target triple which LLDB uses to select the instruction set.
+    triple: &'static str,
+    features: Vec<Feature>,
+}
+
+impl TargetDescription {
+    pub(crate) fn new(core: &mut Core) -> Self {
+        match core.architecture() {
+            probe_rs::Architecture::Arm => Self::arm(core),
+            probe_rs::Architecture::Riscv => Self::riscv(core),
+        }
+    }
+
+    fn arm(core: &mut Core) -> Self {
+        let register_file = core.registers();
+
+        let sp = CoreRegisterAddress::from(register_file.stack_pointer()).0;
+        let lr = CoreRegisterAddress::from(register_file.return_address()).0;
+        let pc = CoreRegisterAddress::from(register_file.program_counter()).0;
+
+        let mut registers: Vec<GdbRegister> = register_file
+            .registers()
+            .map(|register| {
+                let address = CoreRegisterAddress::from(register);
+
+                // GDB expects the lowercase names of the M-profile feature.
+                let (name, typ) = match address.0 {
+                    a if a == sp => ("sp".to_owned(), "data_ptr"),
+                    a if a == lr => ("lr".to_owned(), "int"),
+                    a if a == pc => ("pc".to_owned(), "code_ptr"),
+                    _ => (register.name().to_lowercase(), "int"),
+                };
+
+                GdbRegister::new(name, typ, "general")
+                    .at(address)
+                    .dwarf(register_file.dwarf_register_number(register))
+                    .generic(generic_name(register_file, register))
+            })
+            .collect();
+
+        registers.push(
+            GdbRegister::new("xpsr", "int", "general")
+                .at(XPSR)
+                .generic(Some("flags")),
+        );
+
+        let mut features = vec![Feature {
+            name: "org.gnu.gdb.arm.m-profile",
+            registers,
+        }];
+
+        let has_fpu = match core.read_word_32(MVFR0) {
+            Ok(mvfr0) => mvfr0 != 0,
+            Err(e) => {
+                log::warn!("Unable to determine if the core has an FPU: {}", e);
+                false
+            }
+        };
+
+        if has_fpu {
+            let mut registers: Vec<GdbRegister> = (0..FPU_DOUBLE_REGISTERS)
+                .map(|i| {
+                    GdbRegister::new(format!("d{}", i), "ieee_double", "float")
+                        .at(CoreRegisterAddress(S0 + 2 * i))
+                        .at(CoreRegisterAddress(S0 + 2 * i + 1))
+                        .dwarf(Some(DWARF_D0 + i as usize))
+                })
+                .collect();
+
+            registers.push(GdbRegister::new("fpscr", "int", "float").at(FPSCR));
+
+            features.push(Feature {
+                name: "org.gnu.gdb.arm.vfp",
+                registers,
+            });
+        }
+
+        // Cores with an FPU are at least a Cortex-M4, whose instructions are a superset
+        // of the ones of the other cores.
+        let triple = if has_fpu {
+            "armv7em-none-eabihf"
+        } else {
+            "armv7m-none-eabi"
+        };
+
+        TargetDescription {
+            architecture: "arm",
+            triple,
+            features,
+        }
+    }
+
+    fn riscv(core: &mut Core) -> Self {
+        let register_file = core.registers();
+
+        let sp = CoreRegisterAddress::from(register_file.stack_pointer()).0;
+        let ra = CoreRegisterAddress::from(register_file.return_address()).0;
+
+        let mut registers: Vec<GdbRegister> = register_file
+            .registers()
+            .map(|register| {
+                let address = CoreRegisterAddress::from(register);
+
+                let typ = match address.0 {
+                    a if a == sp => "data_ptr",
+                    a if a == ra => "code_ptr",
+                    _ => "int",
+                };
+
+                GdbRegister::new(register.name(), typ, "general")
+                    .at(address)
+                    .dwarf(register_file.dwarf_register_number(register))
+                    .generic(generic_name(register_file, register))
+            })
+            .collect();
+
+        registers.push(
+            GdbRegister::new("pc", "code_ptr", "general")
+                .at(register_file.program_counter().into())
+                .generic(Some("pc")),
+        );
+
+        TargetDescription {
+            architecture: "riscv:rv32",
+            triple: "riscv32-unknown-none-elf",
+            features: vec![Feature {
+                name: "org.gnu.gdb.riscv.cpu",
+                registers,
+            }],
+        }
+    }
+
+    /// All registers, indexed by their GDB register number.
+    pub(crate) fn registers(&self) -> impl Iterator<Item = &GdbRegister> {
+        self.features
+            .iter()
+            .flat_map(|feature| feature.registers.iter())
+    }
+
+    pub(crate) fn register(&self, number: usize) -> Option<&GdbRegister> {
+        self.registers().nth(number)
+    }
+
+    /// The number of the register with the given generic name, like `pc`.
+    pub(crate) fn generic_register(&self, name: &str) -> Option<usize> {
+        self.registers()
+            .position(|register| register.generic == Some(name))
+    }
+
+    pub(crate) fn triple(&self) -> &'static str {
+        self.triple
+    }
+
+    /// Describes a register in the format of the `qRegisterInfo` packet of LLDB.
+    pub(crate) fn register_info(&self, number: usize) -> Option<String> {
+        let register = self.register(number)?;
+
+        // The offset of the register in the `g` packet.
+        let offset: usize = self.registers().take(number).map(GdbRegister::size).sum();
+
+        let (encoding, format) = match register.typ {
+            "ieee_double" => ("ieee754", "float"),
+            _ => ("uint", "hex"),
+        };
+
+        let set = match register.group {
+            "float" => "Floating Point Registers",
+            _ => "General Purpose Registers",
+        };
+
+        let mut info = format!(
+            "name:{};bitsize:{};offset:{};encoding:{};format:{};set:{};",
+            register.name, register.bitsize, offset, encoding, format, set
+        );
+
+        if let Some(dwarf) = register.dwarf {
+            let _ = write!(info, "ehframe:{};dwarf:{};", dwarf, dwarf);
+        }
+
+        if let Some(generic) = register.generic {
+            let _ = write!(info, "generic:{};", generic);
+        }
+
+        Some(info)
+    }
+
+    /// The target description in the XML format which is read by GDB.
+    pub(crate) fn to_xml(&self) -> String {
+        let mut xml = String::new();
+
+        let _ = writeln!(xml, r#"<?xml version="1.0"?>"#);
+        let _ = writeln!(xml, r#"<!DOCTYPE target SYSTEM "gdb-target.dtd">"#);
+        let _ = writeln!(xml, r#"<target version="1.0">"#);
+        let _ = writeln!(xml, "<architecture>{}</architecture>", self.architecture);
+
+        let mut regnum = 0;
+
+        for feature in &self.features {
+            let _ = writeln!(xml, r#"<feature name="{}">"#, feature.name);
+
+            for register in &feature.registers {
+                let _ = writeln!(
+                    xml,
+                    r#"<reg name="{}" bitsize="{}" regnum="{}" type="{}" group="{}"/>"#,
+                    register.name, register.bitsize, regnum, register.typ, register.group
+                );
+                regnum += 1;
+            }
+
+            let _ = writeln!(xml, "</feature>");
+        }
+
+        let _ = writeln!(xml, "</target>");
+
+        xml
+    }
+}
@@ -0,0 +1,63 @@
+/// The thread id of core `n`, as GDB does not use 0 as a thread id.
+pub(crate) fn thread_id(n: usize) -> usize {
+    n + 1
+}
+
+/// Which cores the packets of GDB refer to.
+pub(crate) struct Threads {
+    /// The core used for register and memory accesses, selected with `Hg`.
+    pub(crate) general: usize,
+    /// The core which is stepped by the `s` packet, selected with `Hc`.
+    pub(crate) step: usize,
+    /// The resumed cores. Once one of them halts, the others are halted as well.
+    pub(crate) running: Vec<usize>,
+}
+
+impl Threads {
+    pub(crate) fn new() -> Self {
+        Threads {
+            general: 0,
+            step: 0,
+            running: vec![],
+        }
+    }
+
+    /// Marks core `n` as running, unless it already is.
+    pub(crate) fn resumed(&mut self, n: usize) {
+        if !self.running.contains(&n) {
+            self.running.push(n);
+        }
+    }
+}
+
+/// A thread id of a packet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ThreadId {
+    /// `-1`, all threads.
+    All,
+    /// `0`, an arbitrary thread.
+    Any,
+    /// The thread of the given core.
+    Core(usize),
+}
+
+impl ThreadId {
+    /// Parses a thread id, which is hex encoded.
+    pub(crate) fn parse(id: &str) -> Option<Self> {
+        match id {
+            "-1" => Some(ThreadId::All),
+            _ => match usize::from_str_radix(id, 16).ok()? {
+                0 => Some(ThreadId::Any),
+                id => Some(ThreadId::Core(id - 1)),
+            },
+        }
+    }
+
+    /// Whether the id refers to core `n`.
+    pub(crate) fn matches(self, n: usize) -> bool {
+        match self {
+            ThreadId::All | ThreadId::Any => true,
+            ThreadId::Core(core) => core == n,
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use async_std::io::{self, Read, Stdin, Stdout, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A connection to GDB, which the packets are read from and written to.
+pub(crate) trait Stream: Read + Write + Unpin + Send + 'static {}
+
+impl<S: Read + Write + Unpin + Send + 'static> Stream for S {}
+
+/// Stdin and stdout, combined into a single stream.
+pub(crate) struct Stdio {
+    stdin: Stdin,
+    stdout: Stdout,
+}
+
+impl Stdio {
+    pub(crate) fn new() -> Self {
+        Stdio {
+            stdin: io::stdin(),
+            stdout: io::stdout(),
+        }
+    }
+}
+
+impl Read for Stdio {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_read(cx, buf)
+    }
+}
+
+impl Write for Stdio {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stdout).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_close(cx)
+    }
+}
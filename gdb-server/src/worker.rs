@@ -8,7 +8,12 @@ use probe_rs::Core;
 use probe_rs::Session;
 use std::time::Duration;
 
-use crate::handlers;
+use crate::handlers::{self, HandlerError, HandlerResult};
+use crate::memory_map::gdb_memory_map;
+use crate::semihosting::{FileIo, Service};
+use crate::target_description::TargetDescription;
+use crate::threads::{thread_id, Threads};
+use probe_rs::server::{first_halted, halt_others, Cores};
 
 type ServerResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
@@ -18,74 +23,159 @@ pub async fn worker(
     mut input_stream: Receiver<CheckedPacket>,
     output_stream: Sender<CheckedPacket>,
     session: &mut Session,
+    reset_halt: bool,
 ) -> ServerResult<()> {
-    let mut core = session.core(0).unwrap();
-    let mut awaits_halt = false;
+    let memory_map = gdb_memory_map(session.memory_map(), session.flash_algorithms());
+    // All cores of a target share the same architecture, so they share the description.
+    let description = TargetDescription::new(&mut session.core(0)?);
+    let mut threads = Threads::new();
+    let mut file_io = FileIo::new();
+    let mut flash_data = vec![];
 
     loop {
         select! {
             potential_packet = input_stream.next().fuse() => {
                 if let Some(packet) = potential_packet {
                     log::warn!("WORKING {}", String::from_utf8_lossy(&packet.data));
-                    if handler(&mut core, &output_stream, &mut awaits_halt, packet).await? {
-                        break;
+                    // Programming the flash and monitor commands need the whole session,
+                    // not only its cores.
+                    if packet.data.starts_with(b"vFlashDone") {
+                        let result = handlers::flash_done(session, &mut flash_data, &mut threads, reset_halt);
+                        send_response(&output_stream, response(result, &packet.data))?;
+                    } else if packet.data.starts_with(b"qRcmd,") {
+                        let packet_string = String::from_utf8_lossy(&packet.data).to_string();
+                        let mut console = String::new();
+                        let result = handlers::monitor(packet_string, session, &mut threads, &mut console);
+
+                        for line in console.split_inclusive('\n') {
+                            send_response(&output_stream, Some(handlers::console_output(line)))?;
+                        }
+
+                        send_response(&output_stream, response(result, &packet.data))?;
+                    } else if handler(session, &description, &memory_map, &mut flash_data, &output_stream, &mut threads, &mut file_io, packet).await? {
+                        return Ok(());
                     }
                 } else {
-                    break
+                    return Ok(());
                 }
             },
-            _ = await_halt(&mut core, &output_stream, &mut awaits_halt).fuse() => {}
+            _ = await_halt(session, &output_stream, &mut threads, &mut file_io).fuse() => {}
         }
     }
-    Ok(())
 }
 
-#[allow(clippy::cognitive_complexity)]
-pub async fn handler(
-    core: &mut Core<'_>,
+#[allow(clippy::cognitive_complexity, clippy::too_many_arguments)]
+pub(crate) async fn handler(
+    cores: &mut dyn Cores,
+    description: &TargetDescription,
+    memory_map: &str,
+    flash_data: &mut Vec<(u32, Vec<u8>)>,
     output_stream: &Sender<CheckedPacket>,
-    awaits_halt: &mut bool,
+    threads: &mut Threads,
+    file_io: &mut FileIo,
     packet: CheckedPacket,
 ) -> ServerResult<bool> {
     let mut break_due = false;
     let packet_string = String::from_utf8_lossy(&packet.data).to_string();
 
-    let response: Option<String> = if packet.data.starts_with(b"qSupported") {
+    let result: HandlerResult = if packet.data.starts_with(b"qSupported") {
         handlers::q_supported()
     } else if packet.data.starts_with(b"vMustReplyEmpty") {
         handlers::reply_empty()
     } else if packet.data.starts_with(b"vCont?") {
         handlers::vcont_supported()
-    } else if packet.data.starts_with(b"vCont;c") || packet.data.starts_with(b"c") {
-        handlers::run(core, awaits_halt)
-    } else if packet.data.starts_with(b"vCont;t") {
-        handlers::stop(core, awaits_halt)
-    } else if packet.data.starts_with(b"vCont;s") || packet.data.starts_with(b"s") {
-        handlers::step(core, awaits_halt)
+    } else if packet.data.starts_with(b"vCont;") {
+        handlers::vcont(packet_string, cores, threads)
+    } else if packet.data.starts_with(b"c") {
+        handlers::run(cores, threads)
+    } else if packet.data.starts_with(b"s") {
+        handlers::step(cores, threads)
+    } else if packet.data.starts_with(b"F") {
+        handlers::file_io_reply(packet_string, cores, threads, file_io)
     } else if packet.data.starts_with(b"qAttached") {
         handlers::q_attached()
+    } else if packet.data.starts_with(b"QStartNoAckMode") {
+        handlers::start_no_ack_mode()
+    } else if packet.data.starts_with(b"qHostInfo") {
+        handlers::host_info(description)
+    } else if packet.data.starts_with(b"qProcessInfo") {
+        handlers::process_info(description)
+    } else if packet.data.starts_with(b"qRegisterInfo") {
+        handlers::register_info(packet_string, description)
+    } else if packet.data.starts_with(b"qMemoryRegionInfo:") {
+        handlers::memory_region_info(packet_string, cores)
+    } else if packet.data.starts_with(b"qThreadStopInfo") {
+        handlers::thread_stop_info(packet_string, cores)
+    } else if packet.data.starts_with(b"jThreadsInfo") {
+        handlers::threads_info(cores, description)
+    } else if packet.data.starts_with(b"qfThreadInfo") {
+        handlers::thread_info_first(cores)
+    } else if packet.data.starts_with(b"qsThreadInfo") {
+        handlers::thread_info_subsequent()
+    } else if packet.data.starts_with(b"qThreadExtraInfo") {
+        handlers::thread_extra_info(packet_string, cores)
+    } else if &packet.data[..] == b"qC" {
+        handlers::current_thread(threads)
+    } else if packet.data.starts_with(b"H") {
+        handlers::set_thread(packet_string, cores, threads)
+    } else if packet.data.starts_with(b"T") {
+        handlers::thread_alive(packet_string, cores)
     } else if packet.data.starts_with(b"?") {
-        handlers::halt_reason()
+        handlers::halt_reason(cores, threads)
     } else if packet.data.starts_with(b"g") {
-        handlers::read_general_registers()
+        with_general_core(cores, threads, |core| {
+            handlers::read_general_registers(core, description)
+        })
+    } else if packet.data.starts_with(b"G") {
+        with_general_core(cores, threads, |core| {
+            handlers::write_general_registers(packet_string, core, description)
+        })
     } else if packet.data.starts_with(b"p") {
-        handlers::read_register(packet_string, core)
+        with_general_core(cores, threads, |core| {
+            handlers::read_register(packet_string, core, description)
+        })
+    } else if packet.data.starts_with(b"P") {
+        with_general_core(cores, threads, |core| {
+            handlers::write_register(packet_string, core, description)
+        })
     } else if packet.data.starts_with(b"m") {
-        handlers::read_memory(packet_string, core)
+        with_general_core(cores, threads, |core| {
+            handlers::read_memory(packet_string, core)
+        })
+    } else if packet.data.starts_with(b"Z0") {
+        handlers::insert_software_break(packet_string, cores)
+    } else if packet.data.starts_with(b"z0") {
+        handlers::remove_software_break(packet_string, cores)
     } else if packet.data.starts_with(b"Z1") {
-        handlers::insert_hardware_break(packet_string, core)
+        handlers::insert_hardware_break(packet_string, cores)
     } else if packet.data.starts_with(b"z1") {
-        handlers::remove_hardware_break(packet_string, core)
+        handlers::remove_hardware_break(packet_string, cores)
+    } else if packet.data.starts_with(b"Z2")
+        || packet.data.starts_with(b"Z3")
+        || packet.data.starts_with(b"Z4")
+    {
+        handlers::insert_watchpoint(packet_string, cores)
+    } else if packet.data.starts_with(b"z2")
+        || packet.data.starts_with(b"z3")
+        || packet.data.starts_with(b"z4")
+    {
+        handlers::remove_watchpoint(packet_string, cores)
     } else if packet.data.starts_with(b"X") {
-        handlers::write_memory(packet_string, &packet.data, core)
+        with_general_core(cores, threads, |core| {
+            handlers::write_memory(packet_string, &packet.data, core)
+        })
     } else if packet.data.starts_with(b"qXfer:memory-map:read") {
-        handlers::get_memory_map()
+        handlers::get_memory_map(packet_string, memory_map)
+    } else if packet.data.starts_with(b"vFlashErase") {
+        handlers::flash_erase(packet_string)
+    } else if packet.data.starts_with(b"vFlashWrite") {
+        handlers::flash_write(&packet.data, flash_data)
+    } else if packet.data.starts_with(b"qXfer:features:read") {
+        handlers::read_target_description(packet_string, description)
     } else if packet.data.starts_with(&[0x03]) {
-        handlers::user_halt(core, awaits_halt)
+        handlers::user_halt(cores, threads)
     } else if packet.data.starts_with(b"D") {
-        handlers::detach(&mut break_due)
-    } else if packet.data.starts_with(b"qRcmdb,7265736574") {
-        handlers::reset_halt(core)
+        handlers::detach(cores, &mut break_due)
     } else {
         log::warn!(
             "Unknown command: '{}'",
@@ -96,35 +186,112 @@ pub async fn handler(
         handlers::reply_empty()
     };
 
+    send_response(output_stream, response(result, &packet.data))?;
+
+    Ok(break_due)
+}
+
+/// Runs a handler on the core selected with `Hg`.
+fn with_general_core(
+    cores: &mut dyn Cores,
+    threads: &Threads,
+    handler: impl FnOnce(&mut Core) -> HandlerResult,
+) -> HandlerResult {
+    let mut core = cores.core(threads.general).map_err(HandlerError::from)?;
+
+    handler(&mut core)
+}
+
+/// Turns the result of a handler into the reply for GDB.
+fn response(result: HandlerResult, packet: &[u8]) -> Option<String> {
+    result.unwrap_or_else(|e| {
+        log::warn!(
+            "Failed to handle packet '{}': {}",
+            String::from_utf8_lossy(packet),
+            e
+        );
+        Some(e.reply())
+    })
+}
+
+fn send_response(
+    output_stream: &Sender<CheckedPacket>,
+    response: Option<String>,
+) -> ServerResult<()> {
     if let Some(response) = response {
         let response = CheckedPacket::from_data(PacketKind::Packet, response.into_bytes());
 
-        let mut bytes = Vec::new();
-        response.encode(&mut bytes).unwrap();
-        log::debug!(
-            "Response: '{:x?}'",
-            std::str::from_utf8(&response.data).unwrap()
-        );
+        log::debug!("Response: '{}'", String::from_utf8_lossy(&response.data));
         log::debug!("-----------------------------------------------");
         output_stream.unbounded_send(response)?;
     };
 
-    Ok(break_due)
+    Ok(())
 }
 
-pub async fn await_halt(
-    core: &mut Core<'_>,
+/// Polls the resumed cores. Once one of them halts, the others are halted as well,
+/// and the halted core becomes the current thread.
+///
+/// A core which halted at a semihosting request is resumed once the request is serviced.
+/// The exit of the application is reported with its exit code.
+pub(crate) async fn await_halt(
+    cores: &mut dyn Cores,
     output_stream: &Sender<CheckedPacket>,
-    await_halt: &mut bool,
+    threads: &mut Threads,
+    file_io: &mut FileIo,
 ) {
     task::sleep(Duration::from_millis(10)).await;
-    if *await_halt && core.core_halted().unwrap() {
-        let response = CheckedPacket::from_data(PacketKind::Packet, b"T05hwbreak:;".to_vec());
-
-        let mut bytes = Vec::new();
-        response.encode(&mut bytes).unwrap();
-        *await_halt = false;
 
-        let _ = output_stream.unbounded_send(response);
+    // GDB only expects a stop reply once it answered the `F` packet.
+    if file_io.is_pending() {
+        return;
     }
+
+    let halted = first_halted(cores, &threads.running);
+
+    let halted = match halted {
+        Some(halted) => halted,
+        None => return,
+    };
+
+    let exit = match file_io.serve(cores, halted) {
+        Ok(Service::Gdb(request)) => {
+            threads.running.retain(|&n| n != halted);
+            threads.general = halted;
+
+            let request = CheckedPacket::from_data(PacketKind::Packet, request.into_bytes());
+            let _ = output_stream.unbounded_send(request);
+            return;
+        }
+        Ok(Service::Done) => return,
+        Ok(Service::Stop) => None,
+        Ok(Service::Exit(code)) => Some(code),
+        Err(e) => {
+            log::warn!("Unable to service the semihosting request: {}", e);
+            None
+        }
+    };
+
+    halt_others(cores, &std::mem::take(&mut threads.running), halted);
+
+    threads.general = halted;
+
+    let reply = match exit {
+        // GDB only knows the 8 bit exit status of a process.
+        Some(code) => format!("W{:02x}", code as u8),
+        None => match cores.core(halted) {
+            Ok(mut core) => handlers::stop_reply(&mut core, halted),
+            Err(e) => {
+                log::warn!("Unable to read the halt reason: {}", e);
+                format!("T05thread:{:x};", thread_id(halted))
+            }
+        },
+    };
+
+    let response = CheckedPacket::from_data(PacketKind::Packet, reply.into_bytes());
+
+    let _ = output_stream.unbounded_send(response);
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,1010 @@
+//! Drives the packet dispatch of the worker with scripted packets against simulated cores.
+
+use super::{await_halt, handler};
+use crate::handlers::console_output;
+use crate::semihosting::FileIo;
+use crate::target_description::TargetDescription;
+use crate::threads::Threads;
+use probe_rs::server::Cores;
+
+use async_std::task;
+use futures::channel::mpsc;
+use gdb_protocol::packet::{CheckedPacket, Kind as PacketKind};
+use probe_rs::architecture::arm::ARM_REGISTER_FILE;
+use probe_rs::config::{MemoryRegion, RamRegion};
+use probe_rs::{
+    Architecture, Core, CoreInformation, CoreInterface, CoreRegisterAddress, CoreState, CoreStatus,
+    DebugProbeError, Error, HaltReason, MemoryInterface, RegisterFile, WatchpointKind,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_SIZE: u32 = 0x1000;
+
+const PC: u16 = 15;
+
+/// Thumb `BKPT #0`, as placed by software breakpoints.
+const BKPT: [u8; 2] = [0x00, 0xbe];
+
+/// Thumb `BKPT 0xAB`, a semihosting request.
+const BKPT_SEMIHOSTING: [u8; 2] = [0xab, 0xbe];
+
+const SYS_OPEN: u32 = 0x01;
+const SYS_WRITE: u32 = 0x05;
+const SYS_EXIT: u32 = 0x18;
+const SYS_EXIT_EXTENDED: u32 = 0x20;
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+struct State {
+    registers: HashMap<u16, u32>,
+    status: CoreStatus,
+    breakpoints: [Option<u32>; 2],
+    watchpoints: [Option<u32>; 2],
+    /// The watchpoint unit which halted the core, until it is checked.
+    matched: Option<usize>,
+    /// The number of following operations which fail, like a probe with a flaky connection.
+    failures: usize,
+}
+
+/// A Cortex-M core with RAM, which executes until it hits a breakpoint or a watchpoint.
+#[derive(Clone)]
+struct SimulatedCore(Rc<RefCell<State>>, Rc<RefCell<Vec<u8>>>);
+
+impl SimulatedCore {
+    fn new() -> Self {
+        Self::with_ram(Rc::new(RefCell::new(vec![0; RAM_SIZE as usize])))
+    }
+
+    /// A second core of the same chip, which shares the RAM with this core.
+    fn sibling(&self) -> Self {
+        Self::with_ram(self.1.clone())
+    }
+
+    fn with_ram(ram: Rc<RefCell<Vec<u8>>>) -> Self {
+        SimulatedCore(
+            Rc::new(RefCell::new(State {
+                registers: HashMap::new(),
+                status: CoreStatus::Halted(HaltReason::Request),
+                breakpoints: [None; 2],
+                watchpoints: [None; 2],
+                matched: None,
+                failures: 0,
+            })),
+            ram,
+        )
+    }
+
+    fn status(&self) -> CoreStatus {
+        self.0.borrow().status
+    }
+
+    fn fail_next(&self, count: usize) {
+        self.0.borrow_mut().failures = count;
+    }
+
+    fn pc(&self) -> u32 {
+        self.register(PC)
+    }
+
+    fn register(&self, address: u16) -> u32 {
+        self.0
+            .borrow()
+            .registers
+            .get(&address)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Places a semihosting request at `address`, with its parameter block at `parameter`.
+    fn request_semihosting(&self, address: u32, operation: u32, parameter: &[u32]) {
+        let block = RAM_START + 0x100;
+
+        for (i, word) in parameter.iter().enumerate() {
+            self.write(block + 4 * i as u32, &word.to_le_bytes());
+        }
+
+        self.write(address, &BKPT_SEMIHOSTING);
+
+        let mut state = self.0.borrow_mut();
+        state.registers.insert(0, operation);
+        state.registers.insert(1, block);
+    }
+
+    fn write(&self, address: u32, data: &[u8]) {
+        let offset = (address - RAM_START) as usize;
+        self.1.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn check(&mut self) -> Result<(), Error> {
+        let mut state = self.0.borrow_mut();
+
+        if state.failures > 0 {
+            state.failures -= 1;
+            return Err(Error::Probe(DebugProbeError::Unknown));
+        }
+
+        Ok(())
+    }
+
+    fn ram_range(&self, address: u32, len: usize) -> Result<std::ops::Range<usize>, Error> {
+        let start = address.wrapping_sub(RAM_START) as usize;
+
+        if address < RAM_START || start + len > RAM_SIZE as usize {
+            return Err(Error::Probe(DebugProbeError::Unknown));
+        }
+
+        Ok(start..start + len)
+    }
+}
+
+impl MemoryInterface for SimulatedCore {
+    fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+        let mut data = [0u8; 4];
+        self.read_8(address, &mut data)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
+        let mut data = [0u8; 1];
+        self.read_8(address, &mut data)?;
+        Ok(data[0])
+    }
+
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+        for (i, word) in data.iter_mut().enumerate() {
+            *word = self.read_word_32(address + 4 * i as u32)?;
+        }
+        Ok(())
+    }
+
+    fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        self.check()?;
+        let range = self.ram_range(address, data.len())?;
+        data.copy_from_slice(&self.1.borrow()[range]);
+        Ok(())
+    }
+
+    fn write_word_32(&mut self, address: u32, data: u32) -> Result<(), Error> {
+        self.write_8(address, &data.to_le_bytes())
+    }
+
+    fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), Error> {
+        self.write_8(address, &[data])
+    }
+
+    fn write_32(&mut self, address: u32, data: &[u32]) -> Result<(), Error> {
+        for (i, word) in data.iter().enumerate() {
+            self.write_word_32(address + 4 * i as u32, *word)?;
+        }
+        Ok(())
+    }
+
+    fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.check()?;
+        let range = self.ram_range(address, data.len())?;
+        self.1.borrow_mut()[range].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl CoreInterface for SimulatedCore {
+    fn wait_for_core_halted(&mut self) -> Result<(), Error> {
+        match self.core_halted()? {
+            true => Ok(()),
+            false => Err(Error::Probe(DebugProbeError::Timeout)),
+        }
+    }
+
+    fn core_halted(&mut self) -> Result<bool, Error> {
+        self.check()?;
+        Ok(self.0.borrow().status.is_halted())
+    }
+
+    fn status(&mut self) -> Result<CoreStatus, Error> {
+        self.check()?;
+        Ok(self.0.borrow().status)
+    }
+
+    fn halt(&mut self) -> Result<CoreInformation, Error> {
+        self.check()?;
+        self.0.borrow_mut().status = CoreStatus::Halted(HaltReason::Request);
+        Ok(CoreInformation { pc: self.pc() })
+    }
+
+    /// Stops at the first hardware breakpoint, then at any `BKPT` instruction
+    /// following the program counter, and then at the first watchpoint unit in use.
+    fn run(&mut self) -> Result<(), Error> {
+        self.check()?;
+
+        let pc = self.pc();
+        let mut state = self.0.borrow_mut();
+        let ram = self.1.borrow();
+
+        let software_breakpoint = (pc.max(RAM_START)..RAM_START + RAM_SIZE)
+            .step_by(2)
+            .skip(1)
+            .find(|&address| {
+                let offset = (address - RAM_START) as usize;
+                ram[offset + 1] == BKPT[1]
+            });
+
+        if let Some(address) = state.breakpoints.iter().flatten().next().copied() {
+            state.registers.insert(PC, address);
+            state.status = CoreStatus::Halted(HaltReason::Breakpoint);
+        } else if let Some(address) = software_breakpoint {
+            state.registers.insert(PC, address);
+            state.status = CoreStatus::Halted(HaltReason::Breakpoint);
+        } else if let Some(unit) = state.watchpoints.iter().position(Option::is_some) {
+            state.matched = Some(unit);
+            state.status = CoreStatus::Halted(HaltReason::Watchpoint);
+        } else {
+            state.status = CoreStatus::Running;
+        }
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.check()?;
+        self.0.borrow_mut().registers.insert(PC, 0);
+        Ok(())
+    }
+
+    fn reset_and_halt(&mut self) -> Result<CoreInformation, Error> {
+        self.reset()?;
+        self.halt()
+    }
+
+    fn step(&mut self) -> Result<CoreInformation, Error> {
+        self.check()?;
+        let pc = self.pc() + 2;
+
+        let mut state = self.0.borrow_mut();
+        state.registers.insert(PC, pc);
+        state.status = CoreStatus::Halted(HaltReason::Step);
+
+        Ok(CoreInformation { pc })
+    }
+
+    fn read_core_reg(&mut self, address: CoreRegisterAddress) -> Result<u32, Error> {
+        self.check()?;
+        Ok(self
+            .0
+            .borrow()
+            .registers
+            .get(&address.0)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    fn write_core_reg(&mut self, address: CoreRegisterAddress, value: u32) -> Result<(), Error> {
+        self.check()?;
+        self.0.borrow_mut().registers.insert(address.0, value);
+        Ok(())
+    }
+
+    fn get_available_breakpoint_units(&mut self) -> Result<u32, Error> {
+        Ok(self.0.borrow().breakpoints.len() as u32)
+    }
+
+    fn enable_breakpoints(&mut self, _state: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_breakpoint(&mut self, bp_unit_index: usize, addr: u32) -> Result<(), Error> {
+        self.check()?;
+        self.0.borrow_mut().breakpoints[bp_unit_index] = Some(addr);
+        Ok(())
+    }
+
+    fn clear_breakpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        self.check()?;
+        self.0.borrow_mut().breakpoints[unit_index] = None;
+        Ok(())
+    }
+
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+        Ok(self.0.borrow().watchpoints.len() as u32)
+    }
+
+    fn set_watchpoint(
+        &mut self,
+        unit_index: usize,
+        address: u32,
+        _mask: u32,
+        _kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.check()?;
+        self.0.borrow_mut().watchpoints[unit_index] = Some(address);
+        Ok(())
+    }
+
+    fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        self.check()?;
+        self.0.borrow_mut().watchpoints[unit_index] = None;
+        Ok(())
+    }
+
+    fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, Error> {
+        self.check()?;
+        let mut state = self.0.borrow_mut();
+
+        // Like the MATCHED flag of the DWT, checking the unit clears it.
+        if state.matched == Some(unit_index) {
+            state.matched = None;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn registers(&self) -> &'static RegisterFile {
+        &ARM_REGISTER_FILE
+    }
+
+    fn hw_breakpoints_enabled(&self) -> bool {
+        true
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Arm
+    }
+}
+
+/// The simulated cores of a chip, attached like the cores of a session.
+struct SimulatedCores {
+    cores: Vec<(SimulatedCore, CoreState)>,
+    memory_map: Vec<MemoryRegion>,
+}
+
+impl Cores for SimulatedCores {
+    fn count(&self) -> usize {
+        self.cores.len()
+    }
+
+    fn core(&mut self, n: usize) -> Result<Core<'_>, Error> {
+        let (core, state) = self.cores.get_mut(n).ok_or(Error::CoreNotFound(n))?;
+
+        Ok(Core::new(core.clone(), state, &self.memory_map))
+    }
+
+    fn memory_map(&self) -> &[MemoryRegion] {
+        &self.memory_map
+    }
+}
+
+/// Runs a GDB session with the simulated core.
+fn session(simulated: &SimulatedCore, test: impl FnOnce(&mut Gdb)) {
+    multicore_session(std::slice::from_ref(simulated), test)
+}
+
+/// Runs a GDB session with several simulated cores, which share their RAM.
+fn multicore_session(simulated: &[SimulatedCore], test: impl FnOnce(&mut Gdb)) {
+    let memory_state = Core::create_state();
+    let mut cores = SimulatedCores {
+        cores: simulated
+            .iter()
+            .map(|core| {
+                let state = Core::create_state_sharing_memory(&memory_state);
+                (core.clone(), state)
+            })
+            .collect(),
+        memory_map: vec![MemoryRegion::Ram(RamRegion {
+            range: RAM_START..RAM_START + RAM_SIZE,
+            is_boot_memory: false,
+        })],
+    };
+
+    let description = TargetDescription::new(&mut cores.core(0).unwrap());
+
+    let (sender, receiver) = mpsc::unbounded();
+
+    let mut gdb = Gdb {
+        cores,
+        description,
+        sender,
+        receiver,
+        threads: Threads::new(),
+        file_io: FileIo::new(),
+        detached: false,
+    };
+
+    test(&mut gdb);
+}
+
+struct Gdb {
+    cores: SimulatedCores,
+    description: TargetDescription,
+    sender: mpsc::UnboundedSender<CheckedPacket>,
+    receiver: mpsc::UnboundedReceiver<CheckedPacket>,
+    threads: Threads,
+    file_io: FileIo,
+    detached: bool,
+}
+
+impl Gdb {
+    /// Sends a packet and returns the reply, if there is one.
+    fn send(&mut self, packet: impl AsRef<[u8]>) -> Option<String> {
+        let packet = CheckedPacket::from_data(PacketKind::Packet, packet.as_ref().to_vec());
+
+        self.detached = task::block_on(handler(
+            &mut self.cores,
+            &self.description,
+            "",
+            &mut vec![],
+            &self.sender,
+            &mut self.threads,
+            &mut self.file_io,
+            packet,
+        ))
+        .expect("The connection failed");
+
+        self.reply()
+    }
+
+    /// Returns the stop reply, once a core halted after it was resumed.
+    fn wait_for_stop(&mut self) -> Option<String> {
+        task::block_on(await_halt(
+            &mut self.cores,
+            &self.sender,
+            &mut self.threads,
+            &mut self.file_io,
+        ));
+
+        self.reply()
+    }
+
+    fn reply(&mut self) -> Option<String> {
+        self.receiver
+            .try_recv()
+            .ok()
+            .map(|packet| String::from_utf8(packet.data.clone()).unwrap())
+    }
+}
+
+#[test]
+fn unknown_packets_are_answered_empty() {
+    session(&SimulatedCore::new(), |gdb| {
+        assert_eq!(gdb.send("qSomethingNew"), Some("".into()));
+        assert_eq!(gdb.send("qAttached"), Some("1".into()));
+    });
+}
+
+#[test]
+fn registers_are_read_and_written() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        assert_eq!(gdb.send("P1=78563412"), Some("OK".into()));
+        assert_eq!(gdb.send("p1"), Some("78563412".into()));
+
+        // r0 and r1 are the first registers of the `g` packet.
+        let registers = gdb.send("g").unwrap();
+        assert!(registers.starts_with("0000000078563412"));
+
+        assert_eq!(gdb.send("P0=1"), Some("E00".into()));
+        assert_eq!(gdb.send("p1000"), Some("E00".into()));
+    });
+}
+
+#[test]
+fn memory_is_read_and_written() {
+    session(&SimulatedCore::new(), |gdb| {
+        let mut packet = b"X20000010,4:".to_vec();
+        packet.extend_from_slice(&[0xde, 0xad, 0x7d, 0x03]);
+
+        assert_eq!(gdb.send(packet), Some("OK".into()));
+        assert_eq!(gdb.send("m20000010,4"), Some("dead7d03".into()));
+
+        // Outside of the RAM of the core.
+        assert_eq!(gdb.send("m10000000,4"), Some("E79".into()));
+
+        // Longer reads are cut to fit into a packet, and GDB reads the rest with another one.
+        let reply = gdb.send("m20000000,ffffffff").unwrap();
+        assert_eq!(reply.len(), 2048);
+    });
+}
+
+#[test]
+fn malformed_packets_are_answered_with_errors() {
+    session(&SimulatedCore::new(), |gdb| {
+        assert_eq!(gdb.send("m2000zzzz,4"), Some("E00".into()));
+        assert_eq!(gdb.send("p"), Some("E00".into()));
+        assert_eq!(gdb.send("Z0,"), Some("E00".into()));
+        assert_eq!(gdb.send("X20000000,8:ab"), Some("E00".into()));
+        assert_eq!(gdb.send("G123"), Some("E00".into()));
+        assert_eq!(
+            gdb.send("qXfer:features:read:other.xml:0,100"),
+            Some("E00".into())
+        );
+        assert_eq!(gdb.send("vFlashWrite:nocolon"), Some("E00".into()));
+
+        // The connection is still usable.
+        assert_eq!(gdb.send("m20000000,2"), Some("0000".into()));
+        assert!(!gdb.detached);
+    });
+}
+
+#[test]
+fn probe_errors_do_not_end_the_session() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        simulated.fail_next(1);
+        assert_eq!(gdb.send("Z1,20000100,2"), Some("E01".into()));
+
+        simulated.fail_next(1);
+        assert_eq!(gdb.send("m20000000,4"), Some("E79".into()));
+
+        assert_eq!(gdb.send("Z1,20000100,2"), Some("OK".into()));
+    });
+}
+
+#[test]
+fn breakpoints_do_not_reset_the_core() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        assert_eq!(gdb.send("Pf=00010020"), Some("OK".into()));
+
+        assert_eq!(gdb.send("Z1,20000200,2"), Some("OK".into()));
+        assert_eq!(simulated.pc(), 0x2000_0100);
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("T05thread:1;hwbreak:;".into()));
+        assert_eq!(simulated.pc(), 0x2000_0200);
+
+        assert_eq!(gdb.send("z1,20000200,2"), Some("OK".into()));
+        assert_eq!(gdb.send("z1,20000200,2"), Some("E01".into()));
+    });
+}
+
+#[test]
+fn software_breakpoints_report_swbreak() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        assert_eq!(gdb.send("Pf=00010020"), Some("OK".into()));
+
+        // The breakpoint is placed in RAM, although hardware breakpoints are available.
+        assert_eq!(gdb.send("Z0,20000300,2"), Some("OK".into()));
+        assert_eq!(gdb.send("m20000300,2"), Some("00be".into()));
+        assert!(simulated.0.borrow().breakpoints.iter().all(Option::is_none));
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("T05thread:1;swbreak:;".into()));
+        assert_eq!(simulated.pc(), 0x2000_0300);
+
+        // The original instruction is restored when detaching.
+        assert_eq!(gdb.send("D"), Some("OK".into()));
+        assert!(gdb.detached);
+        assert_eq!(gdb.send("m20000300,2"), Some("0000".into()));
+    });
+}
+
+#[test]
+fn software_breakpoints_outside_of_ram_use_hardware_breakpoints() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        assert_eq!(gdb.send("Z0,100,2"), Some("OK".into()));
+        assert_eq!(simulated.0.borrow().breakpoints, [Some(0x100), None]);
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("T05thread:1;hwbreak:;".into()));
+
+        assert_eq!(gdb.send("z0,100,2"), Some("OK".into()));
+        assert_eq!(simulated.0.borrow().breakpoints, [None, None]);
+    });
+}
+
+#[test]
+fn watchpoints_report_the_watched_address() {
+    session(&SimulatedCore::new(), |gdb| {
+        assert_eq!(gdb.send("Z2,20000010,4"), Some("OK".into()));
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(
+            gdb.wait_for_stop(),
+            Some("T05thread:1;watch:20000010;".into())
+        );
+
+        assert_eq!(gdb.send("z2,20000010,4"), Some("OK".into()));
+        assert_eq!(gdb.send("Z3,20000011,1"), Some("OK".into()));
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(
+            gdb.wait_for_stop(),
+            Some("T05thread:1;rwatch:20000011;".into())
+        );
+    });
+}
+
+#[test]
+fn watchpoints_report_the_matched_unit() {
+    session(&SimulatedCore::new(), |gdb| {
+        // Frees the first unit, so the last watchpoint set uses the first unit.
+        assert_eq!(gdb.send("Z2,20000010,4"), Some("OK".into()));
+        assert_eq!(gdb.send("Z2,20000020,4"), Some("OK".into()));
+        assert_eq!(gdb.send("z2,20000010,4"), Some("OK".into()));
+        assert_eq!(gdb.send("Z4,20000030,4"), Some("OK".into()));
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(
+            gdb.wait_for_stop(),
+            Some("T05thread:1;awatch:20000030;".into())
+        );
+
+        // The matched watchpoint is still known when the reply is requested again.
+        assert_eq!(
+            gdb.send("qThreadStopInfo1"),
+            Some("T05thread:1;awatch:20000030;".into())
+        );
+    });
+}
+
+#[test]
+fn target_description_is_read_in_parts() {
+    session(&SimulatedCore::new(), |gdb| {
+        let first = gdb.send("qXfer:features:read:target.xml:0,10").unwrap();
+        assert_eq!(first, r#"m<?xml version="1"#);
+
+        let rest = gdb.send("qXfer:features:read:target.xml:10,10000").unwrap();
+        assert!(rest.starts_with('l'));
+        assert!(rest.contains("org.gnu.gdb.arm.m-profile"));
+    });
+}
+
+#[test]
+fn cores_are_listed_as_threads() {
+    let first = SimulatedCore::new();
+
+    multicore_session(&[first.clone(), first.sibling()], |gdb| {
+        assert_eq!(gdb.send("qfThreadInfo"), Some("m1,2".into()));
+        assert_eq!(gdb.send("qsThreadInfo"), Some("l".into()));
+        assert_eq!(gdb.send("qC"), Some("QC1".into()));
+
+        assert_eq!(gdb.send("T2"), Some("OK".into()));
+        assert_eq!(gdb.send("T3"), Some("E00".into()));
+
+        // "Core 1", hex encoded.
+        assert_eq!(gdb.send("qThreadExtraInfo,2"), Some("436f72652031".into()));
+    });
+}
+
+#[test]
+fn registers_are_accessed_on_the_selected_core() {
+    let first = SimulatedCore::new();
+    let second = first.sibling();
+
+    multicore_session(&[first.clone(), second.clone()], |gdb| {
+        assert_eq!(gdb.send("Hg2"), Some("OK".into()));
+        assert_eq!(gdb.send("qC"), Some("QC2".into()));
+        assert_eq!(gdb.send("Pf=00030020"), Some("OK".into()));
+        assert_eq!(second.pc(), 0x2000_0300);
+        assert_eq!(first.pc(), 0);
+
+        assert_eq!(gdb.send("Hg1"), Some("OK".into()));
+        assert_eq!(gdb.send("pf"), Some("00000000".into()));
+
+        assert_eq!(gdb.send("Hg3"), Some("E00".into()));
+        assert_eq!(gdb.send("qC"), Some("QC1".into()));
+    });
+}
+
+#[test]
+fn stop_reply_names_the_halted_core() {
+    let first = SimulatedCore::new();
+    let second = first.sibling();
+
+    multicore_session(&[first.clone(), second.clone()], |gdb| {
+        // Only the second core runs into the breakpoint compiled into the program.
+        assert_eq!(gdb.send("Hg1"), Some("OK".into()));
+        assert_eq!(gdb.send("Pf=00040020"), Some("OK".into()));
+        assert_eq!(gdb.send("Hg2"), Some("OK".into()));
+        assert_eq!(gdb.send("Pf=00010020"), Some("OK".into()));
+        assert_eq!(gdb.send("Hg1"), Some("OK".into()));
+
+        let mut packet = b"X20000300,2:".to_vec();
+        packet.extend_from_slice(&BKPT);
+        assert_eq!(gdb.send(packet), Some("OK".into()));
+
+        assert_eq!(gdb.send("vCont;c"), None);
+        assert_eq!(first.status(), CoreStatus::Running);
+
+        assert_eq!(gdb.wait_for_stop(), Some("T05thread:2;swbreak:;".into()));
+        assert_eq!(second.pc(), 0x2000_0300);
+
+        // The other core is stopped as well, and the halted core becomes the current thread.
+        assert!(first.status().is_halted());
+        assert_eq!(gdb.send("qC"), Some("QC2".into()));
+        assert_eq!(gdb.send("?"), Some("T05thread:2;swbreak:;".into()));
+    });
+}
+
+#[test]
+fn vcont_applies_actions_per_thread() {
+    let first = SimulatedCore::new();
+    let second = first.sibling();
+
+    multicore_session(&[first.clone(), second.clone()], |gdb| {
+        // Stepping one thread leaves the others halted.
+        assert_eq!(gdb.send("vCont;s:2;c"), Some("T05thread:2;".into()));
+        assert_eq!(second.pc(), 2);
+        assert_eq!(first.pc(), 0);
+
+        assert_eq!(gdb.send("vCont;c:2"), None);
+        assert_eq!(second.status(), CoreStatus::Running);
+        assert!(first.status().is_halted());
+
+        assert_eq!(gdb.send("vCont;t:2"), Some("OK".into()));
+        assert!(second.status().is_halted());
+
+        assert_eq!(gdb.send("vCont;x"), Some("E00".into()));
+    });
+}
+
+#[test]
+fn semihosting_is_executed_by_gdb() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        simulated.write(0x2000_0200, b"hello");
+        simulated.request_semihosting(0x2000_0010, SYS_WRITE, &[3, 0x2000_0200, 5]);
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("Fwrite,3,20000200,5".into()));
+
+        // GDB reads the data from the target while executing the request.
+        assert_eq!(gdb.send("m20000200,5"), Some("68656c6c6f".into()));
+
+        // Three bytes were written, so two were not.
+        assert_eq!(gdb.send("F3"), None);
+        assert_eq!(simulated.register(0), 2);
+        assert_eq!(simulated.pc(), 0x2000_0012);
+        assert_eq!(simulated.status(), CoreStatus::Running);
+    });
+}
+
+#[test]
+fn interrupted_semihosting_halts_the_core() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        simulated.request_semihosting(0x2000_0010, SYS_WRITE, &[3, 0x2000_0200, 5]);
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("Fwrite,3,20000200,5".into()));
+
+        assert_eq!(gdb.send("F-1,4,C"), Some("T02thread:1;".into()));
+        assert_eq!(simulated.register(0), 5);
+        assert!(simulated.status().is_halted());
+
+        // No request is pending anymore.
+        assert_eq!(gdb.send("F0"), Some("E00".into()));
+    });
+}
+
+#[test]
+fn exit_is_reported_with_the_exit_code() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        simulated.request_semihosting(
+            0x2000_0010,
+            SYS_EXIT_EXTENDED,
+            &[ADP_STOPPED_APPLICATION_EXIT, 3],
+        );
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("W03".into()));
+        assert!(simulated.status().is_halted());
+
+        // `SYS_EXIT` passes the reason instead of a parameter block.
+        simulated.request_semihosting(0x2000_0020, SYS_EXIT, &[]);
+        simulated
+            .0
+            .borrow_mut()
+            .registers
+            .insert(1, ADP_STOPPED_APPLICATION_EXIT);
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), Some("W00".into()));
+    });
+}
+
+#[test]
+fn console_is_opened_without_gdb() {
+    let simulated = SimulatedCore::new();
+
+    session(&simulated, |gdb| {
+        simulated.write(0x2000_0200, b":tt");
+        // Mode 4 is "w", which opens stdout.
+        simulated.request_semihosting(0x2000_0010, SYS_OPEN, &[0x2000_0200, 4, 3]);
+
+        assert_eq!(gdb.send("c"), None);
+        assert_eq!(gdb.wait_for_stop(), None);
+
+        assert_eq!(simulated.register(0), 1);
+        assert_eq!(simulated.status(), CoreStatus::Running);
+    });
+}
+
+#[test]
+fn lldb_queries_are_answered() {
+    session(&SimulatedCore::new(), |gdb| {
+        assert_eq!(gdb.send("QStartNoAckMode"), Some("OK".into()));
+
+        // "armv7m-none-eabi", hex encoded, as the simulated core has no FPU.
+        assert_eq!(
+            gdb.send("qHostInfo"),
+            Some("triple:61726d76376d2d6e6f6e652d65616269;endian:little;ptrsize:4;".into())
+        );
+
+        assert_eq!(
+            gdb.send("qMemoryRegionInfo:20000010"),
+            Some("start:20000000;size:1000;permissions:rwx;".into())
+        );
+        assert_eq!(gdb.send("qMemoryRegionInfo:zz"), Some("E00".into()));
+    });
+}
+
+#[test]
+fn registers_are_described_to_lldb() {
+    session(&SimulatedCore::new(), |gdb| {
+        assert_eq!(
+            gdb.send("qRegisterInfo0"),
+            Some("name:r0;bitsize:32;offset:0;encoding:uint;format:hex;set:General Purpose Registers;ehframe:0;dwarf:0;generic:arg1;".into())
+        );
+        assert_eq!(
+            gdb.send("qRegisterInfof"),
+            Some("name:pc;bitsize:32;offset:60;encoding:uint;format:hex;set:General Purpose Registers;ehframe:15;dwarf:15;generic:pc;".into())
+        );
+        assert_eq!(
+            gdb.send("qRegisterInfo10"),
+            Some("name:xpsr;bitsize:32;offset:64;encoding:uint;format:hex;set:General Purpose Registers;generic:flags;".into())
+        );
+
+        // LLDB stops asking once a register does not exist.
+        assert_eq!(gdb.send("qRegisterInfo11"), Some("E45".into()));
+    });
+}
+
+#[test]
+fn threads_are_described_to_lldb() {
+    let first = SimulatedCore::new();
+    let second = first.sibling();
+
+    multicore_session(&[first.clone(), second.clone()], |gdb| {
+        assert_eq!(gdb.send("Hg2"), Some("OK".into()));
+        assert_eq!(gdb.send("Pf=00010020"), Some("OK".into()));
+        assert_eq!(gdb.send("Pd=00020020"), Some("OK".into()));
+
+        assert_eq!(
+            gdb.send("jThreadsInfo"),
+            Some(
+                concat!(
+                    r#"[{"tid":1,"name":"Core 0","reason":"signal","signal":2,"registers":{"15":"00000000","13":"00000000"}},"#,
+                    r#"{"tid":2,"name":"Core 1","reason":"signal","signal":2,"registers":{"15":"00010020","13":"00020020"}}]"#
+                )
+                .into()
+            )
+        );
+
+        assert_eq!(gdb.send("qThreadStopInfo2"), Some("T02thread:2;".into()));
+        assert_eq!(gdb.send("qThreadStopInfo3"), Some("E00".into()));
+    });
+}
+
+/// The vector table, with the reset handler at 0x40, and `NOP; NOP; BKPT #1; B .` at 0x40.
+const FLASH_PACKETS: [&[u8]; 4] = [
+    b"vFlashErase:0,1000",
+    b"vFlashWrite:0:\x00\x00\x01\x20\x41\x00\x00\x00",
+    b"vFlashWrite:40:\x00\xbf\x00\xbf\x01\xbe\xfe\xe7",
+    b"vFlashDone",
+];
+
+/// Runs the worker on a session of the simulated chip, and returns the first `replies` replies
+/// to the packets.
+fn simulated_worker(
+    chip: &probe_rs::simulator::SimulatedCortexM,
+    reset_halt: bool,
+    packets: &[&[u8]],
+    replies: usize,
+) -> Vec<String> {
+    use super::worker;
+    use futures::{SinkExt, StreamExt};
+    use probe_rs::{simulator::SimulatedCortexM, Probe};
+
+    let mut session = Probe::new(chip.clone())
+        .attach(SimulatedCortexM::target())
+        .unwrap();
+
+    let (mut input, input_receiver) = mpsc::unbounded();
+    let (output_sender, mut output) = mpsc::unbounded::<CheckedPacket>();
+
+    let gdb = async move {
+        for packet in packets {
+            let packet = CheckedPacket::from_data(PacketKind::Packet, packet.to_vec());
+            input.unbounded_send(packet).unwrap();
+        }
+
+        let mut received = vec![];
+        while received.len() < replies {
+            let packet = output.next().await.unwrap();
+            received.push(String::from_utf8_lossy(&packet.data).into_owned());
+        }
+
+        input.close().await.unwrap();
+        received
+    };
+
+    let (result, received) = task::block_on(async {
+        futures::join!(
+            worker(input_receiver, output_sender, &mut session, reset_halt),
+            gdb
+        )
+    });
+
+    result.unwrap();
+    received
+}
+
+#[test]
+fn simulated_chip_is_flashed_and_debugged() {
+    use probe_rs::simulator::SimulatedCortexM;
+
+    let chip = SimulatedCortexM::new();
+
+    let mut packets = FLASH_PACKETS.to_vec();
+    packets.extend_from_slice(&[b"Z1,42,2", b"c"]);
+
+    assert_eq!(
+        simulated_worker(&chip, true, &packets, 6),
+        ["OK", "OK", "OK", "OK", "OK", "T05thread:1;hwbreak:;"]
+    );
+    assert_eq!(chip.core_register(15), 0x42);
+
+    let mut flash = [0; 8];
+    chip.read_memory(0x40, &mut flash);
+    assert_eq!(flash, [0x00, 0xbf, 0x00, 0xbf, 0x01, 0xbe, 0xfe, 0xe7]);
+}
+
+#[test]
+fn flashed_chip_runs_without_reset_halt() {
+    use probe_rs::simulator::SimulatedCortexM;
+
+    let chip = SimulatedCortexM::new();
+
+    // The core is running after the reset, so its halt at the BKPT is reported to GDB.
+    assert_eq!(
+        simulated_worker(&chip, false, &FLASH_PACKETS, 5),
+        ["OK", "OK", "OK", "OK", "T05thread:1;swbreak:;"]
+    );
+    assert_eq!(chip.core_register(15), 0x44);
+}
+
+#[test]
+fn probe_speed_is_changed_while_attached() {
+    use probe_rs::simulator::SimulatedCortexM;
+
+    let chip = SimulatedCortexM::new();
+    // `monitor speed 4000`, `monitor speed` and `monitor speed 0`, which the probe rejects.
+    let packets: &[&[u8]] = &[
+        b"qRcmd,73706565642034303030",
+        b"qRcmd,7370656564",
+        b"qRcmd,73706565642030",
+    ];
+    let replies = simulated_worker(&chip, false, packets, 6);
+
+    assert_eq!(replies[0], console_output("Probe speed set to 4000 kHz\n"));
+    assert_eq!(replies[1], "OK");
+    assert_eq!(replies[2], console_output("Probe speed is 4000 kHz\n"));
+    assert_eq!(replies[3], "OK");
+
+    let error = probe_rs::Error::Probe(DebugProbeError::UnsupportedSpeed(0));
+    assert_eq!(replies[4], console_output(&format!("Error: {}\n", error)));
+    assert!(replies[5].starts_with('E'));
+}
@@ -1,15 +1,19 @@
-use async_std::{io::Write, net::TcpStream, prelude::*};
+use crate::reader::AckMode;
+use crate::transport::Stream;
+
+use async_std::{io::Write, prelude::*};
 use futures::channel::mpsc;
 use gdb_protocol::packet::{CheckedPacket, Kind as PacketKind};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
 
-pub async fn writer(
+pub(crate) async fn writer(
     packet: CheckedPacket,
-    stream: &mut TcpStream,
+    stream: &mut impl Stream,
     packet_stream: &Sender<CheckedPacket>,
     buffer: &mut Vec<u8>,
+    ack_mode: &mut AckMode,
 ) -> Result<()> {
     let mut tmp_buf = [0; 128];
     log::debug!("WRITE WIN");
@@ -17,6 +21,10 @@ pub async fn writer(
     encode(&packet, stream).await?;
     stream.flush().await?;
 
+    if *ack_mode == AckMode::Disabled {
+        return super::reader::reader(stream, packet_stream, buffer, ack_mode).await;
+    }
+
     log::debug!("Request ACK for {}", String::from_utf8_lossy(&packet.data));
     'ack: loop {
         log::debug!("Reading");
@@ -48,7 +56,12 @@ pub async fn writer(
         log::debug!("Done checking ACK");
     }
 
-    super::reader::reader(stream, packet_stream, buffer).await
+    // The reply to `QStartNoAckMode` was the last acknowledged packet.
+    if *ack_mode == AckMode::Disabling {
+        *ack_mode = AckMode::Disabled;
+    }
+
+    super::reader::reader(stream, packet_stream, buffer, ack_mode).await
 }
 
 pub async fn encode<W>(packet: &CheckedPacket, w: &mut W) -> Result<()>
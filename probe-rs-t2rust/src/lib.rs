@@ -37,7 +37,7 @@ pub fn run(input_dir: impl AsRef<Path>, output_file: impl AsRef<Path>) {
         quote::quote! {
             #[allow(unused_imports)]
             use jep106::JEP106Code;
-            use crate::config::{Chip, RawFlashAlgorithm, FlashRegion, MemoryRegion, RamRegion, SectorDescription, FlashProperties};
+            use crate::config::{Chip, CoreDescription, RawFlashAlgorithm, FlashRegion, MemoryRegion, RamRegion, SectorDescription, FlashProperties};
 
             use std::borrow::Cow;
         }
@@ -314,6 +314,9 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                 .as_sequence()
                 .unwrap();
             let flash_algorithm_names = flash_algorithms.iter().map(|a| a.as_str().unwrap());
+
+            let cores = extract_cores(variant);
+
             quote::quote! {
                 Chip {
                     name: Cow::Borrowed(#name),
@@ -324,6 +327,43 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                     flash_algorithms: Cow::Borrowed(&[
                         #(Cow::Borrowed(#flash_algorithm_names),)*
                     ]),
+                    cores: Cow::Borrowed(&[
+                        #(#cores,)*
+                    ]),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Extracts a list of core token streams from a yaml value.
+fn extract_cores(variant: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
+    // The cores are optional, single core chips use the core of the family.
+    let cores = match variant.get("cores") {
+        Some(cores) => cores.as_sequence().unwrap(),
+        None => return vec![],
+    };
+
+    cores
+        .iter()
+        .map(|core| {
+            let core_type = core
+                .get("core_type")
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_ascii_lowercase();
+            let ap = core.get("ap").and_then(|v| v.as_u64()).unwrap_or(0) as u8;
+            let debug_base = quote_option(
+                core.get("debug_base")
+                    .and_then(|v| v.as_u64().map(|v| v as u32)),
+            );
+
+            quote::quote! {
+                CoreDescription {
+                    core_type: Cow::Borrowed(#core_type),
+                    ap: #ap,
+                    debug_base: #debug_base,
                 }
             }
         })
@@ -0,0 +1,5 @@
+/// Thumb `BKPT #0` instruction
+pub const BKPT: u16 = 0xbe00;
+
+/// Thumb `BKPT #0xAB` instruction, used for semihosting requests
+pub const BKPT_SEMIHOSTING: u16 = 0xbeab;
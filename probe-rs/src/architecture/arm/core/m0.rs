@@ -1,7 +1,10 @@
-use super::{CortexState, Dfsr, ARM_REGISTER_FILE};
+use super::{
+    clear_dwt_watchpoint, dwt_watchpoint_matched, set_dwt_watchpoint, CortexState, Dfsr, DwtCtrl,
+    ARM_REGISTER_FILE,
+};
 use crate::core::{
     Architecture, CoreInformation, CoreInterface, CoreRegister, CoreRegisterAddress,
-    RegisterDescription, RegisterFile, RegisterKind,
+    RegisterDescription, RegisterFile, RegisterKind, WatchpointKind,
 };
 use crate::error::Error;
 use crate::memory::Memory;
@@ -259,8 +262,8 @@ const REGISTERS: RegisterFile = RegisterFile {
 };
 */
 
-pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b01001);
-pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b01010);
+pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b1_0001);
+pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b1_0010);
 
 const PC: RegisterDescription = RegisterDescription {
     name: "PC",
@@ -328,6 +331,18 @@ impl<'probe> M0<'probe> {
         }
         Err(Error::Probe(DebugProbeError::Timeout))
     }
+
+    /// Enable the DWT, which is required to access the watchpoint comparators.
+    fn enable_dwt(&mut self) -> Result<(), Error> {
+        let mut demcr = Demcr(self.memory.read_word_32(Demcr::ADDRESS)?);
+
+        if !demcr.dwtena() {
+            demcr.set_dwtena(true);
+            self.memory.write_word_32(Demcr::ADDRESS, demcr.into())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'probe> CoreInterface for M0<'probe> {
@@ -524,6 +539,34 @@ impl<'probe> CoreInterface for M0<'probe> {
         Ok(())
     }
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+        self.enable_dwt()?;
+
+        let reg = DwtCtrl::from(self.memory.read_word_32(DwtCtrl::ADDRESS)?);
+
+        Ok(reg.numcomp())
+    }
+
+    fn set_watchpoint(
+        &mut self,
+        unit_index: usize,
+        address: u32,
+        mask: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.enable_dwt()?;
+
+        set_dwt_watchpoint(&mut self.memory, unit_index, address, mask, kind)
+    }
+
+    fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        clear_dwt_watchpoint(&mut self.memory, unit_index)
+    }
+
+    fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, Error> {
+        dwt_watchpoint_matched(&mut self.memory, unit_index)
+    }
+
     fn registers(&self) -> &'static RegisterFile {
         &ARM_REGISTER_FILE
     }
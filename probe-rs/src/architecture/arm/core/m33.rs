@@ -6,7 +6,7 @@ use crate::memory::Memory;
 use crate::{
     core::{
         Architecture, CoreInformation, CoreInterface, CoreRegister, CoreRegisterAddress,
-        RegisterFile,
+        RegisterFile, WatchpointKind,
     },
     CoreStatus, DebugProbeError, HaltReason,
 };
@@ -15,7 +15,7 @@ use crate::{architecture::arm::core::register, MemoryInterface};
 
 use bitfield::bitfield;
 
-use super::{CortexState, Dfsr, ARM_REGISTER_FILE};
+use super::{CortexState, Dfsr, DwtCompX, DwtCtrl, ARM_REGISTER_FILE, DWT_COMPARATOR_STRIDE};
 use std::mem::size_of;
 
 pub struct M33<'probe> {
@@ -72,6 +72,18 @@ impl<'probe> M33<'probe> {
         }
         Err(Error::Probe(DebugProbeError::Timeout))
     }
+
+    /// Enable the DWT, which is required to access the watchpoint comparators.
+    fn enable_dwt(&mut self) -> Result<(), Error> {
+        let mut demcr = Demcr(self.memory.read_word_32(Demcr::ADDRESS)?);
+
+        if !demcr.trcena() {
+            demcr.set_trcena(true);
+            self.memory.write_word_32(Demcr::ADDRESS, demcr.into())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'probe> CoreInterface for M33<'probe> {
@@ -263,6 +275,65 @@ impl<'probe> CoreInterface for M33<'probe> {
         Ok(())
     }
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+        self.enable_dwt()?;
+
+        let reg = DwtCtrl::from(self.memory.read_word_32(DwtCtrl::ADDRESS)?);
+
+        Ok(reg.numcomp())
+    }
+
+    fn set_watchpoint(
+        &mut self,
+        unit_index: usize,
+        address: u32,
+        mask: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        // ARMv8-M has no mask register, a single comparator can only match
+        // accesses of up to one word. Larger regions need a pair of comparators.
+        if mask > 2 {
+            log::warn!(
+                "Watching 2^{} bytes is not supported on ARMv8-M, at most 4 bytes can be watched.",
+                mask
+            );
+            return Err(Error::Probe(DebugProbeError::NotImplemented(
+                "ARMv8-M address range watchpoints",
+            )));
+        }
+
+        self.enable_dwt()?;
+
+        let offset = unit_index as u32 * DWT_COMPARATOR_STRIDE;
+
+        self.memory
+            .write_word_32(DwtCompX::ADDRESS + offset, address)?;
+        self.memory.write_word_32(
+            DwtFunctionX::ADDRESS + offset,
+            DwtFunctionX::watchpoint_configuration(mask, kind).into(),
+        )?;
+
+        Ok(())
+    }
+
+    fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        let offset = unit_index as u32 * DWT_COMPARATOR_STRIDE;
+
+        self.memory
+            .write_word_32(DwtFunctionX::ADDRESS + offset, DwtFunctionX(0).into())?;
+
+        Ok(())
+    }
+
+    fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, Error> {
+        let offset = unit_index as u32 * DWT_COMPARATOR_STRIDE;
+
+        // Reading the MATCHED flag clears it.
+        let function = DwtFunctionX(self.memory.read_word_32(DwtFunctionX::ADDRESS + offset)?);
+
+        Ok(function.matched())
+    }
+
     fn registers(&self) -> &'static RegisterFile {
         &ARM_REGISTER_FILE
     }
@@ -644,3 +715,63 @@ impl From<FpCompX> for u32 {
         value.0
     }
 }
+
+bitfield! {
+    /// DWT_FUNCTION register as defined by ARMv8-M.
+    #[derive(Copy,Clone)]
+    pub struct DwtFunctionX(u32);
+    impl Debug;
+
+    pub id, _: 31, 27;
+    pub matched, _: 24;
+    pub datavsize, set_datavsize: 11, 10;
+    pub action, set_action: 5, 4;
+    pub match_, set_match: 3, 0;
+}
+
+impl DwtFunctionX {
+    /// Get the register configuration which halts the core on the given kind
+    /// of data access to the 2^`size` bytes at the comparator address.
+    fn watchpoint_configuration(size: u32, kind: WatchpointKind) -> Self {
+        let mut reg = DwtFunctionX::from(0);
+
+        reg.set_datavsize(size);
+        // Generate a debug event
+        reg.set_action(0b01);
+        reg.set_match(match kind {
+            WatchpointKind::Access => 0b0100,
+            WatchpointKind::Write => 0b0101,
+            WatchpointKind::Read => 0b0110,
+        });
+
+        reg
+    }
+}
+
+impl CoreRegister for DwtFunctionX {
+    const ADDRESS: u32 = 0xE000_1028;
+    const NAME: &'static str = "DWT_FUNCTION";
+}
+
+impl From<u32> for DwtFunctionX {
+    fn from(value: u32) -> Self {
+        DwtFunctionX(value)
+    }
+}
+
+impl From<DwtFunctionX> for u32 {
+    fn from(value: DwtFunctionX) -> Self {
+        value.0
+    }
+}
+
+#[test]
+fn watchpoint_register_value() {
+    // Halt on word sized writes.
+    //
+    // See ARMv8-M Architecture Reference Manual, Section D1.2.62
+    let reg = DwtFunctionX::watchpoint_configuration(2, WatchpointKind::Write);
+    let reg_val: u32 = reg.into();
+
+    assert_eq!(0x0000_0815, reg_val);
+}
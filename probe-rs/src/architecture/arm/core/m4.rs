@@ -1,11 +1,14 @@
 use crate::core::{
-    CoreInformation, CoreInterface, CoreRegister, CoreRegisterAddress, RegisterFile,
+    CoreInformation, CoreInterface, CoreRegister, CoreRegisterAddress, RegisterFile, WatchpointKind,
 };
 use crate::error::Error;
 use crate::memory::Memory;
 use crate::DebugProbeError;
 
-use super::{register, CortexState, Dfsr, ARM_REGISTER_FILE};
+use super::{
+    clear_dwt_watchpoint, dwt_watchpoint_matched, register, set_dwt_watchpoint, CortexState, Dfsr,
+    DwtCtrl, ARM_REGISTER_FILE,
+};
 use crate::{
     core::{Architecture, CoreStatus, HaltReason},
     MemoryInterface,
@@ -324,8 +327,8 @@ impl FpRev2CompX {
     }
 }
 
-pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b000_1001);
-pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b000_1010);
+pub const MSP: CoreRegisterAddress = CoreRegisterAddress(0b001_0001);
+pub const PSP: CoreRegisterAddress = CoreRegisterAddress(0b001_0010);
 
 pub struct M4<'probe> {
     memory: Memory<'probe>,
@@ -381,6 +384,18 @@ impl<'probe> M4<'probe> {
         }
         Err(Error::Probe(DebugProbeError::Timeout))
     }
+
+    /// Enable the DWT, which is required to access the watchpoint comparators.
+    fn enable_dwt(&mut self) -> Result<(), Error> {
+        let mut demcr = Demcr(self.memory.read_word_32(Demcr::ADDRESS)?);
+
+        if !demcr.trcena() {
+            demcr.set_trcena(true);
+            self.memory.write_word_32(Demcr::ADDRESS, demcr.into())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'probe> CoreInterface for M4<'probe> {
@@ -652,6 +667,34 @@ impl<'probe> CoreInterface for M4<'probe> {
         Ok(())
     }
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+        self.enable_dwt()?;
+
+        let reg = DwtCtrl::from(self.memory.read_word_32(DwtCtrl::ADDRESS)?);
+
+        Ok(reg.numcomp())
+    }
+
+    fn set_watchpoint(
+        &mut self,
+        unit_index: usize,
+        address: u32,
+        mask: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), Error> {
+        self.enable_dwt()?;
+
+        set_dwt_watchpoint(&mut self.memory, unit_index, address, mask, kind)
+    }
+
+    fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        clear_dwt_watchpoint(&mut self.memory, unit_index)
+    }
+
+    fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, Error> {
+        dwt_watchpoint_matched(&mut self.memory, unit_index)
+    }
+
     fn registers(&self) -> &'static RegisterFile {
         &ARM_REGISTER_FILE
     }
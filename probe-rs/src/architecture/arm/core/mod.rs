@@ -1,6 +1,9 @@
 use crate::{
-    core::{CoreRegister, CoreRegisterAddress, RegisterDescription, RegisterFile, RegisterKind},
-    CoreStatus, HaltReason,
+    core::{
+        CoreRegister, CoreRegisterAddress, RegisterDescription, RegisterFile, RegisterKind,
+        WatchpointKind,
+    },
+    CoreStatus, DebugProbeError, Error, HaltReason, Memory,
 };
 
 use bitfield::bitfield;
@@ -57,7 +60,8 @@ pub(crate) mod register {
     };
 }
 
-static ARM_REGISTER_FILE: RegisterFile = RegisterFile {
+/// The registers of Cortex-M cores.
+pub static ARM_REGISTER_FILE: RegisterFile = RegisterFile {
     platform_registers: &[
         RegisterDescription {
             name: "R0",
@@ -240,6 +244,179 @@ impl CoreRegister for Dfsr {
     const NAME: &'static str = "DFSR";
 }
 
+/// Distance between the register sets of two DWT comparators.
+const DWT_COMPARATOR_STRIDE: u32 = 0x10;
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct DwtCtrl(u32);
+    impl Debug;
+    /// Number of comparators
+    pub numcomp, _: 31, 28;
+}
+
+impl From<u32> for DwtCtrl {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DwtCtrl> for u32 {
+    fn from(value: DwtCtrl) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for DwtCtrl {
+    const ADDRESS: u32 = 0xE000_1000;
+    const NAME: &'static str = "DWT_CTRL";
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DwtCompX(u32);
+
+impl From<u32> for DwtCompX {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DwtCompX> for u32 {
+    fn from(value: DwtCompX) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for DwtCompX {
+    const ADDRESS: u32 = 0xE000_1020;
+    const NAME: &'static str = "DWT_COMP";
+}
+
+bitfield! {
+    #[derive(Copy, Clone)]
+    pub struct DwtMaskX(u32);
+    impl Debug;
+    /// Number of low address bits ignored by the comparator
+    pub mask, set_mask: 4, 0;
+}
+
+impl From<u32> for DwtMaskX {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DwtMaskX> for u32 {
+    fn from(value: DwtMaskX) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for DwtMaskX {
+    const ADDRESS: u32 = 0xE000_1024;
+    const NAME: &'static str = "DWT_MASK";
+}
+
+bitfield! {
+    /// DWT_FUNCTION register as defined by ARMv6-M and ARMv7-M.
+    ///
+    /// ARMv8-M uses a different layout, see `m33::DwtFunctionX`.
+    #[derive(Copy, Clone)]
+    pub struct DwtFunctionX(u32);
+    impl Debug;
+    pub matched, _: 24;
+    pub datavmatch, set_datavmatch: 8;
+    pub function, set_function: 3, 0;
+}
+
+impl DwtFunctionX {
+    /// Get the register configuration which halts the core
+    /// on the given kind of data access.
+    fn watchpoint_configuration(kind: WatchpointKind) -> Self {
+        let mut reg = DwtFunctionX(0);
+
+        reg.set_function(match kind {
+            WatchpointKind::Read => 0b0101,
+            WatchpointKind::Write => 0b0110,
+            WatchpointKind::Access => 0b0111,
+        });
+
+        reg
+    }
+}
+
+impl From<u32> for DwtFunctionX {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<DwtFunctionX> for u32 {
+    fn from(value: DwtFunctionX) -> Self {
+        value.0
+    }
+}
+
+impl CoreRegister for DwtFunctionX {
+    const ADDRESS: u32 = 0xE000_1028;
+    const NAME: &'static str = "DWT_FUNCTION";
+}
+
+/// Program DWT comparator `unit` as a data watchpoint on ARMv6-M and ARMv7-M.
+///
+/// The DWT has to be enabled in DEMCR before.
+fn set_dwt_watchpoint(
+    memory: &mut Memory,
+    unit: usize,
+    address: u32,
+    mask: u32,
+    kind: WatchpointKind,
+) -> Result<(), Error> {
+    let offset = unit as u32 * DWT_COMPARATOR_STRIDE;
+
+    // The maximum mask size is implementation defined, unsupported bits read as zero.
+    let mut mask_reg = DwtMaskX(0);
+    mask_reg.set_mask(mask);
+    memory.write_word_32(DwtMaskX::ADDRESS + offset, mask_reg.into())?;
+
+    let mask_readback = DwtMaskX(memory.read_word_32(DwtMaskX::ADDRESS + offset)?).mask();
+
+    if mask_readback != mask {
+        log::warn!(
+            "DWT comparator {} does not support watching 2^{} bytes.",
+            unit,
+            mask
+        );
+        return Err(Error::Probe(DebugProbeError::Unknown));
+    }
+
+    memory.write_word_32(DwtCompX::ADDRESS + offset, address)?;
+    memory.write_word_32(
+        DwtFunctionX::ADDRESS + offset,
+        DwtFunctionX::watchpoint_configuration(kind).into(),
+    )?;
+
+    Ok(())
+}
+
+/// Disable DWT comparator `unit` on ARMv6-M and ARMv7-M.
+fn clear_dwt_watchpoint(memory: &mut Memory, unit: usize) -> Result<(), Error> {
+    let offset = unit as u32 * DWT_COMPARATOR_STRIDE;
+
+    memory.write_word_32(DwtFunctionX::ADDRESS + offset, DwtFunctionX(0).into())?;
+
+    Ok(())
+}
+
+/// Check if DWT comparator `unit` matched on ARMv6-M and ARMv7-M.
+///
+/// Reading the MATCHED flag clears it.
+fn dwt_watchpoint_matched(memory: &mut Memory, unit: usize) -> Result<bool, Error> {
+    let offset = unit as u32 * DWT_COMPARATOR_STRIDE;
+
+    Ok(DwtFunctionX(memory.read_word_32(DwtFunctionX::ADDRESS + offset)?).matched())
+}
+
 pub(crate) struct CortexState {
     initialized: bool,
 
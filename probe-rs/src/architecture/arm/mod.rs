@@ -1,4 +1,5 @@
 pub mod ap;
+pub(crate) mod assembly;
 pub(crate) mod communication_interface;
 pub(crate) mod core;
 pub mod dp;
@@ -13,3 +14,4 @@ pub use self::core::m0;
 pub use self::core::m33;
 pub use self::core::m4;
 pub use self::core::CortexDump;
+pub use self::core::ARM_REGISTER_FILE;
@@ -1,6 +1,18 @@
 /// RISCV breakpoint instruction
 pub const EBREAK: u32 = 0b000000000001_00000_000_00000_1110011;
 
+/// Compressed RISCV breakpoint instruction
+pub const C_EBREAK: u16 = 0x9002;
+
+/// `fence.i`, which synchronizes the instruction fetches with the writes to memory
+pub const FENCE_I: u32 = 0x0000_100F;
+
+/// `slli x0, x0, 0x1f`, which precedes the `ebreak` of a semihosting request
+pub const SEMIHOSTING_ENTRY: u32 = 0x01f0_1013;
+
+/// `srai x0, x0, 7`, which follows the `ebreak` of a semihosting request
+pub const SEMIHOSTING_EXIT: u32 = 0x4070_5013;
+
 /// Assemble a `lw` instruction.
 pub const fn lw(offset: u32, base: u32, width: u32, destination: u32) -> u32 {
     let opcode = 0b000_0011;
@@ -33,6 +33,10 @@ pub(crate) enum RiscvError {
     RequestNotAcknowledged,
     #[error("The version '{0}' of the debug module is currently not supported.")]
     UnsupportedDebugModuleVersion(u8),
+    #[error("No free trigger is available.")]
+    NoTriggerAvailable,
+    #[error("Trigger {0} does not support the requested match.")]
+    UnsupportedTrigger(u32),
 }
 
 impl From<RiscvError> for ProbeRsError {
@@ -154,7 +158,7 @@ impl<'probe> RiscvCommunicationInterface<'probe> {
         if probe.has_jtag_interface() {
             let mut s = Self { probe, state };
 
-            if s.state.initialized() {
+            if !s.state.initialized() {
                 s.enter_debug_mode()?;
                 s.state.initialize();
             }
@@ -1063,7 +1067,7 @@ bitfield! {
 }
 
 impl DebugRegister for Abstractauto {
-    const ADDRESS: u8 = 0x38;
+    const ADDRESS: u8 = 0x18;
     const NAME: &'static str = "abstractauto";
 }
 
@@ -9,7 +9,7 @@ use communication_interface::{
     RiscvError,
 };
 
-use crate::core::{CoreInformation, RegisterFile};
+use crate::core::{CoreInformation, RegisterFile, WatchpointKind};
 use crate::{CoreRegisterAddress, CoreStatus, Error, HaltReason, MemoryInterface};
 use bitfield::bitfield;
 use register::RISCV_REGISTERS;
@@ -21,13 +21,303 @@ pub(crate) mod assembly;
 
 pub mod communication_interface;
 
+/// CSR addresses of the trigger module.
+const TSELECT: u16 = 0x7a0;
+const TDATA1: u16 = 0x7a1;
+const TDATA2: u16 = 0x7a2;
+const TINFO: u16 = 0x7a4;
+
+/// Trigger type of an address / data match trigger.
+const MCONTROL_TYPE: u32 = 2;
+
+/// What a trigger is currently used for.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum TriggerUse {
+    Breakpoint(u32),
+    Watchpoint,
+}
+
+/// State of a hart which is kept between attaches.
+pub(crate) struct RiscvState {
+    /// Indices of the triggers which support address matching.
+    ///
+    /// Discovering them requires a halted hart, so this is `None`
+    /// until the hart was halted once.
+    triggers: Option<Vec<u32>>,
+
+    /// Usage of the triggers in `triggers`, by index into that list.
+    trigger_use: Vec<Option<TriggerUse>>,
+
+    /// The watchpoint trigger whose hit bit was cleared when the halt reason was read.
+    hit_trigger: Option<u32>,
+}
+
+impl RiscvState {
+    pub(crate) fn new() -> Self {
+        Self {
+            triggers: None,
+            trigger_use: Vec::new(),
+            hit_trigger: None,
+        }
+    }
+}
+
 pub struct Riscv32<'probe> {
     interface: RiscvCommunicationInterface<'probe>,
+
+    state: &'probe mut RiscvState,
 }
 
 impl<'probe> Riscv32<'probe> {
-    pub fn new(interface: RiscvCommunicationInterface<'probe>) -> Self {
-        Self { interface }
+    pub(crate) fn new(
+        interface: RiscvCommunicationInterface<'probe>,
+        state: &'probe mut RiscvState,
+    ) -> Result<Self, Error> {
+        let mut core = Self { interface, state };
+
+        if core.state.triggers.is_none() && core.core_halted()? {
+            core.triggers()?;
+        }
+
+        Ok(core)
+    }
+
+    /// Returns the triggers which can be used for breakpoints and watchpoints,
+    /// discovering them on first use.
+    fn triggers(&mut self) -> Result<&[u32], RiscvError> {
+        if self.state.triggers.is_none() {
+            let triggers = self.discover_triggers()?;
+
+            self.state.trigger_use = vec![None; triggers.len()];
+            self.state.triggers = Some(triggers);
+        }
+
+        Ok(self.state.triggers.as_deref().unwrap_or_default())
+    }
+
+    fn discover_triggers(&mut self) -> Result<Vec<u32>, RiscvError> {
+        log::debug!("Determining number of HW breakpoints supported");
+
+        let mut triggers = Vec::new();
+        let mut tselect_index = 0;
+
+        // These steps follow the debug specification 0.13, section 5.1 Enumeration
+        loop {
+            log::debug!("Trying tselect={}", tselect_index);
+            if let Err(e) = self.write_csr(TSELECT, tselect_index) {
+                match e {
+                    RiscvError::AbstractCommand(AbstractCommandErrorKind::Exception) => break,
+                    other_error => return Err(other_error),
+                }
+            }
+
+            let readback = self.read_csr(TSELECT)?;
+
+            if readback != tselect_index {
+                break;
+            }
+
+            let supports_mcontrol = match self.read_csr(TINFO) {
+                Ok(tinfo_val) => {
+                    // Each bit in the lower half marks a supported trigger type
+                    let types = tinfo_val & 0xffff;
+
+                    if types == 1 {
+                        // Trigger doesn't exist, break the loop
+                        break;
+                    }
+
+                    log::info!(
+                        "Discovered trigger with index {} and types {:#b}",
+                        tselect_index,
+                        types
+                    );
+
+                    types & (1 << MCONTROL_TYPE) != 0
+                }
+                Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::Exception)) => {
+                    // An exception means we have to read tdata1 to discover the type
+                    let tdata_val = self.read_csr(TDATA1)?;
+
+                    // TODO: Proper handle xlen
+                    let xlen = 32;
+
+                    let trigger_type = tdata_val >> (xlen - 4);
+
+                    if trigger_type == 0 {
+                        break;
+                    }
+
+                    log::info!(
+                        "Discovered trigger with index {} and type {}",
+                        tselect_index,
+                        trigger_type,
+                    );
+
+                    trigger_type == MCONTROL_TYPE
+                }
+                Err(other) => return Err(other),
+            };
+
+            if supports_mcontrol {
+                triggers.push(tselect_index);
+            }
+
+            tselect_index += 1;
+        }
+
+        Ok(triggers)
+    }
+
+    /// Watchpoints use the triggers from the end of the list,
+    /// so they only collide with breakpoints when all triggers are in use.
+    fn watchpoint_trigger(&mut self, unit_index: usize) -> Result<u32, RiscvError> {
+        let triggers = self.triggers()?;
+
+        triggers
+            .len()
+            .checked_sub(unit_index + 1)
+            .map(|position| triggers[position])
+            .ok_or(RiscvError::NoTriggerAvailable)
+    }
+
+    fn trigger_position(&self, trigger: u32) -> usize {
+        self.state
+            .triggers
+            .as_ref()
+            .and_then(|triggers| triggers.iter().position(|t| *t == trigger))
+            .expect("Trigger positions are only looked up for discovered triggers")
+    }
+
+    /// Checks that a trigger is unused, and returns its position in the trigger list.
+    fn free_trigger_position(&self, trigger: u32) -> Result<usize, RiscvError> {
+        let position = self.trigger_position(trigger);
+
+        if self.state.trigger_use[position].is_some() {
+            return Err(RiscvError::NoTriggerAvailable);
+        }
+
+        Ok(position)
+    }
+
+    fn release_trigger(&mut self, trigger: u32) -> Result<(), RiscvError> {
+        let position = self.trigger_position(trigger);
+
+        self.write_csr(TSELECT, trigger)?;
+        self.write_csr(TDATA1, 0)?;
+        self.write_csr(TDATA2, 0)?;
+
+        self.state.trigger_use[position] = None;
+
+        Ok(())
+    }
+
+    /// Programs a trigger and returns the configuration read back from it.
+    fn write_trigger(
+        &mut self,
+        trigger: u32,
+        config: Mcontrol,
+        tdata2: u32,
+    ) -> Result<Mcontrol, RiscvError> {
+        self.write_csr(TSELECT, trigger)?;
+
+        // verify the trigger has the correct type
+        let tdata_value = Mcontrol(self.read_csr(TDATA1)?);
+
+        if tdata_value.type_() != MCONTROL_TYPE {
+            return Err(RiscvError::UnsupportedTrigger(trigger));
+        }
+
+        // Disable the trigger while changing the address, so it doesn't fire
+        // with a mix of old and new settings.
+        self.write_csr(TDATA1, 0)?;
+        self.write_csr(TDATA2, tdata2)?;
+        self.write_csr(TDATA1, config.0)?;
+
+        Ok(Mcontrol(self.read_csr(TDATA1)?))
+    }
+
+    /// Make `ebreak` instructions halt the hart instead of raising an exception,
+    /// which is required for software breakpoints.
+    fn enable_ebreak_halt(&mut self) -> Result<(), RiscvError> {
+        let mut dcsr = Dcsr(self.read_csr(0x7b0)?);
+
+        if !(dcsr.ebreakm() && dcsr.ebreaks() && dcsr.ebreaku()) {
+            dcsr.set_ebreakm(true);
+            dcsr.set_ebreaks(true);
+            dcsr.set_ebreaku(true);
+
+            self.write_csr(0x7b0, dcsr.0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes `fence.i`, so that the hart fetches the instructions which were changed in
+    /// memory, like the ones replaced by software breakpoints, instead of cached ones.
+    fn fence_i(&mut self) -> Result<(), RiscvError> {
+        self.interface
+            .setup_program_buffer(&[assembly::FENCE_I, assembly::EBREAK])?;
+
+        let mut postexec_cmd = AccessRegisterCommand(0);
+        postexec_cmd.set_postexec(true);
+
+        match self.interface.execute_abstract_command(postexec_cmd.0) {
+            // Harts without the Zifencei extension have no instruction cache to synchronize.
+            Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::Exception)) => {
+                log::debug!("fence.i is not supported, the instruction cache is not synchronized.");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Determines whether a trigger halt was caused by a watchpoint.
+    fn trigger_halt_reason(&mut self) -> Result<HaltReason, RiscvError> {
+        let in_use: Vec<(u32, TriggerUse)> = match &self.state.triggers {
+            Some(triggers) => triggers
+                .iter()
+                .zip(self.state.trigger_use.iter())
+                .filter_map(|(trigger, usage)| usage.map(|usage| (*trigger, usage)))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        // The hit bit is optional, but gives a definite answer if implemented.
+        for (trigger, usage) in &in_use {
+            self.write_csr(TSELECT, *trigger)?;
+            let mut tdata1 = Mcontrol(self.read_csr(TDATA1)?);
+
+            if tdata1.hit() {
+                tdata1.set_hit(false);
+                self.write_csr(TDATA1, tdata1.0)?;
+
+                return Ok(match usage {
+                    TriggerUse::Breakpoint(_) => HaltReason::Breakpoint,
+                    TriggerUse::Watchpoint => {
+                        self.state.hit_trigger = Some(*trigger);
+                        HaltReason::Watchpoint
+                    }
+                });
+            }
+        }
+
+        // Otherwise, a breakpoint must have stopped the hart at its address.
+        let dpc = self.read_csr(0x7b1)?;
+
+        let breakpoint_hit = in_use
+            .iter()
+            .any(|(_, usage)| *usage == TriggerUse::Breakpoint(dpc));
+
+        let watchpoints_set = in_use
+            .iter()
+            .any(|(_, usage)| *usage == TriggerUse::Watchpoint);
+
+        if watchpoints_set && !breakpoint_hit {
+            Ok(HaltReason::Watchpoint)
+        } else {
+            Ok(HaltReason::Breakpoint)
+        }
     }
 
     fn read_csr(&mut self, address: u16) -> Result<u32, RiscvError> {
@@ -161,6 +451,16 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
     fn run(&mut self) -> Result<(), crate::Error> {
         // TODO: test if core halted?
 
+        self.state.hit_trigger = None;
+
+        if self.core_halted()? {
+            self.enable_ebreak_halt()?;
+
+            // Instructions can have been changed while halted, by software breakpoints or by
+            // writes of the debugger.
+            self.fence_i()?;
+        }
+
         // set resume request
         let mut dmcontrol = Dmcontrol(0);
         dmcontrol.set_dmactive(true);
@@ -305,9 +605,14 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
     }
 
     fn step(&mut self) -> Result<crate::core::CoreInformation, crate::Error> {
+        self.state.hit_trigger = None;
+
         let mut dcsr = Dcsr(self.read_core_reg(CoreRegisterAddress(0x7b0))?);
 
         dcsr.set_step(true);
+        dcsr.set_ebreakm(true);
+        dcsr.set_ebreaks(true);
+        dcsr.set_ebreaku(true);
 
         self.write_csr(0x7b0, dcsr.0)?;
 
@@ -361,129 +666,140 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
     }
 
     fn get_available_breakpoint_units(&mut self) -> Result<u32, crate::Error> {
-        // TODO: This should probably only be done once, when initialising
+        Ok(self.triggers()?.len() as u32)
+    }
 
-        log::debug!("Determining number of HW breakpoints supported");
+    fn enable_breakpoints(&mut self, _state: bool) -> Result<(), crate::Error> {
+        // seems not needed on RISCV
+        Ok(())
+    }
 
-        let tselect = 0x7a0;
-        let tdata1 = 0x7a1;
-        let tinfo = 0x7a4;
+    fn set_breakpoint(&mut self, bp_unit_index: usize, addr: u32) -> Result<(), crate::Error> {
+        // Breakpoints use the triggers from the start of the list.
+        let trigger = self.triggers()?.get(bp_unit_index).copied();
+        let trigger = trigger.ok_or(RiscvError::NoTriggerAvailable)?;
 
-        let mut tselect_index = 0;
+        let position = self.free_trigger_position(trigger)?;
 
-        // These steps follow the debug specification 0.13, section 5.1 Enumeration
-        loop {
-            log::debug!("Trying tselect={}", tselect_index);
-            if let Err(e) = self.write_csr(tselect, tselect_index) {
-                match e {
-                    RiscvError::AbstractCommand(AbstractCommandErrorKind::Exception) => break,
-                    other_error => return Err(other_error.into()),
-                }
-            }
+        // Setup the trigger
 
-            let readback = self.read_csr(tselect)?;
+        let mut instruction_breakpoint = Mcontrol(0);
+        instruction_breakpoint.set_action(1);
+        instruction_breakpoint.set_match(0);
 
-            if readback != tselect_index {
-                break;
-            }
+        instruction_breakpoint.set_m(true);
+        instruction_breakpoint.set_s(true);
+        instruction_breakpoint.set_u(true);
 
-            match self.read_csr(tinfo) {
-                Ok(tinfo_val) => {
-                    if tinfo_val & 0xffff == 1 {
-                        // Trigger doesn't exist, break the loop
-                        break;
-                    } else {
-                        log::info!(
-                            "Discovered trigger with index {} and type {}",
-                            tselect_index,
-                            tinfo_val & 0xffff
-                        );
-                    }
-                }
-                Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::Exception)) => {
-                    // An exception means we have to read tdata1 to discover the type
-                    let tdata_val = self.read_csr(tdata1)?;
+        instruction_breakpoint.set_execute(true);
 
-                    // TODO: Proper handle xlen
-                    let xlen = 32;
+        instruction_breakpoint.set_dmode(true);
 
-                    let trigger_type = tdata_val >> (xlen - 4);
+        self.write_trigger(trigger, instruction_breakpoint, addr)?;
 
-                    if trigger_type == 0 {
-                        break;
-                    }
+        self.state.trigger_use[position] = Some(TriggerUse::Breakpoint(addr));
 
-                    log::info!(
-                        "Discovered trigger with index {} and type {}",
-                        tselect_index,
-                        trigger_type,
-                    );
-                }
-                Err(other) => return Err(other.into()),
-            }
+        Ok(())
+    }
 
-            tselect_index += 1;
-        }
+    fn clear_breakpoint(&mut self, unit_index: usize) -> Result<(), crate::Error> {
+        let trigger = self.triggers()?.get(unit_index).copied();
+        let trigger = trigger.ok_or(RiscvError::NoTriggerAvailable)?;
 
-        Ok(tselect_index)
-    }
+        self.release_trigger(trigger)?;
 
-    fn enable_breakpoints(&mut self, _state: bool) -> Result<(), crate::Error> {
-        // seems not needed on RISCV
         Ok(())
     }
 
-    fn set_breakpoint(&mut self, bp_unit_index: usize, addr: u32) -> Result<(), crate::Error> {
-        // select requested trigger
-        let tselect = 0x7a0;
-        let tdata1 = 0x7a1;
-        let tdata2 = 0x7a2;
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, crate::Error> {
+        // Watchpoints and breakpoints share the same triggers.
+        Ok(self.triggers()?.len() as u32)
+    }
 
-        self.write_csr(tselect, bp_unit_index as u32)?;
+    fn set_watchpoint(
+        &mut self,
+        unit_index: usize,
+        address: u32,
+        mask: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), crate::Error> {
+        let trigger = self.watchpoint_trigger(unit_index)?;
+        let position = self.free_trigger_position(trigger)?;
 
-        // verify the trigger has the correct type
+        let mut data_trigger = Mcontrol(0);
+        data_trigger.set_action(1);
+        data_trigger.set_dmode(true);
 
-        let tdata_value = Mcontrol(self.read_csr(tdata1)?);
+        data_trigger.set_m(true);
+        data_trigger.set_s(true);
+        data_trigger.set_u(true);
 
-        // This should not happen
-        assert_eq!(
-            tdata_value.type_(),
-            2,
-            "Error: Incorrect trigger type for address breakpoint"
-        );
+        data_trigger.set_load(kind != WatchpointKind::Write);
+        data_trigger.set_store(kind != WatchpointKind::Read);
 
-        // Setup the trigger
+        // A range of 2^mask bytes is matched using the NAPOT encoding, where
+        // the lowest (mask - 1) bits of tdata2 are set to one.
+        let tdata2 = if mask == 0 {
+            data_trigger.set_match(0);
+            address
+        } else {
+            data_trigger.set_match(1);
+            address | ((1 << (mask - 1)) - 1)
+        };
 
-        let mut instruction_breakpoint = Mcontrol(0);
-        instruction_breakpoint.set_action(1);
-        instruction_breakpoint.set_match(0);
+        let readback = self.write_trigger(trigger, data_trigger, tdata2)?;
 
-        instruction_breakpoint.set_m(true);
-        instruction_breakpoint.set_s(true);
-        instruction_breakpoint.set_u(true);
+        // Load and store matching is optional, unsupported bits read as zero.
+        if readback.load() != data_trigger.load()
+            || readback.store() != data_trigger.store()
+            || readback.match_() != data_trigger.match_()
+        {
+            log::warn!(
+                "Trigger {} does not support {:?} watchpoints covering 2^{} bytes.",
+                trigger,
+                kind,
+                mask
+            );
 
-        instruction_breakpoint.set_execute(true);
+            self.release_trigger(trigger)?;
 
-        instruction_breakpoint.set_dmode(true);
+            return Err(RiscvError::UnsupportedTrigger(trigger).into());
+        }
 
-        self.write_csr(tdata1, instruction_breakpoint.0)?;
-        self.write_csr(tdata2, addr)?;
+        self.state.trigger_use[position] = Some(TriggerUse::Watchpoint);
 
         Ok(())
     }
 
-    fn clear_breakpoint(&mut self, unit_index: usize) -> Result<(), crate::Error> {
-        let tselect = 0x7a0;
-        let tdata1 = 0x7a1;
-        let tdata2 = 0x7a2;
+    fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), crate::Error> {
+        let trigger = self.watchpoint_trigger(unit_index)?;
 
-        self.write_csr(tselect, unit_index as u32)?;
-        self.write_csr(tdata1, 0)?;
-        self.write_csr(tdata2, 0)?;
+        self.release_trigger(trigger)?;
 
         Ok(())
     }
 
+    fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, crate::Error> {
+        let trigger = self.watchpoint_trigger(unit_index)?;
+
+        // Reading the halt reason already cleared the hit bit.
+        if self.state.hit_trigger == Some(trigger) {
+            self.state.hit_trigger = None;
+            return Ok(true);
+        }
+
+        self.write_csr(TSELECT, trigger)?;
+        let mut tdata1 = Mcontrol(self.read_csr(TDATA1)?);
+        let hit = tdata1.hit();
+
+        if hit {
+            tdata1.set_hit(false);
+            self.write_csr(TDATA1, tdata1.0)?;
+        }
+
+        Ok(hit)
+    }
+
     fn registers(&self) -> &'static RegisterFile {
         &RISCV_REGISTERS
     }
@@ -512,7 +828,7 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
                 // An ebreak instruction was hit
                 1 => HaltReason::Breakpoint,
                 // Trigger module caused halt
-                2 => HaltReason::Breakpoint,
+                2 => self.trigger_halt_reason()?,
                 // Debugger requested a halt
                 3 => HaltReason::Request,
                 // Core halted after single step
@@ -746,6 +1062,7 @@ data_register! { pub Progbuf14, 0x2E, "progbuf14" }
 data_register! { pub Progbuf15, 0x2F, "progbuf15" }
 
 bitfield! {
+    #[derive(Copy, Clone)]
     struct Mcontrol(u32);
     impl Debug;
 
@@ -16,4 +16,23 @@ pub struct Chip {
     pub memory_map: Cow<'static, [MemoryRegion]>,
 
     pub flash_algorithms: Cow<'static, [Cow<'static, str>]>,
+    /// The cores of the chip, if it has more than one.
+    /// Otherwise the core of the chip family on access port 0 is used.
+    #[serde(default)]
+    pub cores: Cow<'static, [CoreDescription]>,
+}
+
+/// This describes a single core of a chip and how it is accessed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoreDescription {
+    /// The name of the core type.
+    /// E.g. `M0` or `M4`.
+    pub core_type: Cow<'static, str>,
+    /// The number of the access port the core is connected to.
+    #[serde(default)]
+    pub ap: u8,
+    /// The base address of the debug registers, if they are not at the
+    /// default location of the architecture.
+    #[serde(default)]
+    pub debug_base: Option<u32>,
 }
@@ -7,7 +7,7 @@ mod memory;
 pub mod registry;
 mod target;
 
-pub use chip::Chip;
+pub use chip::{Chip, CoreDescription};
 pub use chip_family::ChipFamily;
 pub use chip_info::ChipInfo;
 pub use flash_algorithm::{FlashAlgorithm, RawFlashAlgorithm};
@@ -16,4 +16,4 @@ pub use memory::{
     FlashRegion, MemoryRange, MemoryRegion, PageInfo, RamRegion, SectorDescription, SectorInfo,
 };
 pub use registry::RegistryError;
-pub use target::{Target, TargetParseError, TargetSelector};
+pub use target::{Target, TargetCore, TargetParseError, TargetSelector};
@@ -1,4 +1,4 @@
-use super::target::Target;
+use super::target::{Target, TargetCore};
 use crate::config::{Chip, ChipFamily, ChipInfo};
 use crate::core::CoreType;
 use lazy_static::lazy_static;
@@ -25,6 +25,8 @@ pub enum RegistryError {
     AlgorithmNotFound,
     #[error("The requested core '{0}' was not found.")]
     CoreNotFound(String),
+    #[error("The target '{0}' has no cores.")]
+    NoCores(String),
     #[error("No RAM description was found.")]
     RamMissing,
     #[error("No flash description was found.")]
@@ -52,6 +54,7 @@ const GENERIC_TARGETS: [ChipFamily; 5] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M0"),
@@ -64,6 +67,7 @@ const GENERIC_TARGETS: [ChipFamily; 5] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M4"),
@@ -76,6 +80,7 @@ const GENERIC_TARGETS: [ChipFamily; 5] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M3"),
@@ -88,6 +93,7 @@ const GENERIC_TARGETS: [ChipFamily; 5] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M33"),
@@ -100,6 +106,7 @@ const GENERIC_TARGETS: [ChipFamily; 5] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("riscv"),
@@ -201,13 +208,29 @@ impl Registry {
     }
 
     fn get_target(&self, family: &ChipFamily, chip: &Chip) -> Result<Target, RegistryError> {
-        // Try get the corresponding chip.
-        let core = if let Some(core) = CoreType::from_string(&family.core) {
-            core
+        // Try get the corresponding cores.
+        let core_type = |name: &Cow<'static, str>| {
+            CoreType::from_string(name)
+                .ok_or_else(|| RegistryError::CoreNotFound(name.clone().into_owned()))
+        };
+
+        let cores = if chip.cores.is_empty() {
+            vec![TargetCore {
+                core_type: core_type(&family.core)?,
+                ap: 0,
+                debug_base: None,
+            }]
         } else {
-            return Err(RegistryError::CoreNotFound(
-                family.core.clone().into_owned(),
-            ));
+            chip.cores
+                .iter()
+                .map(|core| {
+                    Ok(TargetCore {
+                        core_type: core_type(&core.core_type)?,
+                        ap: core.ap,
+                        debug_base: core.debug_base,
+                    })
+                })
+                .collect::<Result<_, RegistryError>>()?
         };
 
         // find relevant algorithms
@@ -218,7 +241,7 @@ impl Registry {
             .cloned()
             .collect();
 
-        Ok(Target::new(chip, chip_algorithms, core))
+        Target::new(chip, chip_algorithms, cores)
     }
 
     fn add_target_from_yaml(&mut self, path_to_yaml: &Path) -> Result<(), RegistryError> {
@@ -296,4 +319,38 @@ mod tests {
         let registry = Registry::from_builtin_families();
         assert!(registry.get_target_by_name("nrf51822_Xxaa").is_ok());
     }
+
+    #[test]
+    fn single_core_uses_family_core() {
+        let registry = Registry::from_builtin_families();
+        let target = registry.get_target_by_name("nrf51822_Xxaa").unwrap();
+
+        assert_eq!(target.cores.len(), 1);
+        assert_eq!(target.cores[0].core_type, CoreType::M0);
+        assert_eq!(target.cores[0].ap, 0);
+    }
+
+    #[test]
+    fn dual_core_chip() {
+        let registry = Registry::from_builtin_families();
+        let target = registry.get_target_by_name("STM32H745ZITx").unwrap();
+
+        let cores: Vec<_> = target
+            .cores
+            .iter()
+            .map(|core| (core.core_type, core.ap))
+            .collect();
+        assert_eq!(cores, vec![(CoreType::M7, 0), (CoreType::M4, 3)]);
+    }
+
+    #[test]
+    fn targets_without_cores_are_rejected() {
+        let registry = Registry::from_builtin_families();
+        let chip = &registry.families[0].variants[0];
+
+        assert!(matches!(
+            Target::new(chip, vec![], vec![]),
+            Err(RegistryError::NoCores(_))
+        ));
+    }
 }
@@ -1,7 +1,7 @@
 use super::chip::Chip;
 use super::flash_algorithm::RawFlashAlgorithm;
 use super::memory::MemoryRegion;
-use super::registry::TargetIdentifier;
+use super::registry::{RegistryError, TargetIdentifier};
 use crate::core::{Architecture, CoreType};
 
 /// This describes a complete target with a fixed chip model and variant.
@@ -11,8 +11,8 @@ pub struct Target {
     pub identifier: TargetIdentifier,
     /// The name of the flash algorithm.
     pub flash_algorithms: Vec<RawFlashAlgorithm>,
-    /// The core type.
-    pub core_type: CoreType,
+    /// The cores of the target. The first one is the main core, which is used for flashing.
+    pub cores: Vec<TargetCore>,
     /// The memory map of the target.
     pub memory_map: Vec<MemoryRegion>,
 }
@@ -24,33 +24,54 @@ impl std::fmt::Debug for Target {
             "Target {{
             identifier: {:?},
             flash_algorithms: {:?},
+            cores: {:?},
             memory_map: {:?},
         }}",
-            self.identifier, self.flash_algorithms, self.memory_map
+            self.identifier, self.flash_algorithms, self.cores, self.memory_map
         )
     }
 }
 
+/// A core of a target and how it is accessed.
+#[derive(Debug, Copy, Clone)]
+pub struct TargetCore {
+    /// The core type.
+    pub core_type: CoreType,
+    /// The number of the access port the core is connected to.
+    /// This is only used for ARM cores.
+    pub ap: u8,
+    /// The base address of the debug registers, if they are not at the
+    /// default location of the architecture.
+    pub debug_base: Option<u32>,
+}
+
 pub type TargetParseError = serde_yaml::Error;
 
 impl Target {
+    /// Creates the target for a chip.
+    ///
+    /// `cores` must not be empty, as every target has a main core.
     pub fn new(
         chip: &Chip,
         flash_algorithms: Vec<RawFlashAlgorithm>,
-        core_type: CoreType,
-    ) -> Target {
-        Target {
+        cores: Vec<TargetCore>,
+    ) -> Result<Target, RegistryError> {
+        if cores.is_empty() {
+            return Err(RegistryError::NoCores(chip.name.clone().into_owned()));
+        }
+
+        Ok(Target {
             identifier: TargetIdentifier {
                 chip_name: chip.name.clone().into_owned(),
             },
             flash_algorithms,
-            core_type,
+            cores,
             memory_map: chip.memory_map.clone().into_owned(),
-        }
+        })
     }
 
     pub fn architecture(&self) -> Architecture {
-        match &self.core_type {
+        match &self.cores[0].core_type {
             CoreType::M0 => Architecture::Arm,
             CoreType::M3 => Architecture::Arm,
             CoreType::M33 => Architecture::Arm,
@@ -2,15 +2,25 @@ pub(crate) mod communication_interface;
 
 pub use communication_interface::CommunicationInterface;
 
+use crate::config::{MemoryRegion, TargetCore};
 use crate::error;
+use crate::semihosting::{self, SemihostingRequest};
 use crate::{
     architecture::{
-        arm::{core::CortexState, memory::ADIMemoryInterface, ArmCommunicationInterface},
-        riscv::communication_interface::RiscvCommunicationInterface,
+        arm::{
+            assembly::BKPT, core::CortexState, memory::ADIMemoryInterface,
+            ArmCommunicationInterface,
+        },
+        riscv::{
+            assembly::{C_EBREAK, EBREAK},
+            communication_interface::{RiscvCommunicationInterface, RiscvError},
+            RiscvState,
+        },
     },
     Error, MemoryInterface,
 };
 use crate::{DebugProbeError, Memory};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 pub trait CoreRegister: Clone + From<u32> + Into<u32> + Sized + std::fmt::Debug {
     const ADDRESS: u32;
@@ -71,6 +81,7 @@ pub(crate) enum RegisterKind {
 
 #[derive(Debug)]
 pub struct RegisterFile {
+    /// The general purpose registers, in the order of their DWARF register numbers.
     pub(crate) platform_registers: &'static [RegisterDescription],
 
     pub(crate) program_counter: &'static RegisterDescription,
@@ -123,6 +134,13 @@ impl RegisterFile {
     pub fn get_platform_register(&self, index: usize) -> Option<&RegisterDescription> {
         self.platform_registers.get(index)
     }
+
+    /// Returns the DWARF register number of a register, if it is a platform register.
+    pub fn dwarf_register_number(&self, register: &RegisterDescription) -> Option<usize> {
+        self.platform_registers
+            .iter()
+            .position(|platform_register| platform_register.address.0 == register.address.0)
+    }
 }
 
 pub trait CoreInterface: MemoryInterface {
@@ -179,6 +197,23 @@ pub trait CoreInterface: MemoryInterface {
 
     fn clear_breakpoint(&mut self, unit_index: usize) -> Result<(), error::Error>;
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, error::Error>;
+
+    /// Configure watchpoint unit `unit_index` to halt the core on accesses of the given `kind`
+    /// to the 2<sup>`mask`</sup> bytes starting at `address`.
+    fn set_watchpoint(
+        &mut self,
+        unit_index: usize,
+        address: u32,
+        mask: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), error::Error>;
+
+    fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), error::Error>;
+
+    /// Check if watchpoint unit `unit_index` matched an access since this was last checked.
+    fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, error::Error>;
+
     fn registers(&self) -> &'static RegisterFile;
 
     fn hw_breakpoints_enabled(&self) -> bool;
@@ -221,7 +256,7 @@ impl<'probe> MemoryInterface for Core<'probe> {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum CoreType {
     M3,
     M4,
@@ -251,30 +286,42 @@ impl CoreType {
             SpecificCoreState::M33(_) => CoreType::M33,
             SpecificCoreState::M4(_) => CoreType::M4,
             SpecificCoreState::M7(_) => CoreType::M7,
-            SpecificCoreState::Riscv => CoreType::Riscv,
+            SpecificCoreState::Riscv(_) => CoreType::Riscv,
         }
     }
 }
 
 pub struct CoreState {
     breakpoints: Vec<Breakpoint>,
+    /// The software breakpoints in the memory of the core, which are shared with the
+    /// other cores using the same memory.
+    software_breakpoints: Arc<Mutex<Vec<SoftwareBreakpoint>>>,
+    watchpoints: Vec<Watchpoint>,
+    /// The unit of the watchpoint which halted the core, until the core is resumed.
+    matched_watchpoint: Option<usize>,
 }
 
 impl CoreState {
     fn new() -> Self {
         Self {
             breakpoints: vec![],
+            software_breakpoints: Arc::new(Mutex::new(vec![])),
+            watchpoints: vec![],
+            matched_watchpoint: None,
         }
     }
 }
 
+/// Base address of the System Control Space, which contains the debug registers of Cortex-M cores.
+const CORTEX_M_DEBUG_BASE: u32 = 0xE000_E000;
+
 pub(crate) enum SpecificCoreState {
     M3(CortexState),
     M4(CortexState),
     M33(CortexState),
     M0(CortexState),
     M7(CortexState),
-    Riscv,
+    Riscv(RiscvState),
 }
 
 impl SpecificCoreState {
@@ -285,7 +332,7 @@ impl SpecificCoreState {
             CoreType::M33 => SpecificCoreState::M33(CortexState::new()),
             CoreType::M4 => SpecificCoreState::M4(CortexState::new()),
             CoreType::M7 => SpecificCoreState::M7(CortexState::new()),
-            CoreType::Riscv => SpecificCoreState::Riscv,
+            CoreType::Riscv => SpecificCoreState::Riscv(RiscvState::new()),
         }
     }
 
@@ -293,9 +340,20 @@ impl SpecificCoreState {
         &'probe mut self,
         state: &'probe mut CoreState,
         interface: ArmCommunicationInterface<'probe>,
+        target_core: &TargetCore,
+        memory_map: &'probe [MemoryRegion],
     ) -> Result<Core<'probe>, Error> {
+        // All supported ARM cores are Cortex-M cores, which have their
+        // debug registers at a fixed location.
+        match target_core.debug_base {
+            Some(debug_base) if debug_base != CORTEX_M_DEBUG_BASE => {
+                return Err(Error::UnsupportedDebugBase(debug_base))
+            }
+            _ => (),
+        }
+
         let memory = Memory::new(
-            ADIMemoryInterface::<ArmCommunicationInterface>::new(interface, 0)
+            ADIMemoryInterface::<ArmCommunicationInterface>::new(interface, target_core.ap)
                 .map_err(Error::architecture_specific)?,
         );
 
@@ -304,14 +362,22 @@ impl SpecificCoreState {
             // Cortex-M3, M4 and M7 use the Armv7[E]-M architecture and are
             // identical for our purposes.
             SpecificCoreState::M3(s) | SpecificCoreState::M4(s) | SpecificCoreState::M7(s) => {
-                Core::new(crate::architecture::arm::m4::M4::new(memory, s)?, state)
-            }
-            SpecificCoreState::M33(s) => {
-                Core::new(crate::architecture::arm::m33::M33::new(memory, s)?, state)
-            }
-            SpecificCoreState::M0(s) => {
-                Core::new(crate::architecture::arm::m0::M0::new(memory, s)?, state)
+                Core::new(
+                    crate::architecture::arm::m4::M4::new(memory, s)?,
+                    state,
+                    memory_map,
+                )
             }
+            SpecificCoreState::M33(s) => Core::new(
+                crate::architecture::arm::m33::M33::new(memory, s)?,
+                state,
+                memory_map,
+            ),
+            SpecificCoreState::M0(s) => Core::new(
+                crate::architecture::arm::m0::M0::new(memory, s)?,
+                state,
+                memory_map,
+            ),
             _ => {
                 return Err(Error::UnableToOpenProbe(
                     "Core architecture and Probe mismatch.",
@@ -321,14 +387,26 @@ impl SpecificCoreState {
     }
 
     pub(crate) fn attach_riscv<'probe>(
-        &self,
+        &'probe mut self,
         state: &'probe mut CoreState,
         interface: RiscvCommunicationInterface<'probe>,
+        target_core: &TargetCore,
+        memory_map: &'probe [MemoryRegion],
     ) -> Result<Core<'probe>, Error> {
-        Ok(match self {
-            SpecificCoreState::Riscv => {
-                Core::new(crate::architecture::riscv::Riscv32::new(interface), state)
+        // Only a single debug module at the start of the DMI address space is supported.
+        match target_core.debug_base {
+            Some(debug_base) if debug_base != 0 => {
+                return Err(Error::UnsupportedDebugBase(debug_base))
             }
+            _ => (),
+        }
+
+        Ok(match self {
+            SpecificCoreState::Riscv(s) => Core::new(
+                crate::architecture::riscv::Riscv32::new(interface, s)?,
+                state,
+                memory_map,
+            ),
             _ => {
                 return Err(Error::UnableToOpenProbe(
                     "Core architecture and Probe mismatch.",
@@ -341,13 +419,21 @@ impl SpecificCoreState {
 pub struct Core<'probe> {
     inner: Box<dyn CoreInterface + 'probe>,
     state: &'probe mut CoreState,
+    memory_map: &'probe [MemoryRegion],
 }
 
 impl<'probe> Core<'probe> {
-    pub fn new(core: impl CoreInterface + 'probe, state: &'probe mut CoreState) -> Core<'probe> {
+    /// Create a new core. The `memory_map` is used to decide where
+    /// software breakpoints can be placed.
+    pub fn new(
+        core: impl CoreInterface + 'probe,
+        state: &'probe mut CoreState,
+        memory_map: &'probe [MemoryRegion],
+    ) -> Core<'probe> {
         Self {
             inner: Box::new(core),
             state,
+            memory_map,
         }
     }
 
@@ -355,6 +441,15 @@ impl<'probe> Core<'probe> {
         CoreState::new()
     }
 
+    /// Create the state of a core which shares its memory with the core of `state`,
+    /// so that a software breakpoint placed by either core is known to both.
+    pub fn create_state_sharing_memory(state: &CoreState) -> CoreState {
+        CoreState {
+            software_breakpoints: state.software_breakpoints.clone(),
+            ..CoreState::new()
+        }
+    }
+
     /// Wait until the core is halted. If the core does not halt on its own,
     /// a [`DebugProbeError::Timeout`] error will be returned.
     ///
@@ -379,7 +474,12 @@ impl<'probe> Core<'probe> {
         self.inner.halt()
     }
 
+    /// Resume the core. If it is halted on a software breakpoint,
+    /// the replaced instruction is executed first.
     pub fn run(&mut self) -> Result<(), error::Error> {
+        self.state.matched_watchpoint = None;
+        self.step_over_software_breakpoint()?;
+
         self.inner.run()
     }
 
@@ -388,6 +488,7 @@ impl<'probe> Core<'probe> {
     ///
     /// [`reset_and_halt`]: trait.Core.html#tymethod.reset_and_halt
     pub fn reset(&mut self) -> Result<(), error::Error> {
+        self.state.matched_watchpoint = None;
         self.inner.reset()
     }
 
@@ -396,16 +497,34 @@ impl<'probe> Core<'probe> {
     ///
     /// [`reset`]: trait.Core.html#tymethod.reset
     pub fn reset_and_halt(&mut self) -> Result<CoreInformation, error::Error> {
+        self.state.matched_watchpoint = None;
         self.inner.reset_and_halt()
     }
 
     /// Steps one instruction and then enters halted state again.
     pub fn step(&mut self) -> Result<CoreInformation, error::Error> {
-        self.inner.step()
+        self.state.matched_watchpoint = None;
+
+        match self.step_over_software_breakpoint()? {
+            Some(info) => Ok(info),
+            None => self.inner.step(),
+        }
     }
 
+    /// Returns the current status of the core.
+    ///
+    /// A breakpoint is reported as `HaltReason::Semihosting` if the core is halted at a
+    /// semihosting request.
     pub fn status(&mut self) -> Result<CoreStatus, error::Error> {
-        self.inner.status()
+        let status = self.inner.status()?;
+
+        if status == CoreStatus::Halted(HaltReason::Breakpoint) {
+            if let Some(request) = semihosting::check_request(self)? {
+                return Ok(CoreStatus::Halted(HaltReason::Semihosting(request)));
+            }
+        }
+
+        Ok(status)
     }
 
     pub fn read_core_reg(
@@ -460,7 +579,7 @@ impl<'probe> Core<'probe> {
             self.enable_breakpoints(true)?;
         }
 
-        let bp_unit = self.find_free_breakpoint_unit();
+        let bp_unit = find_free_unit(self.state.breakpoints.iter().map(|bp| bp.register_hw));
 
         log::debug!("Using comparator {} of breakpoint unit", bp_unit);
         // actually set the breakpoint
@@ -494,31 +613,327 @@ impl<'probe> Core<'probe> {
         }
     }
 
-    pub fn architecture(&self) -> Architecture {
-        self.inner.architecture()
+    /// Set a breakpoint
+    ///
+    /// A hardware breakpoint is used while breakpoint units are available. Once they
+    /// are exhausted, a software breakpoint is placed if `address` is located in RAM.
+    /// On RISC-V, the triggers used by watchpoints are not available for breakpoints.
+    pub fn set_breakpoint(&mut self, address: u32) -> Result<(), error::Error> {
+        if self.has_breakpoint(address) {
+            log::debug!("Breakpoint at {:#08x} is already set.", address);
+            return Ok(());
+        }
+
+        let num_hw_breakpoints = match self.get_available_breakpoint_units() {
+            Ok(num) => num as usize,
+            Err(e) => {
+                log::warn!("Unable to determine number of HW breakpoints: {}", e);
+                0
+            }
+        };
+
+        if self.state.breakpoints.len() < num_hw_breakpoints {
+            match self.set_hw_breakpoint(address) {
+                Err(error::Error::ArchitectureSpecific(e))
+                    if matches!(e.downcast_ref(), Some(RiscvError::NoTriggerAvailable)) =>
+                {
+                    log::debug!("The trigger is used by a watchpoint, falling back to a software breakpoint.");
+                }
+                result => return result,
+            }
+        } else {
+            log::debug!("No HW breakpoint available, falling back to a software breakpoint.");
+        }
+
+        self.set_sw_breakpoint(address)
     }
 
-    fn find_free_breakpoint_unit(&self) -> usize {
-        let mut used_bp: Vec<_> = self
+    /// Clear the breakpoint at `address`, regardless of how it was set.
+    pub fn clear_breakpoint(&mut self, address: u32) -> Result<(), error::Error> {
+        if self.has_sw_breakpoint(address) {
+            self.clear_sw_breakpoint(address)
+        } else {
+            self.clear_hw_breakpoint(address)
+        }
+    }
+
+    /// Clear all hardware and software breakpoints, restoring the original instructions.
+    pub fn clear_all_breakpoints(&mut self) -> Result<(), error::Error> {
+        let addresses: Vec<_> = self
             .state
             .breakpoints
             .iter()
-            .map(|bp| bp.register_hw)
+            .map(|bp| bp.address)
+            .chain(self.software_breakpoints().iter().map(|bp| bp.address))
             .collect();
-        used_bp.sort();
 
-        let mut free_bp = 0;
+        for address in addresses {
+            self.clear_breakpoint(address)?;
+        }
+
+        Ok(())
+    }
 
-        for bp in used_bp {
-            if bp == free_bp {
-                free_bp += 1;
-            } else {
-                return free_bp;
+    /// Check if a hardware or software breakpoint is set at `address`.
+    pub fn has_breakpoint(&self, address: u32) -> bool {
+        self.has_hw_breakpoint(address) || self.has_sw_breakpoint(address)
+    }
+
+    /// Check if a hardware breakpoint is set at `address`.
+    pub fn has_hw_breakpoint(&self, address: u32) -> bool {
+        self.state
+            .breakpoints
+            .iter()
+            .any(|bp| bp.address == address)
+    }
+
+    /// Check if a software breakpoint is set at `address`, by this core or
+    /// by another core sharing its memory.
+    pub fn has_sw_breakpoint(&self, address: u32) -> bool {
+        self.software_breakpoints()
+            .iter()
+            .any(|bp| bp.address == address)
+    }
+
+    fn software_breakpoints(&self) -> MutexGuard<'_, Vec<SoftwareBreakpoint>> {
+        self.state
+            .software_breakpoints
+            .lock()
+            .expect("The software breakpoints of another core were left inconsistent")
+    }
+
+    /// Set a software breakpoint
+    ///
+    /// The instruction at `address` is replaced by a breakpoint instruction,
+    /// so this only works for code located in RAM.
+    pub fn set_sw_breakpoint(&mut self, address: u32) -> Result<(), error::Error> {
+        log::debug!("Trying to set SW breakpoint at address {:#08x}", address);
+
+        // The instruction in memory is already the breakpoint instruction, if another
+        // core sharing the memory placed it.
+        if self.has_sw_breakpoint(address) {
+            log::debug!("SW breakpoint at {:#08x} is already set.", address);
+            return Ok(());
+        }
+
+        // The first half word is enough to determine the instruction length.
+        let mut original = [0u8; 4];
+        self.inner.read_8(address, &mut original[..2])?;
+
+        let instruction = software_breakpoint_instruction(self.architecture(), &original);
+
+        if !self.is_ram(address, instruction.len() as u32) {
+            log::warn!(
+                "Unable to set SW breakpoint at {:#08x}, the address is not located in RAM.",
+                address
+            );
+
+            return Err(error::Error::Probe(DebugProbeError::Unknown));
+        }
+
+        let original = &mut original[..instruction.len()];
+        self.inner.read_8(address, original)?;
+
+        self.inner.write_8(address, instruction)?;
+
+        self.software_breakpoints().push(SoftwareBreakpoint {
+            address,
+            original: original.to_vec(),
+            instruction,
+        });
+
+        Ok(())
+    }
+
+    /// Clear a software breakpoint and restore the original instruction.
+    pub fn clear_sw_breakpoint(&mut self, address: u32) -> Result<(), error::Error> {
+        let bp = self
+            .software_breakpoints()
+            .iter()
+            .find(|bp| bp.address == address)
+            .cloned();
+
+        match bp {
+            Some(bp) => {
+                self.inner.write_8(bp.address, &bp.original)?;
+
+                // We only remove the breakpoint if we have actually managed to clear it.
+                self.software_breakpoints()
+                    .retain(|bp| bp.address != address);
+                Ok(())
+            }
+            None => Err(error::Error::Probe(DebugProbeError::Unknown)),
+        }
+    }
+
+    /// If the core is halted on a software breakpoint, step the original instruction
+    /// and put the breakpoint back afterwards.
+    ///
+    /// Returns `None` if the core is not halted on a software breakpoint.
+    fn step_over_software_breakpoint(&mut self) -> Result<Option<CoreInformation>, error::Error> {
+        if self.software_breakpoints().is_empty() || !self.inner.core_halted()? {
+            return Ok(None);
+        }
+
+        let pc = self
+            .inner
+            .read_core_reg(self.registers().program_counter().into())?;
+
+        let bp = match self
+            .software_breakpoints()
+            .iter()
+            .find(|bp| bp.address == pc)
+            .cloned()
+        {
+            Some(bp) => bp,
+            None => return Ok(None),
+        };
+
+        log::debug!("Stepping over SW breakpoint at {:#08x}", pc);
+
+        self.inner.write_8(bp.address, &bp.original)?;
+
+        let step_result = self.inner.step();
+
+        // Put the breakpoint back, even if the step failed.
+        self.inner.write_8(bp.address, bp.instruction)?;
+
+        step_result.map(Some)
+    }
+
+    fn is_ram(&self, address: u32, len: u32) -> bool {
+        self.memory_map.iter().any(|region| match region {
+            MemoryRegion::Ram(ram) => {
+                ram.range.start <= address
+                    && u64::from(address) + u64::from(len) <= u64::from(ram.range.end)
+            }
+            _ => false,
+        })
+    }
+
+    pub fn get_available_watchpoint_units(&mut self) -> Result<u32, error::Error> {
+        self.inner.get_available_watchpoint_units()
+    }
+
+    /// Set a hardware watchpoint
+    ///
+    /// The core halts when the 2<sup>`mask`</sup> bytes starting at `address`
+    /// are accessed as specified by `kind`. `address` has to be aligned to the size
+    /// of the watched region. The amount of hardware watchpoints is chip specific,
+    /// and can be queried using the `get_available_watchpoint_units` function.
+    pub fn set_hw_watchpoint(
+        &mut self,
+        address: u32,
+        mask: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), error::Error> {
+        log::debug!(
+            "Trying to set HW watchpoint at address {:#08x} (mask {}, {:?})",
+            address,
+            mask,
+            kind
+        );
+
+        if mask >= 32 || address & ((1 << mask) - 1) != 0 {
+            log::warn!(
+                "Watchpoint address {:#08x} is not aligned to the watched size of 2^{} bytes.",
+                address,
+                mask
+            );
+
+            return Err(error::Error::Probe(DebugProbeError::Unknown));
+        }
+
+        let num_hw_watchpoints = self.get_available_watchpoint_units()? as usize;
+
+        log::debug!("{} HW watchpoints are supported.", num_hw_watchpoints);
+
+        if num_hw_watchpoints <= self.state.watchpoints.len() {
+            log::warn!("Maximum number of watchpoints ({}) reached, unable to set additional HW watchpoint.", num_hw_watchpoints);
+
+            return Err(error::Error::Probe(DebugProbeError::Unknown));
+        }
+
+        let wp_unit = find_free_unit(self.state.watchpoints.iter().map(|wp| wp.register_hw));
+
+        log::debug!("Using comparator {} of watchpoint unit", wp_unit);
+        self.inner.set_watchpoint(wp_unit, address, mask, kind)?;
+
+        self.state.watchpoints.push(Watchpoint {
+            address,
+            mask,
+            kind,
+            register_hw: wp_unit,
+        });
+
+        Ok(())
+    }
+
+    pub fn clear_hw_watchpoint(&mut self, address: u32) -> Result<(), error::Error> {
+        let wp_position = self
+            .state
+            .watchpoints
+            .iter()
+            .position(|wp| wp.address == address);
+
+        match wp_position {
+            Some(wp_position) => {
+                let wp = &self.state.watchpoints[wp_position];
+                self.inner.clear_watchpoint(wp.register_hw)?;
+
+                // We only remove the watchpoint if we have actually managed to clear it.
+                self.state.watchpoints.swap_remove(wp_position);
+                Ok(())
+            }
+            None => Err(error::Error::Probe(DebugProbeError::Unknown)),
+        }
+    }
+
+    /// Returns the watchpoints which are currently set.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.state.watchpoints
+    }
+
+    /// Returns the watchpoint which halted the core, if the core reports which one matched.
+    pub fn matched_watchpoint(&mut self) -> Result<Option<&Watchpoint>, error::Error> {
+        if self.state.matched_watchpoint.is_none() {
+            for unit in self.state.watchpoints.iter().map(|wp| wp.register_hw) {
+                if self.inner.watchpoint_matched(unit)? {
+                    self.state.matched_watchpoint = Some(unit);
+                    break;
+                }
             }
         }
 
-        free_bp
+        let watchpoints = &self.state.watchpoints;
+
+        Ok(self
+            .state
+            .matched_watchpoint
+            .and_then(|unit| watchpoints.iter().find(|wp| wp.register_hw == unit)))
+    }
+
+    pub fn architecture(&self) -> Architecture {
+        self.inner.architecture()
+    }
+}
+
+/// Returns the lowest unit index which is not contained in `used`.
+fn find_free_unit(used: impl Iterator<Item = usize>) -> usize {
+    let mut used: Vec<_> = used.collect();
+    used.sort();
+
+    let mut free = 0;
+
+    for unit in used {
+        if unit == free {
+            free += 1;
+        } else {
+            return free;
+        }
     }
+
+    free
 }
 
 pub struct CoreList<'probe>(&'probe [CoreType]);
@@ -551,6 +966,60 @@ pub struct Breakpoint {
     register_hw: usize,
 }
 
+/// A breakpoint which replaced an instruction in RAM.
+#[derive(Clone)]
+struct SoftwareBreakpoint {
+    address: u32,
+    original: Vec<u8>,
+    instruction: &'static [u8],
+}
+
+/// Returns the breakpoint instruction to replace the instruction starting with `original`.
+fn software_breakpoint_instruction(architecture: Architecture, original: &[u8]) -> &'static [u8] {
+    const BKPT_BYTES: [u8; 2] = BKPT.to_le_bytes();
+    const EBREAK_BYTES: [u8; 4] = EBREAK.to_le_bytes();
+    const C_EBREAK_BYTES: [u8; 2] = C_EBREAK.to_le_bytes();
+
+    match architecture {
+        Architecture::Arm => &BKPT_BYTES,
+        // Compressed instructions are identified by the lowest two bits not being set.
+        Architecture::Riscv if original[0] & 0b11 != 0b11 => &C_EBREAK_BYTES,
+        Architecture::Riscv => &EBREAK_BYTES,
+    }
+}
+
+/// The kind of memory access which triggers a watchpoint.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WatchpointKind {
+    Read,
+    Write,
+    /// Either a read or a write.
+    Access,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    address: u32,
+    mask: u32,
+    kind: WatchpointKind,
+    register_hw: usize,
+}
+
+impl Watchpoint {
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// The watched region spans 2<sup>`mask`</sup> bytes.
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    pub fn kind(&self) -> WatchpointKind {
+        self.kind
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Architecture {
     Arm,
@@ -584,6 +1053,9 @@ pub enum HaltReason {
     Exception,
     /// Core halted due to a data watchpoint
     Watchpoint,
+    /// Core halted at a semihosting request, which has to be
+    /// serviced before the core is resumed.
+    Semihosting(SemihostingRequest),
     /// Core halted after single step
     Step,
     /// Core halted because of a debugger request
@@ -594,3 +1066,396 @@ pub enum HaltReason {
     /// example when the core is already halted when we connect.
     Unknown,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RamRegion;
+    use std::{cell::RefCell, rc::Rc};
+
+    const RAM_START: u32 = 0x2000_0000;
+    const RESET_PC: u32 = RAM_START + 0x10;
+
+    static PC: RegisterDescription = RegisterDescription {
+        name: "PC",
+        kind: RegisterKind::PC,
+        address: CoreRegisterAddress(15),
+    };
+
+    static MOCK_REGISTER_FILE: RegisterFile = RegisterFile {
+        platform_registers: &[],
+        program_counter: &PC,
+        stack_pointer: &PC,
+        return_address: &PC,
+        argument_registers: &[],
+        result_registers: &[],
+    };
+
+    #[derive(Default)]
+    struct MockState {
+        /// The RAM, which can be shared with a second core.
+        ram: Rc<RefCell<Vec<u8>>>,
+        pc: u32,
+        halted: bool,
+        hw_breakpoints: Vec<Option<u32>>,
+        watchpoints: Vec<Option<u32>>,
+        /// The watchpoint unit which matched, cleared when it is checked.
+        matched_watchpoint: Option<usize>,
+        /// Watchpoints use the breakpoint units from the end, like the triggers of RISC-V cores.
+        shared_units: bool,
+        /// The instruction found at the PC for each single step.
+        stepped_instructions: Vec<Vec<u8>>,
+    }
+
+    /// A halted Cortex-M core, only the first half of its memory is RAM.
+    struct MockCore(Rc<RefCell<MockState>>);
+
+    impl MockCore {
+        fn new(hw_breakpoints: usize) -> (Self, Rc<RefCell<MockState>>) {
+            let ram = (0..0x200).map(|i| i as u8).collect();
+            Self::with_ram(hw_breakpoints, Rc::new(RefCell::new(ram)))
+        }
+
+        /// A second core of the same chip, which shares the RAM of the core of `state`.
+        fn sharing_ram(state: &Rc<RefCell<MockState>>) -> (Self, Rc<RefCell<MockState>>) {
+            let state = state.borrow();
+            Self::with_ram(state.hw_breakpoints.len(), state.ram.clone())
+        }
+
+        fn with_ram(
+            hw_breakpoints: usize,
+            ram: Rc<RefCell<Vec<u8>>>,
+        ) -> (Self, Rc<RefCell<MockState>>) {
+            let state = Rc::new(RefCell::new(MockState {
+                ram,
+                pc: RESET_PC,
+                halted: true,
+                hw_breakpoints: vec![None; hw_breakpoints],
+                watchpoints: vec![None; 2],
+                ..Default::default()
+            }));
+
+            (MockCore(state.clone()), state)
+        }
+    }
+
+    impl MemoryInterface for MockCore {
+        fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+            let mut bytes = [0; 4];
+            self.read_8(address, &mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+        fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
+            let mut byte = [0];
+            self.read_8(address, &mut byte)?;
+            Ok(byte[0])
+        }
+        fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+            for (word, address) in data.iter_mut().zip((address..).step_by(4)) {
+                *word = self.read_word_32(address)?;
+            }
+            Ok(())
+        }
+        fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+            let offset = (address - RAM_START) as usize;
+            data.copy_from_slice(&self.0.borrow().ram.borrow()[offset..offset + data.len()]);
+            Ok(())
+        }
+        fn write_word_32(&mut self, address: u32, data: u32) -> Result<(), Error> {
+            self.write_8(address, &data.to_le_bytes())
+        }
+        fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), Error> {
+            self.write_8(address, &[data])
+        }
+        fn write_32(&mut self, address: u32, data: &[u32]) -> Result<(), Error> {
+            for (&word, address) in data.iter().zip((address..).step_by(4)) {
+                self.write_word_32(address, word)?;
+            }
+            Ok(())
+        }
+        fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+            let offset = (address - RAM_START) as usize;
+            self.0.borrow().ram.borrow_mut()[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    impl CoreInterface for MockCore {
+        fn wait_for_core_halted(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+        fn core_halted(&mut self) -> Result<bool, Error> {
+            Ok(self.0.borrow().halted)
+        }
+        fn status(&mut self) -> Result<CoreStatus, Error> {
+            if self.0.borrow().halted {
+                Ok(CoreStatus::Halted(HaltReason::Request))
+            } else {
+                Ok(CoreStatus::Running)
+            }
+        }
+        fn halt(&mut self) -> Result<CoreInformation, Error> {
+            let mut state = self.0.borrow_mut();
+            state.halted = true;
+            Ok(CoreInformation { pc: state.pc })
+        }
+        fn run(&mut self) -> Result<(), Error> {
+            self.0.borrow_mut().halted = false;
+            Ok(())
+        }
+        fn reset(&mut self) -> Result<(), Error> {
+            let mut state = self.0.borrow_mut();
+            state.pc = RESET_PC;
+            state.halted = false;
+            Ok(())
+        }
+        fn reset_and_halt(&mut self) -> Result<CoreInformation, Error> {
+            self.reset()?;
+            self.halt()
+        }
+        fn step(&mut self) -> Result<CoreInformation, Error> {
+            let mut state = self.0.borrow_mut();
+            let offset = (state.pc - RAM_START) as usize;
+            let instruction = state.ram.borrow()[offset..offset + 2].to_vec();
+            state.stepped_instructions.push(instruction);
+            state.pc += 2;
+            Ok(CoreInformation { pc: state.pc })
+        }
+        fn read_core_reg(&mut self, address: CoreRegisterAddress) -> Result<u32, Error> {
+            assert_eq!(address.0, PC.address.0);
+            Ok(self.0.borrow().pc)
+        }
+        fn write_core_reg(
+            &mut self,
+            address: CoreRegisterAddress,
+            value: u32,
+        ) -> Result<(), Error> {
+            assert_eq!(address.0, PC.address.0);
+            self.0.borrow_mut().pc = value;
+            Ok(())
+        }
+        fn get_available_breakpoint_units(&mut self) -> Result<u32, Error> {
+            Ok(self.0.borrow().hw_breakpoints.len() as u32)
+        }
+        fn enable_breakpoints(&mut self, _state: bool) -> Result<(), Error> {
+            Ok(())
+        }
+        fn set_breakpoint(&mut self, bp_unit_index: usize, addr: u32) -> Result<(), Error> {
+            let mut state = self.0.borrow_mut();
+            let watchpoint_unit = state.hw_breakpoints.len() - 1 - bp_unit_index;
+
+            if state.shared_units && state.watchpoints[watchpoint_unit].is_some() {
+                return Err(RiscvError::NoTriggerAvailable.into());
+            }
+
+            state.hw_breakpoints[bp_unit_index] = Some(addr);
+            Ok(())
+        }
+        fn clear_breakpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+            self.0.borrow_mut().hw_breakpoints[unit_index] = None;
+            Ok(())
+        }
+        fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+            Ok(self.0.borrow().watchpoints.len() as u32)
+        }
+        fn set_watchpoint(
+            &mut self,
+            unit_index: usize,
+            address: u32,
+            _mask: u32,
+            _kind: WatchpointKind,
+        ) -> Result<(), Error> {
+            self.0.borrow_mut().watchpoints[unit_index] = Some(address);
+            Ok(())
+        }
+        fn clear_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+            self.0.borrow_mut().watchpoints[unit_index] = None;
+            Ok(())
+        }
+        fn watchpoint_matched(&mut self, unit_index: usize) -> Result<bool, Error> {
+            let mut state = self.0.borrow_mut();
+            if state.matched_watchpoint == Some(unit_index) {
+                state.matched_watchpoint = None;
+                return Ok(true);
+            }
+            Ok(false)
+        }
+        fn registers(&self) -> &'static RegisterFile {
+            &MOCK_REGISTER_FILE
+        }
+        fn hw_breakpoints_enabled(&self) -> bool {
+            true
+        }
+        fn architecture(&self) -> Architecture {
+            Architecture::Arm
+        }
+    }
+
+    fn memory_map() -> Vec<MemoryRegion> {
+        vec![MemoryRegion::Ram(RamRegion {
+            range: RAM_START..RAM_START + 0x100,
+            is_boot_memory: false,
+        })]
+    }
+
+    /// Runs `test` with a core of a `MockCore` with `hw_breakpoints` breakpoint units.
+    fn with_core(hw_breakpoints: usize, test: impl FnOnce(&mut Core, Rc<RefCell<MockState>>)) {
+        let (mock, mock_state) = MockCore::new(hw_breakpoints);
+        let mut state = Core::create_state();
+        let memory_map = memory_map();
+
+        test(&mut Core::new(mock, &mut state, &memory_map), mock_state);
+    }
+
+    #[test]
+    fn matched_watchpoint_is_kept_until_the_core_resumes() {
+        with_core(0, |core, mock_state| {
+            core.set_hw_watchpoint(RAM_START + 0x10, 2, WatchpointKind::Write)
+                .unwrap();
+            core.set_hw_watchpoint(RAM_START + 0x20, 2, WatchpointKind::Read)
+                .unwrap();
+            mock_state.borrow_mut().matched_watchpoint = Some(1);
+
+            for _ in 0..2 {
+                let watchpoint = core.matched_watchpoint().unwrap().unwrap();
+                assert_eq!(watchpoint.address(), RAM_START + 0x20);
+            }
+
+            core.run().unwrap();
+            assert!(core.matched_watchpoint().unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn free_unit_fills_gaps() {
+        assert_eq!(find_free_unit(vec![].into_iter()), 0);
+        assert_eq!(find_free_unit(vec![1, 0, 2].into_iter()), 3);
+        assert_eq!(find_free_unit(vec![3, 0, 1].into_iter()), 2);
+    }
+
+    #[test]
+    fn breakpoints_fall_back_to_software() {
+        with_core(1, |core, mock_state| {
+            core.set_breakpoint(RAM_START + 0x20).unwrap();
+            assert_eq!(
+                mock_state.borrow().hw_breakpoints,
+                vec![Some(RAM_START + 0x20)]
+            );
+
+            core.set_breakpoint(RAM_START + 0x40).unwrap();
+            assert_eq!(&mock_state.borrow().ram.borrow()[0x40..0x42], &[0x00, 0xbe]);
+            assert!(core.has_breakpoint(RAM_START + 0x40));
+
+            core.clear_breakpoint(RAM_START + 0x40).unwrap();
+            assert_eq!(&mock_state.borrow().ram.borrow()[0x40..0x42], &[0x40, 0x41]);
+            assert!(!core.has_breakpoint(RAM_START + 0x40));
+
+            core.clear_all_breakpoints().unwrap();
+            assert_eq!(mock_state.borrow().hw_breakpoints, vec![None]);
+        });
+    }
+
+    #[test]
+    fn breakpoints_fall_back_to_software_on_units_used_by_watchpoints() {
+        with_core(2, |core, mock_state| {
+            mock_state.borrow_mut().shared_units = true;
+            core.set_hw_watchpoint(RAM_START + 0x80, 2, WatchpointKind::Write)
+                .unwrap();
+
+            core.set_breakpoint(RAM_START + 0x20).unwrap();
+            core.set_breakpoint(RAM_START + 0x40).unwrap();
+
+            assert_eq!(
+                mock_state.borrow().hw_breakpoints,
+                vec![Some(RAM_START + 0x20), None]
+            );
+            assert_eq!(&mock_state.borrow().ram.borrow()[0x40..0x42], &[0x00, 0xbe]);
+            assert!(core.has_breakpoint(RAM_START + 0x40));
+        });
+    }
+
+    #[test]
+    fn software_breakpoints_need_ram() {
+        with_core(0, |core, _| {
+            assert!(core.set_breakpoint(RAM_START + 0xff).is_err());
+            assert!(core.set_breakpoint(RAM_START + 0x180).is_err());
+            assert!(!core.has_breakpoint(RAM_START + 0x180));
+        });
+    }
+
+    #[test]
+    fn run_steps_over_software_breakpoint() {
+        with_core(0, |core, mock_state| {
+            core.set_breakpoint(RAM_START + 0x10).unwrap();
+            core.run().unwrap();
+
+            let mock_state = mock_state.borrow();
+            assert_eq!(mock_state.stepped_instructions, vec![vec![0x10, 0x11]]);
+            assert_eq!(&mock_state.ram.borrow()[0x10..0x12], &[0x00, 0xbe]);
+            assert!(!mock_state.halted);
+        });
+    }
+
+    #[test]
+    fn step_executes_replaced_instruction() {
+        with_core(0, |core, mock_state| {
+            core.set_breakpoint(RAM_START + 0x10).unwrap();
+            assert_eq!(core.step().unwrap().pc, RAM_START + 0x12);
+            assert_eq!(core.step().unwrap().pc, RAM_START + 0x14);
+
+            assert_eq!(
+                mock_state.borrow().stepped_instructions,
+                vec![vec![0x10, 0x11], vec![0x12, 0x13]]
+            );
+        });
+    }
+
+    #[test]
+    fn software_breakpoints_are_shared_by_cores_with_the_same_ram() {
+        let (first_mock, first_mock_state) = MockCore::new(0);
+        let (second_mock, second_mock_state) = MockCore::sharing_ram(&first_mock_state);
+        let memory_map = memory_map();
+
+        let mut first_state = Core::create_state();
+        let mut second_state = Core::create_state_sharing_memory(&first_state);
+        let mut first = Core::new(first_mock, &mut first_state, &memory_map);
+        let mut second = Core::new(second_mock, &mut second_state, &memory_map);
+
+        first.set_breakpoint(RAM_START + 0x10).unwrap();
+        second.set_breakpoint(RAM_START + 0x10).unwrap();
+        assert!(second.has_breakpoint(RAM_START + 0x10));
+
+        // The second core executes the original instruction, not the breakpoint of the first core.
+        assert_eq!(second.step().unwrap().pc, RAM_START + 0x12);
+        assert_eq!(
+            second_mock_state.borrow().stepped_instructions,
+            vec![vec![0x10, 0x11]]
+        );
+        assert_eq!(
+            &first_mock_state.borrow().ram.borrow()[0x10..0x12],
+            &[0x00, 0xbe]
+        );
+
+        second.clear_breakpoint(RAM_START + 0x10).unwrap();
+        assert_eq!(
+            &first_mock_state.borrow().ram.borrow()[0x10..0x12],
+            &[0x10, 0x11]
+        );
+        assert!(!first.has_breakpoint(RAM_START + 0x10));
+    }
+
+    #[test]
+    fn riscv_breakpoint_instruction_matches_length() {
+        // `addi` is a regular 32 bit instruction
+        assert_eq!(
+            software_breakpoint_instruction(Architecture::Riscv, &[0x13, 0x05]),
+            &[0x73, 0x00, 0x10, 0x00]
+        );
+
+        // `c.li` is a compressed 16 bit instruction
+        assert_eq!(
+            software_breakpoint_instruction(Architecture::Riscv, &[0x01, 0x45]),
+            &[0x02, 0x90]
+        );
+    }
+}
@@ -3,15 +3,23 @@
 //! The `debug` module contains various debug functionality, which can be
 //! used to implement a debugger based on `probe-rs`.
 
+mod stepping;
 mod typ;
 mod variable;
 
-use crate::{core::Core, MemoryInterface};
-use typ::Type;
-use variable::Variable;
+use crate::architecture::arm::m0::PSP;
+use crate::{
+    core::{Architecture, Core, RegisterFile},
+    MemoryInterface,
+};
+pub use stepping::SteppingMode;
+pub use typ::{Encoding, Member, Type, TypeKind, Variant};
+pub use variable::{Value, Variable};
 
 use std::{
-    borrow, io,
+    borrow,
+    collections::HashMap,
+    io,
     path::{Path, PathBuf},
     rc::Rc,
     str::{from_utf8, Utf8Error},
@@ -22,6 +30,12 @@ use log::{debug, error, info};
 use object::read::{Object, ObjectSection};
 use thiserror::Error;
 
+/// Types nested deeper than this are not decoded.
+const MAX_TYPE_DEPTH: usize = 16;
+
+/// Variables larger than this are only read partially.
+const MAX_VALUE_SIZE: u64 = 4096;
+
 #[derive(Debug, Error)]
 pub enum DebugError {
     #[error("IO Error while accessing debug data: {0}")]
@@ -34,6 +48,12 @@ pub enum DebugError {
     NonUtf8(#[from] Utf8Error),
     #[error("Error using the probe: {0}")]
     Probe(#[from] crate::Error),
+    #[error("Unable to evaluate DWARF expression: {0}")]
+    UnsupportedExpression(String),
+    #[error("Unable to determine the return address of the current function")]
+    UnknownReturnAddress,
+    #[error("Stepping was aborted after {0} instructions")]
+    SteppingLimit(usize),
 }
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ColumnType {
@@ -60,6 +80,13 @@ pub struct StackFrame {
     pub variables: Vec<Variable>,
 }
 
+impl StackFrame {
+    /// The address of the instruction which is executed in this frame.
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+}
+
 impl std::fmt::Display for StackFrame {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         writeln!(f, "{}: {}", self.id, self.function_name)?;
@@ -88,32 +115,84 @@ impl std::fmt::Display for StackFrame {
         for variable in &self.variables {
             writeln!(
                 f,
-                "\t\t{}: {}:{} = 0x{:08x}",
-                variable.name, variable.file, variable.line, variable.value
+                "\t\t{}: {} = {}",
+                variable.name, variable.typ.name, variable.value
             )?;
         }
         write!(f, "")
     }
 }
 
+/// The register values of a stack frame, indexed by their DWARF register number.
 #[derive(Debug, Clone)]
-struct Registers([Option<u32>; 16]);
+struct Registers {
+    values: Vec<Option<u32>>,
+    register_file: &'static RegisterFile,
+    architecture: Architecture,
+}
 
 impl Registers {
     pub fn from_core(core: &mut Core) -> Self {
-        let mut registers = Registers([None; 16]);
-        for i in 0..16 {
-            registers[i as usize] = Some(core.read_core_reg(i).unwrap());
+        let register_file = core.registers();
+
+        let values = register_file
+            .registers()
+            .map(|register| match core.read_core_reg(register) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    info!("Failed to read register {}: {}", register.name(), e);
+                    None
+                }
+            })
+            .collect();
+
+        Registers {
+            values,
+            register_file,
+            architecture: core.architecture(),
         }
-        registers
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn get(&self, register: usize) -> Option<u32> {
+        self.values.get(register).copied().flatten()
+    }
+
+    pub fn stack_pointer(&self) -> Option<usize> {
+        self.register_file
+            .dwarf_register_number(self.register_file.stack_pointer())
+    }
+
+    pub fn return_address(&self) -> Option<usize> {
+        self.register_file
+            .dwarf_register_number(self.register_file.return_address())
     }
 
     pub fn get_call_frame_address(&self) -> Option<u32> {
-        self.0[13]
+        self.stack_pointer().and_then(|sp| self.get(sp))
     }
 
     pub fn set_call_frame_address(&mut self, value: Option<u32>) {
-        self.0[13] = value;
+        if let Some(sp) = self.stack_pointer() {
+            self.values[sp] = value;
+        }
+    }
+
+    pub fn get_return_address(&self) -> Option<u32> {
+        self.return_address().and_then(|ra| self.get(ra))
+    }
+
+    /// Returns true if the register is preserved across function calls.
+    pub fn is_callee_saved(&self, register: usize) -> bool {
+        match self.architecture {
+            // r4-r11
+            Architecture::Arm => (4..12).contains(&register),
+            // s0-s11
+            Architecture::Riscv => matches!(register, 8 | 9 | 18..=27),
+        }
     }
 }
 
@@ -121,27 +200,13 @@ impl std::ops::Index<usize> for Registers {
     type Output = Option<u32>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.0[index]
+        &self.values[index]
     }
 }
 
 impl std::ops::IndexMut<usize> for Registers {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.0[index]
-    }
-}
-
-impl std::ops::Index<std::ops::Range<usize>> for Registers {
-    type Output = [Option<u32>];
-
-    fn index(&self, index: std::ops::Range<usize>) -> &Self::Output {
-        &self.0[index]
-    }
-}
-
-impl std::ops::IndexMut<std::ops::Range<usize>> for Registers {
-    fn index_mut(&mut self, index: std::ops::Range<usize>) -> &mut Self::Output {
-        &mut self.0[index]
+        &mut self.values[index]
     }
 }
 
@@ -160,6 +225,9 @@ pub struct StackFrameIterator<'debuginfo, 'probe, 'core> {
     frame_count: u64,
     pc: Option<u64>,
     registers: Registers,
+    /// True if the current frame was interrupted by a halt or an exception, instead
+    /// of calling another function.
+    interrupted: bool,
 }
 
 impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
@@ -177,26 +245,68 @@ impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
             frame_count: 0,
             pc: Some(pc),
             registers,
+            interrupted: true,
         }
     }
 }
 
-impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'probe, 'core> {
-    type Item = StackFrame;
+impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
+    /// Restores the registers saved by the hardware on exception entry, and returns
+    /// the program counter of the interrupted code.
+    fn unwind_exception_frame(&mut self, exc_return: u32) -> Option<u64> {
+        // The frame is on the stack which was active before the exception.
+        let frame_address = if exc_return & EXC_RETURN_PROCESS_STACK != 0 {
+            match self.core.read_core_reg(PSP) {
+                Ok(psp) => psp,
+                Err(e) => {
+                    info!("Failed to read the process stack pointer: {}", e);
+                    return None;
+                }
+            }
+        } else {
+            self.registers.get_call_frame_address()?
+        };
 
-    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = [0u32; 8];
+        if let Err(e) = self.core.read_32(frame_address, &mut frame) {
+            info!(
+                "Failed to read exception frame at {:#010x}: {}",
+                frame_address, e
+            );
+            return None;
+        }
+
+        let [r0, r1, r2, r3, r12, lr, pc, xpsr] = frame;
+
+        debug!(
+            "Exception frame at {:#010x}, pc={:#010x}",
+            frame_address, pc
+        );
+
+        self.registers[0] = Some(r0);
+        self.registers[1] = Some(r1);
+        self.registers[2] = Some(r2);
+        self.registers[3] = Some(r3);
+        self.registers[12] = Some(r12);
+        self.registers[14] = Some(lr);
+        self.registers[15] = Some(pc);
+        self.registers
+            .set_call_frame_address(Some(frame_address + exception_frame_size(exc_return, xpsr)));
+
+        // The stacked program counter is the address of the interrupted instruction.
+        Some(u64::from(pc))
+    }
+}
+
+impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
+    /// Unwinds the frame at `pc`, so that the iterator points to its caller.
+    ///
+    /// Returns the registers and the CFA of the frame at `pc`.
+    fn unwind(&mut self, pc: u64) -> Option<(Registers, Option<u32>)> {
         use gimli::UnwindSection;
         let mut ctx = gimli::UninitializedUnwindContext::new();
         let bases = gimli::BaseAddresses::default();
 
-        let pc = match self.pc {
-            Some(pc) => pc,
-            None => {
-                debug!("Unable to determine next frame, program counter is zero");
-                return None;
-            }
-        };
-
         let unwind_info = self.debug_info.frame_section.unwind_info_for_address(
             &bases,
             &mut ctx,
@@ -216,51 +326,143 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
         };
 
         let current_cfa = match unwind_info.cfa() {
-            gimli::CfaRule::RegisterAndOffset { register, offset } => {
-                let reg_val = self.registers[register.0 as usize];
-
-                Some((i64::from(reg_val.unwrap()) + offset) as u32)
+            gimli::CfaRule::RegisterAndOffset { register, offset } => self
+                .registers
+                .get(register.0 as usize)
+                .map(|reg_val| (i64::from(reg_val) + offset) as u32),
+            gimli::CfaRule::Expression(expression) => {
+                match evaluate_unwind_expression(
+                    self.core,
+                    &self.registers,
+                    expression.clone(),
+                    None,
+                ) {
+                    Ok(cfa) => Some(cfa),
+                    Err(e) => {
+                        info!("Failed to evaluate CFA expression: {}", e);
+                        None
+                    }
+                }
             }
-            gimli::CfaRule::Expression(_) => unimplemented!(),
         };
 
         if let Some(ref cfa) = &current_cfa {
             debug!("Current CFA: {:#x}", cfa);
         }
 
+        // The rules refer to the register values of the current frame,
+        // so they must not be affected by the registers already unwound.
+        let current_registers = self.registers.clone();
+
+        let stack_pointer = self.registers.stack_pointer();
+        let return_address = self.registers.return_address();
+
         // generate previous registers
-        for i in 0..16 {
-            if i == 13 {
+        for i in 0..self.registers.len() {
+            if Some(i) == stack_pointer {
                 continue;
             }
 
             use gimli::read::RegisterRule::*;
 
-            self.registers[i] = match unwind_info.register(gimli::Register(i as u16)) {
-                Undefined => None,
-                SameValue => self.registers[i],
-                Offset(o) => {
-                    let addr = i64::from(current_cfa.unwrap()) + o;
-                    let mut buff = [0u8; 4];
-                    self.core.read_8(addr as u32, &mut buff).unwrap();
-
-                    let val = u32::from_le_bytes(buff);
+            let rule = unwind_info.register(gimli::Register(i as u16));
 
-                    debug!("reg[{: >}]={:#08x}", i, val);
-
-                    Some(val)
+            self.registers[i] = match rule {
+                // Registers without a rule keep their value if they are preserved across calls,
+                // and the link register still holds the return address if the frame was interrupted.
+                Undefined if current_registers.is_callee_saved(i) => current_registers[i],
+                Undefined if Some(i) == return_address && self.interrupted => current_registers[i],
+                Undefined => None,
+                SameValue => current_registers[i],
+                Offset(o) => current_cfa.and_then(|cfa| {
+                    let addr = (i64::from(cfa) + o) as u32;
+
+                    read_stacked_register(self.core, addr)
+                }),
+                ValOffset(o) => current_cfa.map(|cfa| (i64::from(cfa) + o) as u32),
+                Register(register) => current_registers.get(register.0 as usize),
+                Expression(expression) => match evaluate_unwind_expression(
+                    self.core,
+                    &current_registers,
+                    expression,
+                    current_cfa,
+                ) {
+                    Ok(addr) => read_stacked_register(self.core, addr),
+                    Err(e) => {
+                        info!("Failed to evaluate rule for register {}: {}", i, e);
+                        None
+                    }
+                },
+                ValExpression(expression) => match evaluate_unwind_expression(
+                    self.core,
+                    &current_registers,
+                    expression,
+                    current_cfa,
+                ) {
+                    Ok(value) => Some(value),
+                    Err(e) => {
+                        info!("Failed to evaluate rule for register {}: {}", i, e);
+                        None
+                    }
+                },
+                Architectural => {
+                    info!("Architectural rule for register {} is not supported", i);
+                    None
                 }
-                _ => unimplemented!(),
+            };
+
+            if let Some(val) = self.registers[i] {
+                debug!("reg[{: >}]={:#08x}", i, val);
             }
         }
 
         self.registers.set_call_frame_address(current_cfa);
 
+        let architecture = self.registers.architecture;
+
+        self.pc = match self.registers.get_return_address() {
+            // The current function is an exception handler, so the registers of the
+            // interrupted code have been stacked by the hardware.
+            Some(lr) if architecture == Architecture::Arm && is_exc_return(lr) => {
+                self.interrupted = true;
+                self.unwind_exception_frame(lr)
+            }
+            // Next function is where our current return register is pointing to.
+            // On ARM, we just have to remove the lowest bit (indicator for Thumb mode).
+            Some(lr) if architecture == Architecture::Arm => {
+                self.interrupted = false;
+                Some(u64::from(lr & !1))
+            }
+            ra => {
+                self.interrupted = false;
+                ra.map(u64::from)
+            }
+        };
+
+        Some((current_registers, current_cfa))
+    }
+}
+
+impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'probe, 'core> {
+    type Item = StackFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pc = match self.pc {
+            Some(pc) => pc,
+            None => {
+                debug!("Unable to determine next frame, program counter is zero");
+                return None;
+            }
+        };
+
+        let (registers, cfa) = self.unwind(pc)?;
+
         let return_frame = match self.debug_info.get_stackframe_info(
             &mut self.core,
             pc,
             self.frame_count,
-            self.registers.clone(),
+            registers,
+            cfa,
         ) {
             Ok(frame) => Some(frame),
             Err(e) => {
@@ -271,14 +473,113 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
 
         self.frame_count += 1;
 
-        // Next function is where our current return register is pointing to.
-        // We just have to remove the lowest bit (indicator for Thumb mode).
-        self.pc = self.registers[14].map(|pc| u64::from(pc & !1));
-
         return_frame
     }
 }
 
+/// Bit of `EXC_RETURN` which is set if the exception frame is on the process stack.
+const EXC_RETURN_PROCESS_STACK: u32 = 1 << 2;
+
+/// Bit of `EXC_RETURN` which is cleared if the exception frame contains the FPU registers.
+const EXC_RETURN_STANDARD_FRAME: u32 = 1 << 4;
+
+/// Bit of the stacked xPSR which is set if the stack was realigned to 8 bytes on exception entry.
+const XPSR_STACK_ALIGNED: u32 = 1 << 9;
+
+/// Returns true if the link register contains an `EXC_RETURN` value,
+/// i.e. the current function is an exception handler.
+fn is_exc_return(lr: u32) -> bool {
+    lr & 0xff00_0000 == 0xff00_0000
+}
+
+/// Size of a hardware-stacked exception frame in bytes.
+fn exception_frame_size(exc_return: u32, stacked_xpsr: u32) -> u32 {
+    // r0-r3, r12, lr, pc and xPSR, extended by s0-s15, FPSCR and a reserved word.
+    let mut size = if exc_return & EXC_RETURN_STANDARD_FRAME != 0 {
+        0x20
+    } else {
+        0x68
+    };
+
+    if stacked_xpsr & XPSR_STACK_ALIGNED != 0 {
+        size += 4;
+    }
+
+    size
+}
+
+fn read_stacked_register(core: &mut Core<'_>, address: u32) -> Option<u32> {
+    match core.read_word_32(address) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            info!(
+                "Failed to read register from stack at {:#010x}: {}",
+                address, e
+            );
+            None
+        }
+    }
+}
+
+/// Evaluates a DWARF expression from the call frame information.
+///
+/// The result is the address computed by the expression.
+fn evaluate_unwind_expression(
+    core: &mut Core<'_>,
+    registers: &Registers,
+    expression: gimli::Expression<R>,
+    cfa: Option<u32>,
+) -> Result<u32, DebugError> {
+    let encoding = gimli::Encoding {
+        address_size: 4,
+        format: gimli::Format::Dwarf32,
+        version: 4,
+    };
+
+    let mut evaluation = expression.evaluation(encoding);
+
+    // Register rules are evaluated with the CFA pushed on the stack.
+    if let Some(cfa) = cfa {
+        evaluation.set_initial_value(u64::from(cfa));
+    }
+
+    let mut result = evaluation.evaluate()?;
+
+    loop {
+        use gimli::EvaluationResult::*;
+
+        result = match result {
+            Complete => break,
+            RequiresMemory { address, size, .. } => {
+                let mut buff = [0u8; 8];
+                let size = usize::from(size).min(buff.len());
+                core.read_8(address as u32, &mut buff[..size])?;
+
+                evaluation.resume_with_memory(gimli::Value::Generic(u64::from_le_bytes(buff)))?
+            }
+            RequiresRegister { register, .. } => {
+                let value = registers
+                    .get(register.0 as usize)
+                    .ok_or(gimli::Error::UnsupportedRegister(register.0.into()))?;
+
+                evaluation.resume_with_register(gimli::Value::Generic(u64::from(value)))?
+            }
+            other => return Err(DebugError::UnsupportedExpression(format!("{:?}", other))),
+        }
+    }
+
+    match evaluation.result().first() {
+        Some(gimli::Piece {
+            location: gimli::Location::Address { address },
+            ..
+        }) => Ok(*address as u32),
+        other => Err(DebugError::UnsupportedExpression(format!(
+            "result {:?}",
+            other
+        ))),
+    }
+}
+
 type R = gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>;
 type DwarfReader = gimli::read::EndianRcSlice<gimli::LittleEndian>;
 type FunctionDie<'abbrev, 'unit> = gimli::DebuggingInformationEntry<
@@ -299,6 +600,7 @@ type UnitIter =
 pub struct DebugInfo {
     dwarf: gimli::Dwarf<DwarfReader>,
     frame_section: gimli::DebugFrame<DwarfReader>,
+    symbols: HashMap<String, u64>,
 }
 
 impl DebugInfo {
@@ -341,13 +643,28 @@ impl DebugInfo {
 
         let frame_section = gimli::DebugFrame::load(load_section).unwrap();
 
+        let symbols = object
+            .symbols()
+            .filter_map(|(_, symbol)| {
+                symbol
+                    .name()
+                    .map(|name| (name.to_owned(), symbol.address()))
+            })
+            .collect();
+
         Ok(DebugInfo {
             //object,
             dwarf: dwarf_cow,
             frame_section,
+            symbols,
         })
     }
 
+    /// Returns the address of the symbol with the given name, if it is present in the symbol table.
+    pub fn get_symbol_address(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
     pub fn get_source_location(&self, address: u64) -> Option<SourceLocation> {
         let mut units = self.dwarf.units();
 
@@ -465,6 +782,7 @@ impl DebugInfo {
         address: u64,
         frame_count: u64,
         registers: Registers,
+        cfa: Option<u32>,
     ) -> Result<StackFrame, DebugError> {
         let mut units = self.get_units();
         let unknown_function = format!("<unknown_function_{}>", frame_count);
@@ -474,11 +792,11 @@ impl DebugInfo {
                     .get_function_name(&die_cursor_state.function_die)
                     .unwrap_or(unknown_function);
 
-                let variables = unit_info.get_variables(
-                    core,
-                    die_cursor_state,
-                    u64::from(registers.get_call_frame_address().unwrap()),
-                )?;
+                let frame_base =
+                    unit_info.get_frame_base(&die_cursor_state.function_die, &registers, cfa);
+
+                let variables =
+                    unit_info.get_variables(core, die_cursor_state, &registers, frame_base)?;
 
                 // dbg!(&variables);
 
@@ -707,11 +1025,58 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
         None
     }
 
+    /// Evaluates `DW_AT_frame_base` of a function, which its variables can be located relative
+    /// to. Without the attribute, the frame base is the CFA.
+    fn get_frame_base(
+        &self,
+        function_die: &FunctionDie,
+        registers: &Registers,
+        cfa: Option<u32>,
+    ) -> Option<u64> {
+        let expression = match function_die.attr_value(gimli::DW_AT_frame_base) {
+            Ok(Some(gimli::AttributeValue::Exprloc(expression))) => expression,
+            _ => return cfa.map(u64::from),
+        };
+
+        let mut evaluation = expression.evaluation(self.unit.encoding());
+        let mut result = evaluation.evaluate().ok()?;
+
+        loop {
+            use gimli::EvaluationResult::*;
+
+            result = match result {
+                Complete => break,
+                RequiresRegister { register, .. } => {
+                    let value = registers.get(register.0 as usize)?;
+                    evaluation
+                        .resume_with_register(gimli::Value::Generic(u64::from(value)))
+                        .ok()?
+                }
+                RequiresCallFrameCfa => evaluation
+                    .resume_with_call_frame_cfa(u64::from(cfa?))
+                    .ok()?,
+                x => {
+                    debug!("Unable to evaluate the frame base: {:?}", x);
+                    return None;
+                }
+            }
+        }
+
+        match evaluation.result().first()?.location {
+            gimli::Location::Register { register } => {
+                registers.get(register.0 as usize).map(u64::from)
+            }
+            gimli::Location::Address { address } => Some(address),
+            _ => None,
+        }
+    }
+
     fn expr_to_piece(
         &self,
         core: &mut Core<'_>,
         expression: gimli::Expression<R>,
-        frame_base: u64,
+        registers: &Registers,
+        frame_base: Option<u64>,
     ) -> Result<Vec<gimli::Piece<R, usize>>, DebugError> {
         let mut evaluation = expression.evaluation(self.unit.encoding());
 
@@ -730,14 +1095,11 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
                     match size {
                         1 => evaluation.resume_with_memory(gimli::Value::U8(buff[0]))?,
                         2 => {
-                            let val = (u16::from(buff[0]) << 8) | (u16::from(buff[1]) as u16);
+                            let val = u16::from_le_bytes([buff[0], buff[1]]);
                             evaluation.resume_with_memory(gimli::Value::U16(val))?
                         }
                         4 => {
-                            let val = (u32::from(buff[0]) << 24)
-                                | (u32::from(buff[1]) << 16)
-                                | (u32::from(buff[2]) << 8)
-                                | u32::from(buff[3]);
+                            let val = u32::from_le_bytes([buff[0], buff[1], buff[2], buff[3]]);
                             evaluation.resume_with_memory(gimli::Value::U32(val))?
                         }
                         x => {
@@ -749,12 +1111,21 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
                         }
                     }
                 }
-                RequiresFrameBase => evaluation.resume_with_frame_base(frame_base).unwrap(),
+                RequiresFrameBase => match frame_base {
+                    Some(frame_base) => evaluation.resume_with_frame_base(frame_base)?,
+                    None => {
+                        return Err(DebugError::UnsupportedExpression(
+                            "frame base is unknown".to_owned(),
+                        ))
+                    }
+                },
                 RequiresRegister {
                     register,
                     base_type,
                 } => {
-                    let raw_value = core.read_core_reg(register.0 as u16)?;
+                    let raw_value = registers
+                        .get(register.0 as usize)
+                        .ok_or(gimli::Error::UnsupportedRegister(register.0.into()))?;
 
                     if base_type != gimli::UnitOffset(0) {
                         unimplemented!(
@@ -778,7 +1149,8 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
         &self,
         core: &mut Core<'_>,
         die_cursor_state: &mut DieCursorState,
-        frame_base: u64,
+        registers: &Registers,
+        frame_base: Option<u64>,
     ) -> Result<Vec<Variable>, DebugError> {
         let mut variables = vec![];
 
@@ -791,9 +1163,10 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
                     name: String::new(),
                     file: String::new(),
                     line: u64::max_value(),
-                    value: 0,
                     ..Default::default()
                 };
+                let mut location = None;
+
                 let mut attrs = current.attrs();
                 while let Ok(Some(attr)) = attrs.next() {
                     match attr.name() {
@@ -811,91 +1184,450 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
                                 .unwrap_or_else(u64::max_value);
                         }
                         gimli::DW_AT_type => {
-                            variable.typ =
-                                extract_type(&self, attr.value()).unwrap_or_else(|| Type {
-                                    name: "<undefined>".to_string(),
-                                    named_children: None,
-                                    indexed_children: None,
+                            if let gimli::AttributeValue::UnitRef(offset) = attr.value() {
+                                variable.typ = self.extract_type(offset, 0).unwrap_or_else(|e| {
+                                    log::debug!("Unable to decode type of variable: {}", e);
+                                    Type::default()
                                 });
+                            }
                         }
                         gimli::DW_AT_location => {
-                            variable.value =
-                                extract_location(&self, core, frame_base, attr.value())?
-                                    .unwrap_or_else(u64::max_value);
+                            if let gimli::AttributeValue::Exprloc(expression) = attr.value() {
+                                location = Some(expression);
+                            }
                         }
                         _ => (),
                     }
                 }
+
+                // The location is only evaluated once the type is known, as it
+                // determines how many bytes have to be read.
+                if let Some(expression) = location {
+                    match self.expr_to_piece(core, expression, registers, frame_base) {
+                        Ok(pieces) => {
+                            if let Some(data) =
+                                read_pieces(core, registers, &pieces, variable.typ.size)
+                            {
+                                variable.value = Value::decode(&variable.typ, &data);
+                            }
+                        }
+                        Err(e) => {
+                            debug!("Unable to locate variable {}: {}", variable.name, e);
+                        }
+                    }
+                }
+
                 variables.push(variable);
             };
         }
 
         Ok(variables)
     }
-}
 
-fn extract_location(
-    unit_info: &UnitInfo,
-    core: &mut Core<'_>,
-    frame_base: u64,
-    attribute_value: gimli::AttributeValue<R>,
-) -> Result<Option<u64>, DebugError> {
-    match attribute_value {
-        gimli::AttributeValue::Exprloc(expression) => {
-            let piece = unit_info.expr_to_piece(core, expression, frame_base)?;
+    /// Decodes the type at the given offset, including all nested types.
+    fn extract_type(&self, offset: gimli::UnitOffset, depth: usize) -> Result<Type, DebugError> {
+        if depth > MAX_TYPE_DEPTH {
+            return Ok(Type::default());
+        }
+
+        let entry = self.unit.entry(offset)?;
+
+        let name = self.entry_name(&entry);
+        let size = entry
+            .attr_value(gimli::DW_AT_byte_size)?
+            .and_then(|size| size.udata_value());
+
+        let typ = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(encoding)) => {
+                        Encoding::from_dwarf(encoding)
+                    }
+                    _ => None,
+                };
+
+                Type {
+                    name: name.unwrap_or_else(|| "<unnamed>".to_owned()),
+                    size: size.unwrap_or(0),
+                    kind: encoding.map(TypeKind::Base).unwrap_or(TypeKind::Unknown),
+                }
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type => {
+                let mut members = vec![];
+                let mut variant_enum = None;
+
+                for child in self.children(offset)? {
+                    let child_entry = self.unit.entry(child)?;
+
+                    match child_entry.tag() {
+                        gimli::DW_TAG_member => members.push(self.extract_member(child, depth)?),
+                        gimli::DW_TAG_variant_part => {
+                            variant_enum = Some(self.extract_variant_part(child, depth)?)
+                        }
+                        _ => (),
+                    }
+                }
+
+                Type {
+                    name: name.unwrap_or_else(|| "<unnamed>".to_owned()),
+                    size: size.unwrap_or(0),
+                    kind: variant_enum.unwrap_or(TypeKind::Struct(members)),
+                }
+            }
+            gimli::DW_TAG_union_type => {
+                let mut members = vec![];
+
+                for child in self.children(offset)? {
+                    if self.unit.entry(child)?.tag() == gimli::DW_TAG_member {
+                        members.push(self.extract_member(child, depth)?);
+                    }
+                }
+
+                Type {
+                    name: name.unwrap_or_else(|| "<unnamed>".to_owned()),
+                    size: size.unwrap_or(0),
+                    kind: TypeKind::Union(members),
+                }
+            }
+            gimli::DW_TAG_enumeration_type => {
+                let mut enumerators = vec![];
+
+                for child in self.children(offset)? {
+                    let child_entry = self.unit.entry(child)?;
+
+                    if child_entry.tag() != gimli::DW_TAG_enumerator {
+                        continue;
+                    }
+
+                    let value = match child_entry.attr_value(gimli::DW_AT_const_value)? {
+                        Some(gimli::AttributeValue::Sdata(value)) => Some(value),
+                        Some(value) => value.udata_value().map(|value| value as i64),
+                        None => None,
+                    };
+
+                    if let (Some(name), Some(value)) = (self.entry_name(&child_entry), value) {
+                        enumerators.push((name, value));
+                    }
+                }
+
+                Type {
+                    name: name.unwrap_or_else(|| "<unnamed>".to_owned()),
+                    size: size.unwrap_or(0),
+                    kind: TypeKind::Enum(enumerators),
+                }
+            }
+            gimli::DW_TAG_array_type => {
+                let element = match referenced_type(&entry)? {
+                    Some(element) => self.extract_type(element, depth + 1)?,
+                    None => Type::default(),
+                };
+
+                let mut counts = vec![];
+
+                for child in self.children(offset)? {
+                    let child_entry = self.unit.entry(child)?;
+
+                    if child_entry.tag() != gimli::DW_TAG_subrange_type {
+                        continue;
+                    }
+
+                    let count = match child_entry.attr_value(gimli::DW_AT_count)? {
+                        Some(count) => count.udata_value(),
+                        None => {
+                            let lower_bound = child_entry
+                                .attr_value(gimli::DW_AT_lower_bound)?
+                                .and_then(|bound| bound.udata_value())
+                                .unwrap_or(0);
+
+                            child_entry
+                                .attr_value(gimli::DW_AT_upper_bound)?
+                                .and_then(|bound| bound.udata_value())
+                                .map(|upper_bound| upper_bound + 1 - lower_bound)
+                        }
+                    };
+
+                    counts.push(count.unwrap_or(0));
+                }
+
+                // Multi-dimensional arrays are arrays of arrays, the last dimension is the innermost one.
+                counts.iter().rev().fold(element, |element, &count| Type {
+                    name: format!("[{}; {}]", element.name, count),
+                    size: element.size * count,
+                    kind: TypeKind::Array {
+                        element: Box::new(element),
+                        count,
+                    },
+                })
+            }
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => {
+                let name = match name {
+                    Some(name) => name,
+                    None => format!("*{}", self.referenced_type_name(&entry, depth)?),
+                };
+
+                Type {
+                    name,
+                    size: size.unwrap_or_else(|| u64::from(self.unit.encoding().address_size)),
+                    kind: TypeKind::Pointer,
+                }
+            }
+            gimli::DW_TAG_typedef => match referenced_type(&entry)? {
+                Some(typ) => Type {
+                    name: name.unwrap_or_else(|| "<unnamed>".to_owned()),
+                    ..self.extract_type(typ, depth + 1)?
+                },
+                None => Type::default(),
+            },
+            gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type => match referenced_type(&entry)? {
+                Some(typ) => self.extract_type(typ, depth + 1)?,
+                None => Type::default(),
+            },
+            _ => Type {
+                name: name.unwrap_or_else(|| "<unknown>".to_owned()),
+                size: size.unwrap_or(0),
+                kind: TypeKind::Unknown,
+            },
+        };
+
+        Ok(typ)
+    }
+
+    fn extract_member(
+        &self,
+        offset: gimli::UnitOffset,
+        depth: usize,
+    ) -> Result<Member, DebugError> {
+        let entry = self.unit.entry(offset)?;
+
+        let name = self
+            .entry_name(&entry)
+            .unwrap_or_else(|| "<unnamed>".to_owned());
+
+        let member_offset = match entry.attr_value(gimli::DW_AT_data_member_location)? {
+            // Older compilers describe the offset as an expression, which is applied to the start of the parent.
+            Some(gimli::AttributeValue::Exprloc(expression)) => {
+                let mut evaluation = expression.evaluation(self.unit.encoding());
+                evaluation.set_initial_value(0);
+
+                match evaluation.evaluate()? {
+                    gimli::EvaluationResult::Complete => match evaluation.result().first() {
+                        Some(gimli::Piece {
+                            location: gimli::Location::Address { address },
+                            ..
+                        }) => *address,
+                        _ => 0,
+                    },
+                    _ => 0,
+                }
+            }
+            Some(value) => value.udata_value().unwrap_or(0),
+            None => 0,
+        };
+
+        // Bit fields are not supported, they are shown as unavailable.
+        let typ = if entry.attr_value(gimli::DW_AT_bit_size)?.is_some() {
+            Type::default()
+        } else {
+            match referenced_type(&entry)? {
+                Some(typ) => self.extract_type(typ, depth + 1)?,
+                None => Type::default(),
+            }
+        };
+
+        Ok(Member {
+            name,
+            offset: member_offset,
+            typ,
+        })
+    }
+
+    /// Decodes the variants of an enum with data, like a Rust `enum`.
+    fn extract_variant_part(
+        &self,
+        offset: gimli::UnitOffset,
+        depth: usize,
+    ) -> Result<TypeKind, DebugError> {
+        let entry = self.unit.entry(offset)?;
+
+        let discriminant_offset = match entry.attr_value(gimli::DW_AT_discr)? {
+            Some(gimli::AttributeValue::UnitRef(discriminant)) => Some(discriminant),
+            _ => None,
+        };
+
+        let mut discriminant = None;
+        let mut variants = vec![];
+
+        for child in self.children(offset)? {
+            let child_entry = self.unit.entry(child)?;
+
+            match child_entry.tag() {
+                gimli::DW_TAG_member if Some(child) == discriminant_offset => {
+                    discriminant = Some(Box::new(self.extract_member(child, depth)?));
+                }
+                gimli::DW_TAG_variant => {
+                    let discriminant_value =
+                        match child_entry.attr_value(gimli::DW_AT_discr_value)? {
+                            Some(gimli::AttributeValue::Sdata(value)) => Some(value as u64),
+                            Some(value) => value.udata_value(),
+                            None => None,
+                        };
 
-            let value = get_piece_value(core, &piece[0]);
-            Ok(value.map(u64::from))
+                    // Each variant contains a single member, which holds the data of the variant.
+                    for variant_child in self.children(child)? {
+                        if self.unit.entry(variant_child)?.tag() == gimli::DW_TAG_member {
+                            variants.push(Variant {
+                                discriminant: discriminant_value,
+                                member: self.extract_member(variant_child, depth)?,
+                            });
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(TypeKind::VariantEnum {
+            discriminant,
+            variants,
+        })
+    }
+
+    /// The name of the type an entry refers to, without decoding the type itself.
+    ///
+    /// This is used for pointers, as the pointee might refer back to the pointer.
+    fn referenced_type_name(
+        &self,
+        entry: &FunctionDie,
+        depth: usize,
+    ) -> Result<String, DebugError> {
+        let offset = match referenced_type(entry)? {
+            Some(offset) => offset,
+            None => return Ok("void".to_owned()),
+        };
+
+        let referenced = self.unit.entry(offset)?;
+
+        if let Some(name) = self.entry_name(&referenced) {
+            return Ok(name);
+        }
+
+        if depth > MAX_TYPE_DEPTH {
+            return Ok("<unnamed>".to_owned());
+        }
+
+        match referenced.tag() {
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => Ok(format!(
+                "*{}",
+                self.referenced_type_name(&referenced, depth + 1)?
+            )),
+            gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+                self.referenced_type_name(&referenced, depth + 1)
+            }
+            _ => Ok("<unnamed>".to_owned()),
         }
+    }
+
+    fn entry_name(&self, entry: &FunctionDie) -> Option<String> {
+        let name = entry.attr_value(gimli::DW_AT_name).ok()??;
+        let name = self.debug_info.dwarf.attr_string(&self.unit, name).ok()?;
+
+        Some(String::from_utf8_lossy(&name).into_owned())
+    }
+
+    /// Returns the offsets of the direct children of an entry.
+    fn children(&self, offset: gimli::UnitOffset) -> Result<Vec<gimli::UnitOffset>, DebugError> {
+        let mut tree = self.unit.entries_tree(Some(offset))?;
+        let mut children = tree.root()?.children();
+
+        let mut offsets = vec![];
+        while let Some(child) = children.next()? {
+            offsets.push(child.entry().offset());
+        }
+
+        Ok(offsets)
+    }
+}
+
+/// Returns the type an entry refers to through its `DW_AT_type` attribute.
+fn referenced_type(entry: &FunctionDie) -> Result<Option<gimli::UnitOffset>, DebugError> {
+    match entry.attr_value(gimli::DW_AT_type)? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => Ok(Some(offset)),
         _ => Ok(None),
     }
 }
 
-fn extract_type(unit_info: &UnitInfo, attribute_value: gimli::AttributeValue<R>) -> Option<Type> {
-    match attribute_value {
-        gimli::AttributeValue::UnitRef(unit_ref) => {
-            if let Ok(mut tree) = unit_info.unit.entries_tree(Some(unit_ref)) {
-                let node = tree.root().unwrap();
-
-                // Examine the entry attributes.
-                let entry = node.entry();
-                if let gimli::DW_TAG_structure_type = entry.tag() {
-                    let type_name = extract_name(
-                        &unit_info.debug_info,
-                        entry.attr(gimli::DW_AT_name).unwrap().unwrap().value(),
-                    );
-                    let mut named_children = std::collections::HashMap::new();
-
-                    let mut children = node.children();
-                    while let Ok(Some(child)) = children.next() {
-                        // Recursively process a child.
-                        let entry = child.entry();
-                        if let gimli::DW_TAG_member = entry.tag() {
-                            let member_name = extract_name(
-                                &unit_info.debug_info,
-                                entry.attr(gimli::DW_AT_name).unwrap().unwrap().value(),
-                            );
-                            named_children.insert(
-                                member_name?,
-                                extract_type(
-                                    unit_info,
-                                    (entry.attr(gimli::DW_AT_type).ok()?)?.value(),
-                                )?,
-                            );
-                        };
-                    }
+/// Reads the bytes of a value from the locations described by the pieces.
+///
+/// Returns `None` if the value is not available, e.g. because it was optimized out.
+fn read_pieces(
+    core: &mut Core<'_>,
+    registers: &Registers,
+    pieces: &[gimli::Piece<R>],
+    size: u64,
+) -> Option<Vec<u8>> {
+    use gimli::Location;
 
-                    return Some(Type {
-                        name: type_name.unwrap_or_else(|| "<unnamed type>".to_string()),
-                        named_children: Some(named_children),
-                        indexed_children: Some(vec![]),
-                    });
-                };
+    let size = size.min(MAX_VALUE_SIZE) as usize;
+    let mut data = Vec::with_capacity(size);
+
+    for piece in pieces {
+        let piece_size = match piece.size_in_bits {
+            Some(bits) if bits % 8 == 0 => (bits / 8) as usize,
+            // Pieces which are not a multiple of a byte are not supported.
+            Some(_) => return None,
+            None => size.saturating_sub(data.len()),
+        };
+
+        match &piece.location {
+            Location::Address { address } => {
+                let mut buffer = vec![0u8; piece_size];
+
+                if let Err(e) = core.read_8(*address as u32, &mut buffer) {
+                    log::debug!("Unable to read variable at {:#010x}: {}", address, e);
+                    return None;
+                }
+
+                data.extend_from_slice(&buffer);
             }
-            None
+            Location::Register { register } => {
+                let value = registers.get(register.0 as usize)?;
+
+                data.extend(
+                    value
+                        .to_le_bytes()
+                        .iter()
+                        .copied()
+                        .chain(std::iter::repeat(0))
+                        .take(piece_size),
+                );
+            }
+            Location::Value { value } => {
+                let value = value.to_u64(u64::MAX).ok()?;
+
+                data.extend(
+                    value
+                        .to_le_bytes()
+                        .iter()
+                        .copied()
+                        .chain(std::iter::repeat(0))
+                        .take(piece_size),
+                );
+            }
+            Location::Bytes { value } => {
+                data.extend_from_slice(&gimli::Reader::to_slice(value).ok()?)
+            }
+            _ => return None,
         }
-        _ => None,
     }
+
+    Some(data)
 }
 
 fn extract_file(
@@ -940,23 +1672,6 @@ fn extract_name(
     }
 }
 
-fn get_piece_value(core: &mut Core<'_>, p: &gimli::Piece<DwarfReader>) -> Option<u32> {
-    use gimli::Location;
-
-    match &p.location {
-        Location::Empty => None,
-        Location::Address { address } => Some(*address as u32),
-        Location::Value { value } => Some(value.to_u64(0xff_ff_ff_ff).unwrap() as u32),
-        Location::Register { register } => {
-            let val = core
-                .read_core_reg(register.0 as u16)
-                .expect("Failed to read register from target");
-            Some(val)
-        }
-        l => unimplemented!("Location {:?} not implemented", l),
-    }
-}
-
 pub(crate) fn _print_all_attributes(
     core: &mut Core<'_>,
     frame_base: Option<u32>,
@@ -1046,3 +1761,111 @@ pub(crate) fn _print_all_attributes(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        exception_frame_size, is_exc_return, DebugInfo, StackFrameIterator,
+        EXC_RETURN_PROCESS_STACK, PSP,
+    };
+    use crate::simulator::SimulatedCortexM;
+    use crate::{MemoryInterface, Probe};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /// The PC, LR and SP of the code which was interrupted by the exception.
+    const CALLER_PC: u32 = 0x44;
+    const CALLER_LR: u32 = 0x49;
+    const CALLER_SP: u32 = 0x2001_0000;
+
+    /// Debug information without any functions, as exception frames are unwound without it.
+    fn no_debug_info() -> DebugInfo {
+        let empty = gimli::EndianRcSlice::new(Rc::from(&[][..]), gimli::LittleEndian);
+
+        let load_section = |_| Ok::<_, gimli::Error>(empty.clone());
+
+        DebugInfo {
+            dwarf: gimli::Dwarf::load(load_section, load_section).unwrap(),
+            frame_section: gimli::DebugFrame::from(empty),
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Halts the core in an exception handler, with the registers of the caller stacked in a
+    /// frame of `frame_size` bytes like on exception entry, and unwinds the exception frame.
+    fn unwind_exception_frame(exc_return: u32, frame_size: u32) {
+        let chip = SimulatedCortexM::new();
+
+        // The stack at the end of the RAM, `B .` at the reset vector and `NOP; BKPT #3` in the
+        // handler at 0x60.
+        chip.write_memory(0x0, &[0x00, 0x00, 0x01, 0x20, 0x41, 0x00, 0x00, 0x00]);
+        chip.write_memory(0x40, &[0xfe, 0xe7]);
+        chip.write_memory(0x60, &[0x00, 0xbf, 0x03, 0xbe]);
+
+        let frame_address = CALLER_SP - frame_size;
+        let mut frame = vec![0u32; frame_size as usize / 4];
+        frame[..8].copy_from_slice(&[10, 11, 12, 13, 14, CALLER_LR, CALLER_PC, 0x0100_0000]);
+
+        let mut session = Probe::new(chip.clone())
+            .attach(SimulatedCortexM::target())
+            .unwrap();
+        let mut core = session.core(0).unwrap();
+
+        core.reset_and_halt().unwrap();
+        core.write_32(frame_address, &frame).unwrap();
+        if exc_return & EXC_RETURN_PROCESS_STACK != 0 {
+            core.write_core_reg(PSP, frame_address).unwrap();
+        } else {
+            core.write_core_reg(13.into(), frame_address).unwrap();
+        }
+        core.write_core_reg(14.into(), exc_return).unwrap();
+        core.write_core_reg(15.into(), 0x60).unwrap();
+
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(core.read_core_reg(15).unwrap(), 0x62);
+
+        let debug_info = no_debug_info();
+        let mut frames = StackFrameIterator::new(&debug_info, &mut core, 0x62);
+
+        assert_eq!(
+            frames.unwind_exception_frame(exc_return),
+            Some(u64::from(CALLER_PC))
+        );
+        assert_eq!(frames.registers[15], Some(CALLER_PC));
+        assert_eq!(frames.registers[14], Some(CALLER_LR));
+        assert_eq!(frames.registers[0], Some(10));
+        assert_eq!(frames.registers[12], Some(14));
+        assert_eq!(frames.registers.get_call_frame_address(), Some(CALLER_SP));
+    }
+
+    #[test]
+    fn basic_exception_frames_are_unwound() {
+        unwind_exception_frame(0xffff_fff9, 0x20);
+    }
+
+    #[test]
+    fn extended_exception_frames_are_unwound() {
+        // The frame with the FPU registers is on the process stack.
+        unwind_exception_frame(0xffff_ffed, 0x68);
+    }
+
+    #[test]
+    fn exc_return_values() {
+        assert!(is_exc_return(0xffff_fff9));
+        assert!(is_exc_return(0xffff_ffed));
+        assert!(!is_exc_return(0x0800_1235));
+        assert!(!is_exc_return(0x2000_0000));
+    }
+
+    #[test]
+    fn exception_frame_sizes() {
+        // Basic frame on the main stack.
+        assert_eq!(exception_frame_size(0xffff_fff9, 0x0100_0000), 0x20);
+        // Stack was realigned on exception entry.
+        assert_eq!(exception_frame_size(0xffff_fffd, 0x0100_0200), 0x24);
+        // Extended frame with the FPU registers.
+        assert_eq!(exception_frame_size(0xffff_ffed, 0x0100_0000), 0x68);
+        assert_eq!(exception_frame_size(0xffff_ffe9, 0x0100_0200), 0x6c);
+    }
+}
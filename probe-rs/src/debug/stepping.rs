@@ -0,0 +1,627 @@
+use super::{DebugError, DebugInfo, StackFrameIterator, UnitInfo, R};
+use crate::core::Core;
+
+use std::ops::Range;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Instructions which are stepped at most, before stepping is aborted.
+const MAX_STEPS: usize = 100_000;
+
+/// Time to wait for a called function to return, before the core is halted.
+const RETURN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between checks whether the core halted after a called function returned.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How far `DebugInfo::step` moves the core.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SteppingMode {
+    /// Step to the next line, stepping over called and inlined functions.
+    StepOver,
+    /// Step to the next line, stopping at the first line of a called or inlined function.
+    StepInto,
+    /// Run until the current function returns to its caller.
+    StepOut,
+}
+
+/// The frame of the function which is executed, identified by its CFA.
+#[derive(Debug)]
+struct Frame {
+    cfa: Option<u32>,
+    return_address: Option<u64>,
+}
+
+/// `frame` is a function called by `other`, as the stack grows downwards.
+fn is_called_by(frame: &Frame, other: &Frame) -> bool {
+    match (frame.cfa, other.cfa) {
+        (Some(cfa), Some(other_cfa)) => cfa < other_cfa,
+        _ => false,
+    }
+}
+
+/// A row of the line program.
+#[derive(Debug, Copy, Clone)]
+struct LineRow {
+    address: u64,
+    file: u64,
+    line: Option<u64>,
+    is_stmt: bool,
+    end_sequence: bool,
+}
+
+/// The instructions generated for one source line.
+#[derive(Debug, PartialEq)]
+struct LineRange {
+    range: Range<u64>,
+    line: Option<u64>,
+    /// The address is the start of a statement, i.e. a location where stepping stops.
+    is_statement: bool,
+}
+
+/// Finds the line containing `address` in the rows of a sequence.
+fn find_line_range(rows: &[LineRow], address: u64) -> Option<LineRange> {
+    let index = rows
+        .iter()
+        .rposition(|row| !row.end_sequence && row.address <= address)?;
+    let row = rows[index];
+
+    let same_line =
+        |other: &LineRow| !other.end_sequence && other.file == row.file && other.line == row.line;
+
+    let start = rows[..index]
+        .iter()
+        .rposition(|other| !same_line(other))
+        .map(|i| rows[i + 1].address)
+        .unwrap_or(rows[0].address);
+
+    let end = rows[index + 1..]
+        .iter()
+        .find(|other| !same_line(other))?
+        .address;
+
+    // Rows with line 0 belong to compiler generated code without a source line.
+    let is_statement = row.line.map(|line| line != 0).unwrap_or(false)
+        && rows
+            .iter()
+            .any(|other| !other.end_sequence && other.address == address && other.is_stmt);
+
+    Some(LineRange {
+        range: start..end,
+        line: row.line,
+        is_statement,
+    })
+}
+
+/// The code of a function, and of the functions inlined into it.
+#[derive(Debug)]
+struct Function {
+    ranges: Vec<Range<u64>>,
+    /// The first instruction, which is where a call enters the function.
+    entry: u64,
+    /// The ranges of each inlined function, which are nested for nested inlined functions.
+    inlined: Vec<Vec<Range<u64>>>,
+}
+
+fn ranges_contain(ranges: &[Range<u64>], address: u64) -> bool {
+    ranges.iter().any(|range| range.contains(&address))
+}
+
+/// The debug information used while stepping, which is read once per sequence of the
+/// line program and once per function, instead of for every instruction.
+struct StepCache<'debuginfo> {
+    debug_info: &'debuginfo DebugInfo,
+    sequences: Vec<Vec<LineRow>>,
+    functions: Vec<Function>,
+}
+
+impl<'debuginfo> StepCache<'debuginfo> {
+    fn new(debug_info: &'debuginfo DebugInfo) -> Self {
+        StepCache {
+            debug_info,
+            sequences: vec![],
+            functions: vec![],
+        }
+    }
+
+    /// Returns the instructions of the source line containing `address`.
+    fn line_range(&mut self, address: u64) -> Result<Option<LineRange>, DebugError> {
+        let contains = |rows: &Vec<LineRow>| match (rows.first(), rows.last()) {
+            (Some(first), Some(last)) => first.address <= address && address < last.address,
+            _ => false,
+        };
+
+        let index = match self.sequences.iter().position(contains) {
+            Some(index) => index,
+            None => match self.debug_info.line_sequence(address)? {
+                Some(rows) => {
+                    self.sequences.push(rows);
+                    self.sequences.len() - 1
+                }
+                None => return Ok(None),
+            },
+        };
+
+        Ok(find_line_range(&self.sequences[index], address))
+    }
+
+    fn function(&mut self, address: u64) -> Result<Option<&Function>, DebugError> {
+        let index = match self
+            .functions
+            .iter()
+            .position(|function| ranges_contain(&function.ranges, address))
+        {
+            Some(index) => index,
+            None => match self.debug_info.function(address)? {
+                Some(function) => {
+                    self.functions.push(function);
+                    self.functions.len() - 1
+                }
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(&self.functions[index]))
+    }
+
+    /// Returns the entry of the function containing `address`.
+    fn function_entry(&mut self, address: u64) -> Result<Option<u64>, DebugError> {
+        Ok(self.function(address)?.map(|function| function.entry))
+    }
+
+    /// Returns the number of nested inlined functions containing `address`.
+    fn inline_depth(&mut self, address: u64) -> Result<usize, DebugError> {
+        Ok(self
+            .function(address)?
+            .map(|function| {
+                function
+                    .inlined
+                    .iter()
+                    .filter(|ranges| ranges_contain(ranges, address))
+                    .count()
+            })
+            .unwrap_or(0))
+    }
+}
+
+/// The core is still in the frame of the function with the entry `function`.
+///
+/// The frame only changes when the core leaves the function, or enters it again
+/// through a recursive call.
+fn in_same_frame(function: Option<u64>, pc_function: Option<u64>, pc: u64) -> bool {
+    pc_function.is_some() && pc_function == function && pc_function != Some(pc)
+}
+
+impl DebugInfo {
+    /// Steps the halted core through the source code, and returns the program
+    /// counter at which it stopped.
+    ///
+    /// Functions are identified by the CFA of their frame, so recursive calls are
+    /// stepped over correctly. If the core halts for another reason while a function
+    /// is executed, e.g. at a breakpoint, stepping stops there.
+    pub fn step(&self, core: &mut Core<'_>, mode: SteppingMode) -> Result<u64, DebugError> {
+        let pc = u64::from(core.read_core_reg(core.registers().program_counter())?);
+        let mut cache = StepCache::new(self);
+
+        match mode {
+            SteppingMode::StepOver => self.step_line(core, &mut cache, pc, false),
+            SteppingMode::StepInto => self.step_line(core, &mut cache, pc, true),
+            SteppingMode::StepOut => self.step_out(core, &mut cache, pc),
+        }
+    }
+
+    fn step_line(
+        &self,
+        core: &mut Core<'_>,
+        cache: &mut StepCache<'_>,
+        mut pc: u64,
+        into: bool,
+    ) -> Result<u64, DebugError> {
+        let mut range = match cache.line_range(pc)? {
+            Some(line) => line.range,
+            // Without line information, only a single instruction is stepped.
+            None => return Ok(u64::from(core.step()?.pc)),
+        };
+
+        let mut frame = self.frame(core, pc);
+        let mut function = cache.function_entry(pc)?;
+        let mut inline_depth = cache.inline_depth(pc)?;
+
+        for _ in 0..MAX_STEPS {
+            pc = u64::from(core.step()?.pc);
+
+            if range.contains(&pc) {
+                continue;
+            }
+
+            let pc_function = cache.function_entry(pc)?;
+
+            if !in_same_frame(function, pc_function, pc) {
+                let current = self.frame(core, pc);
+
+                if is_called_by(&current, &frame) {
+                    if into && cache.line_range(pc)?.is_some() {
+                        return Ok(pc);
+                    }
+
+                    // Functions without line information are always stepped over.
+                    pc = match (current.return_address, current.cfa) {
+                        (Some(return_address), Some(cfa)) => {
+                            let stopped_at = self.run_to_return(core, return_address, cfa)?;
+
+                            if stopped_at != return_address {
+                                return Ok(stopped_at);
+                            }
+
+                            stopped_at
+                        }
+                        _ => return Ok(pc),
+                    };
+
+                    if range.contains(&pc) {
+                        continue;
+                    }
+                } else if is_called_by(&frame, &current) {
+                    // The function returned, finish the line of the caller.
+                    frame = current;
+                    function = pc_function;
+                    inline_depth = cache.inline_depth(pc)?;
+                }
+            }
+
+            match cache.line_range(pc)? {
+                Some(line) => {
+                    if !into && cache.inline_depth(pc)? > inline_depth {
+                        continue;
+                    }
+
+                    if line.is_statement {
+                        return Ok(pc);
+                    }
+
+                    range = line.range;
+                }
+                None => return Ok(pc),
+            }
+        }
+
+        Err(DebugError::SteppingLimit(MAX_STEPS))
+    }
+
+    fn step_out(
+        &self,
+        core: &mut Core<'_>,
+        cache: &mut StepCache<'_>,
+        mut pc: u64,
+    ) -> Result<u64, DebugError> {
+        let frame = self.frame(core, pc);
+        let function = cache.function_entry(pc)?;
+        let inline_depth = cache.inline_depth(pc)?;
+
+        if inline_depth == 0 {
+            return match (frame.return_address, frame.cfa) {
+                (Some(return_address), Some(cfa)) => self.run_to_return(core, return_address, cfa),
+                _ => Err(DebugError::UnknownReturnAddress),
+            };
+        }
+
+        // Inlined functions have no frame of their own, so they are left by stepping
+        // until the program counter is outside of them.
+        for _ in 0..MAX_STEPS {
+            pc = u64::from(core.step()?.pc);
+
+            if !in_same_frame(function, cache.function_entry(pc)?, pc) {
+                let current = self.frame(core, pc);
+
+                if is_called_by(&current, &frame) {
+                    if let (Some(return_address), Some(cfa)) = (current.return_address, current.cfa)
+                    {
+                        pc = self.run_to_return(core, return_address, cfa)?;
+
+                        if pc != return_address {
+                            return Ok(pc);
+                        }
+                    }
+                } else if is_called_by(&frame, &current) {
+                    return Ok(pc);
+                }
+            }
+
+            if cache.inline_depth(pc)? < inline_depth {
+                return Ok(pc);
+            }
+        }
+
+        Err(DebugError::SteppingLimit(MAX_STEPS))
+    }
+
+    /// Runs the core until the function with the given CFA returns to `return_address`.
+    ///
+    /// Returns the program counter at which the core halted, which is different from
+    /// `return_address` if the core halted for another reason.
+    fn run_to_return(
+        &self,
+        core: &mut Core<'_>,
+        return_address: u64,
+        cfa: u32,
+    ) -> Result<u64, DebugError> {
+        let address = return_address as u32;
+
+        // An existing breakpoint must not be removed afterwards.
+        let temporary = !core.has_breakpoint(address);
+
+        if temporary {
+            core.set_breakpoint(address)?;
+        }
+
+        let result = run_until_returned(core, return_address, cfa);
+
+        if temporary {
+            core.clear_breakpoint(address)?;
+        }
+
+        result
+    }
+
+    fn frame(&self, core: &mut Core<'_>, pc: u64) -> Frame {
+        let mut frames = StackFrameIterator::new(self, core, pc);
+
+        let cfa = frames.unwind(pc).and_then(|(_, cfa)| cfa);
+
+        Frame {
+            cfa,
+            return_address: frames.pc,
+        }
+    }
+
+    /// Returns the rows of the sequence of the line program which contains `address`.
+    fn line_sequence(&self, address: u64) -> Result<Option<Vec<LineRow>>, DebugError> {
+        let unit_info = match self.unit_info_for_address(address)? {
+            Some(unit_info) => unit_info,
+            None => return Ok(None),
+        };
+
+        let program = match unit_info.unit.line_program.clone() {
+            Some(program) => program,
+            None => return Ok(None),
+        };
+
+        let (program, sequences) = program.sequences()?;
+
+        let sequence = match sequences
+            .iter()
+            .find(|sequence| sequence.start <= address && address < sequence.end)
+        {
+            Some(sequence) => sequence,
+            None => return Ok(None),
+        };
+
+        let mut rows = program.resume_from(sequence);
+        let mut sequence_rows = vec![];
+
+        while let Some((_, row)) = rows.next_row()? {
+            sequence_rows.push(LineRow {
+                address: row.address(),
+                file: row.file_index(),
+                line: row.line(),
+                is_stmt: row.is_stmt(),
+                end_sequence: row.end_sequence(),
+            });
+
+            if row.end_sequence() {
+                break;
+            }
+        }
+
+        Ok(Some(sequence_rows))
+    }
+
+    /// Returns the function containing `address`, with the functions inlined into it.
+    fn function(&self, address: u64) -> Result<Option<Function>, DebugError> {
+        let unit_info = match self.unit_info_for_address(address)? {
+            Some(unit_info) => unit_info,
+            None => return Ok(None),
+        };
+        let unit = &unit_info.unit;
+
+        let mut entries = unit.entries();
+        let mut offset = None;
+        let mut ranges = vec![];
+
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+
+            ranges = self.die_ranges(unit, entry)?;
+
+            if ranges_contain(&ranges, address) {
+                offset = Some(entry.offset());
+                break;
+            }
+        }
+
+        let offset = match offset {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        // Only the subtree of the function is searched for inlined functions.
+        let mut entries = unit.entries_at_offset(offset)?;
+        let mut depth = 0;
+        let mut inlined = vec![];
+
+        entries.next_dfs()?;
+
+        while let Some((delta, entry)) = entries.next_dfs()? {
+            depth += delta;
+
+            if depth <= 0 {
+                break;
+            }
+
+            if entry.tag() == gimli::DW_TAG_inlined_subroutine {
+                inlined.push(self.die_ranges(unit, entry)?);
+            }
+        }
+
+        let entry = ranges
+            .iter()
+            .map(|range| range.start)
+            .min()
+            .unwrap_or(address);
+
+        Ok(Some(Function {
+            ranges,
+            entry,
+            inlined,
+        }))
+    }
+
+    fn die_ranges(
+        &self,
+        unit: &gimli::Unit<R>,
+        entry: &gimli::DebuggingInformationEntry<R>,
+    ) -> Result<Vec<Range<u64>>, DebugError> {
+        let mut ranges = self.dwarf.die_ranges(unit, entry)?;
+        let mut result = vec![];
+
+        while let Some(range) = ranges.next()? {
+            result.push(range.begin..range.end);
+        }
+
+        Ok(result)
+    }
+
+    fn unit_info_for_address(&self, address: u64) -> Result<Option<UnitInfo<'_>>, DebugError> {
+        let mut units = self.get_units();
+
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut ranges = self.dwarf.unit_ranges(&unit_info.unit)?;
+
+            while let Some(range) = ranges.next()? {
+                if range.begin <= address && address < range.end {
+                    return Ok(Some(unit_info));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn run_until_returned(
+    core: &mut Core<'_>,
+    return_address: u64,
+    cfa: u32,
+) -> Result<u64, DebugError> {
+    loop {
+        core.run()?;
+
+        let start = Instant::now();
+
+        while !core.core_halted()? {
+            if start.elapsed() > RETURN_TIMEOUT {
+                log::info!("Function did not return in time, halting the core.");
+                return Ok(u64::from(core.halt()?.pc));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let pc = u64::from(core.read_core_reg(core.registers().program_counter())?);
+
+        if pc != return_address {
+            return Ok(pc);
+        }
+
+        // After returning, the stack pointer is at the CFA of the returned function.
+        // Otherwise, the breakpoint was hit by a deeper recursive call.
+        let sp = core.read_core_reg(core.registers().stack_pointer())?;
+
+        if sp >= cfa {
+            return Ok(pc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_line_range, in_same_frame, LineRange, LineRow};
+
+    fn row(address: u64, line: u64) -> LineRow {
+        LineRow {
+            address,
+            file: 1,
+            line: Some(line),
+            is_stmt: true,
+            end_sequence: false,
+        }
+    }
+
+    fn end(address: u64) -> LineRow {
+        LineRow {
+            address,
+            file: 1,
+            line: None,
+            is_stmt: false,
+            end_sequence: true,
+        }
+    }
+
+    #[test]
+    fn line_spans_multiple_rows() {
+        let rows = [
+            row(0x100, 10),
+            row(0x104, 11),
+            row(0x108, 11),
+            row(0x110, 12),
+            end(0x118),
+        ];
+
+        assert_eq!(
+            find_line_range(&rows, 0x10a),
+            Some(LineRange {
+                range: 0x104..0x110,
+                line: Some(11),
+                is_statement: false,
+            })
+        );
+
+        assert_eq!(
+            find_line_range(&rows, 0x110),
+            Some(LineRange {
+                range: 0x110..0x118,
+                line: Some(12),
+                is_statement: true,
+            })
+        );
+    }
+
+    #[test]
+    fn line_zero_is_not_a_statement() {
+        let rows = [row(0x100, 10), row(0x104, 0), row(0x108, 11), end(0x10c)];
+
+        let line = find_line_range(&rows, 0x104).unwrap();
+
+        assert_eq!(line.range, 0x104..0x108);
+        assert!(!line.is_statement);
+    }
+
+    #[test]
+    fn address_outside_of_sequence() {
+        let rows = [row(0x100, 10), end(0x104)];
+
+        assert_eq!(find_line_range(&rows, 0xfc), None);
+    }
+
+    #[test]
+    fn frame_is_unwound_when_leaving_or_entering_the_function() {
+        assert!(in_same_frame(Some(0x100), Some(0x100), 0x104));
+
+        // A recursive call enters the function at its first instruction.
+        assert!(!in_same_frame(Some(0x100), Some(0x100), 0x100));
+        assert!(!in_same_frame(Some(0x100), Some(0x200), 0x204));
+        assert!(!in_same_frame(None, None, 0x104));
+    }
+}
@@ -1,6 +1,84 @@
-#[derive(Debug, Default)]
+/// The type of a variable, decoded from the DWARF type information.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Type {
     pub name: String,
-    pub named_children: Option<std::collections::HashMap<String, Type>>,
-    pub indexed_children: Option<Vec<Type>>,
+    /// Size of the type in bytes.
+    pub size: u64,
+    pub kind: TypeKind,
+}
+
+impl Default for Type {
+    fn default() -> Self {
+        Type {
+            name: "<unknown>".to_owned(),
+            size: 0,
+            kind: TypeKind::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeKind {
+    Base(Encoding),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    /// A C-style enumeration, with the value of each enumerator.
+    Enum(Vec<(String, i64)>),
+    /// An enumeration whose variants carry data, like a Rust `enum`.
+    ///
+    /// The active variant is selected by the value of the discriminant. A variant
+    /// without a discriminant value is used if no other variant matches.
+    VariantEnum {
+        discriminant: Option<Box<Member>>,
+        variants: Vec<Variant>,
+    },
+    Array {
+        element: Box<Type>,
+        count: u64,
+    },
+    /// A pointer or a reference. The pointee is not decoded, its name
+    /// is part of the name of the pointer type.
+    Pointer,
+    Unknown,
+}
+
+/// How the bytes of a base type are interpreted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    Float,
+    Boolean,
+    Char,
+    Address,
+}
+
+impl Encoding {
+    pub(crate) fn from_dwarf(encoding: gimli::DwAte) -> Option<Self> {
+        match encoding {
+            gimli::DW_ATE_signed | gimli::DW_ATE_signed_char => Some(Encoding::Signed),
+            gimli::DW_ATE_unsigned | gimli::DW_ATE_unsigned_char => Some(Encoding::Unsigned),
+            gimli::DW_ATE_float => Some(Encoding::Float),
+            gimli::DW_ATE_boolean => Some(Encoding::Boolean),
+            gimli::DW_ATE_UTF => Some(Encoding::Char),
+            gimli::DW_ATE_address => Some(Encoding::Address),
+            _ => None,
+        }
+    }
+}
+
+/// A member of a struct or a union.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    /// Offset of the member from the start of the parent in bytes.
+    pub offset: u64,
+    pub typ: Type,
+}
+
+/// A variant of a `TypeKind::VariantEnum`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub discriminant: Option<u64>,
+    pub member: Member,
 }
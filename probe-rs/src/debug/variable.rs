@@ -1,10 +1,437 @@
-use super::*;
+use super::typ::{Encoding, Member, Type, TypeKind};
+
+use std::fmt;
+
+/// Arrays with more elements are shortened when they are displayed.
+const MAX_DISPLAYED_ELEMENTS: usize = 32;
 
 #[derive(Debug, Default)]
 pub struct Variable {
     pub name: String,
     pub file: String,
     pub line: u64,
-    pub value: u64,
+    pub value: Value,
     pub typ: Type,
 }
+
+/// The decoded value of a variable.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Pointer(u64),
+    /// A known enumerator of a C-style enumeration.
+    Enumerator(String),
+    /// A struct, a union or the active variant of an enum.
+    Struct {
+        name: String,
+        fields: Vec<(String, Value)>,
+    },
+    Array(Vec<Value>),
+    /// The value could not be read or decoded.
+    #[default]
+    Unavailable,
+}
+
+impl Value {
+    /// Decodes the bytes of a variable of the given type.
+    ///
+    /// Parts of the value which are not covered by `data` are `Value::Unavailable`.
+    pub fn decode(typ: &Type, data: &[u8]) -> Value {
+        let size = typ.size as usize;
+
+        match &typ.kind {
+            TypeKind::Base(encoding) => match data.get(..size) {
+                Some(bytes) => decode_base(*encoding, bytes),
+                None => Value::Unavailable,
+            },
+            TypeKind::Pointer => match data.get(..size) {
+                Some(bytes) if size <= 8 => Value::Pointer(read_unsigned(bytes)),
+                _ => Value::Unavailable,
+            },
+            TypeKind::Enum(enumerators) => {
+                let raw = match data.get(..size) {
+                    Some(bytes) if size > 0 && size <= 8 => read_signed(bytes),
+                    _ => return Value::Unavailable,
+                };
+
+                // Enumerators are compared by their bits, as unsigned
+                // enumerations might be stored with a negative value.
+                let mask = if size == 8 {
+                    u64::MAX
+                } else {
+                    (1 << (size * 8)) - 1
+                };
+
+                enumerators
+                    .iter()
+                    .find(|(_, value)| *value as u64 & mask == raw as u64 & mask)
+                    .map(|(name, _)| Value::Enumerator(name.clone()))
+                    .unwrap_or(Value::Signed(raw))
+            }
+            TypeKind::Struct(members) | TypeKind::Union(members) => Value::Struct {
+                name: typ.name.clone(),
+                fields: members
+                    .iter()
+                    .map(|member| (member.name.clone(), decode_member(member, data)))
+                    .collect(),
+            },
+            TypeKind::VariantEnum {
+                discriminant,
+                variants,
+            } => {
+                let discriminant = match discriminant {
+                    Some(member) => match decode_member(member, data) {
+                        Value::Unsigned(value) => Some(value),
+                        Value::Signed(value) => Some(value as u64),
+                        _ => return Value::Unavailable,
+                    },
+                    None => None,
+                };
+
+                let variant = variants
+                    .iter()
+                    .find(|variant| {
+                        variant.discriminant.is_some() && variant.discriminant == discriminant
+                    })
+                    .or_else(|| {
+                        variants
+                            .iter()
+                            .find(|variant| variant.discriminant.is_none())
+                    });
+
+                match variant {
+                    Some(variant) => match decode_member(&variant.member, data) {
+                        // The type of the variant has the name of the enum, use the variant name instead.
+                        Value::Struct { fields, .. } => Value::Struct {
+                            name: variant.member.name.clone(),
+                            fields,
+                        },
+                        other => other,
+                    },
+                    None => Value::Unavailable,
+                }
+            }
+            TypeKind::Array { element, count } => {
+                let element_size = element.size as usize;
+
+                // Only decode the elements which are covered by the data, and
+                // one more than is displayed to know that the array was shortened.
+                let available = match element_size {
+                    0 => *count as usize,
+                    size => (*count as usize).min(data.len() / size),
+                };
+
+                let elements = (0..available.min(MAX_DISPLAYED_ELEMENTS + 1))
+                    .map(|index| Value::decode(element, &data[index * element_size..]))
+                    .collect();
+
+                Value::Array(elements)
+            }
+            TypeKind::Unknown => Value::Unavailable,
+        }
+    }
+}
+
+fn decode_member(member: &Member, data: &[u8]) -> Value {
+    match data.get(member.offset as usize..) {
+        Some(bytes) => Value::decode(&member.typ, bytes),
+        None => Value::Unavailable,
+    }
+}
+
+fn decode_base(encoding: Encoding, bytes: &[u8]) -> Value {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return Value::Unavailable;
+    }
+
+    match encoding {
+        Encoding::Signed => Value::Signed(read_signed(bytes)),
+        Encoding::Unsigned => Value::Unsigned(read_unsigned(bytes)),
+        Encoding::Address => Value::Pointer(read_unsigned(bytes)),
+        Encoding::Boolean => Value::Bool(read_unsigned(bytes) != 0),
+        Encoding::Char => std::char::from_u32(read_unsigned(bytes) as u32)
+            .map(Value::Char)
+            .unwrap_or(Value::Unavailable),
+        Encoding::Float => match bytes.len() {
+            4 => Value::Float(f64::from(f32::from_bits(read_unsigned(bytes) as u32))),
+            8 => Value::Float(f64::from_bits(read_unsigned(bytes))),
+            _ => Value::Unavailable,
+        },
+    }
+}
+
+/// Reads a little endian integer of up to 8 bytes.
+fn read_unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+}
+
+/// Reads a little endian integer of up to 8 bytes and sign extends it.
+fn read_signed(bytes: &[u8]) -> i64 {
+    if bytes.is_empty() {
+        return 0;
+    }
+
+    let shift = 64 - 8 * bytes.len() as u32;
+
+    ((read_unsigned(bytes) << shift) as i64) >> shift
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Unsigned(value) => write!(f, "{}", value),
+            Value::Signed(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Char(value) => write!(f, "{:?}", value),
+            Value::Pointer(value) => write!(f, "{:#010x}", value),
+            Value::Enumerator(name) => write!(f, "{}", name),
+            Value::Struct { name, fields } => {
+                write!(f, "{}", name)?;
+
+                if fields.is_empty() {
+                    return Ok(());
+                }
+
+                // Tuples and tuple structs have numbered fields, `__0`, `__1`, ...
+                let is_tuple = fields
+                    .iter()
+                    .enumerate()
+                    .all(|(index, (field, _))| *field == format!("__{}", index));
+
+                if is_tuple {
+                    write!(f, "(")?;
+                    for (index, (_, value)) in fields.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", value)?;
+                    }
+                    write!(f, ")")
+                } else {
+                    write!(f, " {{ ")?;
+                    for (index, (field, value)) in fields.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}: {}", field, value)?;
+                    }
+                    write!(f, " }}")
+                }
+            }
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                for (index, value) in elements.iter().take(MAX_DISPLAYED_ELEMENTS).enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                if elements.len() > MAX_DISPLAYED_ELEMENTS {
+                    write!(f, ", ..")?;
+                }
+                write!(f, "]")
+            }
+            Value::Unavailable => write!(f, "<unavailable>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debug::typ::Variant;
+
+    fn base(name: &str, size: u64, encoding: Encoding) -> Type {
+        Type {
+            name: name.to_owned(),
+            size,
+            kind: TypeKind::Base(encoding),
+        }
+    }
+
+    fn member(name: &str, offset: u64, typ: Type) -> Member {
+        Member {
+            name: name.to_owned(),
+            offset,
+            typ,
+        }
+    }
+
+    #[test]
+    fn base_types() {
+        let i16_type = base("i16", 2, Encoding::Signed);
+        assert_eq!(Value::decode(&i16_type, &[0xfe, 0xff]), Value::Signed(-2));
+
+        let u32_type = base("u32", 4, Encoding::Unsigned);
+        assert_eq!(
+            Value::decode(&u32_type, &[0x78, 0x56, 0x34, 0x12]),
+            Value::Unsigned(0x1234_5678)
+        );
+        assert_eq!(Value::decode(&u32_type, &[0x78, 0x56]), Value::Unavailable);
+
+        let f32_type = base("f32", 4, Encoding::Float);
+        assert_eq!(
+            Value::decode(&f32_type, &1.5f32.to_le_bytes()),
+            Value::Float(1.5)
+        );
+
+        let char_type = base("char", 4, Encoding::Char);
+        assert_eq!(
+            Value::decode(&char_type, &[0x41, 0, 0, 0]),
+            Value::Char('A')
+        );
+    }
+
+    #[test]
+    fn struct_with_array() {
+        let typ = Type {
+            name: "Packet".to_owned(),
+            size: 8,
+            kind: TypeKind::Struct(vec![
+                member("valid", 0, base("bool", 1, Encoding::Boolean)),
+                member(
+                    "data",
+                    2,
+                    Type {
+                        name: "[u16; 3]".to_owned(),
+                        size: 6,
+                        kind: TypeKind::Array {
+                            element: Box::new(base("u16", 2, Encoding::Unsigned)),
+                            count: 3,
+                        },
+                    },
+                ),
+            ]),
+        };
+
+        let value = Value::decode(&typ, &[1, 0, 1, 0, 2, 0, 3, 0]);
+
+        assert_eq!(value.to_string(), "Packet { valid: true, data: [1, 2, 3] }");
+    }
+
+    #[test]
+    fn c_enum() {
+        let typ = Type {
+            name: "State".to_owned(),
+            size: 1,
+            kind: TypeKind::Enum(vec![("Idle".to_owned(), 0), ("Busy".to_owned(), -1)]),
+        };
+
+        assert_eq!(
+            Value::decode(&typ, &[0xff]),
+            Value::Enumerator("Busy".to_owned())
+        );
+        assert_eq!(Value::decode(&typ, &[3]), Value::Signed(3));
+    }
+
+    #[test]
+    fn empty_enum_is_unavailable() {
+        let typ = Type {
+            name: "Never".to_owned(),
+            size: 0,
+            kind: TypeKind::Enum(vec![]),
+        };
+
+        assert_eq!(Value::decode(&typ, &[]), Value::Unavailable);
+    }
+
+    #[test]
+    fn rust_option() {
+        let some = Type {
+            name: "Option<u32>".to_owned(),
+            size: 8,
+            kind: TypeKind::Struct(vec![member("__0", 4, base("u32", 4, Encoding::Unsigned))]),
+        };
+        let none = Type {
+            name: "Option<u32>".to_owned(),
+            size: 8,
+            kind: TypeKind::Struct(vec![]),
+        };
+
+        let typ = Type {
+            name: "Option<u32>".to_owned(),
+            size: 8,
+            kind: TypeKind::VariantEnum {
+                discriminant: Some(Box::new(member(
+                    "RUST$ENUM$DISR",
+                    0,
+                    base("u32", 4, Encoding::Unsigned),
+                ))),
+                variants: vec![
+                    Variant {
+                        discriminant: Some(0),
+                        member: member("None", 0, none),
+                    },
+                    Variant {
+                        discriminant: Some(1),
+                        member: member("Some", 0, some),
+                    },
+                ],
+            },
+        };
+
+        let value = Value::decode(&typ, &[1, 0, 0, 0, 42, 0, 0, 0]);
+        assert_eq!(value.to_string(), "Some(42)");
+
+        let value = Value::decode(&typ, &[0, 0, 0, 0, 42, 0, 0, 0]);
+        assert_eq!(value.to_string(), "None");
+    }
+
+    #[test]
+    fn niche_enum_uses_default_variant() {
+        let reference = Type {
+            name: "&u8".to_owned(),
+            size: 4,
+            kind: TypeKind::Pointer,
+        };
+
+        let typ = Type {
+            name: "Option<&u8>".to_owned(),
+            size: 4,
+            kind: TypeKind::VariantEnum {
+                discriminant: Some(Box::new(member(
+                    "__0",
+                    0,
+                    base("u32", 4, Encoding::Unsigned),
+                ))),
+                variants: vec![
+                    Variant {
+                        discriminant: Some(0),
+                        member: member(
+                            "None",
+                            0,
+                            Type {
+                                name: "None".to_owned(),
+                                size: 4,
+                                kind: TypeKind::Struct(vec![]),
+                            },
+                        ),
+                    },
+                    Variant {
+                        discriminant: None,
+                        member: member(
+                            "Some",
+                            0,
+                            Type {
+                                name: "Some".to_owned(),
+                                size: 4,
+                                kind: TypeKind::Struct(vec![member("__0", 0, reference)]),
+                            },
+                        ),
+                    },
+                ],
+            },
+        };
+
+        let value = Value::decode(&typ, &[0x00, 0x01, 0x00, 0x20]);
+        assert_eq!(value.to_string(), "Some(0x20000100)");
+    }
+}
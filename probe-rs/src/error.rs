@@ -12,6 +12,8 @@ pub enum Error {
     UnableToOpenProbe(&'static str),
     #[error("Core {0} does not exist")]
     CoreNotFound(usize),
+    #[error("Debug registers at {0:#010x} are not supported for this core")]
+    UnsupportedDebugBase(u32),
     #[error("Unable to load specification for chip: {0}")]
     ChipNotFound(#[from] RegistryError),
 }
@@ -0,0 +1,74 @@
+use super::{loader::flash_algorithm_for_region, FlashError, Flasher};
+use crate::config::{FlashRegion, MemoryRegion};
+use crate::session::Session;
+
+/// Erases the entire flash of the target.
+///
+/// If the flash algorithm of a region can not erase the whole chip,
+/// the region is erased sector by sector.
+pub fn erase_all(session: &mut Session) -> Result<(), FlashError> {
+    for region in flash_regions(session) {
+        let flash_algorithm = flash_algorithm_for_region(session, &region)?;
+
+        let sectors = if flash_algorithm.pc_erase_all.is_none() {
+            let mut sectors = vec![];
+            let mut address = region.range.start;
+
+            while address < region.range.end {
+                let sector = match flash_algorithm.sector_info(address) {
+                    Some(sector) => sector,
+                    None => break,
+                };
+
+                sectors.push(sector.base_address);
+                address = sector.base_address + sector.size;
+            }
+
+            Some(sectors)
+        } else {
+            None
+        };
+
+        let mut flasher = Flasher::new(session, flash_algorithm, region);
+
+        match sectors {
+            Some(sectors) => flasher.run_erase(|active| {
+                sectors
+                    .iter()
+                    .try_for_each(|&address| active.erase_sector(address))
+            })?,
+            None => flasher.run_erase(|active| active.erase_all())?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Erases the flash sector which contains `address`.
+pub fn erase_sector(session: &mut Session, address: u32) -> Result<(), FlashError> {
+    let region = flash_regions(session)
+        .into_iter()
+        .find(|region| region.range.contains(&address))
+        .ok_or(FlashError::InvalidFlashAddress(address))?;
+
+    let flash_algorithm = flash_algorithm_for_region(session, &region)?;
+
+    let sector = flash_algorithm
+        .sector_info(address)
+        .ok_or(FlashError::InvalidFlashAddress(address))?;
+
+    let mut flasher = Flasher::new(session, flash_algorithm, region);
+
+    flasher.run_erase(|active| active.erase_sector(sector.base_address))
+}
+
+fn flash_regions(session: &Session) -> Vec<FlashRegion> {
+    session
+        .memory_map()
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Flash(region) => Some(region.clone()),
+            _ => None,
+        })
+        .collect()
+}
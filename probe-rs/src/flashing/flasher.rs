@@ -158,7 +158,7 @@ impl<'session> Flasher<'session> {
         F: FnOnce(&mut ActiveFlasher<'_, Erase>) -> Result<T, E> + Sized,
     {
         // TODO: Fix those values (None, None).
-        let mut active = self.init(None, None).unwrap();
+        let mut active = self.init(None, None)?;
         let r = f(&mut active)?;
         active.uninit()?;
         Ok(r)
@@ -1,5 +1,5 @@
 use super::{FlashBuilder, FlashError, FlashProgress, Flasher};
-use crate::config::{FlashRegion, MemoryRange, MemoryRegion};
+use crate::config::{FlashAlgorithm, FlashRegion, MemoryRange, MemoryRegion};
 use crate::session::Session;
 use std::collections::HashMap;
 
@@ -8,14 +8,17 @@ use std::collections::HashMap;
 /// Once you are done adding all your data, use `commit()` to flash the data.
 /// The flash loader will make sure to select the appropriate flash region for the right data chunks.
 /// Region crossing data chunks are allowed as long as the regions are contiguous.
-pub(super) struct FlashLoader<'mmap, 'data> {
+pub struct FlashLoader<'mmap, 'data> {
     memory_map: &'mmap [MemoryRegion],
     builders: HashMap<FlashRegion, FlashBuilder<'data>>,
     keep_unwritten: bool,
 }
 
 impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
-    pub(super) fn new(memory_map: &'mmap [MemoryRegion], keep_unwritten: bool) -> Self {
+    /// Creates a loader for the flash regions in `memory_map`.
+    ///
+    /// If `keep_unwritten` is `true`, erased bytes which are not overwritten are restored afterwards.
+    pub fn new(memory_map: &'mmap [MemoryRegion], keep_unwritten: bool) -> Self {
         Self {
             memory_map,
             builders: HashMap::new(),
@@ -25,11 +28,7 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
     /// Stages a chunk of data to be programmed.
     ///
     /// The chunk can cross flash boundaries as long as one flash region connects to another flash region.
-    pub(super) fn add_data(
-        &mut self,
-        mut address: u32,
-        data: &'data [u8],
-    ) -> Result<(), FlashError> {
+    pub fn add_data(&mut self, mut address: u32, data: &'data [u8]) -> Result<(), FlashError> {
         let size = data.len();
         let mut remaining = size;
         while remaining > 0 {
@@ -86,7 +85,7 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
     /// Requires a session with an attached target that has a known flash algorithm.
     ///
     /// If `do_chip_erase` is `true` the entire flash will be erased.
-    pub(super) fn commit(
+    pub fn commit(
         &mut self,
         session: &mut Session,
         progress: &FlashProgress,
@@ -100,56 +99,7 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
                 region.range.end
             );
 
-            // Try to find a flash algorithm for the range of the current builder
-            for algorithm in session.flash_algorithms() {
-                log::debug!(
-                    "Algorithm {} - start: {:#08x} - size: {:#08x}",
-                    algorithm.name,
-                    algorithm.flash_properties.address_range.start,
-                    algorithm.flash_properties.address_range.end
-                        - algorithm.flash_properties.address_range.start
-                );
-            }
-
-            let algorithms = session.flash_algorithms();
-            let algorithms = algorithms
-                .iter()
-                .filter(|fa| {
-                    fa.flash_properties
-                        .address_range
-                        .contains_range(&region.range)
-                })
-                .collect::<Vec<_>>();
-
-            log::debug!("Algorithms: {:?}", &algorithms);
-
-            let raw_flash_algorithm = match algorithms.len() {
-                0 => {
-                    return Err(FlashError::NoFlashLoaderAlgorithmAttached);
-                }
-                1 => &algorithms[0],
-                _ => algorithms
-                    .iter()
-                    .find(|a| a.default)
-                    .ok_or(FlashError::NoFlashLoaderAlgorithmAttached)?,
-            };
-
-            let mm = session.memory_map();
-            let ram = mm
-                .iter()
-                .find(|mm| match mm {
-                    MemoryRegion::Ram(_) => true,
-                    _ => false,
-                })
-                .expect("No RAM defined for chip.");
-
-            let unwrapped_ram = match ram {
-                MemoryRegion::Ram(ram) => ram,
-                _ => unreachable!(),
-            };
-
-            let flash_algorithm =
-                raw_flash_algorithm.assemble(unwrapped_ram, session.architecture());
+            let flash_algorithm = flash_algorithm_for_region(session, region)?;
 
             // Program the data.
             let mut flasher = Flasher::new(session, flash_algorithm, region.clone());
@@ -159,3 +109,59 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
         Ok(())
     }
 }
+
+/// Finds the flash algorithm for `region`, and assembles it to run from the RAM of the target.
+pub(super) fn flash_algorithm_for_region(
+    session: &Session,
+    region: &FlashRegion,
+) -> Result<FlashAlgorithm, FlashError> {
+    // Try to find a flash algorithm for the range of the region
+    for algorithm in session.flash_algorithms() {
+        log::debug!(
+            "Algorithm {} - start: {:#08x} - size: {:#08x}",
+            algorithm.name,
+            algorithm.flash_properties.address_range.start,
+            algorithm.flash_properties.address_range.end
+                - algorithm.flash_properties.address_range.start
+        );
+    }
+
+    let algorithms = session.flash_algorithms();
+    let algorithms = algorithms
+        .iter()
+        .filter(|fa| {
+            fa.flash_properties
+                .address_range
+                .contains_range(&region.range)
+        })
+        .collect::<Vec<_>>();
+
+    log::debug!("Algorithms: {:?}", &algorithms);
+
+    let raw_flash_algorithm = match algorithms.len() {
+        0 => {
+            return Err(FlashError::NoFlashLoaderAlgorithmAttached);
+        }
+        1 => &algorithms[0],
+        _ => algorithms
+            .iter()
+            .find(|a| a.default)
+            .ok_or(FlashError::NoFlashLoaderAlgorithmAttached)?,
+    };
+
+    let mm = session.memory_map();
+    let ram = mm
+        .iter()
+        .find(|mm| match mm {
+            MemoryRegion::Ram(_) => true,
+            _ => false,
+        })
+        .expect("No RAM defined for chip.");
+
+    let unwrapped_ram = match ram {
+        MemoryRegion::Ram(ram) => ram,
+        _ => unreachable!(),
+    };
+
+    Ok(raw_flash_algorithm.assemble(unwrapped_ram, session.architecture()))
+}
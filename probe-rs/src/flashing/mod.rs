@@ -7,6 +7,7 @@
 
 mod builder;
 mod download;
+mod erase;
 mod error;
 mod flasher;
 mod loader;
@@ -15,8 +16,9 @@ mod visualizer;
 
 use builder::*;
 pub use download::*;
+pub use erase::*;
 pub use error::*;
 pub use flasher::*;
-use loader::*;
+pub use loader::*;
 pub use progress::*;
 pub use visualizer::*;
@@ -78,15 +78,27 @@ mod error;
 pub mod flashing;
 mod memory;
 mod probe;
+pub mod rtt;
+pub mod semihosting;
+pub mod server;
 mod session;
 
 pub use crate::config::Target;
 pub use crate::core::CoreType;
 pub use crate::core::{
-    Breakpoint, BreakpointId, CommunicationInterface, Core, CoreInterface, CoreList,
-    CoreRegisterAddress, CoreStatus, HaltReason,
+    Architecture, Breakpoint, BreakpointId, CommunicationInterface, Core, CoreInformation,
+    CoreInterface, CoreList, CoreRegisterAddress, CoreState, CoreStatus, HaltReason,
+    RegisterDescription, RegisterFile, Watchpoint, WatchpointKind,
 };
 pub use crate::error::Error;
 pub use crate::memory::{Memory, MemoryInterface, MemoryList};
-pub use crate::probe::{DebugProbe, DebugProbeError, DebugProbeInfo, Probe, WireProtocol};
+#[cfg(feature = "recording")]
+pub use crate::probe::recording;
+#[cfg(feature = "remote")]
+pub use crate::probe::remote;
+#[cfg(any(test, feature = "simulator"))]
+pub use crate::probe::simulator;
+pub use crate::probe::{
+    DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeType, Probe, WireProtocol,
+};
 pub use crate::session::Session;
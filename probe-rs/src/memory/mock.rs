@@ -0,0 +1,83 @@
+use super::MemoryInterface;
+use crate::{DebugProbeError, Error};
+
+/// Target memory backed by a plain byte buffer, for tests of code on top of `MemoryInterface`.
+///
+/// Accesses outside of the buffer fail with `DebugProbeError::Unknown`.
+pub(crate) struct MockMemory {
+    pub data: Vec<u8>,
+    start: u32,
+}
+
+impl MockMemory {
+    /// Creates `len` bytes of zeroed memory at `start`.
+    pub fn new(start: u32, len: usize) -> Self {
+        MockMemory {
+            data: vec![0; len],
+            start,
+        }
+    }
+
+    fn offset(&self, address: u32, len: usize) -> Result<usize, Error> {
+        let offset = address
+            .checked_sub(self.start)
+            .ok_or(Error::Probe(DebugProbeError::Unknown))? as usize;
+
+        if offset + len > self.data.len() {
+            return Err(Error::Probe(DebugProbeError::Unknown));
+        }
+
+        Ok(offset)
+    }
+}
+
+impl MemoryInterface for MockMemory {
+    fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+        let mut data = [0u32; 1];
+        self.read_32(address, &mut data)?;
+        Ok(data[0])
+    }
+
+    fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
+        let offset = self.offset(address, 1)?;
+        Ok(self.data[offset])
+    }
+
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+        let offset = self.offset(address, data.len() * 4)?;
+        for (i, word) in data.iter_mut().enumerate() {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&self.data[offset + i * 4..offset + i * 4 + 4]);
+            *word = u32::from_le_bytes(bytes);
+        }
+        Ok(())
+    }
+
+    fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        let offset = self.offset(address, data.len())?;
+        data.copy_from_slice(&self.data[offset..offset + data.len()]);
+        Ok(())
+    }
+
+    fn write_word_32(&mut self, address: u32, data: u32) -> Result<(), Error> {
+        self.write_32(address, &[data])
+    }
+
+    fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), Error> {
+        self.write_8(address, &[data])
+    }
+
+    fn write_32(&mut self, address: u32, data: &[u32]) -> Result<(), Error> {
+        let offset = self.offset(address, data.len() * 4)?;
+        for (i, word) in data.iter().enumerate() {
+            self.data[offset + i * 4..offset + i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        let offset = self.offset(address, data.len())?;
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
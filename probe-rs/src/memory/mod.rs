@@ -1,5 +1,8 @@
 use crate::error;
 
+#[cfg(test)]
+pub(crate) mod mock;
+
 pub trait MemoryInterface {
     /// Read a 32bit word of at `address`.
     ///
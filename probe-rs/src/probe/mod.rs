@@ -1,5 +1,11 @@
 pub(crate) mod daplink;
 pub(crate) mod jlink;
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(any(test, feature = "simulator"))]
+pub mod simulator;
 pub(crate) mod stlink;
 
 use crate::architecture::arm::{DAPAccess, PortType};
@@ -7,6 +13,7 @@ use crate::config::{RegistryError, TargetSelector};
 use crate::error::Error;
 use crate::{Memory, Session};
 use jlink::list_jlink_devices;
+use std::collections::HashMap;
 use std::fmt;
 use thiserror::Error;
 
@@ -160,6 +167,11 @@ impl Probe {
                 let link = jlink::JLink::new_from_probe_info(info)?;
                 Probe::from_specific_probe(link)
             }
+            #[cfg(feature = "remote")]
+            DebugProbeType::Remote => {
+                let remote = remote::RemoteProbe::new_from_probe_info(info)?;
+                Probe::from_specific_probe(remote)
+            }
         };
 
         Ok(probe)
@@ -274,6 +286,14 @@ impl Probe {
         }
     }
 
+    /// Changes the protocol speed in kHz while the probe is attached.
+    ///
+    /// Probes like CMSIS-DAP and J-Link change their clock in the middle of a session, others
+    /// return an error if they can't.
+    pub(crate) fn set_speed_attached(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.inner.set_speed(speed_khz)
+    }
+
     /// Configured protocol speed in kHz
     pub fn speed_khz(&self) -> u32 {
         self.inner.speed()
@@ -388,6 +408,9 @@ pub enum DebugProbeType {
     DAPLink,
     STLink,
     JLink,
+    /// A probe of a probe server, see [`remote`].
+    #[cfg(feature = "remote")]
+    Remote,
 }
 
 #[derive(Clone)]
@@ -433,14 +456,54 @@ impl DebugProbeInfo {
         }
     }
 
+    /// Describes a probe of a probe server, selected with `host:port/serial`.
+    ///
+    /// The serial number can be left out if only one probe is connected to the server.
+    #[cfg(feature = "remote")]
+    pub fn remote(selector: &str) -> Result<Self, DebugProbeError> {
+        let (address, serial) = remote::parse_selector(selector)?;
+
+        Ok(Self::new(
+            address,
+            0,
+            0,
+            serial.map(str::to_owned),
+            DebugProbeType::Remote,
+        ))
+    }
+
     /// Open the probe described by this `DebugProbeInfo`.
     pub fn open(&self) -> Result<Probe, DebugProbeError> {
         Probe::from_probe_info(&self)
     }
 }
 
-#[derive(Default, Debug)]
-pub struct FakeProbe;
+/// A probe without a target, which returns the values that were written to its DAP and JTAG
+/// registers when they are read. The debug port identifies itself as a DPv1 debug port, and
+/// acknowledges requests for debug and system power in its CTRL/STAT register.
+#[derive(Debug)]
+pub struct FakeProbe {
+    speed_khz: u32,
+    attached: bool,
+    dap_registers: HashMap<(u16, u16), u32>,
+    jtag_registers: HashMap<u32, Vec<u8>>,
+}
+
+impl Default for FakeProbe {
+    fn default() -> Self {
+        let mut dap_registers = HashMap::new();
+
+        // The DPIDR of a Cortex-M4.
+        dap_registers.insert((PortType::DebugPort.into(), 0x0), 0x2ba0_1477);
+
+        FakeProbe {
+            speed_khz: 1_000,
+            attached: false,
+            dap_registers,
+            jtag_registers: HashMap::new(),
+        }
+    }
+}
 
 impl DebugProbe for FakeProbe {
     fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
@@ -456,29 +519,36 @@ impl DebugProbe for FakeProbe {
     }
 
     fn speed(&self) -> u32 {
-        unimplemented!()
+        self.speed_khz
     }
 
-    fn set_speed(&mut self, _speed_khz: u32) -> Result<u32, DebugProbeError> {
-        unimplemented!()
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        if speed_khz == 0 {
+            return Err(DebugProbeError::UnsupportedSpeed(speed_khz));
+        }
+
+        self.speed_khz = speed_khz;
+        Ok(speed_khz)
     }
 
     fn attach(&mut self) -> Result<(), DebugProbeError> {
-        unimplemented!()
+        self.attached = true;
+        Ok(())
     }
 
     fn select_protocol(&mut self, _protocol: WireProtocol) -> Result<(), DebugProbeError> {
-        unimplemented!()
+        Ok(())
     }
 
     /// Leave debug mode
     fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = false;
         Ok(())
     }
 
     /// Resets the target device.
     fn target_reset(&mut self) -> Result<(), DebugProbeError> {
-        Err(DebugProbeError::Unknown)
+        Ok(())
     }
 
     fn dedicated_memory_interface(&self) -> Option<Memory> {
@@ -486,36 +556,89 @@ impl DebugProbe for FakeProbe {
     }
 
     fn get_interface_dap(&self) -> Option<&dyn DAPAccess> {
-        None
+        Some(self as _)
     }
 
     fn get_interface_dap_mut(&mut self) -> Option<&mut dyn DAPAccess> {
-        None
+        Some(self as _)
     }
 
     fn get_interface_jtag(&self) -> Option<&dyn JTAGAccess> {
-        None
+        Some(self as _)
     }
 
     fn get_interface_jtag_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
-        None
+        Some(self as _)
     }
 }
 
 impl DAPAccess for FakeProbe {
     /// Reads the DAP register on the specified port and address
-    fn read_register(&mut self, _port: PortType, _addr: u16) -> Result<u32, DebugProbeError> {
-        Err(DebugProbeError::Unknown)
+    fn read_register(&mut self, port: PortType, addr: u16) -> Result<u32, DebugProbeError> {
+        if !self.attached {
+            return Err(DebugProbeError::NotAttached);
+        }
+
+        Ok(self
+            .dap_registers
+            .get(&(port.into(), addr))
+            .copied()
+            .unwrap_or(0))
     }
 
     /// Writes a value to the DAP register on the specified port and address
     fn write_register(
         &mut self,
-        _port: PortType,
-        _addr: u16,
-        _value: u32,
+        port: PortType,
+        addr: u16,
+        value: u32,
     ) -> Result<(), DebugProbeError> {
-        Err(DebugProbeError::Unknown)
+        if !self.attached {
+            return Err(DebugProbeError::NotAttached);
+        }
+
+        let value = match (port, addr) {
+            // The writes to ABORT don't change the DPIDR, which shares its address.
+            (PortType::DebugPort, 0x0) => return Ok(()),
+            // CSYSPWRUPREQ and CDBGPWRUPREQ are acknowledged by CSYSPWRUPACK and CDBGPWRUPACK.
+            (PortType::DebugPort, 0x4) => value | (value & 0x5000_0000) << 1,
+            _ => value,
+        };
+
+        self.dap_registers.insert((port.into(), addr), value);
+        Ok(())
+    }
+}
+
+impl JTAGAccess for FakeProbe {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        if !self.attached {
+            return Err(DebugProbeError::NotAttached);
+        }
+
+        let mut data = self
+            .jtag_registers
+            .get(&address)
+            .cloned()
+            .unwrap_or_default();
+        data.resize((len as usize).div_ceil(8), 0);
+
+        Ok(data)
+    }
+
+    fn set_idle_cycles(&mut self, _idle_cycles: u8) {}
+
+    /// Returns the previous value of the register.
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        let previous = JTAGAccess::read_register(self, address, len)?;
+
+        self.jtag_registers.insert(address, data.to_vec());
+        Ok(previous)
     }
 }
 
@@ -0,0 +1,663 @@
+//! Recording the operations of a probe, and replaying them without the probe.
+//!
+//! A [`RecordingProbe`] forwards the operations to a probe, and writes each of them together
+//! with its result to a recording. A [`ReplayProbe`] returns the recorded results for the same
+//! operations, so that a session which was recorded on hardware can be repeated in a test.
+//!
+//! The recording starts with [`MAGIC`], which is followed by the description of the probe and
+//! then by each operation and its result, encoded like the messages of the [`remote`] protocol.
+//!
+//! [`remote`]: super::remote
+
+use super::remote::protocol::{receive, send, Command, Description, Failure, Reply};
+use super::remote::server::execute;
+use super::remote::RemoteError;
+use crate::architecture::arm::{DAPAccess, PortType};
+use crate::probe::{DebugProbe, DebugProbeError, DebugProbeInfo, JTAGAccess, Probe, WireProtocol};
+use crate::Memory;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+/// The start of each recording.
+pub const MAGIC: &[u8] = b"probe-rs recording 1\n";
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("Unable to access the recording: {0}")]
+    Io(#[from] io::Error),
+    #[error("The recording is invalid")]
+    InvalidRecording,
+    #[error("The replayed operations diverged from the recording: expected {expected}, but got {actual}")]
+    Diverged { expected: String, actual: String },
+    #[error("The recording ended, but got {0}")]
+    Ended(String),
+}
+
+impl From<RemoteError> for RecordingError {
+    fn from(error: RemoteError) -> Self {
+        match error {
+            RemoteError::Io(e) => RecordingError::Io(e),
+            _ => RecordingError::InvalidRecording,
+        }
+    }
+}
+
+impl From<RecordingError> for DebugProbeError {
+    fn from(error: RecordingError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+/// A probe which records the operations of another probe.
+///
+/// The memory interface of the probe is not used, the memory is accessed through the DAP
+/// registers instead, so that these accesses are recorded as well.
+#[derive(Debug)]
+pub struct RecordingProbe<W: Write = BufWriter<File>> {
+    inner: Box<dyn DebugProbe>,
+    writer: W,
+}
+
+impl RecordingProbe {
+    /// Records the operations of `probe` to the file at `path`.
+    pub fn create(probe: Probe, path: impl AsRef<Path>) -> Result<Self, DebugProbeError> {
+        let file = File::create(path).map_err(RecordingError::from)?;
+
+        Self::new(probe, BufWriter::new(file))
+    }
+}
+
+impl<W: Write> RecordingProbe<W> {
+    /// Records the operations of `probe` to `writer`.
+    pub fn new(probe: Probe, mut writer: W) -> Result<Self, DebugProbeError> {
+        let inner = probe.inner;
+
+        let description = Description {
+            name: inner.get_name().to_owned(),
+            speed_khz: inner.speed(),
+            dap: inner.get_interface_dap().is_some(),
+            jtag: inner.get_interface_jtag().is_some(),
+        };
+
+        writer.write_all(MAGIC).map_err(RecordingError::from)?;
+        send(&mut writer, &description).map_err(RecordingError::from)?;
+
+        Ok(RecordingProbe { inner, writer })
+    }
+
+    /// Ends the recording, and returns the writer.
+    pub fn finish(mut self) -> Result<W, DebugProbeError> {
+        self.writer.flush().map_err(RecordingError::from)?;
+
+        Ok(self.writer)
+    }
+
+    /// Executes `command` on the probe, and records it with its result.
+    fn record(&mut self, command: Command) -> Result<Reply, DebugProbeError> {
+        let result = execute(self.inner.as_mut(), &command);
+
+        let recorded: Result<&Reply, Failure> = result.as_ref().map_err(Failure::from);
+
+        send(&mut self.writer, &command).map_err(RecordingError::from)?;
+        send(&mut self.writer, &recorded.cloned()).map_err(RecordingError::from)?;
+
+        result
+    }
+}
+
+impl<W: Write + Send + Sync + fmt::Debug> DebugProbe for RecordingProbe<W> {
+    fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn get_name(&self) -> &str {
+        self.inner.get_name()
+    }
+
+    fn speed(&self) -> u32 {
+        self.inner.speed()
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.record(Command::SetSpeed(speed_khz))?.into_value()
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.record(Command::Attach)?.into_done()
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.record(Command::Detach)?.into_done()
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.record(Command::TargetReset)?.into_done()
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.record(Command::SelectProtocol(protocol))?.into_done()
+    }
+
+    fn dedicated_memory_interface(&self) -> Option<Memory<'_>> {
+        None
+    }
+
+    fn get_interface_dap(&self) -> Option<&dyn DAPAccess> {
+        if self.inner.get_interface_dap().is_some() {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_dap_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        if self.inner.get_interface_dap().is_some() {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_jtag(&self) -> Option<&dyn JTAGAccess> {
+        if self.inner.get_interface_jtag().is_some() {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_jtag_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        if self.inner.get_interface_jtag().is_some() {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+}
+
+impl<W: Write + Send + Sync + fmt::Debug> DAPAccess for RecordingProbe<W> {
+    fn read_register(&mut self, port: PortType, addr: u16) -> Result<u32, DebugProbeError> {
+        self.record(Command::DapRead(port, addr))?.into_value()
+    }
+
+    fn read_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        self.record(Command::DapReadBlock(port, addr, values.len() as u32))?
+            .into_values(values)
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.record(Command::DapWrite(port, addr, value))?
+            .into_done()
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        self.record(Command::DapWriteBlock(port, addr, values.to_vec()))?
+            .into_done()
+    }
+}
+
+impl<W: Write + Send + Sync + fmt::Debug> JTAGAccess for RecordingProbe<W> {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        self.record(Command::JtagRead(address, len))?.into_bytes()
+    }
+
+    fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        if let Err(e) = self.record(Command::JtagIdleCycles(idle_cycles)) {
+            log::warn!("Unable to record the idle cycles: {}", e);
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        self.record(Command::JtagWrite(address, data.to_vec(), len))?
+            .into_bytes()
+    }
+}
+
+/// A probe which returns the results of a recording.
+///
+/// Each operation has to be the next operation of the recording, otherwise it fails with
+/// [`RecordingError::Diverged`]. This error is reported by the following operation for
+/// operations which can't fail, like setting the JTAG idle cycles.
+#[derive(Debug)]
+pub struct ReplayProbe {
+    name: String,
+    speed_khz: u32,
+    dap: bool,
+    jtag: bool,
+    operations: VecDeque<(Command, Result<Reply, Failure>)>,
+    divergence: Option<RecordingError>,
+}
+
+impl ReplayProbe {
+    /// Replays the recording in the file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DebugProbeError> {
+        let file = File::open(path).map_err(RecordingError::from)?;
+
+        Self::new(BufReader::new(file))
+    }
+
+    /// Replays the recording which is read from `reader`.
+    pub fn new(mut reader: impl Read) -> Result<Self, DebugProbeError> {
+        Ok(Self::read(&mut reader)?)
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, RecordingError> {
+        let mut magic = vec![0; MAGIC.len()];
+
+        match reader.read_exact(&mut magic) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(RecordingError::InvalidRecording)
+            }
+            result => result?,
+        }
+
+        if magic != MAGIC {
+            return Err(RecordingError::InvalidRecording);
+        }
+
+        let description: Description = receive(reader)?.ok_or(RecordingError::InvalidRecording)?;
+
+        let mut operations = VecDeque::new();
+
+        while let Some(command) = receive(reader)? {
+            let result = receive(reader)?.ok_or(RecordingError::InvalidRecording)?;
+            operations.push_back((command, result));
+        }
+
+        Ok(ReplayProbe {
+            name: description.name,
+            speed_khz: description.speed_khz,
+            dap: description.dap,
+            jtag: description.jtag,
+            operations,
+            divergence: None,
+        })
+    }
+
+    /// The number of recorded operations which were not replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// Returns the recorded result of `command`.
+    fn replay(&mut self, command: Command) -> Result<Reply, DebugProbeError> {
+        if let Some(divergence) = self.divergence.take() {
+            return Err(divergence.into());
+        }
+
+        Ok(self.next(command)??)
+    }
+
+    /// Returns the recorded result of `command`, which has to be the next recorded operation.
+    fn next(&mut self, command: Command) -> Result<Result<Reply, Failure>, RecordingError> {
+        let (expected, result) = self
+            .operations
+            .pop_front()
+            .ok_or_else(|| RecordingError::Ended(format!("{:?}", command)))?;
+
+        if expected != command {
+            log::error!("Expected {:?}, but got {:?}", expected, command);
+
+            return Err(RecordingError::Diverged {
+                expected: format!("{:?}", expected),
+                actual: format!("{:?}", command),
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+impl DebugProbe for ReplayProbe {
+    fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed_khz
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.speed_khz = self.replay(Command::SetSpeed(speed_khz))?.into_value()?;
+        Ok(self.speed_khz)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.replay(Command::Attach)?.into_done()
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.replay(Command::Detach)?.into_done()
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.replay(Command::TargetReset)?.into_done()
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.replay(Command::SelectProtocol(protocol))?.into_done()
+    }
+
+    fn dedicated_memory_interface(&self) -> Option<Memory<'_>> {
+        None
+    }
+
+    fn get_interface_dap(&self) -> Option<&dyn DAPAccess> {
+        if self.dap {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_dap_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        if self.dap {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_jtag(&self) -> Option<&dyn JTAGAccess> {
+        if self.jtag {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_jtag_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        if self.jtag {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+}
+
+impl DAPAccess for ReplayProbe {
+    fn read_register(&mut self, port: PortType, addr: u16) -> Result<u32, DebugProbeError> {
+        self.replay(Command::DapRead(port, addr))?.into_value()
+    }
+
+    fn read_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        self.replay(Command::DapReadBlock(port, addr, values.len() as u32))?
+            .into_values(values)
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.replay(Command::DapWrite(port, addr, value))?
+            .into_done()
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        self.replay(Command::DapWriteBlock(port, addr, values.to_vec()))?
+            .into_done()
+    }
+}
+
+impl JTAGAccess for ReplayProbe {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        self.replay(Command::JtagRead(address, len))?.into_bytes()
+    }
+
+    fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        if let Err(e) = self.next(Command::JtagIdleCycles(idle_cycles)) {
+            self.divergence.get_or_insert(e);
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        self.replay(Command::JtagWrite(address, data.to_vec(), len))?
+            .into_bytes()
+    }
+}
+
+impl Drop for ReplayProbe {
+    fn drop(&mut self) {
+        if !self.operations.is_empty() {
+            log::warn!(
+                "{} recorded operations were not replayed",
+                self.operations.len()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RecordingError, RecordingProbe, ReplayProbe};
+    use crate::architecture::arm::memory::ADIMemoryInterface;
+    use crate::architecture::arm::{
+        ArmCommunicationInterface, ArmCommunicationInterfaceState, PortType,
+    };
+    use crate::probe::{DebugProbe, DebugProbeError, FakeProbe, JTAGAccess, Probe};
+    use crate::simulator::SimulatedCortexM;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A recording which can be read while the probe which writes it is in use.
+    #[derive(Debug, Clone, Default)]
+    struct SharedRecording(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedRecording {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn access_memory(probe: &mut Probe) -> u32 {
+        let mut state = ArmCommunicationInterfaceState::new();
+        let interface = ArmCommunicationInterface::new(probe, &mut state)
+            .unwrap()
+            .unwrap();
+        let mut memory =
+            ADIMemoryInterface::<ArmCommunicationInterface>::new(interface, 0).unwrap();
+
+        memory.write_word_32(0x2000_0000, 0xdead_beef).unwrap();
+        memory.read_word_32(0x2000_0000).unwrap()
+    }
+
+    #[test]
+    fn memory_accesses_are_replayed() {
+        let recording = SharedRecording::default();
+
+        let fake = Probe::new(FakeProbe::default());
+        let mut probe = Probe::new(RecordingProbe::new(fake, recording.clone()).unwrap());
+        probe.attach_to_unspecified().unwrap();
+        assert_eq!(access_memory(&mut probe), 0xdead_beef);
+        drop(probe);
+
+        let recording = recording.0.lock().unwrap().clone();
+
+        let mut probe = Probe::new(ReplayProbe::new(&recording[..]).unwrap());
+        assert!(probe.get_name().starts_with("Mock probe for testing"));
+        probe.attach_to_unspecified().unwrap();
+        assert_eq!(access_memory(&mut probe), 0xdead_beef);
+    }
+
+    /// Halts the core of a simulated chip after a reset, reads `register` and steps the core.
+    fn debug_core(probe: Probe, register: u16) -> Result<(u32, u32), crate::Error> {
+        let mut session = probe.attach(SimulatedCortexM::target())?;
+        let mut core = session.core(0)?;
+
+        core.reset_and_halt()?;
+        let value = core.read_core_reg(register)?;
+        let pc = core.step()?.pc;
+
+        Ok((value, pc))
+    }
+
+    #[test]
+    fn session_is_replayed() {
+        let chip = SimulatedCortexM::new();
+        // A vector table with the stack at the end of the RAM and the reset handler at 0x40,
+        // followed by `NOP`s.
+        chip.write_memory(0x0, &[0x00, 0x00, 0x01, 0x20, 0x41, 0x00, 0x00, 0x00]);
+        chip.write_memory(0x40, &[0x00, 0xbf, 0x00, 0xbf]);
+
+        let recording = SharedRecording::default();
+
+        let simulated = Probe::new(chip);
+        let probe = Probe::new(RecordingProbe::new(simulated, recording.clone()).unwrap());
+        assert_eq!(debug_core(probe, 13).unwrap(), (0x2001_0000, 0x42));
+
+        let recording = recording.0.lock().unwrap().clone();
+
+        // The chip is not needed to replay the session.
+        let probe = Probe::new(ReplayProbe::new(&recording[..]).unwrap());
+        assert_eq!(debug_core(probe, 13).unwrap(), (0x2001_0000, 0x42));
+
+        // Reading another register diverges from the recording.
+        let probe = Probe::new(ReplayProbe::new(&recording[..]).unwrap());
+        let error = debug_core(probe, 0).unwrap_err();
+        let diverged = std::iter::successors(Some(&error as &dyn std::error::Error), |error| {
+            error.source()
+        })
+        .any(|error| matches!(error.downcast_ref(), Some(RecordingError::Diverged { .. })));
+        assert!(diverged, "{}", error);
+    }
+
+    #[test]
+    fn results_and_failures_are_replayed() {
+        let mut probe = RecordingProbe::new(Probe::new(FakeProbe::default()), Vec::new()).unwrap();
+
+        assert!(matches!(
+            probe.set_speed(0),
+            Err(DebugProbeError::UnsupportedSpeed(0))
+        ));
+        probe.attach().unwrap();
+        probe.set_idle_cycles(3);
+        probe
+            .get_interface_dap_mut()
+            .unwrap()
+            .write_block(PortType::AccessPort(0), 0x0c, &[1, 2])
+            .unwrap();
+        assert_eq!(
+            probe.write_register(0x11, &[0xaa, 0x55], 16).unwrap(),
+            vec![0, 0]
+        );
+
+        let recording = probe.finish().unwrap();
+        let mut probe = ReplayProbe::new(&recording[..]).unwrap();
+
+        assert_eq!(probe.remaining(), 5);
+        assert!(matches!(
+            probe.set_speed(0),
+            Err(DebugProbeError::UnsupportedSpeed(0))
+        ));
+        probe.attach().unwrap();
+        probe.set_idle_cycles(3);
+        probe
+            .get_interface_dap_mut()
+            .unwrap()
+            .write_block(PortType::AccessPort(0), 0x0c, &[1, 2])
+            .unwrap();
+        assert_eq!(
+            probe.write_register(0x11, &[0xaa, 0x55], 16).unwrap(),
+            vec![0, 0]
+        );
+        assert_eq!(probe.remaining(), 0);
+    }
+
+    fn recording_error(error: DebugProbeError) -> RecordingError {
+        match error {
+            DebugProbeError::ProbeSpecific(error) => *error.downcast().unwrap(),
+            error => panic!("Expected a recording error, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn divergences_are_reported() {
+        let mut probe = RecordingProbe::new(Probe::new(FakeProbe::default()), Vec::new()).unwrap();
+        probe.attach().unwrap();
+        probe.set_idle_cycles(3);
+        probe.detach().unwrap();
+
+        let recording = probe.finish().unwrap();
+        let mut probe = ReplayProbe::new(&recording[..]).unwrap();
+
+        let error = recording_error(probe.detach().unwrap_err());
+        assert!(
+            matches!(error, RecordingError::Diverged { .. }),
+            "{}",
+            error
+        );
+
+        // The divergence of an operation which can't fail is reported by the next one.
+        probe.set_idle_cycles(4);
+        let error = recording_error(probe.detach().unwrap_err());
+        assert!(
+            matches!(error, RecordingError::Diverged { .. }),
+            "{}",
+            error
+        );
+
+        probe.detach().unwrap();
+        let error = recording_error(probe.detach().unwrap_err());
+        assert!(matches!(error, RecordingError::Ended(_)), "{}", error);
+
+        assert!(matches!(
+            recording_error(ReplayProbe::new(&b"probe-rs"[..]).unwrap_err()),
+            RecordingError::InvalidRecording
+        ));
+    }
+}
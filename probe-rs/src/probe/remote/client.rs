@@ -0,0 +1,268 @@
+use super::protocol::{receive, send, Command, Description, Failure, Reply, Request, VERSION};
+use super::RemoteError;
+use crate::architecture::arm::{DAPAccess, PortType};
+use crate::probe::{
+    BatchCommand, DebugProbe, DebugProbeError, DebugProbeInfo, JTAGAccess, WireProtocol,
+};
+use crate::Memory;
+use std::borrow::Cow;
+use std::net::TcpStream;
+
+/// The number of commands which are queued at most, before they are sent to the server.
+const MAX_BATCH_SIZE: usize = 256;
+
+/// A probe which is connected to a probe server.
+///
+/// Operations which don't return a value, like writes to DAP registers, are queued and sent
+/// together with the next operation which returns a value, so that a round trip to the server
+/// is only made when a value is needed.
+#[derive(Debug)]
+pub struct RemoteProbe {
+    stream: TcpStream,
+    name: String,
+    speed_khz: u32,
+    dap: bool,
+    jtag: bool,
+    batch: Vec<Command>,
+}
+
+impl RemoteProbe {
+    /// Connects to the probe server at `address`, and opens the probe with the serial number,
+    /// or the only probe of the server.
+    pub fn connect(address: &str, serial: Option<&str>) -> Result<Self, DebugProbeError> {
+        let mut stream = TcpStream::connect(address).map_err(RemoteError::from)?;
+
+        // The messages are small, and each of them is waited for.
+        stream.set_nodelay(true).map_err(RemoteError::from)?;
+
+        let open = Request::Open {
+            version: VERSION,
+            serial: serial.map(str::to_owned),
+        };
+        send(&mut stream, &open)?;
+
+        let description: Result<Description, Failure> =
+            receive(&mut stream)?.ok_or(RemoteError::ConnectionClosed)?;
+        let description = description?;
+
+        Ok(RemoteProbe {
+            stream,
+            name: format!("{} at {}", description.name, address),
+            speed_khz: description.speed_khz,
+            dap: description.dap,
+            jtag: description.jtag,
+            batch: Vec::new(),
+        })
+    }
+
+    /// Queues a command, which is sent with the next command that returns a value.
+    fn batch_add(&mut self, command: Command) -> Result<(), DebugProbeError> {
+        self.batch.push(command);
+
+        if self.batch.len() >= MAX_BATCH_SIZE {
+            self.process_batch()?;
+        }
+
+        Ok(())
+    }
+
+    /// Immediately sends the queued commands, and reports their errors.
+    fn process_batch(&mut self) -> Result<(), DebugProbeError> {
+        self.execute(None).map(|_| ())
+    }
+
+    /// Sends the queued commands followed by `command`, and returns the reply to `command`.
+    fn transact(&mut self, command: Command) -> Result<Reply, DebugProbeError> {
+        self.execute(Some(command))?
+            .ok_or_else(|| RemoteError::InvalidMessage.into())
+    }
+
+    fn execute(&mut self, command: Option<Command>) -> Result<Option<Reply>, DebugProbeError> {
+        let mut commands = std::mem::take(&mut self.batch);
+        let queued = commands.len();
+        commands.extend(command);
+
+        if commands.is_empty() {
+            return Ok(None);
+        }
+
+        log::debug!("Sending a batch of {} commands", commands.len());
+
+        send(&mut self.stream, &Request::Batch(Cow::Borrowed(&commands)))?;
+
+        let results: Vec<Result<Reply, Failure>> =
+            receive(&mut self.stream)?.ok_or(RemoteError::ConnectionClosed)?;
+        let count = results.len();
+
+        // The server stops at the first command which failed.
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(reply) if i == queued => return Ok(Some(reply)),
+                Ok(_) => (),
+                Err(failure) if i < queued => return Err(batch_error(&commands[i], failure)),
+                Err(failure) => return Err(failure.into()),
+            }
+        }
+
+        // Only queued commands were sent, and all of them succeeded.
+        if count == commands.len() {
+            Ok(None)
+        } else {
+            Err(RemoteError::InvalidMessage.into())
+        }
+    }
+}
+
+/// The error of a queued command, which was reported to the caller as successful.
+fn batch_error(command: &Command, failure: Failure) -> DebugProbeError {
+    log::warn!("Queued command {:?} failed: {:?}", command, failure);
+
+    match *command {
+        Command::DapWrite(port, addr, value) => {
+            DebugProbeError::BatchError(BatchCommand::Write(port, addr, value))
+        }
+        _ => failure.into(),
+    }
+}
+
+impl DebugProbe for RemoteProbe {
+    fn new_from_probe_info(info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Ok(Box::new(Self::connect(
+            &info.identifier,
+            info.serial_number.as_deref(),
+        )?))
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed_khz
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.speed_khz = self.transact(Command::SetSpeed(speed_khz))?.into_value()?;
+        Ok(self.speed_khz)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.transact(Command::Attach)?.into_done()
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.transact(Command::Detach)?.into_done()
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.transact(Command::TargetReset)?.into_done()
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.transact(Command::SelectProtocol(protocol))?
+            .into_done()
+    }
+
+    /// The memory interfaces of probes are not forwarded, the memory is accessed
+    /// through the DAP registers instead.
+    fn dedicated_memory_interface(&self) -> Option<Memory<'_>> {
+        None
+    }
+
+    fn get_interface_dap(&self) -> Option<&dyn DAPAccess> {
+        if self.dap {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_dap_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        if self.dap {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_jtag(&self) -> Option<&dyn JTAGAccess> {
+        if self.jtag {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_interface_jtag_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        if self.jtag {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+}
+
+impl DAPAccess for RemoteProbe {
+    fn read_register(&mut self, port: PortType, addr: u16) -> Result<u32, DebugProbeError> {
+        self.transact(Command::DapRead(port, addr))?.into_value()
+    }
+
+    fn read_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        self.transact(Command::DapReadBlock(port, addr, values.len() as u32))?
+            .into_values(values)
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.batch_add(Command::DapWrite(port, addr, value))
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        self.batch_add(Command::DapWriteBlock(port, addr, values.to_vec()))
+    }
+}
+
+impl JTAGAccess for RemoteProbe {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        self.transact(Command::JtagRead(address, len))?.into_bytes()
+    }
+
+    /// The idle cycles are sent with the next access.
+    fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        self.batch.push(Command::JtagIdleCycles(idle_cycles));
+    }
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        self.transact(Command::JtagWrite(address, data.to_vec(), len))?
+            .into_bytes()
+    }
+}
+
+impl Drop for RemoteProbe {
+    fn drop(&mut self) {
+        // We ignore the error case as we can't do much about it anyways.
+        let _ = self.process_batch();
+    }
+}
@@ -0,0 +1,196 @@
+//! Access to the probes of another machine over TCP.
+//!
+//! [`serve`] exposes the probes which are connected to a machine. A [`RemoteProbe`] opens one of
+//! them, and forwards the operations of the probe to the server. Both sides have to use the same
+//! version of the protocol, the server refuses other clients.
+//!
+//! The server has no authentication or encryption: anybody who can connect to it controls the
+//! probes, and with them the targets. Only expose it on a trusted network.
+
+mod client;
+pub(crate) mod protocol;
+pub(crate) mod server;
+
+pub use client::RemoteProbe;
+pub use server::serve;
+
+use crate::probe::DebugProbeError;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RemoteError {
+    #[error("Unable to communicate with the probe server: {0}")]
+    Io(#[from] io::Error),
+    #[error("The probe server sent an invalid message")]
+    InvalidMessage,
+    #[error("The probe server closed the connection")]
+    ConnectionClosed,
+    #[error("{0}")]
+    Server(String),
+    #[error("No probe with the serial number '{0}' is connected to the probe server")]
+    ProbeNotFound(String),
+    #[error(
+        "Multiple probes are connected to the probe server. Select one with 'host:port/serial'."
+    )]
+    MultipleProbes,
+    #[error("No probe is connected to the probe server")]
+    NoProbe,
+    #[error(
+        "The probe server uses version {server} of the protocol, but this is version {client}. Use the same version of probe-rs on both machines."
+    )]
+    VersionMismatch { client: u16, server: u16 },
+    #[error("The remote probe '{0}' is invalid, expected 'host:port' or 'host:port/serial'")]
+    InvalidSelector(String),
+}
+
+impl From<RemoteError> for DebugProbeError {
+    fn from(error: RemoteError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+/// Splits a `host:port/serial` selector into the address of the server and the serial number
+/// of the probe.
+pub(crate) fn parse_selector(selector: &str) -> Result<(&str, Option<&str>), RemoteError> {
+    let (address, serial) = match selector.split_once('/') {
+        Some((address, serial)) if !serial.is_empty() => (address, Some(serial)),
+        Some((address, _)) => (address, None),
+        None => (selector, None),
+    };
+
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok((address, serial))
+        }
+        _ => Err(RemoteError::InvalidSelector(selector.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::protocol::{receive, send, Description, Failure, Request, VERSION};
+    use super::{parse_selector, RemoteProbe};
+    use crate::architecture::arm::PortType;
+    use crate::probe::{
+        BatchCommand, DebugProbe, DebugProbeError, DebugProbeInfo, FakeProbe, Probe, WireProtocol,
+    };
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Serves a `FakeProbe` on a loopback port, and returns the address of the server.
+    fn fake_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            super::server::serve_with(listener, |serial| match serial {
+                None | Some("fake") => Ok(Box::new(FakeProbe::default()) as Box<dyn DebugProbe>),
+                Some(serial) => Err(super::RemoteError::ProbeNotFound(serial.to_owned()).into()),
+            })
+        });
+
+        address
+    }
+
+    #[test]
+    fn selectors_name_the_server_and_the_probe() {
+        assert_eq!(
+            parse_selector("lab:3000/0001").unwrap(),
+            ("lab:3000", Some("0001"))
+        );
+        assert_eq!(parse_selector("lab:3000").unwrap(), ("lab:3000", None));
+        assert!(parse_selector("lab/0001").is_err());
+        assert!(parse_selector("lab:port").is_err());
+    }
+
+    #[test]
+    fn remote_probe_forwards_the_operations() {
+        let address = fake_server();
+
+        let info = DebugProbeInfo::remote(&format!("{}/fake", address)).unwrap();
+        let mut probe = Probe::from_probe_info(&info).unwrap();
+
+        assert!(probe.get_name().starts_with("Mock probe for testing"));
+        assert_eq!(probe.set_speed(4_000).unwrap(), 4_000);
+        assert_eq!(probe.speed_khz(), 4_000);
+        probe.select_protocol(WireProtocol::Swd).unwrap();
+        probe.attach_to_unspecified().unwrap();
+
+        let dap = probe.get_interface_dap_mut().unwrap().unwrap();
+
+        // The writes are sent together with the read.
+        dap.write_register(PortType::AccessPort(0), 0x04, 0x2000_0000)
+            .unwrap();
+        dap.write_block(PortType::AccessPort(0), 0x0c, &[1, 2, 3])
+            .unwrap();
+        assert_eq!(
+            dap.read_register(PortType::AccessPort(0), 0x04).unwrap(),
+            0x2000_0000
+        );
+
+        let mut values = [0; 2];
+        dap.read_block(PortType::AccessPort(0), 0x0c, &mut values)
+            .unwrap();
+        assert_eq!(values, [3, 3]);
+
+        let jtag = probe.get_interface_jtag_mut().unwrap().unwrap();
+        jtag.set_idle_cycles(7);
+        assert_eq!(
+            jtag.write_register(0x11, &[0xaa, 0x55], 16).unwrap(),
+            vec![0, 0]
+        );
+        assert_eq!(jtag.read_register(0x11, 16).unwrap(), vec![0xaa, 0x55]);
+
+        probe.detach().unwrap();
+    }
+
+    #[test]
+    fn remote_errors_are_reported() {
+        let address = fake_server();
+
+        let error = RemoteProbe::connect(&address, Some("0002")).unwrap_err();
+        assert!(error.to_string().contains("'0002'"), "{}", error);
+
+        let mut probe = RemoteProbe::connect(&address, None).unwrap();
+
+        assert!(matches!(
+            probe.set_speed(0),
+            Err(DebugProbeError::UnsupportedSpeed(0))
+        ));
+
+        // A failed write is reported by the following operation.
+        let dap = probe.get_interface_dap_mut().unwrap();
+        dap.write_register(PortType::DebugPort, 0x08, 1).unwrap();
+        assert!(matches!(
+            dap.read_register(PortType::DebugPort, 0x08),
+            Err(DebugProbeError::BatchError(BatchCommand::Write(
+                PortType::DebugPort,
+                0x08,
+                1
+            )))
+        ));
+    }
+
+    #[test]
+    fn clients_with_another_version_are_refused() {
+        let address = fake_server();
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        let open = Request::Open {
+            version: VERSION + 1,
+            serial: None,
+        };
+        send(&mut stream, &open).unwrap();
+
+        let reply: Result<Description, Failure> = receive(&mut stream).unwrap().unwrap();
+        let failure = reply.unwrap_err();
+        assert_eq!(failure, Failure::VersionMismatch(VERSION));
+
+        let error = DebugProbeError::from(failure);
+        assert!(error.to_string().contains("version"), "{}", error);
+
+        // The server goes on serving the clients of its version.
+        assert!(RemoteProbe::connect(&address, None).is_ok());
+    }
+}
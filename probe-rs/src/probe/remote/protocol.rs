@@ -0,0 +1,704 @@
+//! The messages between the client and the server, and their binary encoding.
+//!
+//! Each message is sent as a frame, which starts with the length of the message as a 32 bit
+//! little endian number. The client opens a probe with an `Open` request, which starts with
+//! [`MAGIC`] and the [`VERSION`] of the protocol, and then sends batches of commands. The server executes the commands of a batch in order, until one of them
+//! fails, and replies with their results.
+
+use super::RemoteError;
+use crate::architecture::arm::PortType;
+use crate::probe::{DebugProbeError, WireProtocol};
+use scroll::{Pread, LE};
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+/// The start of an `Open` request, which tells a probe server apart from other services.
+pub(crate) const MAGIC: &[u8; 8] = b"probe-rs";
+
+/// The version of the protocol. A server refuses clients with another version.
+pub(crate) const VERSION: u16 = 1;
+
+/// The largest message which is accepted, to limit the memory used for a corrupted frame.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Request<'a> {
+    /// Opens the probe with the serial number, or the only probe of the server.
+    Open {
+        version: u16,
+        serial: Option<String>,
+    },
+    Batch(Cow<'a, [Command]>),
+}
+
+/// An operation of a `DebugProbe`, `DAPAccess` or `JTAGAccess`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    SetSpeed(u32),
+    Attach,
+    Detach,
+    TargetReset,
+    SelectProtocol(WireProtocol),
+    DapRead(PortType, u16),
+    DapReadBlock(PortType, u16, u32),
+    DapWrite(PortType, u16, u32),
+    DapWriteBlock(PortType, u16, Vec<u32>),
+    JtagRead(u32, u32),
+    JtagWrite(u32, Vec<u8>, u32),
+    JtagIdleCycles(u8),
+}
+
+/// The result of a successful command.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Reply {
+    Done,
+    Value(u32),
+    Values(Vec<u32>),
+    Bytes(Vec<u8>),
+}
+
+/// The probe which the server opened.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Description {
+    pub(crate) name: String,
+    pub(crate) speed_khz: u32,
+    pub(crate) dap: bool,
+    pub(crate) jtag: bool,
+}
+
+/// A failed command. The errors which callers of a probe handle are kept,
+/// the others are passed on as their message.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Failure {
+    NotAttached,
+    Attached,
+    Timeout,
+    UnsupportedSpeed(u32),
+    UnsupportedProtocol(WireProtocol),
+    InterfaceNotAvailable(String),
+    Other(String),
+    /// The client uses another version of the protocol than the server, which is given.
+    VersionMismatch(u16),
+}
+
+impl Reply {
+    pub(crate) fn into_done(self) -> Result<(), DebugProbeError> {
+        match self {
+            Reply::Done => Ok(()),
+            _ => Err(RemoteError::InvalidMessage.into()),
+        }
+    }
+
+    pub(crate) fn into_value(self) -> Result<u32, DebugProbeError> {
+        match self {
+            Reply::Value(value) => Ok(value),
+            _ => Err(RemoteError::InvalidMessage.into()),
+        }
+    }
+
+    /// The values of a block read, which has to be as long as `values`.
+    pub(crate) fn into_values(self, values: &mut [u32]) -> Result<(), DebugProbeError> {
+        match self {
+            Reply::Values(data) if data.len() == values.len() => {
+                values.copy_from_slice(&data);
+                Ok(())
+            }
+            _ => Err(RemoteError::InvalidMessage.into()),
+        }
+    }
+
+    pub(crate) fn into_bytes(self) -> Result<Vec<u8>, DebugProbeError> {
+        match self {
+            Reply::Bytes(data) => Ok(data),
+            _ => Err(RemoteError::InvalidMessage.into()),
+        }
+    }
+}
+
+impl From<&DebugProbeError> for Failure {
+    fn from(error: &DebugProbeError) -> Self {
+        match error {
+            DebugProbeError::NotAttached => Failure::NotAttached,
+            DebugProbeError::Attached => Failure::Attached,
+            DebugProbeError::Timeout => Failure::Timeout,
+            DebugProbeError::UnsupportedSpeed(speed) => Failure::UnsupportedSpeed(*speed),
+            DebugProbeError::UnsupportedProtocol(protocol) => {
+                Failure::UnsupportedProtocol(*protocol)
+            }
+            DebugProbeError::InterfaceNotAvailable(interface) => {
+                Failure::InterfaceNotAvailable((*interface).to_owned())
+            }
+            DebugProbeError::ProbeSpecific(error) => Failure::Other(error.to_string()),
+            error => Failure::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<Failure> for DebugProbeError {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::NotAttached => DebugProbeError::NotAttached,
+            Failure::Attached => DebugProbeError::Attached,
+            Failure::Timeout => DebugProbeError::Timeout,
+            Failure::UnsupportedSpeed(speed) => DebugProbeError::UnsupportedSpeed(speed),
+            Failure::UnsupportedProtocol(protocol) => {
+                DebugProbeError::UnsupportedProtocol(protocol)
+            }
+            Failure::InterfaceNotAvailable(interface) => match interface.as_str() {
+                "ARM" => DebugProbeError::InterfaceNotAvailable("ARM"),
+                "JTAG" => DebugProbeError::InterfaceNotAvailable("JTAG"),
+                _ => RemoteError::Server(format!("The interface '{}' is not available", interface))
+                    .into(),
+            },
+            Failure::Other(message) => RemoteError::Server(message).into(),
+            Failure::VersionMismatch(server) => RemoteError::VersionMismatch {
+                client: VERSION,
+                server,
+            }
+            .into(),
+        }
+    }
+}
+
+/// Sends a message as a single frame.
+pub(crate) fn send(writer: &mut impl Write, message: &impl Encode) -> Result<(), RemoteError> {
+    let mut buffer = vec![0; 4];
+    message.encode(&mut buffer);
+
+    let len = (buffer.len() - 4) as u32;
+    buffer[..4].copy_from_slice(&len.to_le_bytes());
+
+    writer.write_all(&buffer)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Receives a message. `None` is returned if the connection was closed before the message.
+pub(crate) fn receive<T: Decode>(reader: &mut impl Read) -> Result<Option<T>, RemoteError> {
+    let mut len = [0; 4];
+
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+
+    if len > MAX_MESSAGE_SIZE {
+        return Err(RemoteError::InvalidMessage);
+    }
+
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+
+    let mut decoder = Decoder {
+        data: &data,
+        offset: 0,
+    };
+    let message = T::decode(&mut decoder)?;
+
+    // Trailing data means that the peer speaks a different protocol.
+    if decoder.offset != data.len() {
+        return Err(RemoteError::InvalidMessage);
+    }
+
+    Ok(Some(message))
+}
+
+pub(crate) trait Encode {
+    fn encode(&self, buffer: &mut Vec<u8>);
+}
+
+pub(crate) trait Decode: Sized {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError>;
+}
+
+pub(crate) struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Decoder<'_> {
+    fn u8(&mut self) -> Result<u8, RemoteError> {
+        self.data
+            .gread_with(&mut self.offset, LE)
+            .map_err(|_| RemoteError::InvalidMessage)
+    }
+
+    fn u16(&mut self) -> Result<u16, RemoteError> {
+        self.data
+            .gread_with(&mut self.offset, LE)
+            .map_err(|_| RemoteError::InvalidMessage)
+    }
+
+    fn u32(&mut self) -> Result<u32, RemoteError> {
+        self.data
+            .gread_with(&mut self.offset, LE)
+            .map_err(|_| RemoteError::InvalidMessage)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], RemoteError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(RemoteError::InvalidMessage)?;
+
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+
+        Ok(bytes)
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        decoder.u8()
+    }
+}
+
+impl Encode for u16 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u16 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        decoder.u16()
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u32 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        decoder.u32()
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        (*self as u8).encode(buffer);
+    }
+}
+
+impl Decode for bool {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        match decoder.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(RemoteError::InvalidMessage),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u32).encode(buffer);
+        buffer.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        let len = decoder.u32()? as usize;
+        let bytes = decoder.bytes(len)?;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| RemoteError::InvalidMessage)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        (self.len() as u32).encode(buffer);
+
+        for item in self {
+            item.encode(buffer);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.as_slice().encode(buffer);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        let len = decoder.u32()? as usize;
+
+        // Every item takes at least a byte, so a larger length is corrupted.
+        if len > decoder.data.len() - decoder.offset {
+            return Err(RemoteError::InvalidMessage);
+        }
+
+        (0..len).map(|_| T::decode(decoder)).collect()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            None => buffer.push(0),
+            Some(value) => {
+                buffer.push(1);
+                value.encode(buffer);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        match decoder.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(decoder)?)),
+            _ => Err(RemoteError::InvalidMessage),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Result<T, Failure> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Ok(value) => {
+                buffer.push(0);
+                value.encode(buffer);
+            }
+            Err(failure) => {
+                buffer.push(1);
+                failure.encode(buffer);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Result<T, Failure> {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        match decoder.u8()? {
+            0 => Ok(Ok(T::decode(decoder)?)),
+            1 => Ok(Err(Failure::decode(decoder)?)),
+            _ => Err(RemoteError::InvalidMessage),
+        }
+    }
+}
+
+impl Encode for WireProtocol {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            WireProtocol::Swd => buffer.push(0),
+            WireProtocol::Jtag => buffer.push(1),
+        }
+    }
+}
+
+impl Decode for WireProtocol {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        match decoder.u8()? {
+            0 => Ok(WireProtocol::Swd),
+            1 => Ok(WireProtocol::Jtag),
+            _ => Err(RemoteError::InvalidMessage),
+        }
+    }
+}
+
+impl Encode for PortType {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        u16::from(*self).encode(buffer);
+    }
+}
+
+impl Decode for PortType {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        Ok(decoder.u16()?.into())
+    }
+}
+
+impl Encode for Request<'_> {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Request::Open { version, serial } => {
+                buffer.push(0);
+                buffer.extend_from_slice(MAGIC);
+                version.encode(buffer);
+                serial.encode(buffer);
+            }
+            Request::Batch(commands) => {
+                buffer.push(1);
+                commands.as_ref().encode(buffer);
+            }
+        }
+    }
+}
+
+impl Decode for Request<'_> {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        match decoder.u8()? {
+            0 => {
+                if decoder.bytes(MAGIC.len())? != MAGIC {
+                    return Err(RemoteError::InvalidMessage);
+                }
+
+                let version = decoder.u16()?;
+
+                // The rest of the request is unknown for other versions, which are refused.
+                let serial = if version == VERSION {
+                    Decode::decode(decoder)?
+                } else {
+                    decoder.offset = decoder.data.len();
+                    None
+                };
+
+                Ok(Request::Open { version, serial })
+            }
+            1 => Ok(Request::Batch(Cow::Owned(Decode::decode(decoder)?))),
+            _ => Err(RemoteError::InvalidMessage),
+        }
+    }
+}
+
+impl Encode for Command {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Command::SetSpeed(speed_khz) => {
+                buffer.push(0);
+                speed_khz.encode(buffer);
+            }
+            Command::Attach => buffer.push(1),
+            Command::Detach => buffer.push(2),
+            Command::TargetReset => buffer.push(3),
+            Command::SelectProtocol(protocol) => {
+                buffer.push(4);
+                protocol.encode(buffer);
+            }
+            Command::DapRead(port, addr) => {
+                buffer.push(5);
+                port.encode(buffer);
+                addr.encode(buffer);
+            }
+            Command::DapReadBlock(port, addr, len) => {
+                buffer.push(6);
+                port.encode(buffer);
+                addr.encode(buffer);
+                len.encode(buffer);
+            }
+            Command::DapWrite(port, addr, value) => {
+                buffer.push(7);
+                port.encode(buffer);
+                addr.encode(buffer);
+                value.encode(buffer);
+            }
+            Command::DapWriteBlock(port, addr, values) => {
+                buffer.push(8);
+                port.encode(buffer);
+                addr.encode(buffer);
+                values.encode(buffer);
+            }
+            Command::JtagRead(address, len) => {
+                buffer.push(9);
+                address.encode(buffer);
+                len.encode(buffer);
+            }
+            Command::JtagWrite(address, data, len) => {
+                buffer.push(10);
+                address.encode(buffer);
+                data.encode(buffer);
+                len.encode(buffer);
+            }
+            Command::JtagIdleCycles(idle_cycles) => {
+                buffer.push(11);
+                idle_cycles.encode(buffer);
+            }
+        }
+    }
+}
+
+impl Decode for Command {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        let command = match decoder.u8()? {
+            0 => Command::SetSpeed(decoder.u32()?),
+            1 => Command::Attach,
+            2 => Command::Detach,
+            3 => Command::TargetReset,
+            4 => Command::SelectProtocol(WireProtocol::decode(decoder)?),
+            5 => Command::DapRead(PortType::decode(decoder)?, decoder.u16()?),
+            6 => Command::DapReadBlock(PortType::decode(decoder)?, decoder.u16()?, decoder.u32()?),
+            7 => Command::DapWrite(PortType::decode(decoder)?, decoder.u16()?, decoder.u32()?),
+            8 => Command::DapWriteBlock(
+                PortType::decode(decoder)?,
+                decoder.u16()?,
+                Decode::decode(decoder)?,
+            ),
+            9 => Command::JtagRead(decoder.u32()?, decoder.u32()?),
+            10 => Command::JtagWrite(decoder.u32()?, Decode::decode(decoder)?, decoder.u32()?),
+            11 => Command::JtagIdleCycles(decoder.u8()?),
+            _ => return Err(RemoteError::InvalidMessage),
+        };
+
+        Ok(command)
+    }
+}
+
+impl Encode for Reply {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Reply::Done => buffer.push(0),
+            Reply::Value(value) => {
+                buffer.push(1);
+                value.encode(buffer);
+            }
+            Reply::Values(values) => {
+                buffer.push(2);
+                values.encode(buffer);
+            }
+            Reply::Bytes(bytes) => {
+                buffer.push(3);
+                bytes.encode(buffer);
+            }
+        }
+    }
+}
+
+impl Decode for Reply {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        match decoder.u8()? {
+            0 => Ok(Reply::Done),
+            1 => Ok(Reply::Value(decoder.u32()?)),
+            2 => Ok(Reply::Values(Decode::decode(decoder)?)),
+            3 => Ok(Reply::Bytes(Decode::decode(decoder)?)),
+            _ => Err(RemoteError::InvalidMessage),
+        }
+    }
+}
+
+impl Encode for Description {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        self.name.encode(buffer);
+        self.speed_khz.encode(buffer);
+        self.dap.encode(buffer);
+        self.jtag.encode(buffer);
+    }
+}
+
+impl Decode for Description {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        Ok(Description {
+            name: Decode::decode(decoder)?,
+            speed_khz: decoder.u32()?,
+            dap: Decode::decode(decoder)?,
+            jtag: Decode::decode(decoder)?,
+        })
+    }
+}
+
+impl Encode for Failure {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Failure::NotAttached => buffer.push(0),
+            Failure::Attached => buffer.push(1),
+            Failure::Timeout => buffer.push(2),
+            Failure::UnsupportedSpeed(speed_khz) => {
+                buffer.push(3);
+                speed_khz.encode(buffer);
+            }
+            Failure::UnsupportedProtocol(protocol) => {
+                buffer.push(4);
+                protocol.encode(buffer);
+            }
+            Failure::InterfaceNotAvailable(interface) => {
+                buffer.push(5);
+                interface.encode(buffer);
+            }
+            Failure::Other(message) => {
+                buffer.push(6);
+                message.encode(buffer);
+            }
+            Failure::VersionMismatch(version) => {
+                buffer.push(7);
+                version.encode(buffer);
+            }
+        }
+    }
+}
+
+impl Decode for Failure {
+    fn decode(decoder: &mut Decoder) -> Result<Self, RemoteError> {
+        let failure = match decoder.u8()? {
+            0 => Failure::NotAttached,
+            1 => Failure::Attached,
+            2 => Failure::Timeout,
+            3 => Failure::UnsupportedSpeed(decoder.u32()?),
+            4 => Failure::UnsupportedProtocol(WireProtocol::decode(decoder)?),
+            5 => Failure::InterfaceNotAvailable(Decode::decode(decoder)?),
+            6 => Failure::Other(Decode::decode(decoder)?),
+            7 => Failure::VersionMismatch(decoder.u16()?),
+            _ => return Err(RemoteError::InvalidMessage),
+        };
+
+        Ok(failure)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{receive, send, Command, Failure, Reply, Request, VERSION};
+    use crate::architecture::arm::PortType;
+    use crate::probe::WireProtocol;
+    use std::borrow::Cow;
+
+    #[test]
+    fn messages_are_decoded_like_they_were_encoded() {
+        let request = Request::Batch(Cow::Owned(vec![
+            Command::SelectProtocol(WireProtocol::Swd),
+            Command::DapWrite(PortType::AccessPort(1), 0x04, 0x2000_0000),
+            Command::DapWriteBlock(PortType::AccessPort(1), 0x0c, vec![1, 2, 3]),
+            Command::DapRead(PortType::DebugPort, 0x00),
+            Command::JtagWrite(0x11, vec![0xaa, 0x55], 12),
+        ]));
+
+        let mut data = vec![];
+        send(&mut data, &request).unwrap();
+        assert_eq!(receive::<Request>(&mut &data[..]).unwrap(), Some(request));
+
+        let results: Vec<Result<Reply, Failure>> = vec![
+            Ok(Reply::Done),
+            Ok(Reply::Values(vec![7, 8])),
+            Err(Failure::UnsupportedSpeed(50_000)),
+        ];
+
+        let mut data = vec![];
+        send(&mut data, &results).unwrap();
+        assert_eq!(receive(&mut &data[..]).unwrap(), Some(results));
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let mut data = vec![];
+        let request = Request::Open {
+            version: VERSION,
+            serial: Some("0001".to_owned()),
+        };
+        send(&mut data, &request).unwrap();
+
+        // The length still covers the whole message.
+        data.truncate(data.len() - 1);
+        assert!(receive::<Request>(&mut &data[..]).is_err());
+
+        // A closed connection is not an error.
+        assert_eq!(receive::<Request>(&mut &[][..]).unwrap(), None);
+    }
+}
@@ -0,0 +1,189 @@
+use super::protocol::{receive, send, Command, Description, Failure, Reply, Request, VERSION};
+use super::RemoteError;
+use crate::architecture::arm::DAPAccess;
+use crate::probe::{DebugProbe, DebugProbeError, JTAGAccess, Probe};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+
+/// The largest number of values which are read from a DAP register in one command.
+const MAX_BLOCK_LEN: u32 = 1024 * 1024;
+
+/// Serves the probes which are connected to this machine to [`RemoteProbe`]s.
+///
+/// The connections are served one after another, as a probe can only be used by one
+/// client at a time. Anybody who can connect is served, so only listen on a trusted network.
+///
+/// [`RemoteProbe`]: super::RemoteProbe
+pub fn serve(listener: TcpListener) -> io::Result<()> {
+    serve_with(listener, open_local_probe)
+}
+
+/// Serves the probes which `open` opens by their serial number.
+pub(super) fn serve_with(
+    listener: TcpListener,
+    mut open: impl FnMut(Option<&str>) -> Result<Box<dyn DebugProbe>, DebugProbeError>,
+) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+
+        log::info!("Accepted a connection from {}", peer);
+
+        match serve_connection(stream, &mut open) {
+            Ok(()) => log::info!("Closed the connection from {}", peer),
+            Err(e) => log::warn!("The connection from {} failed: {}", peer, e),
+        }
+    }
+
+    Ok(())
+}
+
+fn serve_connection(
+    mut stream: TcpStream,
+    open: &mut impl FnMut(Option<&str>) -> Result<Box<dyn DebugProbe>, DebugProbeError>,
+) -> Result<(), RemoteError> {
+    stream.set_nodelay(true)?;
+
+    let serial = match receive(&mut stream)? {
+        Some(Request::Open { version, serial }) if version == VERSION => serial,
+        Some(Request::Open { version, .. }) => {
+            log::warn!(
+                "Refusing a client with version {} of the protocol, instead of {}",
+                version,
+                VERSION
+            );
+            let failure: Result<Description, Failure> = Err(Failure::VersionMismatch(VERSION));
+            return send(&mut stream, &failure);
+        }
+        Some(Request::Batch(_)) => return Err(RemoteError::InvalidMessage),
+        None => return Ok(()),
+    };
+
+    let mut probe = match open(serial.as_deref()) {
+        Ok(probe) => probe,
+        Err(e) => {
+            log::warn!("Unable to open the probe: {}", e);
+            let failure: Result<Description, Failure> = Err(Failure::from(&e));
+            return send(&mut stream, &failure);
+        }
+    };
+
+    let description: Result<Description, Failure> = Ok(Description {
+        name: probe.get_name().to_owned(),
+        speed_khz: probe.speed(),
+        dap: probe.get_interface_dap().is_some(),
+        jtag: probe.get_interface_jtag().is_some(),
+    });
+    send(&mut stream, &description)?;
+
+    // The probe is detached when it is dropped, once the client closed the connection.
+    while let Some(request) = receive(&mut stream)? {
+        let commands = match request {
+            Request::Batch(commands) => commands,
+            Request::Open { .. } => return Err(RemoteError::InvalidMessage),
+        };
+
+        let mut results: Vec<Result<Reply, Failure>> = Vec::with_capacity(commands.len());
+
+        for command in commands.iter() {
+            let result = execute(probe.as_mut(), command).map_err(|e| Failure::from(&e));
+            let failed = result.is_err();
+
+            results.push(result);
+
+            // The following commands may depend on the failed one.
+            if failed {
+                break;
+            }
+        }
+
+        send(&mut stream, &results)?;
+    }
+
+    Ok(())
+}
+
+/// Executes a command on a probe.
+pub(crate) fn execute(
+    probe: &mut dyn DebugProbe,
+    command: &Command,
+) -> Result<Reply, DebugProbeError> {
+    let reply = match *command {
+        Command::SetSpeed(speed_khz) => Reply::Value(probe.set_speed(speed_khz)?),
+        Command::Attach => {
+            probe.attach()?;
+            Reply::Done
+        }
+        Command::Detach => {
+            probe.detach()?;
+            Reply::Done
+        }
+        Command::TargetReset => {
+            probe.target_reset()?;
+            Reply::Done
+        }
+        Command::SelectProtocol(protocol) => {
+            probe.select_protocol(protocol)?;
+            Reply::Done
+        }
+        Command::DapRead(port, addr) => Reply::Value(dap(probe)?.read_register(port, addr)?),
+        Command::DapReadBlock(port, addr, len) => {
+            if len > MAX_BLOCK_LEN {
+                return Err(RemoteError::InvalidMessage.into());
+            }
+
+            let mut values = vec![0; len as usize];
+            dap(probe)?.read_block(port, addr, &mut values)?;
+            Reply::Values(values)
+        }
+        Command::DapWrite(port, addr, value) => {
+            dap(probe)?.write_register(port, addr, value)?;
+            Reply::Done
+        }
+        Command::DapWriteBlock(port, addr, ref values) => {
+            dap(probe)?.write_block(port, addr, values)?;
+            Reply::Done
+        }
+        Command::JtagRead(address, len) => Reply::Bytes(jtag(probe)?.read_register(address, len)?),
+        Command::JtagWrite(address, ref data, len) => {
+            Reply::Bytes(jtag(probe)?.write_register(address, data, len)?)
+        }
+        Command::JtagIdleCycles(idle_cycles) => {
+            jtag(probe)?.set_idle_cycles(idle_cycles);
+            Reply::Done
+        }
+    };
+
+    Ok(reply)
+}
+
+fn dap(probe: &mut dyn DebugProbe) -> Result<&mut dyn DAPAccess, DebugProbeError> {
+    probe
+        .get_interface_dap_mut()
+        .ok_or(DebugProbeError::InterfaceNotAvailable("ARM"))
+}
+
+fn jtag(probe: &mut dyn DebugProbe) -> Result<&mut dyn JTAGAccess, DebugProbeError> {
+    probe
+        .get_interface_jtag_mut()
+        .ok_or(DebugProbeError::InterfaceNotAvailable("JTAG"))
+}
+
+/// Opens the probe with the serial number, or the only probe which is connected.
+fn open_local_probe(serial: Option<&str>) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+    let probes = Probe::list_all();
+
+    let info = match serial {
+        Some(serial) => probes
+            .iter()
+            .find(|info| info.serial_number.as_deref() == Some(serial))
+            .ok_or_else(|| RemoteError::ProbeNotFound(serial.to_owned()))?,
+        None => match probes.as_slice() {
+            [info] => info,
+            [] => return Err(RemoteError::NoProbe.into()),
+            _ => return Err(RemoteError::MultipleProbes.into()),
+        },
+    };
+
+    Ok(Probe::from_probe_info(info)?.inner)
+}
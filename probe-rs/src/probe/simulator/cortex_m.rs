@@ -0,0 +1,1186 @@
+use super::FlashAndRam;
+use crate::architecture::arm::{DAPAccess, DapError, PortType};
+use crate::config::Target;
+use crate::probe::{DebugProbe, DebugProbeError, DebugProbeInfo, JTAGAccess, WireProtocol};
+use crate::{CoreType, Memory};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const FLASH: Range<u32> = 0x0000_0000..0x0001_0000;
+
+const RAM: Range<u32> = 0x2000_0000..0x2001_0000;
+
+/// The private peripheral bus, with the debug components of the core.
+const PPB: Range<u32> = 0xE000_0000..0xE010_0000;
+
+/// The DPIDR of a Cortex-M4.
+const DPIDR: u32 = 0x2ba0_1477;
+const CTRL_STAT_STICKYERR: u32 = 1 << 5;
+/// CSYSPWRUPREQ and CDBGPWRUPREQ, which are acknowledged by the next higher bits.
+const CTRL_STAT_POWER_REQUESTS: u32 = 0x5000_0000;
+const ABORT_STKERRCLR: u32 = 1 << 2;
+
+/// The IDR of an AHB-AP.
+const AHB_AP_IDR: u32 = 0x2477_0011;
+const AP_CSW: u8 = 0x00;
+const AP_TAR: u8 = 0x04;
+const AP_DRW: u8 = 0x0C;
+const AP_BASE: u8 = 0xF8;
+const AP_IDR: u8 = 0xFC;
+const CSW_SIZE: u32 = 0b111;
+const CSW_ADDR_INC: u32 = 0b11 << 4;
+const CSW_DEVICE_EN: u32 = 1 << 6;
+const CSW_TR_IN_PROG: u32 = 1 << 7;
+
+const ROM_TABLE: u32 = 0xE00F_F000;
+/// The ROM table entries, which point to the SCS, the DWT and the FPB.
+const ROM_TABLE_ENTRIES: [u32; 3] = [0xFFF0_F003, 0xFFF0_2003, 0xFFF0_3003];
+const ROM_TABLE_MEMTYPE: u32 = ROM_TABLE + 0xFCC;
+
+/// The base address, the peripheral ID (PIDR0 to PIDR4) and the class of the CoreSight
+/// components.
+const COMPONENTS: [(u32, [u32; 5], u32); 4] = [
+    (ROM_TABLE, [0xC4, 0xB4, 0x0B, 0x00, 0x04], 0x1),
+    (0xE000_E000, [0x0C, 0xB0, 0x0B, 0x00, 0x04], 0xE),
+    (0xE000_1000, [0x02, 0xB0, 0x3B, 0x00, 0x04], 0xE),
+    (0xE000_2000, [0x03, 0xB0, 0x2B, 0x00, 0x04], 0xE),
+];
+
+const CPUID: u32 = 0xE000_ED00;
+/// The CPUID of a Cortex-M4 r0p1.
+const CPUID_VALUE: u32 = 0x410F_C241;
+
+const AIRCR: u32 = 0xE000_ED0C;
+const AIRCR_VECTKEY: u32 = 0x05FA;
+const AIRCR_VECTKEYSTAT: u32 = 0xFA05 << 16;
+/// PRIGROUP, the only field which is stored.
+const AIRCR_PRIGROUP: u32 = 0b111 << 8;
+/// SYSRESETREQ and VECTRESET, which both reset the simulated chip.
+const AIRCR_RESET: u32 = 0b101;
+
+const DFSR: u32 = 0xE000_ED30;
+const DFSR_HALTED: u32 = 1 << 0;
+const DFSR_BKPT: u32 = 1 << 1;
+const DFSR_VCATCH: u32 = 1 << 3;
+
+const DHCSR: u32 = 0xE000_EDF0;
+const DHCSR_KEY: u32 = 0xA05F;
+const DHCSR_C_DEBUGEN: u32 = 1 << 0;
+const DHCSR_C_HALT: u32 = 1 << 1;
+const DHCSR_C_STEP: u32 = 1 << 2;
+const DHCSR_C_MASKINTS: u32 = 1 << 3;
+const DHCSR_S_REGRDY: u32 = 1 << 16;
+const DHCSR_S_HALT: u32 = 1 << 17;
+const DHCSR_S_SLEEP: u32 = 1 << 18;
+const DHCSR_S_LOCKUP: u32 = 1 << 19;
+const DHCSR_S_RESET_ST: u32 = 1 << 25;
+
+const DCRSR: u32 = 0xE000_EDF4;
+const DCRSR_REGSEL: u32 = 0x7F;
+const DCRSR_REGWNR: u32 = 1 << 16;
+const DCRDR: u32 = 0xE000_EDF8;
+
+const DEMCR: u32 = 0xE000_EDFC;
+const DEMCR_VC_CORERESET: u32 = 1 << 0;
+
+const DWT_CTRL: u32 = 0xE000_1000;
+/// NUMCOMP of DWT_CTRL, four comparators.
+const DWT_CTRL_NUMCOMP: u32 = 4 << 28;
+
+const FP_CTRL: u32 = 0xE000_2000;
+/// Revision 0 with two literal comparators and six code comparators.
+const FP_CTRL_VALUE: u32 = 0x0000_0260;
+const FP_CTRL_KEY: u32 = 1 << 1;
+const FP_CTRL_ENABLE: u32 = 1 << 0;
+const FP_COMP: u32 = 0xE000_2008;
+const FP_CODE_COMPARATORS: usize = 6;
+const FP_COMPARATORS: usize = 8;
+
+/// The numbers of the core registers, as selected by DCRSR.
+const REGISTERS: usize = 0x80;
+const SP: usize = 13;
+const LR: usize = 14;
+const PC: usize = 15;
+const XPSR: usize = 16;
+const MSP: usize = 17;
+const XPSR_THUMB: u32 = 1 << 24;
+
+/// The instructions which are executed at most, each time the state of a running core is read.
+const INSTRUCTIONS_PER_POLL: usize = 1_000;
+
+const THUMB_BX_LR: u16 = 0x4770;
+const THUMB_WFI: u16 = 0xBF30;
+
+/// A Cortex-M4 with 64 KiB of flash and 64 KiB of RAM, which is debugged through its
+/// debug port.
+///
+/// The debug port has an AHB-AP, which gives access to the memory and to the debug components
+/// of the core: a ROM table, the SCS with the halting debug registers, a DWT and an FPB.
+/// The core executes a few Thumb instructions, `BKPT`, `B`, `BX LR` and `WFI`, and those of
+/// unoptimized function calls: `BL`, `PUSH`, `POP`, `MOV` and `ADD` of registers, `MOVS`,
+/// `LDR` and `STR` relative to the SP and adjusting the SP. It treats all others as `NOP`s.
+/// `SVC #n` carries out the routine `n` of the flash algorithm of [`target`], as the flash is
+/// read-only over the bus.
+///
+/// [`target`]: SimulatedCortexM::target
+#[derive(Clone)]
+pub struct SimulatedCortexM {
+    chip: Arc<Mutex<Chip>>,
+    speed_khz: u32,
+    attached: bool,
+}
+
+impl SimulatedCortexM {
+    /// Creates a chip with erased flash, which was just powered up.
+    pub fn new() -> Self {
+        let mut chip = Chip {
+            ctrl_stat: 0,
+            sticky_error: false,
+            select: 0,
+            read_buffer: 0,
+            csw: 0,
+            tar: 0,
+            memory: FlashAndRam::new(FLASH, RAM),
+            peripherals: HashMap::new(),
+            registers: [0; REGISTERS],
+            halted: false,
+            sleeping: false,
+            locked_up: false,
+            reset: false,
+            resumed: false,
+            dhcsr: 0,
+            dcrdr: 0,
+            demcr: 0,
+            dfsr: 0,
+            fp_enabled: false,
+            fp_comp: [0; FP_COMPARATORS],
+        };
+
+        chip.reset();
+
+        SimulatedCortexM {
+            chip: Arc::new(Mutex::new(chip)),
+            speed_khz: 1_000,
+            attached: false,
+        }
+    }
+
+    /// The description of the simulated chip, to attach to it.
+    pub fn target() -> Target {
+        // Each routine is `SVC #n; BX LR`.
+        super::target("Simulated Cortex-M4", CoreType::M4, FLASH, RAM, |routine| {
+            let svc = 0xDF00 | routine as u16;
+            [svc.to_le_bytes(), THUMB_BX_LR.to_le_bytes()].concat()
+        })
+    }
+
+    /// Reads the flash or the RAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not fit into the flash or the RAM.
+    pub fn read_memory(&self, address: u32, data: &mut [u8]) {
+        self.chip().memory.read(address, data);
+    }
+
+    /// Writes the flash or the RAM, like a programmer would.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not fit into the flash or the RAM.
+    pub fn write_memory(&self, address: u32, data: &[u8]) {
+        self.chip().memory.write(address, data);
+    }
+
+    /// Returns the value of the core register, numbered like in DCRSR.
+    pub fn core_register(&self, register: u16) -> u32 {
+        self.chip().registers[register_index(u32::from(register))]
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.chip().halted
+    }
+
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.chip.lock().unwrap()
+    }
+
+    fn check_attached(&self) -> Result<(), DebugProbeError> {
+        if self.attached {
+            Ok(())
+        } else {
+            Err(DebugProbeError::NotAttached)
+        }
+    }
+}
+
+impl Default for SimulatedCortexM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SimulatedCortexM {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulatedCortexM")
+            .field("speed_khz", &self.speed_khz)
+            .field("attached", &self.attached)
+            .finish()
+    }
+}
+
+/// The index of a core register, where MSP is the same as SP.
+fn register_index(regsel: u32) -> usize {
+    match (regsel & DCRSR_REGSEL) as usize {
+        MSP => SP,
+        index => index,
+    }
+}
+
+/// The registers of the list of a `PUSH` or `POP`, in ascending order.
+/// Bit 8 of the instruction selects `extra`, the LR or the PC.
+fn stacked_registers(instruction: u16, extra: usize) -> Vec<usize> {
+    let mut registers: Vec<usize> = (0..8).filter(|&n| instruction & (1 << n) != 0).collect();
+
+    if instruction & 0x100 != 0 {
+        registers.push(extra);
+    }
+
+    registers
+}
+
+/// The identification registers of the CoreSight components.
+fn identification_register(address: u32) -> Option<u32> {
+    let (_, pidr, class) = COMPONENTS
+        .iter()
+        .find(|(base, ..)| address & !0xFFF == *base)?;
+
+    match address & 0xFFF {
+        0xFD0 => Some(pidr[4]),
+        0xFD4..=0xFDC => Some(0),
+        offset @ 0xFE0..=0xFEC => Some(pidr[(offset - 0xFE0) as usize / 4]),
+        0xFF0 => Some(0x0D),
+        0xFF4 => Some(class << 4),
+        0xFF8 => Some(0x05),
+        0xFFC => Some(0xB1),
+        _ => None,
+    }
+}
+
+/// An access to memory which is not mapped, or which is read-only.
+struct BusFault;
+
+/// The state of the simulated chip, from the debug port to the core.
+struct Chip {
+    ctrl_stat: u32,
+    sticky_error: bool,
+    select: u32,
+    read_buffer: u32,
+
+    csw: u32,
+    tar: u32,
+
+    memory: FlashAndRam,
+    /// The registers of the private peripheral bus, which have no other function.
+    peripherals: HashMap<u32, u32>,
+
+    registers: [u32; REGISTERS],
+    halted: bool,
+    sleeping: bool,
+    locked_up: bool,
+    /// Whether the chip was reset since DHCSR was read.
+    reset: bool,
+    /// Whether the core was resumed, so that a breakpoint at the first instruction is ignored.
+    resumed: bool,
+    /// The control bits of DHCSR.
+    dhcsr: u32,
+    dcrdr: u32,
+    demcr: u32,
+    dfsr: u32,
+    fp_enabled: bool,
+    fp_comp: [u32; FP_COMPARATORS],
+}
+
+impl Chip {
+    fn read_dp(&mut self, addr: u16) -> u32 {
+        match addr & 0xC {
+            0x0 => DPIDR,
+            // CTRL/STAT is the only register of the DP which is banked.
+            0x4 if self.select & 0xF == 0 => {
+                let acks = (self.ctrl_stat & CTRL_STAT_POWER_REQUESTS) << 1;
+                let sticky = if self.sticky_error {
+                    CTRL_STAT_STICKYERR
+                } else {
+                    0
+                };
+
+                self.ctrl_stat | acks | sticky
+            }
+            // RESEND and RDBUFF both return the result of the last read of an AP.
+            0x8 | 0xC => self.read_buffer,
+            _ => 0,
+        }
+    }
+
+    fn write_dp(&mut self, addr: u16, value: u32) {
+        match addr & 0xC {
+            0x0 if value & ABORT_STKERRCLR != 0 => self.sticky_error = false,
+            0x4 if self.select & 0xF == 0 => self.ctrl_stat = value & !0xA000_00F2,
+            0x8 => self.select = value,
+            _ => (),
+        }
+    }
+
+    /// The AP register which is selected by SELECT and the address.
+    fn ap_register(&self, addr: u16) -> u8 {
+        (self.select & 0xF0) as u8 | (addr & 0xC) as u8
+    }
+
+    fn read_ap(&mut self, addr: u16) -> Result<u32, DebugProbeError> {
+        let register = self.ap_register(addr);
+
+        // Only AP 0 is present, the other APs read as zero.
+        let value = if self.select >> 24 != 0 {
+            0
+        } else {
+            match register {
+                AP_CSW => self.csw | CSW_DEVICE_EN,
+                AP_TAR => self.tar,
+                AP_DRW => {
+                    let value = self.bus_read(self.tar & !0x3)?;
+                    self.increment_tar();
+                    value
+                }
+                0x10..=0x1C => self.bus_read(self.banked_address(register))?,
+                AP_BASE => ROM_TABLE | 0x3,
+                AP_IDR => AHB_AP_IDR,
+                _ => 0,
+            }
+        };
+
+        self.read_buffer = value;
+        Ok(value)
+    }
+
+    fn write_ap(&mut self, addr: u16, value: u32) -> Result<(), DebugProbeError> {
+        if self.select >> 24 != 0 {
+            return Ok(());
+        }
+
+        match self.ap_register(addr) {
+            AP_CSW => self.csw = value & !(CSW_DEVICE_EN | CSW_TR_IN_PROG),
+            AP_TAR => self.tar = value,
+            AP_DRW => {
+                self.bus_write(self.tar, value)?;
+                self.increment_tar();
+            }
+            register @ 0x10..=0x1C => {
+                let address = self.banked_address(register);
+                self.write_bytes(address, &value.to_le_bytes())
+                    .map_err(|_| self.fault())?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// The address which is accessed through a banked data register, BD0 to BD3.
+    fn banked_address(&self, register: u8) -> u32 {
+        (self.tar & !0xF) | u32::from(register & 0xC)
+    }
+
+    /// Increments TAR after an access through DRW, within the 1 KiB block of the address.
+    fn increment_tar(&mut self) {
+        if self.csw & CSW_ADDR_INC != 0 {
+            let size = 1 << (self.csw & CSW_SIZE).min(2);
+            self.tar = (self.tar & !0x3FF) | (self.tar.wrapping_add(size) & 0x3FF);
+        }
+    }
+
+    /// Reports a fault, which sets STICKYERR until it is cleared through ABORT.
+    fn fault(&mut self) -> DebugProbeError {
+        self.sticky_error = true;
+        DapError::FaultResponse.into()
+    }
+
+    fn bus_read(&mut self, address: u32) -> Result<u32, DebugProbeError> {
+        self.read_word(address).map_err(|_| self.fault())
+    }
+
+    /// Writes the bytes of `value` which are selected by the size in CSW.
+    fn bus_write(&mut self, address: u32, value: u32) -> Result<(), DebugProbeError> {
+        let (address, len) = match self.csw & CSW_SIZE {
+            0 => (address, 1),
+            1 => (address & !0x1, 2),
+            _ => (address & !0x3, 4),
+        };
+
+        let bytes = (value >> ((address & 0x3) * 8)).to_le_bytes();
+        self.write_bytes(address, &bytes[..len])
+            .map_err(|_| self.fault())
+    }
+
+    fn read_word(&mut self, address: u32) -> Result<u32, BusFault> {
+        if let Some(bytes) = self.memory.get(address, 4) {
+            let mut word = [0; 4];
+            word.copy_from_slice(bytes);
+            Ok(u32::from_le_bytes(word))
+        } else if PPB.contains(&address) {
+            if address == DHCSR {
+                // The core runs while the debugger polls its state.
+                self.run(INSTRUCTIONS_PER_POLL);
+                let value = self.peripheral(address);
+                self.reset = false;
+                Ok(value)
+            } else {
+                Ok(self.peripheral(address))
+            }
+        } else {
+            Err(BusFault)
+        }
+    }
+
+    /// Writes to the RAM or the private peripheral bus, the flash is read-only.
+    fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), BusFault> {
+        if let Some(ram) = self.memory.ram_mut(address, data.len()) {
+            ram.copy_from_slice(data);
+            Ok(())
+        } else if PPB.contains(&address) {
+            let aligned = address & !0x3;
+            let offset = (address & 0x3) as usize;
+
+            let mut word = self.peripheral(aligned).to_le_bytes();
+            word[offset..offset + data.len()].copy_from_slice(data);
+            self.write_peripheral(aligned, u32::from_le_bytes(word));
+            Ok(())
+        } else {
+            Err(BusFault)
+        }
+    }
+
+    fn peripheral(&self, address: u32) -> u32 {
+        match address {
+            CPUID => CPUID_VALUE,
+            AIRCR => AIRCR_VECTKEYSTAT | self.peripherals.get(&AIRCR).copied().unwrap_or(0),
+            DFSR => self.dfsr,
+            DHCSR => {
+                let status = [
+                    (self.halted, DHCSR_S_HALT),
+                    (self.sleeping, DHCSR_S_SLEEP),
+                    (self.locked_up, DHCSR_S_LOCKUP),
+                    (self.reset, DHCSR_S_RESET_ST),
+                ];
+
+                status
+                    .iter()
+                    .filter(|(set, _)| *set)
+                    .fold(self.dhcsr | DHCSR_S_REGRDY, |value, (_, bit)| value | bit)
+            }
+            DCRSR => 0,
+            DCRDR => self.dcrdr,
+            DEMCR => self.demcr,
+            DWT_CTRL => DWT_CTRL_NUMCOMP | self.peripherals.get(&DWT_CTRL).copied().unwrap_or(0),
+            FP_CTRL if self.fp_enabled => FP_CTRL_VALUE | FP_CTRL_ENABLE,
+            FP_CTRL => FP_CTRL_VALUE,
+            ROM_TABLE_MEMTYPE => 1,
+            _ => {
+                if let Some(comparator) = self.fp_comparator(address) {
+                    self.fp_comp[comparator]
+                } else if let Some(entry) = self.rom_table_entry(address) {
+                    entry
+                } else if let Some(value) = identification_register(address) {
+                    value
+                } else {
+                    self.peripherals.get(&address).copied().unwrap_or(0)
+                }
+            }
+        }
+    }
+
+    fn write_peripheral(&mut self, address: u32, value: u32) {
+        match address {
+            AIRCR => {
+                if value >> 16 == AIRCR_VECTKEY {
+                    self.peripherals.insert(AIRCR, value & AIRCR_PRIGROUP);
+
+                    if value & AIRCR_RESET != 0 {
+                        self.reset();
+                    }
+                }
+            }
+            // The bits of DFSR are cleared by writing ones.
+            DFSR => self.dfsr &= !value,
+            DHCSR => self.write_dhcsr(value),
+            DCRSR => self.transfer_register(value),
+            DCRDR => self.dcrdr = value,
+            DEMCR => self.demcr = value,
+            DWT_CTRL => {
+                self.peripherals.insert(DWT_CTRL, value & !DWT_CTRL_NUMCOMP);
+            }
+            FP_CTRL => {
+                if value & FP_CTRL_KEY != 0 {
+                    self.fp_enabled = value & FP_CTRL_ENABLE != 0;
+                }
+            }
+            CPUID | ROM_TABLE_MEMTYPE => (),
+            _ => {
+                if let Some(comparator) = self.fp_comparator(address) {
+                    self.fp_comp[comparator] = value;
+                } else if self.rom_table_entry(address).is_none()
+                    && identification_register(address).is_none()
+                {
+                    self.peripherals.insert(address, value);
+                }
+            }
+        }
+    }
+
+    fn fp_comparator(&self, address: u32) -> Option<usize> {
+        let offset = address.checked_sub(FP_COMP)? as usize / 4;
+
+        if offset < FP_COMPARATORS {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// The entries of the ROM table, which end with a zero.
+    fn rom_table_entry(&self, address: u32) -> Option<u32> {
+        if (ROM_TABLE..ROM_TABLE_MEMTYPE).contains(&address) {
+            let entry = (address - ROM_TABLE) as usize / 4;
+            Some(ROM_TABLE_ENTRIES.get(entry).copied().unwrap_or(0))
+        } else {
+            None
+        }
+    }
+
+    fn write_dhcsr(&mut self, value: u32) {
+        if value >> 16 != DHCSR_KEY {
+            return;
+        }
+
+        self.dhcsr = value & (DHCSR_C_DEBUGEN | DHCSR_C_HALT | DHCSR_C_STEP | DHCSR_C_MASKINTS);
+
+        if value & DHCSR_C_DEBUGEN == 0 {
+            // Without halting debug, the core can not stay halted.
+            if self.halted {
+                self.resume();
+            }
+        } else if value & DHCSR_C_HALT != 0 {
+            if !self.halted {
+                self.halt(DFSR_HALTED);
+            }
+        } else if self.halted {
+            if value & DHCSR_C_STEP != 0 {
+                self.step();
+            } else {
+                self.resume();
+            }
+        }
+    }
+
+    /// Transfers a core register from or to DCRDR, as selected by DCRSR.
+    fn transfer_register(&mut self, value: u32) {
+        let index = register_index(value);
+
+        if value & DCRSR_REGWNR != 0 {
+            self.registers[index] = self.dcrdr;
+        } else {
+            self.dcrdr = self.registers[index];
+        }
+    }
+
+    /// Resets the core, which starts with the stack pointer and the reset vector from the
+    /// vector table at the start of the flash.
+    fn reset(&mut self) {
+        let vector = |n: u32| {
+            self.load(FLASH.start + n * 4)
+                .expect("The vector table is in the flash")
+        };
+
+        let (sp, reset) = (vector(0), vector(1));
+
+        self.registers = [0; REGISTERS];
+        self.registers[SP] = sp;
+        self.registers[LR] = 0xFFFF_FFFF;
+        self.registers[PC] = reset & !1;
+        self.registers[XPSR] = XPSR_THUMB;
+
+        self.sleeping = false;
+        self.locked_up = false;
+        self.resumed = false;
+        self.reset = true;
+
+        if self.dhcsr & DHCSR_C_DEBUGEN != 0 && self.demcr & DEMCR_VC_CORERESET != 0 {
+            self.halt(DFSR_VCATCH);
+        } else {
+            self.halted = false;
+        }
+    }
+
+    fn halt(&mut self, reason: u32) {
+        self.halted = true;
+        self.sleeping = false;
+        self.dfsr |= reason;
+    }
+
+    fn resume(&mut self) {
+        self.halted = false;
+        self.locked_up = false;
+        self.resumed = true;
+
+        self.run(INSTRUCTIONS_PER_POLL);
+    }
+
+    fn step(&mut self) {
+        self.halted = false;
+        self.locked_up = false;
+
+        self.execute();
+
+        if !self.halted {
+            self.halt(DFSR_HALTED);
+        }
+    }
+
+    /// Executes instructions until the core halts, sleeps or locks up.
+    fn run(&mut self, instructions: usize) {
+        for _ in 0..instructions {
+            if self.halted || self.sleeping || self.locked_up {
+                return;
+            }
+
+            // The core can be resumed at a breakpoint, which then only halts it the next time.
+            if !std::mem::take(&mut self.resumed) && self.breakpoint_hit() {
+                self.halt(DFSR_BKPT);
+                return;
+            }
+
+            self.execute();
+        }
+    }
+
+    /// Whether an FPB comparator matches the next instruction.
+    fn breakpoint_hit(&self) -> bool {
+        if !self.fp_enabled || self.dhcsr & DHCSR_C_DEBUGEN == 0 {
+            return false;
+        }
+
+        let pc = self.registers[PC] & !1;
+
+        self.fp_comp[..FP_CODE_COMPARATORS].iter().any(|&comp| {
+            let address = comp & 0x1FFF_FFFC;
+
+            comp & 1 != 0
+                && match comp >> 30 {
+                    0b01 => pc == address,
+                    0b10 => pc == address + 2,
+                    0b11 => pc & !0x3 == address,
+                    _ => false,
+                }
+        })
+    }
+
+    /// Executes the instruction at the PC. The core locks up if it can't be fetched.
+    fn execute(&mut self) {
+        let pc = self.registers[PC] & !1;
+
+        let instruction = match self.memory.get(pc, 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+            None => {
+                self.locked_up = true;
+                return;
+            }
+        };
+
+        let mut next = pc.wrapping_add(2);
+
+        if instruction >> 11 == 0b11110 {
+            self.execute_32(pc, instruction);
+            return;
+        }
+
+        let rd = usize::from(instruction >> 8) & 0x7;
+        let imm8 = u32::from(instruction & 0xFF);
+
+        match instruction {
+            // BKPT halts the core before the instruction, or locks it up without halting debug.
+            0xBE00..=0xBEFF => {
+                if self.dhcsr & DHCSR_C_DEBUGEN != 0 {
+                    self.halt(DFSR_BKPT);
+                } else {
+                    self.locked_up = true;
+                }
+                return;
+            }
+            0xDF00..=0xDFFF => {
+                self.registers[0] = {
+                    let arguments = [self.registers[0], self.registers[1], self.registers[2]];
+                    self.memory
+                        .call_flash_routine(u32::from(instruction & 0xFF), arguments)
+                }
+            }
+            THUMB_BX_LR => next = self.registers[LR] & !1,
+            // B with an 11 bit offset.
+            0xE000..=0xE7FF => {
+                let offset = (u32::from(instruction) << 21) as i32 >> 20;
+                next = pc.wrapping_add(4).wrapping_add(offset as u32);
+            }
+            THUMB_WFI => self.sleeping = true,
+            // MOVS with an 8 bit immediate.
+            0x2000..=0x27FF => self.registers[rd] = imm8,
+            // ADD and MOV of any two registers.
+            0x4400..=0x44FF | 0x4600..=0x46FF => {
+                let rd = usize::from((instruction >> 4) & 0x8 | instruction & 0x7);
+                let rm = usize::from(instruction >> 3) & 0xF;
+
+                let mut value = self.registers[rm];
+                if instruction & 0x0200 == 0 {
+                    value = value.wrapping_add(self.registers[rd]);
+                }
+
+                if rd == PC {
+                    next = value & !1;
+                } else {
+                    self.registers[rd] = value;
+                }
+            }
+            // STR and LDR relative to the SP.
+            0x9000..=0x9FFF => {
+                let address = self.registers[SP].wrapping_add(imm8 * 4);
+
+                let done = if instruction & 0x0800 == 0 {
+                    self.store(address, self.registers[rd])
+                } else {
+                    self.load(address).map(|value| self.registers[rd] = value)
+                };
+
+                if done.is_none() {
+                    self.locked_up = true;
+                    return;
+                }
+            }
+            // ADD and SUB of the SP with a 7 bit immediate.
+            0xB000..=0xB0FF => {
+                let offset = u32::from(instruction & 0x7F) * 4;
+
+                self.registers[SP] = if instruction & 0x80 == 0 {
+                    self.registers[SP].wrapping_add(offset)
+                } else {
+                    self.registers[SP].wrapping_sub(offset)
+                };
+            }
+            // PUSH, of R0 to R7 and the LR.
+            0xB400..=0xB5FF => {
+                let registers = stacked_registers(instruction, LR);
+                let mut address = self.registers[SP].wrapping_sub(4 * registers.len() as u32);
+                self.registers[SP] = address;
+
+                for register in registers {
+                    if self.store(address, self.registers[register]).is_none() {
+                        self.locked_up = true;
+                        return;
+                    }
+                    address = address.wrapping_add(4);
+                }
+            }
+            // POP, of R0 to R7 and the PC.
+            0xBC00..=0xBDFF => {
+                for register in stacked_registers(instruction, PC) {
+                    let value = match self.load(self.registers[SP]) {
+                        Some(value) => value,
+                        None => {
+                            self.locked_up = true;
+                            return;
+                        }
+                    };
+
+                    self.registers[SP] = self.registers[SP].wrapping_add(4);
+
+                    if register == PC {
+                        next = value & !1;
+                    } else {
+                        self.registers[register] = value;
+                    }
+                }
+            }
+            // The first halfword of a 32 bit instruction.
+            _ if instruction >> 11 >= 0b11101 => next = pc.wrapping_add(4),
+            _ => (),
+        }
+
+        self.registers[PC] = next;
+    }
+
+    /// Executes a 32 bit instruction, of which only `BL` is carried out.
+    fn execute_32(&mut self, pc: u32, first: u16) {
+        let second = match self.memory.get(pc.wrapping_add(2), 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+            None => {
+                self.locked_up = true;
+                return;
+            }
+        };
+
+        let mut next = pc.wrapping_add(4);
+
+        if second & 0xD000 == 0xD000 {
+            let s = u32::from(first >> 10) & 1;
+            let i1 = !(u32::from(second >> 13) ^ s) & 1;
+            let i2 = !(u32::from(second >> 11) ^ s) & 1;
+            let imm = s << 24
+                | i1 << 23
+                | i2 << 22
+                | u32::from(first & 0x3FF) << 12
+                | u32::from(second & 0x7FF) << 1;
+            let offset = (imm << 7) as i32 >> 7;
+
+            self.registers[LR] = next | 1;
+            next = next.wrapping_add(offset as u32);
+        }
+
+        self.registers[PC] = next;
+    }
+
+    /// Loads a word from the flash or the RAM for the core.
+    fn load(&self, address: u32) -> Option<u32> {
+        let bytes = self.memory.get(address, 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Stores a word in the RAM for the core, as the flash is read-only.
+    fn store(&mut self, address: u32, value: u32) -> Option<()> {
+        let ram = self.memory.ram_mut(address, 4)?;
+        ram.copy_from_slice(&value.to_le_bytes());
+        Some(())
+    }
+}
+
+impl DebugProbe for SimulatedCortexM {
+    fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn get_name(&self) -> &str {
+        "Simulated Cortex-M4"
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed_khz
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        if speed_khz == 0 {
+            return Err(DebugProbeError::UnsupportedSpeed(speed_khz));
+        }
+
+        self.speed_khz = speed_khz;
+        Ok(speed_khz)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = true;
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = false;
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.chip().reset();
+        Ok(())
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        match protocol {
+            WireProtocol::Swd => Ok(()),
+            WireProtocol::Jtag => Err(DebugProbeError::UnsupportedProtocol(protocol)),
+        }
+    }
+
+    fn dedicated_memory_interface(&self) -> Option<Memory<'_>> {
+        None
+    }
+
+    fn get_interface_dap(&self) -> Option<&dyn DAPAccess> {
+        Some(self as _)
+    }
+
+    fn get_interface_dap_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
+
+    fn get_interface_jtag(&self) -> Option<&dyn JTAGAccess> {
+        None
+    }
+
+    fn get_interface_jtag_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        None
+    }
+}
+
+/// A fault is reported as the error of the access. Unlike on a real debug port, the following
+/// accesses are still carried out while STICKYERR is set.
+impl DAPAccess for SimulatedCortexM {
+    fn read_register(&mut self, port: PortType, addr: u16) -> Result<u32, DebugProbeError> {
+        self.check_attached()?;
+
+        let mut chip = self.chip();
+
+        // The AP is selected by SELECT, like on a real debug port.
+        match port {
+            PortType::DebugPort => Ok(chip.read_dp(addr)),
+            PortType::AccessPort(_) => chip.read_ap(addr),
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.check_attached()?;
+
+        let mut chip = self.chip();
+
+        match port {
+            PortType::DebugPort => {
+                chip.write_dp(addr, value);
+                Ok(())
+            }
+            PortType::AccessPort(_) => chip.write_ap(addr, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SimulatedCortexM;
+    use crate::architecture::arm::{
+        ArmChipInfo, ArmCommunicationInterface, ArmCommunicationInterfaceState,
+    };
+    use crate::{CoreStatus, HaltReason, MemoryInterface, Probe, Session};
+
+    /// A vector table, with the stack at the end of the RAM and the reset handler at 0x40.
+    const VECTORS: [u8; 8] = [0x00, 0x00, 0x01, 0x20, 0x41, 0x00, 0x00, 0x00];
+
+    /// `NOP; NOP; BKPT #1; B .` at 0x40.
+    const PROGRAM: [u8; 8] = [0x00, 0xbf, 0x00, 0xbf, 0x01, 0xbe, 0xfe, 0xe7];
+
+    fn session(chip: &SimulatedCortexM) -> Session {
+        Probe::new(chip.clone())
+            .attach(SimulatedCortexM::target())
+            .unwrap()
+    }
+
+    fn programmed_chip() -> SimulatedCortexM {
+        let chip = SimulatedCortexM::new();
+        chip.write_memory(0x0, &VECTORS);
+        chip.write_memory(0x40, &PROGRAM);
+        chip
+    }
+
+    #[test]
+    fn rom_table_identifies_the_chip() {
+        let mut probe = Probe::new(SimulatedCortexM::new());
+        probe.attach_to_unspecified().unwrap();
+
+        let mut state = ArmCommunicationInterfaceState::new();
+        let mut interface = ArmCommunicationInterface::new(&mut probe, &mut state)
+            .unwrap()
+            .unwrap();
+
+        let info = ArmChipInfo::read_from_rom_table(&mut interface)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(info.manufacturer.get(), Some("ARM Ltd"));
+        assert_eq!(info.part, 0x4c4);
+    }
+
+    #[test]
+    fn memory_is_accessed_through_the_access_port() {
+        let chip = SimulatedCortexM::new();
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.write_32(0x2000_03f8, &[1, 2, 3, 4]).unwrap();
+        core.write_word_8(0x2000_0401, 0xaa).unwrap();
+        core.write_8(0x2000_0003, &[0x11, 0x22]).unwrap();
+
+        let mut words = [0; 4];
+        core.read_32(0x2000_03f8, &mut words).unwrap();
+        assert_eq!(words, [1, 2, 0xaa03, 4]);
+        assert_eq!(core.read_word_32(0x2000_0004).unwrap(), 0x22);
+        assert_eq!(core.read_word_8(0x2000_0003).unwrap(), 0x11);
+
+        // The flash is read-only, and nothing is mapped at 0x4000_0000.
+        assert_eq!(core.read_word_32(0x100).unwrap(), 0xffff_ffff);
+        assert!(core.write_word_32(0x100, 0).is_err());
+        assert!(core.read_word_32(0x4000_0000).is_err());
+
+        // The faults do not affect the following accesses.
+        assert_eq!(core.read_word_32(0x2000_03f8).unwrap(), 1);
+
+        let mut ram = [0; 2];
+        chip.read_memory(0x2000_0003, &mut ram);
+        assert_eq!(ram, [0x11, 0x22]);
+    }
+
+    #[test]
+    fn core_is_halted_stepped_and_resumed() {
+        let chip = programmed_chip();
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        assert_eq!(core.reset_and_halt().unwrap().pc, 0x40);
+        assert_eq!(core.read_core_reg(13).unwrap(), 0x2001_0000);
+        // The core is halted by the vector catch of the reset.
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Exception)
+        );
+
+        assert_eq!(core.step().unwrap().pc, 0x42);
+        // DFSR reports a step like a halt request.
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Request)
+        );
+
+        core.write_core_reg(0.into(), 0x1234_5678).unwrap();
+        assert_eq!(core.read_core_reg(0).unwrap(), 0x1234_5678);
+        assert_eq!(chip.core_register(0), 0x1234_5678);
+
+        // The core runs into the BKPT instruction.
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Breakpoint)
+        );
+        assert_eq!(chip.core_register(15), 0x44);
+
+        // Behind it, the core loops until it is halted.
+        core.write_core_reg(15.into(), 0x46).unwrap();
+        core.run().unwrap();
+        assert_eq!(core.status().unwrap(), CoreStatus::Running);
+        assert!(!chip.is_halted());
+
+        assert_eq!(core.halt().unwrap().pc, 0x46);
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Request)
+        );
+    }
+
+    #[test]
+    fn hardware_breakpoints_halt_the_core() {
+        let chip = programmed_chip();
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.reset_and_halt().unwrap();
+        assert_eq!(core.get_available_breakpoint_units().unwrap(), 6);
+
+        core.set_hw_breakpoint(0x42).unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Breakpoint)
+        );
+        assert_eq!(chip.core_register(15), 0x42);
+
+        // A cleared breakpoint does not halt the core again.
+        core.clear_hw_breakpoint(0x42).unwrap();
+        core.write_core_reg(15.into(), 0x40).unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(chip.core_register(15), 0x44);
+    }
+
+    #[test]
+    fn functions_are_called_and_return() {
+        let chip = programmed_chip();
+
+        // `MOVS R0, #5; MOVS R1, #7; BL 0x50; BKPT #2` at 0x40, and at 0x50
+        // `PUSH {R7, LR}; MOV R7, SP; SUB SP, #8; STR R0, [SP]; STR R1, [SP, #4];
+        // LDR R2, [SP, #4]; ADD R0, R2; ADD SP, #8; POP {R7, PC}`.
+        chip.write_memory(
+            0x40,
+            &[
+                0x05, 0x20, 0x07, 0x21, 0x00, 0xf0, 0x04, 0xf8, 0x02, 0xbe, 0xfe, 0xe7,
+            ],
+        );
+        chip.write_memory(
+            0x50,
+            &[
+                0x80, 0xb5, 0x6f, 0x46, 0x82, 0xb0, 0x00, 0x90, 0x01, 0x91, 0x01, 0x9a, 0x10, 0x44,
+                0x02, 0xb0, 0x80, 0xbd,
+            ],
+        );
+
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.reset_and_halt().unwrap();
+
+        // The call stores the return address in the LR.
+        for _ in 0..3 {
+            core.step().unwrap();
+        }
+        assert_eq!(chip.core_register(15), 0x50);
+        assert_eq!(chip.core_register(14), 0x49);
+
+        // The frame of the function is on the stack.
+        for _ in 0..5 {
+            core.step().unwrap();
+        }
+        assert_eq!(chip.core_register(7), 0x2000_fff8);
+        assert_eq!(chip.core_register(13), 0x2000_fff0);
+        assert_eq!(core.read_word_32(0x2000_fffc).unwrap(), 0x49);
+        assert_eq!(core.read_word_32(0x2000_fff4).unwrap(), 7);
+
+        // The function returns the sum, and restores the SP.
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(chip.core_register(15), 0x48);
+        assert_eq!(chip.core_register(0), 12);
+        assert_eq!(chip.core_register(2), 7);
+        assert_eq!(chip.core_register(7), 0);
+        assert_eq!(chip.core_register(13), 0x2001_0000);
+    }
+
+    #[test]
+    fn reset_starts_the_program() {
+        let chip = programmed_chip();
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        // Without halting debug, the core locks up at the BKPT.
+        core.reset().unwrap();
+        assert!(core.wait_for_core_halted().is_err());
+        assert_eq!(chip.core_register(15), 0x44);
+
+        // With halting debug, the core halts at the BKPT.
+        core.halt().unwrap();
+        core.reset().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Breakpoint)
+        );
+        assert_eq!(chip.core_register(15), 0x44);
+    }
+}
@@ -0,0 +1,287 @@
+//! Simulated targets, which are debugged like a chip that is connected to a probe.
+//!
+//! The simulators implement the low level interfaces of a probe, so that everything above
+//! them, like a [`Session`], flashing and the GDB stub, can be tested without hardware.
+//! Clones of a simulator share the simulated chip, so a test can inspect the chip while a
+//! session uses it.
+//!
+//! [`Session`]: crate::Session
+
+mod cortex_m;
+mod riscv;
+
+pub use cortex_m::SimulatedCortexM;
+pub use riscv::SimulatedRiscv;
+
+use crate::config::registry::TargetIdentifier;
+use crate::config::{
+    FlashProperties, FlashRegion, MemoryRegion, RamRegion, RawFlashAlgorithm, SectorDescription,
+    Target, TargetCore,
+};
+use crate::CoreType;
+use std::borrow::Cow;
+use std::ops::Range;
+
+const SECTOR_SIZE: u32 = 0x1000;
+const PAGE_SIZE: u32 = 0x400;
+const ERASED: u8 = 0xff;
+
+/// The routines of the flash algorithm, which are selected by the instruction that calls them.
+const INIT: u32 = 0;
+const UNINIT: u32 = 1;
+const ERASE_SECTOR: u32 = 2;
+const PROGRAM_PAGE: u32 = 3;
+const ERASE_ALL: u32 = 4;
+
+/// The description of a simulated chip with a single core, to attach to it.
+///
+/// `routine(n)` is the code of the routine `n` of the flash algorithm, which has to be of the
+/// same size for all routines.
+fn target(
+    chip_name: &str,
+    core_type: CoreType,
+    flash: Range<u32>,
+    ram: Range<u32>,
+    routine: fn(u32) -> Vec<u8>,
+) -> Target {
+    let instructions = [INIT, UNINIT, ERASE_SECTOR, PROGRAM_PAGE, ERASE_ALL]
+        .iter()
+        .flat_map(|&n| routine(n))
+        .collect::<Vec<u8>>();
+
+    let offset = |n: u32| n * routine(n).len() as u32;
+
+    let flash_algorithm = RawFlashAlgorithm {
+        name: "simulated".into(),
+        description: "The flash routines of the simulated chip".into(),
+        default: true,
+        pc_init: Some(offset(INIT)),
+        pc_uninit: Some(offset(UNINIT)),
+        pc_program_page: offset(PROGRAM_PAGE),
+        pc_erase_sector: offset(ERASE_SECTOR),
+        pc_erase_all: Some(offset(ERASE_ALL)),
+        data_section_offset: instructions.len() as u32,
+        instructions: instructions.into(),
+        flash_properties: FlashProperties {
+            address_range: flash.clone(),
+            page_size: PAGE_SIZE,
+            erased_byte_value: ERASED,
+            program_page_timeout: 100,
+            erase_sector_timeout: 100,
+            sectors: Cow::Borrowed(&[SectorDescription {
+                size: SECTOR_SIZE,
+                address: 0,
+            }]),
+        },
+    };
+
+    Target {
+        identifier: TargetIdentifier {
+            chip_name: chip_name.to_owned(),
+        },
+        flash_algorithms: vec![flash_algorithm],
+        cores: vec![TargetCore {
+            core_type,
+            ap: 0,
+            debug_base: None,
+        }],
+        memory_map: vec![
+            MemoryRegion::Flash(FlashRegion {
+                range: flash,
+                is_boot_memory: true,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: ram,
+                is_boot_memory: false,
+            }),
+        ],
+    }
+}
+
+/// The range of `len` bytes at `address` within a memory which covers `memory`.
+fn memory_range(memory: &Range<u32>, address: u32, len: usize) -> Option<Range<usize>> {
+    let start = address.checked_sub(memory.start)? as usize;
+    let end = start.checked_add(len)?;
+
+    if end <= (memory.end - memory.start) as usize {
+        Some(start..end)
+    } else {
+        None
+    }
+}
+
+/// The flash and the RAM of a simulated chip.
+///
+/// The flash is read-only for the core and over the bus, it is only changed by the routines
+/// of the flash algorithm and by a programmer.
+struct FlashAndRam {
+    flash_range: Range<u32>,
+    ram_range: Range<u32>,
+    flash: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl FlashAndRam {
+    /// Erased flash and zeroed RAM.
+    fn new(flash: Range<u32>, ram: Range<u32>) -> Self {
+        FlashAndRam {
+            flash: vec![ERASED; (flash.end - flash.start) as usize],
+            ram: vec![0; (ram.end - ram.start) as usize],
+            flash_range: flash,
+            ram_range: ram,
+        }
+    }
+
+    fn get(&self, address: u32, len: usize) -> Option<&[u8]> {
+        if let Some(range) = memory_range(&self.flash_range, address, len) {
+            Some(&self.flash[range])
+        } else if let Some(range) = memory_range(&self.ram_range, address, len) {
+            Some(&self.ram[range])
+        } else {
+            None
+        }
+    }
+
+    fn get_mut(&mut self, address: u32, len: usize) -> Option<&mut [u8]> {
+        if let Some(range) = memory_range(&self.flash_range, address, len) {
+            Some(&mut self.flash[range])
+        } else if let Some(range) = memory_range(&self.ram_range, address, len) {
+            Some(&mut self.ram[range])
+        } else {
+            None
+        }
+    }
+
+    /// The RAM, which is all that the core and the bus can write.
+    fn ram_mut(&mut self, address: u32, len: usize) -> Option<&mut [u8]> {
+        let range = memory_range(&self.ram_range, address, len)?;
+        Some(&mut self.ram[range])
+    }
+
+    /// Reads the flash or the RAM, for the `read_memory` of the simulators.
+    fn read(&self, address: u32, data: &mut [u8]) {
+        let memory = self
+            .get(address, data.len())
+            .unwrap_or_else(|| panic!("{:#010x} is not in the flash or the RAM", address));
+
+        data.copy_from_slice(memory);
+    }
+
+    /// Writes the flash or the RAM, for the `write_memory` of the simulators.
+    fn write(&mut self, address: u32, data: &[u8]) {
+        let memory = self
+            .get_mut(address, data.len())
+            .unwrap_or_else(|| panic!("{:#010x} is not in the flash or the RAM", address));
+
+        memory.copy_from_slice(data);
+    }
+
+    /// Carries out a routine of the flash algorithm with the values of the first three
+    /// argument registers, and returns zero if it succeeded.
+    fn call_flash_routine(&mut self, routine: u32, arguments: [u32; 3]) -> u32 {
+        let [address, size, data] = arguments;
+
+        let result = match routine {
+            INIT | UNINIT => Some(()),
+            ERASE_SECTOR => self.erase(address, SECTOR_SIZE),
+            PROGRAM_PAGE => self.program(address, size as usize, data),
+            ERASE_ALL => self.erase(self.flash_range.start, self.flash.len() as u32),
+            _ => None,
+        };
+
+        if result.is_some() {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn erase(&mut self, address: u32, size: u32) -> Option<()> {
+        if address & (SECTOR_SIZE - 1) != 0 {
+            return None;
+        }
+
+        let range = memory_range(&self.flash_range, address, size as usize)?;
+        self.flash[range].iter_mut().for_each(|byte| *byte = ERASED);
+
+        Some(())
+    }
+
+    /// Programs the flash with data from the RAM, which can only clear bits.
+    fn program(&mut self, address: u32, size: usize, data: u32) -> Option<()> {
+        let data = memory_range(&self.ram_range, data, size)?;
+        let range = memory_range(&self.flash_range, address, size)?;
+
+        for (byte, data) in self.flash[range].iter_mut().zip(&self.ram[data]) {
+            *byte &= data;
+        }
+
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SimulatedCortexM, SimulatedRiscv};
+    use crate::config::MemoryRegion;
+    use crate::flashing::{FlashLoader, FlashProgress};
+    use crate::{MemoryInterface, Probe, Session};
+
+    /// Programs 6 KiB into the flash of a session, behind 2 KiB which stay erased, and checks
+    /// the flash of the chip with `read_memory`.
+    ///
+    /// The end of the programmed data was written with zeros before, so it is only correct if
+    /// the sector was erased first.
+    fn flash_is_programmed(
+        mut session: Session,
+        read_memory: impl Fn(u32, &mut [u8]),
+        write_memory: impl Fn(u32, &[u8]),
+    ) {
+        let flash = match &session.memory_map()[0] {
+            MemoryRegion::Flash(flash) => flash.range.start,
+            region => panic!("Expected the flash, got {:?}", region),
+        };
+
+        write_memory(flash + 0x1ff0, &[0; 0x20]);
+
+        let data = (0..0x1800).map(|i| i as u8).collect::<Vec<_>>();
+        let memory_map = session.memory_map().to_vec();
+        let mut loader = FlashLoader::new(&memory_map, false);
+        loader.add_data(flash + 0x800, &data).unwrap();
+        loader
+            .commit(&mut session, &FlashProgress::new(|_| {}), false)
+            .unwrap();
+
+        let mut programmed = vec![0; 0x2000];
+        read_memory(flash, &mut programmed);
+
+        assert!(programmed[..0x800].iter().all(|&byte| byte == 0xff));
+        assert_eq!(&programmed[0x800..], &data[..]);
+
+        let mut core = session.core(0).unwrap();
+        let mut read_back = vec![0; data.len()];
+        core.read_8(flash + 0x800, &mut read_back).unwrap();
+        assert_eq!(read_back, data);
+    }
+
+    #[test]
+    fn flash_is_programmed_by_the_flash_algorithm() {
+        let chip = SimulatedCortexM::new();
+        flash_is_programmed(
+            Probe::new(chip.clone())
+                .attach(SimulatedCortexM::target())
+                .unwrap(),
+            |address, data| chip.read_memory(address, data),
+            |address, data| chip.write_memory(address, data),
+        );
+
+        let chip = SimulatedRiscv::new();
+        flash_is_programmed(
+            Probe::new(chip.clone())
+                .attach(SimulatedRiscv::target())
+                .unwrap(),
+            |address, data| chip.read_memory(address, data),
+            |address, data| chip.write_memory(address, data),
+        );
+    }
+}
@@ -0,0 +1,1581 @@
+use super::FlashAndRam;
+use crate::architecture::arm::DAPAccess;
+use crate::config::Target;
+use crate::probe::{DebugProbe, DebugProbeError, DebugProbeInfo, JTAGAccess, WireProtocol};
+use crate::{CoreType, Memory};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const FLASH: Range<u32> = 0x2000_0000..0x2001_0000;
+
+const RAM: Range<u32> = 0x8000_0000..0x8001_0000;
+
+/// The IDCODE of the JTAG TAP.
+const IDCODE_VALUE: u32 = 0x2000_0913;
+
+/// The instructions of the JTAG TAP, which select the data registers.
+const IDCODE: u32 = 0x01;
+const DTMCS: u32 = 0x10;
+const DMI: u32 = 0x11;
+
+/// The width of the address of `dmi`.
+const ABITS: u32 = 7;
+/// `dtmcs` of a DTM for version 0.13 of the debug specification.
+const DTMCS_VALUE: u32 = ABITS << 4 | 1;
+
+const DMI_OP_READ: u64 = 1;
+const DMI_OP_WRITE: u64 = 2;
+
+/// The registers of the debug module.
+const DATA0: u32 = 0x04;
+const DMCONTROL: u32 = 0x10;
+const DMSTATUS: u32 = 0x11;
+const HARTINFO: u32 = 0x12;
+const ABSTRACTCS: u32 = 0x16;
+const COMMAND: u32 = 0x17;
+const PROGBUF0: u32 = 0x20;
+const SBCS: u32 = 0x38;
+const SBADDRESS0: u32 = 0x39;
+const SBDATA0: u32 = 0x3C;
+
+const DATA_COUNT: usize = 2;
+const PROGBUF_SIZE: usize = 4;
+
+const DMCONTROL_HALTREQ: u32 = 1 << 31;
+const DMCONTROL_RESUMEREQ: u32 = 1 << 30;
+const DMCONTROL_HARTRESET: u32 = 1 << 29;
+const DMCONTROL_ACKHAVERESET: u32 = 1 << 28;
+/// `hartsello` and `hartselhi`.
+const DMCONTROL_HARTSEL: u32 = 0x03FF_FFC0;
+const DMCONTROL_NDMRESET: u32 = 1 << 1;
+const DMCONTROL_DMACTIVE: u32 = 1 << 0;
+
+/// Version 0.13 of the debug specification, with authentication not required.
+const DMSTATUS_VALUE: u32 = 1 << 7 | 2;
+const DMSTATUS_HALTED: u32 = 0b11 << 8;
+const DMSTATUS_RUNNING: u32 = 0b11 << 10;
+const DMSTATUS_UNAVAILABLE: u32 = 0b11 << 12;
+const DMSTATUS_NONEXISTENT: u32 = 0b11 << 14;
+const DMSTATUS_RESUMEACK: u32 = 0b11 << 16;
+const DMSTATUS_HAVERESET: u32 = 0b11 << 18;
+
+/// One `dscratch` register.
+const HARTINFO_VALUE: u32 = 1 << 20;
+
+/// The errors of abstract commands, in `cmderr`.
+const CMDERR_NOT_SUPPORTED: u32 = 2;
+const CMDERR_EXCEPTION: u32 = 3;
+const CMDERR_HALT_RESUME: u32 = 4;
+
+/// The fields of the access register command.
+const COMMAND_AARSIZE_32: u32 = 2;
+const COMMAND_POSTEXEC: u32 = 1 << 18;
+const COMMAND_TRANSFER: u32 = 1 << 17;
+const COMMAND_WRITE: u32 = 1 << 16;
+
+/// Version 1 of the system bus access, with 32 bit addresses and 8, 16 and 32 bit accesses.
+const SBCS_VALUE: u32 = 1 << 29 | 32 << 5 | 0b111;
+const SBCS_SBBUSYERROR: u32 = 1 << 22;
+const SBCS_SBREADONADDR: u32 = 1 << 20;
+const SBCS_SBACCESS: u32 = 0b111 << 17;
+const SBCS_SBAUTOINCREMENT: u32 = 1 << 16;
+const SBCS_SBREADONDATA: u32 = 1 << 15;
+const SBCS_SBERROR: u32 = 0b111 << 12;
+const SBERROR_BAD_ADDRESS: u32 = 2;
+const SBERROR_ALIGNMENT: u32 = 3;
+const SBERROR_SIZE: u32 = 4;
+
+/// The CSRs of the hart.
+const MSTATUS: u16 = 0x300;
+const MISA: u16 = 0x301;
+const MIE: u16 = 0x304;
+const MTVEC: u16 = 0x305;
+const MSCRATCH: u16 = 0x340;
+const MEPC: u16 = 0x341;
+const MCAUSE: u16 = 0x342;
+const MTVAL: u16 = 0x343;
+const MIP: u16 = 0x344;
+const TSELECT: u16 = 0x7A0;
+const TDATA1: u16 = 0x7A1;
+const TDATA2: u16 = 0x7A2;
+const TINFO: u16 = 0x7A4;
+const DCSR: u16 = 0x7B0;
+const DPC: u16 = 0x7B1;
+const DSCRATCH0: u16 = 0x7B2;
+const MVENDORID: u16 = 0xF11;
+const MHARTID: u16 = 0xF14;
+
+/// The machine mode CSRs, which are plain registers.
+const MACHINE_CSRS: [u16; 8] = [MSTATUS, MIE, MTVEC, MSCRATCH, MEPC, MCAUSE, MTVAL, MIP];
+
+/// RV32I.
+const MISA_VALUE: u32 = 1 << 30 | 1 << 8;
+
+/// Version 4 of external debug support, in machine mode.
+const DCSR_VALUE: u32 = 4 << 28 | 0b11;
+/// `ebreakm`, `ebreaks`, `ebreaku`, `stepie`, `stopcount`, `stoptime` and `step`.
+const DCSR_WRITABLE: u32 = 0xBE04;
+const DCSR_EBREAKM: u32 = 1 << 15;
+const DCSR_STEP: u32 = 1 << 2;
+
+/// The causes of entering debug mode, in `dcsr`.
+const CAUSE_EBREAK: u32 = 1;
+const CAUSE_TRIGGER: u32 = 2;
+const CAUSE_HALTREQ: u32 = 3;
+const CAUSE_STEP: u32 = 4;
+
+const TRIGGERS: usize = 4;
+/// The type of the triggers, address and data matching (`mcontrol`).
+const MCONTROL_TYPE: u32 = 2;
+/// `maskmax`, so ranges of up to 2<sup>31</sup> bytes can be matched.
+const MCONTROL_MASKMAX: u32 = 31 << 21;
+const MCONTROL_DMODE: u32 = 1 << 27;
+const MCONTROL_HIT: u32 = 1 << 20;
+const MCONTROL_ACTION: u32 = 0xF << 12;
+const MCONTROL_MATCH: u32 = 0xF << 7;
+const MCONTROL_M: u32 = 1 << 6;
+const MCONTROL_EXECUTE: u32 = 1 << 2;
+const MCONTROL_STORE: u32 = 1 << 1;
+const MCONTROL_LOAD: u32 = 1 << 0;
+/// `dmode`, `hit`, `action`, `match`, `m`, `s`, `u`, `execute`, `store` and `load`.
+const MCONTROL_WRITABLE: u32 = MCONTROL_DMODE | MCONTROL_HIT | 0xF7DF;
+
+/// The exceptions of the hart, in `mcause`.
+const INSTRUCTION_ACCESS_FAULT: u32 = 1;
+const ILLEGAL_INSTRUCTION: u32 = 2;
+const BREAKPOINT: u32 = 3;
+const LOAD_ADDRESS_MISALIGNED: u32 = 4;
+const LOAD_ACCESS_FAULT: u32 = 5;
+const STORE_ADDRESS_MISALIGNED: u32 = 6;
+const STORE_ACCESS_FAULT: u32 = 7;
+const ECALL: u32 = 11;
+
+/// The instructions which are executed at most, each time the state of a running hart is read.
+const INSTRUCTIONS_PER_POLL: usize = 1_000;
+
+/// `ret`, which is `jalr x0, 0(ra)`.
+const RET: u32 = 0x0000_8067;
+/// The `custom-0` opcode, which calls a routine of the flash algorithm.
+const CUSTOM_0: u32 = 0b000_1011;
+
+/// The argument registers, `a0` to `a2`.
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+
+/// A RV32I hart with 64 KiB of flash and 64 KiB of RAM, which is debugged through the
+/// JTAG TAP of a debug module of version 0.13 of the RISC-V debug specification.
+///
+/// The debug module supports halting, resuming, stepping and resetting the hart, abstract
+/// commands to access its GPRs and CSRs, a program buffer of four words, system bus access
+/// and four triggers for address and data matching. The hart executes the base integer
+/// instructions, apart from `fence`, and the CSR instructions. The `custom-0` instruction with
+/// the immediate `n` carries out the routine `n` of the flash algorithm of [`target`], as the
+/// flash is read-only over the bus.
+///
+/// [`target`]: SimulatedRiscv::target
+#[derive(Clone)]
+pub struct SimulatedRiscv {
+    chip: Arc<Mutex<Chip>>,
+    speed_khz: u32,
+    attached: bool,
+}
+
+impl SimulatedRiscv {
+    /// Creates a chip with erased flash, which was just powered up.
+    pub fn new() -> Self {
+        let mut chip = Chip {
+            instruction: IDCODE,
+            dmi_address: 0,
+            dmi_data: 0,
+            dmactive: false,
+            dmcontrol: 0,
+            data: [0; DATA_COUNT],
+            progbuf: [0; PROGBUF_SIZE],
+            cmderr: 0,
+            sbcs: 0,
+            sbaddress: 0,
+            sbdata: 0,
+            memory: FlashAndRam::new(FLASH, RAM),
+            registers: [0; 32],
+            pc: 0,
+            csrs: HashMap::new(),
+            dcsr: 0,
+            cause: 0,
+            dscratch0: 0,
+            tselect: 0,
+            triggers: [(0, 0); TRIGGERS],
+            instruction_cache: HashMap::new(),
+            halted: false,
+            in_reset: false,
+            havereset: false,
+            resumeack: false,
+            resumed: false,
+        };
+
+        chip.reset_hart();
+
+        SimulatedRiscv {
+            chip: Arc::new(Mutex::new(chip)),
+            speed_khz: 1_000,
+            attached: false,
+        }
+    }
+
+    /// The description of the simulated chip, to attach to it.
+    pub fn target() -> Target {
+        // Each routine is `custom-0 n; ret`.
+        super::target("Simulated RISC-V", CoreType::Riscv, FLASH, RAM, |routine| {
+            let call = routine << 20 | CUSTOM_0;
+            [call.to_le_bytes(), RET.to_le_bytes()].concat()
+        })
+    }
+
+    /// Reads the flash or the RAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not fit into the flash or the RAM.
+    pub fn read_memory(&self, address: u32, data: &mut [u8]) {
+        self.chip().memory.read(address, data);
+    }
+
+    /// Writes the flash or the RAM, like a programmer would.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` does not fit into the flash or the RAM.
+    pub fn write_memory(&self, address: u32, data: &[u8]) {
+        self.chip().memory.write(address, data);
+    }
+
+    /// Returns the value of a register of the hart, numbered like in abstract commands.
+    /// The GPRs start at 0x1000, and the CSRs are numbered by their address.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hart has no such register.
+    pub fn core_register(&self, register: u16) -> u32 {
+        let chip = self.chip();
+
+        match register {
+            0x1000..=0x101F => chip.registers[usize::from(register - 0x1000)],
+            DPC => chip.pc,
+            _ => chip
+                .read_csr(register)
+                .unwrap_or_else(|| panic!("The hart has no register {:#06x}", register)),
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.chip().halted
+    }
+
+    fn chip(&self) -> MutexGuard<'_, Chip> {
+        self.chip.lock().unwrap()
+    }
+
+    fn check_attached(&self) -> Result<(), DebugProbeError> {
+        if self.attached {
+            Ok(())
+        } else {
+            Err(DebugProbeError::NotAttached)
+        }
+    }
+}
+
+impl Default for SimulatedRiscv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for SimulatedRiscv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulatedRiscv")
+            .field("speed_khz", &self.speed_khz)
+            .field("attached", &self.attached)
+            .finish()
+    }
+}
+
+/// Sign extends the lowest `bits` bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as u32
+}
+
+/// An access to memory which failed.
+enum BusError {
+    Misaligned,
+    Fault,
+}
+
+/// The kinds of accesses which are matched by triggers.
+#[derive(Copy, Clone)]
+enum Access {
+    Execute,
+    Load,
+    Store,
+}
+
+/// What happens after an instruction was executed.
+enum Outcome {
+    Next,
+    Jump(u32),
+    Ebreak,
+    /// An exception, with the values for `mcause` and `mtval`.
+    Exception(u32, u32),
+    /// A trigger matched a load or a store, which was not carried out.
+    Trigger(usize),
+}
+
+/// The state of the simulated chip, from the JTAG TAP to the hart.
+struct Chip {
+    instruction: u32,
+    /// The address and the data which are captured by the next scan of `dmi`.
+    dmi_address: u32,
+    dmi_data: u32,
+
+    dmactive: bool,
+    /// The bits of `dmcontrol` which are read back.
+    dmcontrol: u32,
+    data: [u32; DATA_COUNT],
+    progbuf: [u32; PROGBUF_SIZE],
+    cmderr: u32,
+    /// The bits of `sbcs` which are written.
+    sbcs: u32,
+    sbaddress: u32,
+    sbdata: u32,
+
+    memory: FlashAndRam,
+
+    registers: [u32; 32],
+    /// The PC, which is `dpc` in debug mode.
+    pc: u32,
+    /// The machine mode CSRs.
+    csrs: HashMap<u16, u32>,
+    /// The writable bits of `dcsr`.
+    dcsr: u32,
+    /// Why the hart entered debug mode.
+    cause: u32,
+    dscratch0: u32,
+    tselect: usize,
+    /// The writable bits of `tdata1` and `tdata2` of each trigger.
+    triggers: [(u32, u32); TRIGGERS],
+    /// The instructions fetched by the hart since the last `fence.i`, which are executed
+    /// even if the memory was changed since.
+    instruction_cache: HashMap<u32, u32>,
+
+    /// Whether the hart is in debug mode.
+    halted: bool,
+    in_reset: bool,
+    havereset: bool,
+    resumeack: bool,
+    /// Whether the hart was resumed, so that a trigger at the first instruction is ignored.
+    resumed: bool,
+}
+
+impl Chip {
+    /// Shifts `data` through the data register which is selected by the instruction.
+    fn scan(&mut self, data: &[u8], len: u32) -> Vec<u8> {
+        let mut bytes = [0; 8];
+        let used = data.len().min(8);
+        bytes[..used].copy_from_slice(&data[..used]);
+        let input = u64::from_le_bytes(bytes);
+
+        let output = match self.instruction {
+            IDCODE => u64::from(IDCODE_VALUE),
+            // Accesses to the debug module never fail, so `dmireset` has nothing to reset.
+            DTMCS => u64::from(DTMCS_VALUE),
+            DMI => self.dmi(input),
+            // BYPASS, and all other instructions.
+            _ => 0,
+        };
+
+        let len = (len as usize).div_ceil(8);
+        output.to_le_bytes()[..len.min(8)]
+            .iter()
+            .copied()
+            .chain(std::iter::repeat(0))
+            .take(len)
+            .collect()
+    }
+
+    /// Captures the result of the previous access to the debug module, and starts the next one.
+    fn dmi(&mut self, input: u64) -> u64 {
+        let captured = u64::from(self.dmi_address) << 34 | u64::from(self.dmi_data) << 2;
+
+        let address = (input >> 34) as u32 & ((1 << ABITS) - 1);
+        let data = (input >> 2) as u32;
+
+        match input & 0x3 {
+            DMI_OP_READ => {
+                self.dmi_address = address;
+                self.dmi_data = self.read_dm(address);
+            }
+            DMI_OP_WRITE => {
+                self.dmi_address = address;
+                self.dmi_data = data;
+                self.write_dm(address, data);
+            }
+            _ => (),
+        }
+
+        captured
+    }
+
+    fn hart_selected(&self) -> bool {
+        self.dmcontrol & DMCONTROL_HARTSEL == 0
+    }
+
+    fn read_dm(&mut self, address: u32) -> u32 {
+        match address {
+            a if (DATA0..DATA0 + DATA_COUNT as u32).contains(&a) => self.data[(a - DATA0) as usize],
+            DMCONTROL => self.dmcontrol | self.dmactive as u32,
+            DMSTATUS => self.dmstatus(),
+            HARTINFO => HARTINFO_VALUE,
+            ABSTRACTCS => (PROGBUF_SIZE as u32) << 24 | self.cmderr << 8 | DATA_COUNT as u32,
+            a if (PROGBUF0..PROGBUF0 + PROGBUF_SIZE as u32).contains(&a) => {
+                self.progbuf[(a - PROGBUF0) as usize]
+            }
+            SBCS => SBCS_VALUE | self.sbcs,
+            SBADDRESS0 => self.sbaddress,
+            SBDATA0 => {
+                let value = self.sbdata;
+
+                if self.sbcs & SBCS_SBREADONDATA != 0 {
+                    self.system_bus_read();
+                }
+
+                value
+            }
+            // `command` and `abstractauto`, which is not supported, read as zero,
+            // like all registers which are not implemented.
+            _ => 0,
+        }
+    }
+
+    fn write_dm(&mut self, address: u32, value: u32) {
+        if address == DMCONTROL {
+            self.write_dmcontrol(value);
+            return;
+        }
+
+        // The debug module is held in reset until it is activated.
+        if !self.dmactive {
+            return;
+        }
+
+        match address {
+            a if (DATA0..DATA0 + DATA_COUNT as u32).contains(&a) => {
+                self.data[(a - DATA0) as usize] = value
+            }
+            ABSTRACTCS => self.cmderr &= !(value >> 8),
+            // Commands are ignored until the error of the previous command is cleared.
+            COMMAND if self.cmderr == 0 => {
+                if let Err(cmderr) = self.execute_command(value) {
+                    self.cmderr = cmderr;
+                }
+            }
+            a if (PROGBUF0..PROGBUF0 + PROGBUF_SIZE as u32).contains(&a) => {
+                self.progbuf[(a - PROGBUF0) as usize] = value
+            }
+            SBCS => {
+                let cleared = value & (SBCS_SBBUSYERROR | SBCS_SBERROR);
+                let settings =
+                    SBCS_SBREADONADDR | SBCS_SBACCESS | SBCS_SBAUTOINCREMENT | SBCS_SBREADONDATA;
+
+                self.sbcs = (self.sbcs & !settings & !cleared) | (value & settings);
+            }
+            SBADDRESS0 => {
+                self.sbaddress = value;
+
+                if self.sbcs & SBCS_SBREADONADDR != 0 {
+                    self.system_bus_read();
+                }
+            }
+            SBDATA0 => {
+                self.sbdata = value;
+                self.system_bus_write();
+            }
+            _ => (),
+        }
+    }
+
+    fn write_dmcontrol(&mut self, value: u32) {
+        self.dmactive = value & DMCONTROL_DMACTIVE != 0;
+
+        if !self.dmactive {
+            // Deactivating the debug module resets it, and releases the hart from reset.
+            self.dmcontrol = 0;
+            self.data = [0; DATA_COUNT];
+            self.progbuf = [0; PROGBUF_SIZE];
+            self.cmderr = 0;
+            self.sbcs = 0;
+            self.in_reset = false;
+            return;
+        }
+
+        self.dmcontrol = value & (DMCONTROL_HARTRESET | DMCONTROL_HARTSEL | DMCONTROL_NDMRESET);
+
+        let selected = self.hart_selected();
+        let reset =
+            value & DMCONTROL_NDMRESET != 0 || (selected && value & DMCONTROL_HARTRESET != 0);
+
+        if reset {
+            self.reset_hart();
+            self.in_reset = true;
+        } else {
+            self.in_reset = false;
+        }
+
+        if !selected || self.in_reset {
+            return;
+        }
+
+        if value & DMCONTROL_ACKHAVERESET != 0 {
+            self.havereset = false;
+        }
+
+        if value & DMCONTROL_HALTREQ != 0 {
+            if !self.halted {
+                self.enter_debug_mode(CAUSE_HALTREQ);
+            }
+        } else if value & DMCONTROL_RESUMEREQ != 0 {
+            self.resumeack = false;
+
+            if self.halted {
+                self.resume();
+            }
+        }
+    }
+
+    fn dmstatus(&mut self) -> u32 {
+        if !self.hart_selected() {
+            return DMSTATUS_VALUE | DMSTATUS_NONEXISTENT;
+        }
+
+        // The hart runs while the debugger polls its state.
+        self.run(INSTRUCTIONS_PER_POLL);
+
+        let state = if self.in_reset {
+            DMSTATUS_UNAVAILABLE
+        } else if self.halted {
+            DMSTATUS_HALTED
+        } else {
+            DMSTATUS_RUNNING
+        };
+
+        let flags = [
+            (self.resumeack, DMSTATUS_RESUMEACK),
+            (self.havereset, DMSTATUS_HAVERESET),
+        ];
+
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(DMSTATUS_VALUE | state, |value, (_, bits)| value | bits)
+    }
+
+    /// Executes an abstract command, and returns the error of a failed command.
+    fn execute_command(&mut self, command: u32) -> Result<(), u32> {
+        // Only the access register command is supported.
+        if command >> 24 != 0 {
+            return Err(CMDERR_NOT_SUPPORTED);
+        }
+
+        if !self.hart_selected() || self.in_reset || !self.halted {
+            return Err(CMDERR_HALT_RESUME);
+        }
+
+        if command & COMMAND_TRANSFER != 0 {
+            if (command >> 20) & 0x7 != COMMAND_AARSIZE_32 {
+                return Err(CMDERR_NOT_SUPPORTED);
+            }
+
+            let register = command as u16;
+            let write = command & COMMAND_WRITE != 0;
+
+            match register {
+                0x1000..=0x101F => {
+                    let index = usize::from(register - 0x1000);
+
+                    if write {
+                        self.write_register(index, self.data[0]);
+                    } else {
+                        self.data[0] = self.registers[index];
+                    }
+                }
+                0x0000..=0x0FFF => {
+                    if write {
+                        self.write_csr(register, self.data[0])
+                            .ok_or(CMDERR_EXCEPTION)?;
+                    } else {
+                        self.data[0] = self.read_csr(register).ok_or(CMDERR_EXCEPTION)?;
+                    }
+                }
+                _ => return Err(CMDERR_NOT_SUPPORTED),
+            }
+        }
+
+        if command & COMMAND_POSTEXEC != 0 {
+            self.execute_progbuf()?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes the program buffer until an `ebreak`, or until its end.
+    fn execute_progbuf(&mut self) -> Result<(), u32> {
+        for index in 0..PROGBUF_SIZE {
+            match self.execute(self.progbuf[index], true) {
+                Outcome::Next => (),
+                Outcome::Ebreak => return Ok(()),
+                // Jumps are not supported in the program buffer.
+                _ => return Err(CMDERR_EXCEPTION),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn system_bus_read(&mut self) {
+        if self.sbcs & (SBCS_SBBUSYERROR | SBCS_SBERROR) != 0 {
+            return;
+        }
+
+        let result =
+            self.system_bus_size()
+                .and_then(|size| match self.bus_read(self.sbaddress, size) {
+                    Ok(value) => Ok((value, size)),
+                    Err(error) => Err(sberror(error)),
+                });
+
+        match result {
+            Ok((value, size)) => {
+                self.sbdata = value;
+                self.system_bus_increment(size);
+            }
+            Err(error) => self.sbcs |= error << 12,
+        }
+    }
+
+    fn system_bus_write(&mut self) {
+        if self.sbcs & (SBCS_SBBUSYERROR | SBCS_SBERROR) != 0 {
+            return;
+        }
+
+        let result = self.system_bus_size().and_then(|size| {
+            self.bus_write(self.sbaddress, size, self.sbdata)
+                .map(|_| size)
+                .map_err(sberror)
+        });
+
+        match result {
+            Ok(size) => self.system_bus_increment(size),
+            Err(error) => self.sbcs |= error << 12,
+        }
+    }
+
+    fn system_bus_size(&self) -> Result<u32, u32> {
+        match (self.sbcs & SBCS_SBACCESS) >> 17 {
+            access @ 0..=2 => Ok(1 << access),
+            _ => Err(SBERROR_SIZE),
+        }
+    }
+
+    fn system_bus_increment(&mut self, size: u32) {
+        if self.sbcs & SBCS_SBAUTOINCREMENT != 0 {
+            self.sbaddress = self.sbaddress.wrapping_add(size);
+        }
+    }
+
+    /// Reads `size` bytes from the flash or the RAM, which have to be aligned.
+    fn bus_read(&self, address: u32, size: u32) -> Result<u32, BusError> {
+        if address & (size - 1) != 0 {
+            return Err(BusError::Misaligned);
+        }
+
+        let bytes = self
+            .memory
+            .get(address, size as usize)
+            .ok_or(BusError::Fault)?;
+
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | u32::from(byte)))
+    }
+
+    /// Writes `size` bytes to the RAM, the flash is read-only.
+    fn bus_write(&mut self, address: u32, size: u32, value: u32) -> Result<(), BusError> {
+        if address & (size - 1) != 0 {
+            return Err(BusError::Misaligned);
+        }
+
+        let ram = self
+            .memory
+            .ram_mut(address, size as usize)
+            .ok_or(BusError::Fault)?;
+        ram.copy_from_slice(&value.to_le_bytes()[..size as usize]);
+
+        Ok(())
+    }
+
+    /// Resets the hart, which starts at the beginning of the flash.
+    fn reset_hart(&mut self) {
+        self.registers = [0; 32];
+        self.pc = FLASH.start;
+        self.csrs = MACHINE_CSRS.iter().map(|&csr| (csr, 0)).collect();
+        self.dcsr = 0;
+        self.cause = 0;
+        self.dscratch0 = 0;
+        self.tselect = 0;
+        self.triggers = [(0, 0); TRIGGERS];
+        self.instruction_cache.clear();
+        self.halted = false;
+        self.havereset = true;
+        self.resumed = false;
+    }
+
+    fn enter_debug_mode(&mut self, cause: u32) {
+        self.halted = true;
+        self.cause = cause;
+    }
+
+    fn resume(&mut self) {
+        self.halted = false;
+        self.resumeack = true;
+        self.resumed = true;
+
+        if self.dcsr & DCSR_STEP != 0 {
+            self.step();
+        } else {
+            self.run(INSTRUCTIONS_PER_POLL);
+        }
+    }
+
+    /// Executes a single instruction, and enters debug mode afterwards.
+    fn step(&mut self) {
+        self.run(1);
+
+        if !self.halted {
+            self.enter_debug_mode(CAUSE_STEP);
+        }
+    }
+
+    /// Executes instructions until the hart enters debug mode.
+    fn run(&mut self, instructions: usize) {
+        for _ in 0..instructions {
+            if self.halted || self.in_reset {
+                return;
+            }
+
+            // The hart can be resumed at a trigger, which then only fires the next time.
+            if !std::mem::take(&mut self.resumed) {
+                if let Some(trigger) = self.matching_trigger(Access::Execute, self.pc) {
+                    self.fire_trigger(trigger);
+                    return;
+                }
+            }
+
+            let instruction = match self.instruction_cache.get(&self.pc) {
+                Some(&instruction) => Ok(instruction),
+                None => self.bus_read(self.pc, 4),
+            };
+
+            let outcome = match instruction {
+                Ok(instruction) => {
+                    self.instruction_cache.insert(self.pc, instruction);
+                    self.execute(instruction, false)
+                }
+                Err(_) => Outcome::Exception(INSTRUCTION_ACCESS_FAULT, self.pc),
+            };
+
+            match outcome {
+                Outcome::Next => self.pc = self.pc.wrapping_add(4),
+                Outcome::Jump(target) => self.pc = target,
+                Outcome::Ebreak => {
+                    if self.dcsr & DCSR_EBREAKM != 0 {
+                        self.enter_debug_mode(CAUSE_EBREAK);
+                    } else {
+                        self.trap(BREAKPOINT, self.pc);
+                    }
+                }
+                Outcome::Exception(cause, value) => self.trap(cause, value),
+                Outcome::Trigger(trigger) => self.fire_trigger(trigger),
+            }
+        }
+    }
+
+    /// Takes an exception, which continues at `mtvec`.
+    fn trap(&mut self, cause: u32, value: u32) {
+        self.csrs.insert(MEPC, self.pc);
+        self.csrs.insert(MCAUSE, cause);
+        self.csrs.insert(MTVAL, value);
+        self.pc = self.csrs[&MTVEC] & !0x3;
+    }
+
+    /// Returns the first trigger which matches an access to `address`.
+    fn matching_trigger(&self, access: Access, address: u32) -> Option<usize> {
+        self.triggers.iter().position(|&(tdata1, tdata2)| {
+            let kind = match access {
+                Access::Execute => MCONTROL_EXECUTE,
+                Access::Load => MCONTROL_LOAD,
+                Access::Store => MCONTROL_STORE,
+            };
+
+            // Only triggers which enter debug mode in machine mode are supported.
+            let enabled = tdata1 & kind != 0
+                && tdata1 & MCONTROL_M != 0
+                && tdata1 & MCONTROL_DMODE != 0
+                && (tdata1 & MCONTROL_ACTION) >> 12 == 1;
+
+            let matches = match (tdata1 & MCONTROL_MATCH) >> 7 {
+                0 => address == tdata2,
+                // NAPOT, where the trailing ones of `tdata2` select the size of the range.
+                _ => {
+                    let mask = ((1u64 << (tdata2.trailing_ones() + 1)) - 1) as u32;
+                    address & !mask == tdata2 & !mask
+                }
+            };
+
+            enabled && matches
+        })
+    }
+
+    fn fire_trigger(&mut self, trigger: usize) {
+        self.triggers[trigger].0 |= MCONTROL_HIT;
+        self.enter_debug_mode(CAUSE_TRIGGER);
+    }
+
+    fn write_register(&mut self, index: usize, value: u32) {
+        // x0 is hardwired to zero.
+        if index != 0 {
+            self.registers[index] = value;
+        }
+    }
+
+    /// Reads a CSR, which fails if the CSR is not accessible.
+    fn read_csr(&self, csr: u16) -> Option<u32> {
+        match csr {
+            MISA => Some(MISA_VALUE),
+            MVENDORID..=MHARTID => Some(0),
+            TSELECT => Some(self.tselect as u32),
+            TDATA1 => Some(MCONTROL_TYPE << 28 | MCONTROL_MASKMAX | self.triggers[self.tselect].0),
+            TDATA2 => Some(self.triggers[self.tselect].1),
+            TINFO => Some(1 << MCONTROL_TYPE),
+            // The debug mode CSRs are only accessible in debug mode.
+            DCSR if self.halted => Some(DCSR_VALUE | self.cause << 6 | self.dcsr),
+            DPC if self.halted => Some(self.pc),
+            DSCRATCH0 if self.halted => Some(self.dscratch0),
+            _ => self.csrs.get(&csr).copied(),
+        }
+    }
+
+    /// Writes a CSR, which fails if the CSR is not accessible or read-only.
+    fn write_csr(&mut self, csr: u16, value: u32) -> Option<()> {
+        match csr {
+            // Writes to misa are ignored, as it can't be changed.
+            MISA => (),
+            // Selecting a trigger which doesn't exist keeps the current selection.
+            TSELECT => {
+                if (value as usize) < TRIGGERS {
+                    self.tselect = value as usize;
+                }
+            }
+            TDATA1 => {
+                let mut tdata1 = value & MCONTROL_WRITABLE;
+
+                // Only entering debug mode, and exact and NAPOT matches are supported.
+                if (tdata1 & MCONTROL_ACTION) >> 12 > 1 {
+                    tdata1 &= !MCONTROL_ACTION;
+                }
+                if (tdata1 & MCONTROL_MATCH) >> 7 > 1 {
+                    tdata1 &= !MCONTROL_MATCH;
+                }
+
+                self.triggers[self.tselect].0 = tdata1;
+            }
+            TDATA2 => self.triggers[self.tselect].1 = value,
+            TINFO => (),
+            DCSR if self.halted => self.dcsr = value & DCSR_WRITABLE,
+            DPC if self.halted => self.pc = value & !0x3,
+            DSCRATCH0 if self.halted => self.dscratch0 = value,
+            _ => {
+                let register = self.csrs.get_mut(&csr)?;
+                *register = value;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Executes an instruction, in debug mode from the program buffer.
+    fn execute(&mut self, instruction: u32, debug_mode: bool) -> Outcome {
+        let rd = (instruction >> 7) as usize & 0x1F;
+        let rs1 = (instruction >> 15) as usize & 0x1F;
+        let rs2 = (instruction >> 20) as usize & 0x1F;
+        let funct3 = (instruction >> 12) & 0x7;
+        let funct7 = instruction >> 25;
+
+        let i_immediate = sign_extend(instruction >> 20, 12);
+        let s_immediate = sign_extend((instruction >> 25) << 5 | (instruction >> 7) & 0x1F, 12);
+
+        let illegal = Outcome::Exception(ILLEGAL_INSTRUCTION, instruction);
+        let (x1, x2) = (self.registers[rs1], self.registers[rs2]);
+
+        match instruction & 0x7F {
+            // lui
+            0b011_0111 => self.write_register(rd, instruction & 0xFFFF_F000),
+            // auipc
+            0b001_0111 => {
+                self.write_register(rd, self.pc.wrapping_add(instruction & 0xFFFF_F000));
+            }
+            // jal
+            0b110_1111 => {
+                let offset = sign_extend(
+                    (instruction >> 31) << 20
+                        | (instruction >> 12 & 0xFF) << 12
+                        | (instruction >> 20 & 0x1) << 11
+                        | (instruction >> 21 & 0x3FF) << 1,
+                    21,
+                );
+
+                self.write_register(rd, self.pc.wrapping_add(4));
+                return Outcome::Jump(self.pc.wrapping_add(offset));
+            }
+            // jalr
+            0b110_0111 if funct3 == 0 => {
+                self.write_register(rd, self.pc.wrapping_add(4));
+                return Outcome::Jump(x1.wrapping_add(i_immediate) & !0x3);
+            }
+            // beq, bne, blt, bge, bltu and bgeu
+            0b110_0011 => {
+                let taken = match funct3 {
+                    0b000 => x1 == x2,
+                    0b001 => x1 != x2,
+                    0b100 => (x1 as i32) < (x2 as i32),
+                    0b101 => (x1 as i32) >= (x2 as i32),
+                    0b110 => x1 < x2,
+                    0b111 => x1 >= x2,
+                    _ => return illegal,
+                };
+
+                if taken {
+                    let offset = sign_extend(
+                        (instruction >> 31) << 12
+                            | (instruction >> 7 & 0x1) << 11
+                            | (instruction >> 25 & 0x3F) << 5
+                            | (instruction >> 8 & 0xF) << 1,
+                        13,
+                    );
+
+                    return Outcome::Jump(self.pc.wrapping_add(offset));
+                }
+            }
+            // lb, lh, lw, lbu and lhu
+            0b000_0011 => {
+                let address = x1.wrapping_add(i_immediate);
+                let (size, signed) = match funct3 {
+                    0b000 => (1, true),
+                    0b001 => (2, true),
+                    0b010 => (4, false),
+                    0b100 => (1, false),
+                    0b101 => (2, false),
+                    _ => return illegal,
+                };
+
+                if !debug_mode {
+                    if let Some(trigger) = self.matching_trigger(Access::Load, address) {
+                        return Outcome::Trigger(trigger);
+                    }
+                }
+
+                match self.bus_read(address, size) {
+                    Ok(value) if signed => self.write_register(rd, sign_extend(value, size * 8)),
+                    Ok(value) => self.write_register(rd, value),
+                    Err(BusError::Misaligned) => {
+                        return Outcome::Exception(LOAD_ADDRESS_MISALIGNED, address)
+                    }
+                    Err(BusError::Fault) => return Outcome::Exception(LOAD_ACCESS_FAULT, address),
+                }
+            }
+            // sb, sh and sw
+            0b010_0011 => {
+                let address = x1.wrapping_add(s_immediate);
+                let size = match funct3 {
+                    0b000 => 1,
+                    0b001 => 2,
+                    0b010 => 4,
+                    _ => return illegal,
+                };
+
+                if !debug_mode {
+                    if let Some(trigger) = self.matching_trigger(Access::Store, address) {
+                        return Outcome::Trigger(trigger);
+                    }
+                }
+
+                match self.bus_write(address, size, x2) {
+                    Ok(()) => (),
+                    Err(BusError::Misaligned) => {
+                        return Outcome::Exception(STORE_ADDRESS_MISALIGNED, address)
+                    }
+                    Err(BusError::Fault) => return Outcome::Exception(STORE_ACCESS_FAULT, address),
+                }
+            }
+            // fence, which has nothing to order, and fence.i.
+            0b000_1111 => match funct3 {
+                0b000 => (),
+                0b001 => self.instruction_cache.clear(),
+                _ => return illegal,
+            },
+            // The register-immediate and the register-register operations.
+            opcode @ 0b001_0011 | opcode @ 0b011_0011 => {
+                let immediate = opcode == 0b001_0011;
+                let operand = if immediate { i_immediate } else { x2 };
+                let shift = operand & 0x1F;
+
+                // Only sub and sra, and their immediate forms, have a function code.
+                let alternate = match (funct3, funct7) {
+                    (_, 0) => false,
+                    (0b000, 0b010_0000) if !immediate => true,
+                    (0b101, 0b010_0000) => true,
+                    (0b000, _) | (0b010, _) | (0b011, _) | (0b100, _) | (0b110, _) | (0b111, _)
+                        if immediate =>
+                    {
+                        false
+                    }
+                    _ => return illegal,
+                };
+
+                let value = match funct3 {
+                    0b000 if alternate => x1.wrapping_sub(operand),
+                    0b000 => x1.wrapping_add(operand),
+                    0b001 => x1 << shift,
+                    0b010 => ((x1 as i32) < (operand as i32)) as u32,
+                    0b011 => (x1 < operand) as u32,
+                    0b100 => x1 ^ operand,
+                    0b101 if alternate => ((x1 as i32) >> shift) as u32,
+                    0b101 => x1 >> shift,
+                    0b110 => x1 | operand,
+                    _ => x1 & operand,
+                };
+
+                self.write_register(rd, value);
+            }
+            // ecall, ebreak, mret, wfi and the CSR instructions.
+            0b111_0011 => match funct3 {
+                0b000 => match instruction >> 7 {
+                    0x0000_0000 => return Outcome::Exception(ECALL, 0),
+                    0x0000_2000 => return Outcome::Ebreak,
+                    0x0060_4000 if !debug_mode => return Outcome::Jump(self.csrs[&MEPC]),
+                    // wfi, which returns immediately, as there are no interrupts.
+                    0x0020_A000 => (),
+                    _ => return illegal,
+                },
+                0b100 => return illegal,
+                _ => {
+                    let csr = (instruction >> 20) as u16;
+                    let operand = if funct3 & 0b100 != 0 { rs1 as u32 } else { x1 };
+
+                    // csrrw does not read the CSR for x0, and csrrs and csrrc do not write
+                    // it for x0 or a zero immediate.
+                    let old = if funct3 & 0b11 == 0b01 && rd == 0 {
+                        0
+                    } else {
+                        match self.read_csr(csr) {
+                            Some(value) => value,
+                            None => return illegal,
+                        }
+                    };
+
+                    let new = match funct3 & 0b11 {
+                        0b01 => Some(operand),
+                        _ if rs1 == 0 => None,
+                        0b10 => Some(old | operand),
+                        _ => Some(old & !operand),
+                    };
+
+                    if let Some(new) = new {
+                        if self.write_csr(csr, new).is_none() {
+                            return illegal;
+                        }
+                    }
+
+                    self.write_register(rd, old);
+                }
+            },
+            CUSTOM_0 if !debug_mode => {
+                let arguments = [self.registers[A0], self.registers[A1], self.registers[A2]];
+                let result = self.memory.call_flash_routine(instruction >> 20, arguments);
+                self.write_register(A0, result);
+            }
+            _ => return illegal,
+        }
+
+        Outcome::Next
+    }
+}
+
+fn sberror(error: BusError) -> u32 {
+    match error {
+        BusError::Misaligned => SBERROR_ALIGNMENT,
+        BusError::Fault => SBERROR_BAD_ADDRESS,
+    }
+}
+
+impl DebugProbe for SimulatedRiscv {
+    fn new_from_probe_info(_info: &DebugProbeInfo) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated)
+    }
+
+    fn get_name(&self) -> &str {
+        "Simulated RISC-V"
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed_khz
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        if speed_khz == 0 {
+            return Err(DebugProbeError::UnsupportedSpeed(speed_khz));
+        }
+
+        self.speed_khz = speed_khz;
+        Ok(speed_khz)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = true;
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = false;
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        let mut chip = self.chip();
+
+        chip.reset_hart();
+        chip.in_reset = false;
+
+        Ok(())
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        match protocol {
+            WireProtocol::Jtag => Ok(()),
+            WireProtocol::Swd => Err(DebugProbeError::UnsupportedProtocol(protocol)),
+        }
+    }
+
+    fn dedicated_memory_interface(&self) -> Option<Memory<'_>> {
+        None
+    }
+
+    fn get_interface_dap(&self) -> Option<&dyn DAPAccess> {
+        None
+    }
+
+    fn get_interface_dap_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        None
+    }
+
+    fn get_interface_jtag(&self) -> Option<&dyn JTAGAccess> {
+        Some(self as _)
+    }
+
+    fn get_interface_jtag_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self as _)
+    }
+}
+
+impl JTAGAccess for SimulatedRiscv {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        self.write_register(address, &[], len)
+    }
+
+    fn set_idle_cycles(&mut self, _idle_cycles: u8) {}
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        self.check_attached()?;
+
+        let mut chip = self.chip();
+        chip.instruction = address;
+
+        Ok(chip.scan(data, len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SimulatedRiscv;
+    use crate::architecture::riscv::communication_interface::{
+        RiscvCommunicationInterface, RiscvCommunicationInterfaceState,
+    };
+    use crate::debug::{DebugInfo, Value};
+    use crate::flashing::{download_file, Format};
+    use crate::probe::JTAGAccess;
+    use crate::{
+        CoreRegisterAddress, CoreStatus, HaltReason, MemoryInterface, Probe, Session,
+        WatchpointKind,
+    };
+    use std::path::Path;
+
+    const A0: u16 = 0x100A;
+    const A1: u16 = 0x100B;
+    const PC: u16 = 0x7B1;
+
+    /// `addi a0, a0, 1; addi a0, a0, 1; ebreak; j .` at the start of the flash.
+    const PROGRAM: [u32; 4] = [0x0015_0513, 0x0015_0513, 0x0010_0073, 0x0000_006F];
+
+    /// `sw a0, 0(a1); lw a0, 4(a1); j .` at the start of the flash.
+    const STORE_PROGRAM: [u32; 3] = [0x00A5_A023, 0x0045_A503, 0x0000_006F];
+
+    fn session(chip: &SimulatedRiscv) -> Session {
+        Probe::new(chip.clone())
+            .attach(SimulatedRiscv::target())
+            .unwrap()
+    }
+
+    fn programmed_chip(program: &[u32]) -> SimulatedRiscv {
+        let chip = SimulatedRiscv::new();
+        let bytes = program
+            .iter()
+            .flat_map(|instruction| instruction.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+
+        chip.write_memory(0x2000_0000, &bytes);
+        chip
+    }
+
+    /// Scans `dmi`, and returns the address, data and status of the previous access.
+    fn dmi(chip: &mut SimulatedRiscv, address: u32, data: u32, op: u64) -> (u32, u32, u64) {
+        let value = u64::from(address) << 34 | u64::from(data) << 2 | op;
+        let bytes = chip.write_register(0x11, &value.to_le_bytes(), 41).unwrap();
+
+        assert_eq!(bytes.len(), 6);
+
+        let mut captured = [0; 8];
+        captured[..6].copy_from_slice(&bytes);
+        let captured = u64::from_le_bytes(captured);
+
+        (
+            (captured >> 34) as u32,
+            (captured >> 2) as u32,
+            captured & 0x3,
+        )
+    }
+
+    fn read_dm(chip: &mut SimulatedRiscv, address: u32) -> u32 {
+        dmi(chip, address, 0, 1);
+        dmi(chip, 0, 0, 0).1
+    }
+
+    fn write_dm(chip: &mut SimulatedRiscv, address: u32, value: u32) {
+        dmi(chip, address, value, 2);
+    }
+
+    #[test]
+    fn debug_module_is_found_through_the_dtm() {
+        let mut probe = Probe::new(SimulatedRiscv::new());
+        probe.attach_to_unspecified().unwrap();
+
+        let mut state = RiscvCommunicationInterfaceState::new();
+        let mut interface = RiscvCommunicationInterface::new(&mut probe, &mut state)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(interface.read_idcode().unwrap(), 0x2000_0913);
+    }
+
+    #[test]
+    fn dmi_returns_the_result_of_the_previous_access() {
+        let mut chip = SimulatedRiscv::new();
+        chip.attached = true;
+
+        assert_eq!(dmi(&mut chip, 0x11, 0, 1), (0, 0, 0));
+        let (address, dmstatus, status) = dmi(&mut chip, 0x10, 1, 2);
+        assert_eq!((address, status), (0x11, 0));
+        assert_eq!(dmstatus & 0xF, 2);
+
+        assert_eq!(read_dm(&mut chip, 0x10), 1);
+    }
+
+    #[test]
+    fn system_bus_accesses_memory() {
+        let mut chip = SimulatedRiscv::new();
+        chip.attached = true;
+
+        write_dm(&mut chip, 0x10, 1);
+
+        // 32 bit accesses, with autoincrement and reads on address and data.
+        write_dm(&mut chip, 0x38, 2 << 17 | 1 << 16);
+        write_dm(&mut chip, 0x39, 0x8000_0010);
+        write_dm(&mut chip, 0x3C, 0x1122_3344);
+        write_dm(&mut chip, 0x3C, 0x5566_7788);
+
+        write_dm(&mut chip, 0x38, 2 << 17 | 1 << 20 | 1 << 16 | 1 << 15);
+        write_dm(&mut chip, 0x39, 0x8000_0010);
+        assert_eq!(read_dm(&mut chip, 0x3C), 0x1122_3344);
+        assert_eq!(read_dm(&mut chip, 0x3C), 0x5566_7788);
+
+        let mut ram = [0; 8];
+        chip.read_memory(0x8000_0010, &mut ram);
+        assert_eq!(ram, [0x44, 0x33, 0x22, 0x11, 0x88, 0x77, 0x66, 0x55]);
+
+        // The flash is read-only, and the error stops further accesses until it is cleared.
+        write_dm(&mut chip, 0x38, 2 << 17);
+        write_dm(&mut chip, 0x39, 0x2000_0000);
+        write_dm(&mut chip, 0x3C, 0);
+        assert_eq!(read_dm(&mut chip, 0x38) >> 12 & 0x7, 2);
+
+        write_dm(&mut chip, 0x39, 0x8000_0010);
+        write_dm(&mut chip, 0x3C, 0);
+        chip.read_memory(0x8000_0010, &mut ram);
+        assert_eq!(ram[0], 0x44);
+
+        write_dm(&mut chip, 0x38, 2 << 17 | 0x7 << 12);
+        assert_eq!(read_dm(&mut chip, 0x38) >> 12 & 0x7, 0);
+        write_dm(&mut chip, 0x3C, 0);
+        chip.read_memory(0x8000_0010, &mut ram);
+        assert_eq!(ram[0], 0x00);
+    }
+
+    #[test]
+    fn memory_is_accessed_through_the_program_buffer() {
+        let chip = SimulatedRiscv::new();
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.halt().unwrap();
+
+        core.write_32(0x8000_0100, &[1, 2, 3]).unwrap();
+        core.write_word_8(0x8000_0105, 0xaa).unwrap();
+        core.write_8(0x8000_0200, &[0x11, 0x22, 0x33]).unwrap();
+
+        let mut words = [0; 3];
+        core.read_32(0x8000_0100, &mut words).unwrap();
+        assert_eq!(words, [1, 0xaa02, 3]);
+        assert_eq!(core.read_word_32(0x8000_0200).unwrap(), 0x33_2211);
+        assert_eq!(core.read_word_8(0x8000_0201).unwrap(), 0x22);
+
+        // The flash is read-only, and nothing is mapped at 0x4000_0000.
+        assert_eq!(core.read_word_32(0x2000_0100).unwrap(), 0xffff_ffff);
+        assert!(core.write_word_32(0x2000_0100, 0).is_err());
+        assert!(core.read_word_32(0x4000_0000).is_err());
+
+        let mut ram = [0; 3];
+        chip.read_memory(0x8000_0200, &mut ram);
+        assert_eq!(ram, [0x11, 0x22, 0x33]);
+    }
+
+    #[test]
+    fn hart_is_halted_stepped_and_resumed() {
+        let chip = programmed_chip(&PROGRAM);
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        assert_eq!(core.reset_and_halt().unwrap().pc, 0x2000_0000);
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Request)
+        );
+
+        core.write_core_reg(A0.into(), 40).unwrap();
+        assert_eq!(core.read_core_reg(A0).unwrap(), 40);
+
+        // CSRs are accessed through the program buffer.
+        core.write_core_reg(0x340.into(), 0x1234_5678).unwrap();
+        assert_eq!(core.read_core_reg(0x340).unwrap(), 0x1234_5678);
+        assert_eq!(chip.core_register(0x340), 0x1234_5678);
+
+        assert_eq!(core.step().unwrap().pc, 0x2000_0004);
+        assert_eq!(core.status().unwrap(), CoreStatus::Halted(HaltReason::Step));
+        assert_eq!(chip.core_register(A0), 41);
+
+        // The hart runs into the ebreak.
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Breakpoint)
+        );
+        assert_eq!(core.read_core_reg(PC).unwrap(), 0x2000_0008);
+        assert_eq!(chip.core_register(A0), 42);
+
+        // Behind it, the hart loops until it is halted.
+        core.write_core_reg(CoreRegisterAddress(PC), 0x2000_000c)
+            .unwrap();
+        core.run().unwrap();
+        assert_eq!(core.status().unwrap(), CoreStatus::Running);
+        assert!(!chip.is_halted());
+
+        assert_eq!(core.halt().unwrap().pc, 0x2000_000c);
+
+        // After the reset, the hart runs the program again, from the start of the flash.
+        core.reset().unwrap();
+        assert!(!chip.is_halted());
+        assert_eq!(chip.core_register(A0), 2);
+    }
+
+    #[test]
+    fn software_breakpoint_is_hit_in_code_which_was_executed_before() {
+        // `addi a0, a0, 1; j .-4` in the RAM.
+        let chip = SimulatedRiscv::new();
+        chip.write_memory(
+            0x8000_0000,
+            &[0x13, 0x05, 0x15, 0x00, 0x6f, 0xf0, 0xdf, 0xff],
+        );
+
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.reset_and_halt().unwrap();
+        core.write_core_reg(CoreRegisterAddress(PC), 0x8000_0000)
+            .unwrap();
+        core.run().unwrap();
+        core.halt().unwrap();
+
+        // The hart fetched the `addi` before, but runs into the `ebreak` which replaced it.
+        core.set_sw_breakpoint(0x8000_0000).unwrap();
+        core.write_core_reg(CoreRegisterAddress(PC), 0x8000_0004)
+            .unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Breakpoint)
+        );
+        assert_eq!(chip.core_register(PC), 0x8000_0000);
+    }
+
+    #[test]
+    fn triggers_halt_the_hart() {
+        let chip = programmed_chip(&PROGRAM);
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.reset_and_halt().unwrap();
+        assert_eq!(core.get_available_breakpoint_units().unwrap(), 4);
+
+        core.set_hw_breakpoint(0x2000_0004).unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Breakpoint)
+        );
+        assert_eq!(chip.core_register(PC), 0x2000_0004);
+
+        // A cleared breakpoint does not halt the hart again.
+        core.clear_hw_breakpoint(0x2000_0004).unwrap();
+        core.write_core_reg(CoreRegisterAddress(PC), 0x2000_0000)
+            .unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(chip.core_register(PC), 0x2000_0008);
+    }
+
+    #[test]
+    fn watchpoints_halt_the_hart() {
+        let chip = programmed_chip(&STORE_PROGRAM);
+        let mut session = session(&chip);
+        let mut core = session.core(0).unwrap();
+
+        core.reset_and_halt().unwrap();
+        core.write_core_reg(A0.into(), 0x1234_5678).unwrap();
+        core.write_core_reg(A1.into(), 0x8000_0400).unwrap();
+
+        core.set_hw_watchpoint(0x8000_0800, 2, WatchpointKind::Write)
+            .unwrap();
+        core.set_hw_watchpoint(0x8000_0404, 2, WatchpointKind::Read)
+            .unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+        assert_eq!(
+            core.status().unwrap(),
+            CoreStatus::Halted(HaltReason::Watchpoint)
+        );
+
+        // The hit bit was cleared by reading the status, but the matched trigger is known.
+        let watchpoint = core.matched_watchpoint().unwrap().unwrap();
+        assert_eq!(watchpoint.address(), 0x8000_0404);
+
+        // The hart halts before the load, after the store.
+        assert_eq!(chip.core_register(PC), 0x2000_0004);
+        assert_eq!(core.read_word_32(0x8000_0400).unwrap(), 0x1234_5678);
+        assert_eq!(chip.core_register(A0), 0x1234_5678);
+    }
+
+    /// The program of `tests/riscv_program`, where `main` calls `add`, is flashed and the stack
+    /// is unwound while the hart is halted in `add`.
+    #[test]
+    fn stack_is_unwound_in_a_called_function() {
+        let program = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/riscv_program/program.elf"
+        ));
+
+        let chip = SimulatedRiscv::new();
+        let mut session = session(&chip);
+        download_file(&mut session, program, Format::Elf).unwrap();
+
+        let mut core = session.core(0).unwrap();
+        core.reset_and_halt().unwrap();
+
+        // Behind the prologue of `add`, at `let sum = a + b;`.
+        core.set_hw_breakpoint(0x2000_004c).unwrap();
+        core.run().unwrap();
+        core.wait_for_core_halted().unwrap();
+
+        let debug_info = DebugInfo::from_file(program).unwrap();
+        let frames = debug_info
+            .try_unwind(&mut core, 0x2000_004c)
+            .collect::<Vec<_>>();
+
+        let locations = frames
+            .iter()
+            .map(|frame| {
+                let line = frame
+                    .source_location
+                    .as_ref()
+                    .and_then(|location| location.line);
+                (frame.function_name.as_str(), frame.pc(), line)
+            })
+            .collect::<Vec<_>>();
+
+        // `main` continues behind the first call, and was called by nobody.
+        assert_eq!(
+            locations,
+            [
+                ("add", 0x2000_004c, Some(38)),
+                ("main", 0x2000_0024, Some(31))
+            ]
+        );
+
+        // The locals of `main` are found with the unwound stack pointer.
+        let x = frames[1]
+            .variables
+            .iter()
+            .find(|variable| variable.name == "x")
+            .unwrap();
+        assert_eq!(x.value, Value::Unsigned(5));
+    }
+}
@@ -0,0 +1,339 @@
+use super::RttError;
+use crate::MemoryInterface;
+
+use std::cmp::min;
+
+/// Offsets of the fields in a channel descriptor.
+const NAME_OFFSET: u32 = 0;
+const BUFFER_OFFSET: u32 = 4;
+const SIZE_OFFSET: u32 = 8;
+const WRITE_OFFSET: u32 = 12;
+const READ_OFFSET: u32 = 16;
+const FLAGS_OFFSET: u32 = 20;
+
+/// Channel names longer than this are truncated.
+const MAX_NAME_LEN: usize = 64;
+
+/// Mask for the mode bits in the channel flags.
+const MODE_MASK: u32 = 0b11;
+
+/// What the target does when it writes to an up channel whose buffer is full.
+///
+/// Host side reads and writes never block, the mode only affects the target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelMode {
+    /// Skip the write entirely if the data does not fit.
+    NoBlockSkip,
+    /// Write as much as fits and drop the rest.
+    NoBlockTrim,
+    /// Wait until the host has read enough data.
+    BlockIfFull,
+}
+
+impl ChannelMode {
+    fn from_flags(flags: u32) -> Option<Self> {
+        match flags & MODE_MASK {
+            0 => Some(ChannelMode::NoBlockSkip),
+            1 => Some(ChannelMode::NoBlockTrim),
+            2 => Some(ChannelMode::BlockIfFull),
+            _ => None,
+        }
+    }
+
+    fn to_flags(self) -> u32 {
+        match self {
+            ChannelMode::NoBlockSkip => 0,
+            ChannelMode::NoBlockTrim => 1,
+            ChannelMode::BlockIfFull => 2,
+        }
+    }
+}
+
+/// The parts of a channel descriptor which do not change at runtime.
+#[derive(Debug)]
+pub(super) struct Channel {
+    number: usize,
+    ptr: u32,
+    name: Option<String>,
+    buffer_ptr: u32,
+    size: u32,
+}
+
+impl Channel {
+    /// Size of a channel descriptor in 32 bit words.
+    pub(super) const DESCRIPTOR_WORDS: u32 = 6;
+
+    /// Size of a channel descriptor in bytes.
+    pub(super) const DESCRIPTOR_SIZE: u32 = Self::DESCRIPTOR_WORDS * 4;
+
+    /// Parses the descriptor at `ptr`, which has already been read into `words`.
+    ///
+    /// Returns `None` for unused channels, which have no buffer.
+    pub(super) fn from_descriptor(
+        memory: &mut impl MemoryInterface,
+        number: u32,
+        ptr: u32,
+        words: &[u32],
+    ) -> Result<Option<Channel>, RttError> {
+        let word = |offset: u32| words[(offset / 4) as usize];
+
+        let buffer_ptr = word(BUFFER_OFFSET);
+        if buffer_ptr == 0 {
+            return Ok(None);
+        }
+
+        let size = word(SIZE_OFFSET);
+        if size == 0 {
+            return Err(RttError::ControlBlockCorrupted(
+                ptr,
+                format!("channel {} has an empty buffer", number),
+            ));
+        }
+
+        let name = match word(NAME_OFFSET) {
+            0 => None,
+            name_ptr => Some(read_c_string(memory, name_ptr)?),
+        };
+
+        Ok(Some(Channel {
+            number: number as usize,
+            ptr,
+            name,
+            buffer_ptr,
+            size,
+        }))
+    }
+
+    /// Reads the write and read offsets and checks that they are within the buffer.
+    fn offsets(&self, memory: &mut impl MemoryInterface) -> Result<(u32, u32), RttError> {
+        let mut offsets = [0u32; 2];
+        memory.read_32(self.ptr + WRITE_OFFSET, &mut offsets)?;
+
+        let [write, read] = offsets;
+
+        if write >= self.size || read >= self.size {
+            return Err(RttError::ControlBlockCorrupted(
+                self.ptr,
+                format!(
+                    "offsets of channel {} out of bounds (write {}, read {}, size {})",
+                    self.number, write, read, self.size
+                ),
+            ));
+        }
+
+        Ok((write, read))
+    }
+
+    fn mode(&self, memory: &mut impl MemoryInterface) -> Result<ChannelMode, RttError> {
+        let flags = memory.read_word_32(self.ptr + FLAGS_OFFSET)?;
+
+        ChannelMode::from_flags(flags).ok_or_else(|| {
+            RttError::ControlBlockCorrupted(
+                self.ptr,
+                format!("invalid mode in flags {:#010x}", flags),
+            )
+        })
+    }
+
+    fn set_mode(
+        &self,
+        memory: &mut impl MemoryInterface,
+        mode: ChannelMode,
+    ) -> Result<(), RttError> {
+        let flags = memory.read_word_32(self.ptr + FLAGS_OFFSET)?;
+        memory.write_word_32(
+            self.ptr + FLAGS_OFFSET,
+            (flags & !MODE_MASK) | mode.to_flags(),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// A channel for data sent from the target to the host.
+#[derive(Debug)]
+pub struct UpChannel(pub(super) Channel);
+
+impl UpChannel {
+    /// The channel number, which is its index in the control block.
+    pub fn number(&self) -> usize {
+        self.0.number
+    }
+
+    /// The name the target assigned to the channel.
+    pub fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+
+    /// The size of the ring buffer in bytes.
+    pub fn buffer_size(&self) -> usize {
+        self.0.size as usize
+    }
+
+    /// Reads the current mode from the target.
+    pub fn mode(&self, memory: &mut impl MemoryInterface) -> Result<ChannelMode, RttError> {
+        self.0.mode(memory)
+    }
+
+    /// Changes what the target does when the buffer is full.
+    pub fn set_mode(
+        &self,
+        memory: &mut impl MemoryInterface,
+        mode: ChannelMode,
+    ) -> Result<(), RttError> {
+        self.0.set_mode(memory, mode)
+    }
+
+    /// Reads available data into `buf` and returns the number of bytes read.
+    ///
+    /// Returns immediately with `0` if the target has not written anything.
+    pub fn read(
+        &self,
+        memory: &mut impl MemoryInterface,
+        buf: &mut [u8],
+    ) -> Result<usize, RttError> {
+        let (count, read) = self.read_inner(memory, buf)?;
+
+        if count > 0 {
+            memory.write_word_32(self.0.ptr + READ_OFFSET, read)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Like `read`, but leaves the data in the buffer.
+    pub fn peek(
+        &self,
+        memory: &mut impl MemoryInterface,
+        buf: &mut [u8],
+    ) -> Result<usize, RttError> {
+        Ok(self.read_inner(memory, buf)?.0)
+    }
+
+    /// Copies data out of the buffer, returning the number of bytes and the new read offset.
+    fn read_inner(
+        &self,
+        memory: &mut impl MemoryInterface,
+        buf: &mut [u8],
+    ) -> Result<(usize, u32), RttError> {
+        let (write, mut read) = self.0.offsets(memory)?;
+        let mut total = 0;
+
+        while total < buf.len() {
+            // The readable data ends either at the write offset or at the end of the buffer.
+            let contiguous = if read > write {
+                self.0.size - read
+            } else {
+                write - read
+            };
+
+            let count = min(contiguous as usize, buf.len() - total);
+            if count == 0 {
+                break;
+            }
+
+            memory.read_8(self.0.buffer_ptr + read, &mut buf[total..total + count])?;
+
+            total += count;
+            read += count as u32;
+
+            if read == self.0.size {
+                read = 0;
+            }
+        }
+
+        Ok((total, read))
+    }
+}
+
+/// A channel for data sent from the host to the target.
+#[derive(Debug)]
+pub struct DownChannel(pub(super) Channel);
+
+impl DownChannel {
+    /// The channel number, which is its index in the control block.
+    pub fn number(&self) -> usize {
+        self.0.number
+    }
+
+    /// The name the target assigned to the channel.
+    pub fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+
+    /// The size of the ring buffer in bytes.
+    pub fn buffer_size(&self) -> usize {
+        self.0.size as usize
+    }
+
+    /// Reads the current mode from the target.
+    pub fn mode(&self, memory: &mut impl MemoryInterface) -> Result<ChannelMode, RttError> {
+        self.0.mode(memory)
+    }
+
+    /// Changes the mode stored in the channel descriptor.
+    pub fn set_mode(
+        &self,
+        memory: &mut impl MemoryInterface,
+        mode: ChannelMode,
+    ) -> Result<(), RttError> {
+        self.0.set_mode(memory, mode)
+    }
+
+    /// Writes as much of `buf` as fits into the buffer and returns the number of bytes written.
+    ///
+    /// Returns immediately with `0` if the buffer is full.
+    pub fn write(&self, memory: &mut impl MemoryInterface, buf: &[u8]) -> Result<usize, RttError> {
+        let (mut write, read) = self.0.offsets(memory)?;
+        let mut total = 0;
+
+        while total < buf.len() {
+            // One byte is always left free, so a full buffer can be told apart from an empty one.
+            let contiguous = if read > write {
+                read - write - 1
+            } else if read == 0 {
+                self.0.size - write - 1
+            } else {
+                self.0.size - write
+            };
+
+            let count = min(contiguous as usize, buf.len() - total);
+            if count == 0 {
+                break;
+            }
+
+            memory.write_8(self.0.buffer_ptr + write, &buf[total..total + count])?;
+
+            total += count;
+            write += count as u32;
+
+            if write == self.0.size {
+                write = 0;
+            }
+        }
+
+        // Only publish the new offset after the data is in place.
+        if total > 0 {
+            memory.write_word_32(self.0.ptr + WRITE_OFFSET, write)?;
+        }
+
+        Ok(total)
+    }
+}
+
+/// Reads a null terminated string, which may be located anywhere in target memory.
+fn read_c_string(memory: &mut impl MemoryInterface, ptr: u32) -> Result<String, RttError> {
+    let mut bytes = Vec::new();
+
+    while bytes.len() < MAX_NAME_LEN {
+        let byte = memory.read_word_8(ptr + bytes.len() as u32)?;
+
+        if byte == 0 {
+            break;
+        }
+
+        bytes.push(byte);
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
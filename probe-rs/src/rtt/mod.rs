@@ -0,0 +1,451 @@
+//! Host side of the SEGGER RTT (Real-Time Transfer) protocol.
+//!
+//! RTT lets firmware exchange data with the host through ring buffers in target RAM.
+//! The buffers are described by a control block, which is located either by scanning
+//! RAM for its identifier or by looking up the `_SEGGER_RTT` symbol in the ELF file.
+//! All accesses go through a [MemoryInterface](../trait.MemoryInterface.html), so the
+//! core can keep running while data is transferred.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use probe_rs::Error;
+//! use probe_rs::Session;
+//! use probe_rs::rtt::{Rtt, ScanRegion};
+//!
+//! let mut session = Session::auto_attach("nrf52")?;
+//! let memory_map = session.memory_map().to_vec();
+//! let mut core = session.core(0)?;
+//!
+//! let mut rtt = Rtt::attach(&mut core, &memory_map, &ScanRegion::Ram).unwrap();
+//!
+//! let mut buf = [0u8; 1024];
+//! if let Some(channel) = rtt.up_channel(0) {
+//!     let count = channel.read(&mut core, &mut buf).unwrap();
+//!     println!("{}", String::from_utf8_lossy(&buf[..count]));
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+
+mod channel;
+
+pub use channel::{ChannelMode, DownChannel, UpChannel};
+
+use crate::config::MemoryRegion;
+use crate::debug::DebugInfo;
+use crate::MemoryInterface;
+use channel::Channel;
+
+use std::ops::Range;
+use thiserror::Error;
+
+/// The name of the symbol SEGGER's implementation uses for the control block.
+pub const CONTROL_BLOCK_SYMBOL: &str = "_SEGGER_RTT";
+
+/// The identifier at the start of the control block.
+const CONTROL_BLOCK_ID: &[u8] = b"SEGGER RTT";
+
+/// Size of the identifier field, which is followed by the channel counts.
+const ID_SIZE: u32 = 16;
+
+/// Size of the control block header: identifier, number of up and number of down channels.
+const HEADER_SIZE: u32 = ID_SIZE + 2 * 4;
+
+/// Upper bound for the channel counts, used to reject garbage control blocks.
+const MAX_CHANNELS: u32 = 255;
+
+#[derive(Debug, Error)]
+pub enum RttError {
+    #[error("RTT control block not found in target memory")]
+    ControlBlockNotFound,
+    #[error("Multiple RTT control blocks found in target memory: {0:x?}")]
+    MultipleControlBlocksFound(Vec<u32>),
+    #[error("RTT control block at {0:#010x} is corrupted: {1}")]
+    ControlBlockCorrupted(u32, String),
+    #[error("Error communicating with the probe: {0}")]
+    Probe(#[from] crate::Error),
+}
+
+/// Where to look for the RTT control block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanRegion {
+    /// Scan all RAM regions of the target memory map.
+    Ram,
+    /// Scan the given address range.
+    Range(Range<u32>),
+    /// The control block is located exactly at the given address.
+    Exact(u32),
+}
+
+impl ScanRegion {
+    /// Uses the address of the `_SEGGER_RTT` symbol, if the ELF file contains it.
+    pub fn from_debug_info(debug_info: &DebugInfo) -> Option<ScanRegion> {
+        debug_info
+            .get_symbol_address(CONTROL_BLOCK_SYMBOL)
+            .map(|address| ScanRegion::Exact(address as u32))
+    }
+}
+
+/// An attached RTT control block and the channels it describes.
+#[derive(Debug)]
+pub struct Rtt {
+    ptr: u32,
+    up_channels: Vec<UpChannel>,
+    down_channels: Vec<DownChannel>,
+}
+
+impl Rtt {
+    /// Locates the control block in `region` and reads its channel descriptors.
+    ///
+    /// `memory_map` is only used for `ScanRegion::Ram`.
+    pub fn attach(
+        memory: &mut impl MemoryInterface,
+        memory_map: &[MemoryRegion],
+        region: &ScanRegion,
+    ) -> Result<Rtt, RttError> {
+        let ptr = match region {
+            ScanRegion::Exact(address) => *address,
+            ScanRegion::Range(range) => find_control_block(memory, std::slice::from_ref(range))?,
+            ScanRegion::Ram => {
+                let ranges: Vec<_> = memory_map
+                    .iter()
+                    .filter_map(|region| match region {
+                        MemoryRegion::Ram(ram) => Some(ram.range.clone()),
+                        _ => None,
+                    })
+                    .collect();
+
+                find_control_block(memory, &ranges)?
+            }
+        };
+
+        Rtt::from_control_block(memory, ptr)
+    }
+
+    fn from_control_block(memory: &mut impl MemoryInterface, ptr: u32) -> Result<Rtt, RttError> {
+        let mut id = [0u8; ID_SIZE as usize];
+        memory.read_8(ptr, &mut id)?;
+
+        if !id.starts_with(CONTROL_BLOCK_ID) {
+            return Err(RttError::ControlBlockCorrupted(
+                ptr,
+                "identifier does not match".to_owned(),
+            ));
+        }
+
+        let max_up_channels = memory.read_word_32(ptr + ID_SIZE)?;
+        let max_down_channels = memory.read_word_32(ptr + ID_SIZE + 4)?;
+
+        if max_up_channels > MAX_CHANNELS || max_down_channels > MAX_CHANNELS {
+            return Err(RttError::ControlBlockCorrupted(
+                ptr,
+                format!(
+                    "unreasonable channel count ({} up, {} down)",
+                    max_up_channels, max_down_channels
+                ),
+            ));
+        }
+
+        let mut descriptors = vec![
+            0u32;
+            ((max_up_channels + max_down_channels) * Channel::DESCRIPTOR_WORDS)
+                as usize
+        ];
+        memory.read_32(ptr + HEADER_SIZE, &mut descriptors)?;
+
+        let mut up_channels = Vec::new();
+        let mut down_channels = Vec::new();
+
+        for (i, words) in descriptors
+            .chunks(Channel::DESCRIPTOR_WORDS as usize)
+            .enumerate()
+        {
+            let i = i as u32;
+            let descriptor_ptr = ptr + HEADER_SIZE + i * Channel::DESCRIPTOR_SIZE;
+
+            if i < max_up_channels {
+                if let Some(channel) = Channel::from_descriptor(memory, i, descriptor_ptr, words)? {
+                    up_channels.push(UpChannel(channel));
+                }
+            } else if let Some(channel) =
+                Channel::from_descriptor(memory, i - max_up_channels, descriptor_ptr, words)?
+            {
+                down_channels.push(DownChannel(channel));
+            }
+        }
+
+        log::debug!(
+            "Attached to RTT control block at {:#010x} ({} up, {} down channels)",
+            ptr,
+            up_channels.len(),
+            down_channels.len()
+        );
+
+        Ok(Rtt {
+            ptr,
+            up_channels,
+            down_channels,
+        })
+    }
+
+    /// The address of the control block.
+    pub fn ptr(&self) -> u32 {
+        self.ptr
+    }
+
+    /// The configured up (target to host) channels.
+    pub fn up_channels(&mut self) -> &mut [UpChannel] {
+        &mut self.up_channels
+    }
+
+    /// The configured down (host to target) channels.
+    pub fn down_channels(&mut self) -> &mut [DownChannel] {
+        &mut self.down_channels
+    }
+
+    /// Returns the up channel with the given number, if it is configured.
+    pub fn up_channel(&mut self, number: usize) -> Option<&mut UpChannel> {
+        self.up_channels.iter_mut().find(|c| c.number() == number)
+    }
+
+    /// Returns the down channel with the given number, if it is configured.
+    pub fn down_channel(&mut self, number: usize) -> Option<&mut DownChannel> {
+        self.down_channels.iter_mut().find(|c| c.number() == number)
+    }
+}
+
+/// Scans the given ranges for the control block identifier.
+///
+/// The control block contains 32 bit words, so only word aligned addresses are checked.
+fn find_control_block(
+    memory: &mut impl MemoryInterface,
+    ranges: &[Range<u32>],
+) -> Result<u32, RttError> {
+    let mut candidates = Vec::new();
+
+    for range in ranges {
+        let start = (range.start + 3) & !3;
+        let end = range.end & !3;
+
+        if end <= start {
+            continue;
+        }
+
+        log::debug!(
+            "Scanning {:#010x}..{:#010x} for RTT control block",
+            start,
+            end
+        );
+
+        let mut words = vec![0u32; ((end - start) / 4) as usize];
+        memory.read_32(start, &mut words)?;
+
+        let data: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+
+        candidates.extend(
+            data.windows(CONTROL_BLOCK_ID.len())
+                .enumerate()
+                .step_by(4)
+                .filter(|(_, window)| *window == CONTROL_BLOCK_ID)
+                .map(|(offset, _)| start + offset as u32),
+        );
+    }
+
+    match candidates.len() {
+        0 => Err(RttError::ControlBlockNotFound),
+        1 => Ok(candidates[0]),
+        _ => Err(RttError::MultipleControlBlocksFound(candidates)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RamRegion;
+    use crate::memory::mock::MockMemory;
+
+    const RAM_START: u32 = 0x2000_0000;
+    const CONTROL_BLOCK: u32 = 0x2000_0100;
+
+    fn set_word(memory: &mut MockMemory, address: u32, value: u32) {
+        memory.write_word_32(address, value).unwrap();
+    }
+
+    fn word(memory: &mut MockMemory, address: u32) -> u32 {
+        memory.read_word_32(address).unwrap()
+    }
+
+    /// Sets up a control block with one up and one down channel.
+    fn with_control_block() -> MockMemory {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+
+        memory
+            .write_8(CONTROL_BLOCK, b"SEGGER RTT\0\0\0\0\0\0")
+            .unwrap();
+        set_word(&mut memory, CONTROL_BLOCK + 16, 2);
+        set_word(&mut memory, CONTROL_BLOCK + 20, 1);
+
+        memory.write_8(0x2000_0800, b"Terminal\0").unwrap();
+
+        // Up channel 0: 16 byte buffer, no-block-skip.
+        let up = CONTROL_BLOCK + HEADER_SIZE;
+        set_word(&mut memory, up, 0x2000_0800);
+        set_word(&mut memory, up + 4, 0x2000_0900);
+        set_word(&mut memory, up + 8, 16);
+
+        // Up channel 1 is unused.
+
+        // Down channel 0: 8 byte buffer.
+        let down = CONTROL_BLOCK + HEADER_SIZE + 2 * Channel::DESCRIPTOR_SIZE;
+        set_word(&mut memory, down, 0x2000_0800);
+        set_word(&mut memory, down + 4, 0x2000_0a00);
+        set_word(&mut memory, down + 8, 8);
+
+        memory
+    }
+
+    fn memory_map() -> Vec<MemoryRegion> {
+        vec![MemoryRegion::Ram(RamRegion {
+            range: RAM_START..RAM_START + 0x1000,
+            is_boot_memory: false,
+        })]
+    }
+
+    #[test]
+    fn scan_finds_control_block() {
+        let mut memory = with_control_block();
+
+        let mut rtt = Rtt::attach(&mut memory, &memory_map(), &ScanRegion::Ram).unwrap();
+
+        assert_eq!(rtt.ptr(), CONTROL_BLOCK);
+        assert_eq!(rtt.up_channels().len(), 1);
+        assert_eq!(rtt.down_channels().len(), 1);
+
+        let up = rtt.up_channel(0).unwrap();
+        assert_eq!(up.name(), Some("Terminal"));
+        assert_eq!(up.buffer_size(), 16);
+
+        assert!(rtt.up_channel(1).is_none());
+        assert_eq!(rtt.down_channel(0).unwrap().buffer_size(), 8);
+    }
+
+    #[test]
+    fn scan_without_control_block() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+
+        let result = Rtt::attach(&mut memory, &memory_map(), &ScanRegion::Ram);
+
+        assert!(matches!(result, Err(RttError::ControlBlockNotFound)));
+    }
+
+    #[test]
+    fn scan_with_multiple_control_blocks() {
+        let mut memory = with_control_block();
+        memory.write_8(0x2000_0400, b"SEGGER RTT").unwrap();
+
+        let result = Rtt::attach(&mut memory, &memory_map(), &ScanRegion::Ram);
+
+        match result {
+            Err(RttError::MultipleControlBlocksFound(addresses)) => {
+                assert_eq!(addresses, vec![CONTROL_BLOCK, 0x2000_0400])
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exact_address_is_validated() {
+        let mut memory = with_control_block();
+
+        let result = Rtt::attach(&mut memory, &[], &ScanRegion::Exact(CONTROL_BLOCK + 4));
+
+        assert!(matches!(
+            result,
+            Err(RttError::ControlBlockCorrupted(0x2000_0104, _))
+        ));
+
+        let rtt = Rtt::attach(&mut memory, &[], &ScanRegion::Exact(CONTROL_BLOCK)).unwrap();
+        assert_eq!(rtt.ptr(), CONTROL_BLOCK);
+    }
+
+    #[test]
+    fn up_channel_read_wraps_around() {
+        let mut memory = with_control_block();
+        let mut rtt = Rtt::attach(&mut memory, &[], &ScanRegion::Exact(CONTROL_BLOCK)).unwrap();
+        let up = rtt.up_channel(0).unwrap();
+        let descriptor = CONTROL_BLOCK + HEADER_SIZE;
+
+        // The target wrote "0123456789" starting at offset 12, wrapping at the end.
+        memory.write_8(0x2000_0900 + 12, b"0123").unwrap();
+        memory.write_8(0x2000_0900, b"456789").unwrap();
+        set_word(&mut memory, descriptor + 12, 6);
+        set_word(&mut memory, descriptor + 16, 12);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(up.peek(&mut memory, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"0123");
+        assert_eq!(word(&mut memory, descriptor + 16), 12);
+
+        let mut buf = [0u8; 32];
+        let count = up.read(&mut memory, &mut buf).unwrap();
+        assert_eq!(&buf[..count], b"0123456789");
+        assert_eq!(word(&mut memory, descriptor + 16), 6);
+
+        assert_eq!(up.read(&mut memory, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn down_channel_write_wraps_around() {
+        let mut memory = with_control_block();
+        let mut rtt = Rtt::attach(&mut memory, &[], &ScanRegion::Exact(CONTROL_BLOCK)).unwrap();
+        let down = rtt.down_channel(0).unwrap();
+        let descriptor = CONTROL_BLOCK + HEADER_SIZE + 2 * Channel::DESCRIPTOR_SIZE;
+
+        set_word(&mut memory, descriptor + 12, 5);
+        set_word(&mut memory, descriptor + 16, 5);
+
+        // One slot always stays free, so only 7 of the 8 bytes can be used.
+        assert_eq!(down.write(&mut memory, b"abcdefghij").unwrap(), 7);
+        assert_eq!(word(&mut memory, descriptor + 12), 4);
+
+        let mut buf = [0u8; 8];
+        memory.read_8(0x2000_0a00, &mut buf).unwrap();
+        assert_eq!(&buf[5..], b"abc");
+        assert_eq!(&buf[..4], b"defg");
+
+        assert_eq!(down.write(&mut memory, b"x").unwrap(), 0);
+    }
+
+    #[test]
+    fn corrupted_offsets_are_reported() {
+        let mut memory = with_control_block();
+        let mut rtt = Rtt::attach(&mut memory, &[], &ScanRegion::Exact(CONTROL_BLOCK)).unwrap();
+        let up = rtt.up_channel(0).unwrap();
+
+        set_word(&mut memory, CONTROL_BLOCK + HEADER_SIZE + 12, 16);
+
+        let mut buf = [0u8; 4];
+        assert!(matches!(
+            up.read(&mut memory, &mut buf),
+            Err(RttError::ControlBlockCorrupted(_, _))
+        ));
+    }
+
+    #[test]
+    fn channel_mode() {
+        let mut memory = with_control_block();
+        let mut rtt = Rtt::attach(&mut memory, &[], &ScanRegion::Exact(CONTROL_BLOCK)).unwrap();
+        let up = rtt.up_channel(0).unwrap();
+        let flags = CONTROL_BLOCK + HEADER_SIZE + 20;
+
+        set_word(&mut memory, flags, 0x100);
+        assert_eq!(up.mode(&mut memory).unwrap(), ChannelMode::NoBlockSkip);
+
+        up.set_mode(&mut memory, ChannelMode::BlockIfFull).unwrap();
+        assert_eq!(word(&mut memory, flags), 0x102);
+        assert_eq!(up.mode(&mut memory).unwrap(), ChannelMode::BlockIfFull);
+    }
+}
@@ -0,0 +1,154 @@
+use super::{OpenMode, SemihostingHandler, CONSOLE_PATH};
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::Instant;
+
+/// A file opened by the target.
+enum HostFile {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+/// Handles semihosting requests with the console and the file system of the host.
+///
+/// Paths are relative to the working directory of the host process.
+pub struct HostHandler {
+    files: HashMap<u32, HostFile>,
+    next_handle: u32,
+    start: Instant,
+}
+
+impl HostHandler {
+    pub fn new() -> Self {
+        HostHandler {
+            files: HashMap::new(),
+            next_handle: 1,
+            start: Instant::now(),
+        }
+    }
+
+    fn file(&mut self, handle: u32) -> io::Result<&mut HostFile> {
+        self.files
+            .get_mut(&handle)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+impl Default for HostHandler {
+    fn default() -> Self {
+        HostHandler::new()
+    }
+}
+
+impl SemihostingHandler for HostHandler {
+    fn write_console(&mut self, data: &[u8]) {
+        let mut stdout = io::stdout();
+
+        if let Err(e) = stdout.write_all(data).and_then(|_| stdout.flush()) {
+            log::warn!("Failed to write semihosting output: {}", e);
+        }
+    }
+
+    fn read_console(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        io::stdin().read_exact(&mut byte)?;
+
+        Ok(byte[0])
+    }
+
+    fn open(&mut self, path: &str, mode: OpenMode) -> io::Result<u32> {
+        let file = if path == CONSOLE_PATH {
+            match mode {
+                OpenMode::Read | OpenMode::ReadWrite => HostFile::Stdin,
+                OpenMode::Write | OpenMode::WriteRead => HostFile::Stdout,
+                OpenMode::Append | OpenMode::AppendRead => HostFile::Stderr,
+            }
+        } else {
+            let mut options = OpenOptions::new();
+
+            match mode {
+                OpenMode::Read => options.read(true),
+                OpenMode::ReadWrite => options.read(true).write(true),
+                OpenMode::Write => options.write(true).create(true).truncate(true),
+                OpenMode::WriteRead => options.read(true).write(true).create(true).truncate(true),
+                OpenMode::Append => options.append(true).create(true),
+                OpenMode::AppendRead => options.read(true).append(true).create(true),
+            };
+
+            HostFile::File(options.open(path)?)
+        };
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.files.insert(handle, file);
+
+        Ok(handle)
+    }
+
+    fn close(&mut self, handle: u32) -> io::Result<()> {
+        self.files
+            .remove(&handle)
+            .map(|_| ())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn write(&mut self, handle: u32, data: &[u8]) -> io::Result<usize> {
+        match self.file(handle)? {
+            HostFile::Stdin => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+            HostFile::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(data)?;
+                stdout.flush()?;
+                Ok(data.len())
+            }
+            HostFile::Stderr => {
+                io::stderr().write_all(data)?;
+                Ok(data.len())
+            }
+            HostFile::File(file) => {
+                file.write_all(data)?;
+                Ok(data.len())
+            }
+        }
+    }
+
+    fn read(&mut self, handle: u32, buf: &mut [u8]) -> io::Result<usize> {
+        match self.file(handle)? {
+            HostFile::Stdin => io::stdin().read(buf),
+            HostFile::Stdout | HostFile::Stderr => {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            }
+            HostFile::File(file) => file.read(buf),
+        }
+    }
+
+    fn is_tty(&mut self, handle: u32) -> io::Result<bool> {
+        Ok(!matches!(self.file(handle)?, HostFile::File(_)))
+    }
+
+    fn seek(&mut self, handle: u32, position: u32) -> io::Result<()> {
+        match self.file(handle)? {
+            HostFile::File(file) => file.seek(SeekFrom::Start(position.into())).map(|_| ()),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    fn file_len(&mut self, handle: u32) -> io::Result<u32> {
+        match self.file(handle)? {
+            HostFile::File(file) => Ok(file.metadata()?.len() as u32),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    fn remove(&mut self, path: &str) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn clock(&mut self) -> u32 {
+        (self.start.elapsed().as_millis() / 10) as u32
+    }
+}
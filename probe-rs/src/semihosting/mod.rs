@@ -0,0 +1,864 @@
+//! Host side of ARM semihosting.
+//!
+//! Semihosting lets firmware use the host for console output, file access and to report
+//! its exit status. The target requests an operation by executing `BKPT 0xAB` on ARM, or
+//! the `slli x0, x0, 0x1f; ebreak; srai x0, x0, 7` sequence on RISC-V, with the operation
+//! number in `r0`/`a0` and a pointer to the parameter block in `r1`/`a1`.
+//!
+//! [Core::status](../struct.Core.html#method.status) reports such a halt as
+//! `HaltReason::Semihosting`. The request is then serviced by [Semihosting::serve](struct.Semihosting.html#method.serve),
+//! which writes the result back to the core, so that it can be resumed.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use probe_rs::Error;
+//! use probe_rs::{CoreStatus, HaltReason, Session};
+//! use probe_rs::semihosting::{HostHandler, Semihosting};
+//!
+//! let mut session = Session::auto_attach("nrf52")?;
+//! let mut core = session.core(0)?;
+//!
+//! let mut semihosting = Semihosting::new(HostHandler::new());
+//!
+//! core.run()?;
+//!
+//! loop {
+//!     if let CoreStatus::Halted(HaltReason::Semihosting(request)) = core.status()? {
+//!         if let Some(exit) = semihosting.serve(&mut core, request)? {
+//!             std::process::exit(exit.code());
+//!         }
+//!
+//!         core.run()?;
+//!     }
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+
+mod host;
+
+pub use host::HostHandler;
+
+use crate::architecture::{arm, riscv};
+use crate::core::Architecture;
+use crate::{Core, Error, MemoryInterface};
+
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SYS_OPEN: u32 = 0x01;
+pub const SYS_CLOSE: u32 = 0x02;
+pub const SYS_WRITEC: u32 = 0x03;
+pub const SYS_WRITE0: u32 = 0x04;
+pub const SYS_WRITE: u32 = 0x05;
+pub const SYS_READ: u32 = 0x06;
+pub const SYS_READC: u32 = 0x07;
+pub const SYS_ISERROR: u32 = 0x08;
+pub const SYS_ISTTY: u32 = 0x09;
+pub const SYS_SEEK: u32 = 0x0a;
+pub const SYS_FLEN: u32 = 0x0c;
+pub const SYS_REMOVE: u32 = 0x0e;
+pub const SYS_CLOCK: u32 = 0x10;
+pub const SYS_TIME: u32 = 0x11;
+pub const SYS_ERRNO: u32 = 0x13;
+pub const SYS_EXIT: u32 = 0x18;
+pub const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+/// Exit reason for a normal termination of the application, `ADP_Stopped_ApplicationExit`.
+pub const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// The path firmware opens to access the host console.
+pub const CONSOLE_PATH: &str = ":tt";
+
+/// Returned to the target when an operation failed.
+const FAILURE: u32 = u32::MAX;
+
+/// Reported through `SYS_ERRNO` if an error has no OS error code.
+const EIO: i32 = 5;
+
+/// Strings read from the target are truncated to this length.
+const MAX_STRING_LEN: usize = 4096;
+
+/// `SYS_READ` and `SYS_WRITE` transfer at most this many bytes at once, as the length is
+/// controlled by the target.
+const TRANSFER_CHUNK_LEN: usize = 4096;
+
+/// A semihosting request which the core is halted at.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SemihostingRequest {
+    operation: u32,
+    parameter: u32,
+}
+
+impl SemihostingRequest {
+    /// The requested operation, one of the `SYS_*` constants.
+    pub fn operation(&self) -> u32 {
+        self.operation
+    }
+
+    /// The parameter of the operation, usually the address of the parameter block.
+    pub fn parameter(&self) -> u32 {
+        self.parameter
+    }
+}
+
+/// The exit status reported by the target through `SYS_EXIT` or `SYS_EXIT_EXTENDED`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Exit {
+    reason: u32,
+    subcode: Option<u32>,
+}
+
+impl Exit {
+    /// The `ADP_Stopped_*` reason code.
+    pub fn reason(&self) -> u32 {
+        self.reason
+    }
+
+    /// The exit code, which is only reported by `SYS_EXIT_EXTENDED`.
+    pub fn subcode(&self) -> Option<u32> {
+        self.subcode
+    }
+
+    /// Whether the application exited normally with exit code 0.
+    pub fn is_success(&self) -> bool {
+        self.code() == 0
+    }
+
+    /// The exit status as a process exit code.
+    pub fn code(&self) -> i32 {
+        match (self.reason, self.subcode) {
+            (ADP_STOPPED_APPLICATION_EXIT, subcode) => subcode.unwrap_or(0) as i32,
+            (_, Some(subcode)) if subcode != 0 => subcode as i32,
+            _ => 1,
+        }
+    }
+}
+
+/// The mode passed to `SYS_OPEN`, equivalent to the `fopen` modes.
+///
+/// The binary flag is ignored, files are never translated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OpenMode {
+    /// `r`
+    Read,
+    /// `r+`
+    ReadWrite,
+    /// `w`
+    Write,
+    /// `w+`
+    WriteRead,
+    /// `a`
+    Append,
+    /// `a+`
+    AppendRead,
+}
+
+impl OpenMode {
+    /// Decodes the mode of `SYS_OPEN`.
+    pub fn from_raw(mode: u32) -> Option<Self> {
+        match mode >> 1 {
+            0 => Some(OpenMode::Read),
+            1 => Some(OpenMode::ReadWrite),
+            2 => Some(OpenMode::Write),
+            3 => Some(OpenMode::WriteRead),
+            4 => Some(OpenMode::Append),
+            5 => Some(OpenMode::AppendRead),
+            _ => None,
+        }
+    }
+}
+
+/// Implements the semihosting operations on the host.
+///
+/// Only the console is required, all other operations fail by default.
+pub trait SemihostingHandler {
+    /// Output from `SYS_WRITEC` and `SYS_WRITE0`.
+    fn write_console(&mut self, data: &[u8]);
+
+    /// Read a single byte for `SYS_READC`.
+    fn read_console(&mut self) -> io::Result<u8> {
+        Err(unsupported())
+    }
+
+    /// Open a file and return its handle. `path` is [CONSOLE_PATH](constant.CONSOLE_PATH.html)
+    /// for stdin, stdout or stderr, depending on the mode.
+    fn open(&mut self, _path: &str, _mode: OpenMode) -> io::Result<u32> {
+        Err(unsupported())
+    }
+
+    fn close(&mut self, _handle: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Write to a file and return the number of bytes written.
+    fn write(&mut self, _handle: u32, _data: &[u8]) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    /// Read from a file and return the number of bytes read, `0` at the end of the file.
+    fn read(&mut self, _handle: u32, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    fn is_tty(&mut self, _handle: u32) -> io::Result<bool> {
+        Err(unsupported())
+    }
+
+    /// Seek to an absolute position.
+    fn seek(&mut self, _handle: u32, _position: u32) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    fn file_len(&mut self, _handle: u32) -> io::Result<u32> {
+        Err(unsupported())
+    }
+
+    fn remove(&mut self, _path: &str) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    /// Centiseconds since the application was started.
+    fn clock(&mut self) -> u32;
+
+    /// Seconds since the Unix epoch.
+    fn time(&mut self) -> u32 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() as u32)
+            .unwrap_or(0)
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported by the semihosting handler",
+    )
+}
+
+/// What to do with the core after an operation.
+#[derive(Debug, PartialEq)]
+enum Response {
+    /// Return the value in `r0`.
+    Value(u32),
+    /// The operation has no result.
+    Done,
+    /// The application has exited.
+    Exit(Exit),
+}
+
+/// Services semihosting requests using a [SemihostingHandler](trait.SemihostingHandler.html).
+pub struct Semihosting<H> {
+    handler: H,
+    errno: i32,
+}
+
+impl<H: SemihostingHandler> Semihosting<H> {
+    pub fn new(handler: H) -> Self {
+        Semihosting { handler, errno: 0 }
+    }
+
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Executes the request the core is halted at.
+    ///
+    /// The result is written to the core and the PC is moved past the request, so the core
+    /// can be resumed afterwards. Returns the exit status if the application has exited,
+    /// in which case the core is left untouched.
+    pub fn serve(
+        &mut self,
+        core: &mut Core,
+        request: SemihostingRequest,
+    ) -> Result<Option<Exit>, Error> {
+        let value = match self.execute(core, request)? {
+            Response::Exit(exit) => return Ok(Some(exit)),
+            Response::Value(value) => Some(value),
+            Response::Done => None,
+        };
+
+        complete_request(core, value)?;
+
+        Ok(None)
+    }
+
+    fn execute(
+        &mut self,
+        memory: &mut impl MemoryInterface,
+        request: SemihostingRequest,
+    ) -> Result<Response, Error> {
+        let parameter = request.parameter;
+
+        let value = match request.operation {
+            SYS_OPEN => {
+                let mut block = [0u32; 3];
+                memory.read_32(parameter, &mut block)?;
+                let [path, mode, len] = block;
+
+                let path = read_string(memory, path, Some(len))?;
+
+                let result = match OpenMode::from_raw(mode) {
+                    Some(mode) => self.handler.open(&path, mode),
+                    None => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid open mode {}", mode),
+                    )),
+                };
+
+                self.result(result)
+            }
+            SYS_CLOSE => {
+                let handle = memory.read_word_32(parameter)?;
+                let result = self.handler.close(handle).map(|_| 0);
+
+                self.result(result)
+            }
+            SYS_WRITEC => {
+                let byte = memory.read_word_8(parameter)?;
+                self.handler.write_console(&[byte]);
+
+                return Ok(Response::Done);
+            }
+            SYS_WRITE0 => {
+                let string = read_string(memory, parameter, None)?;
+                self.handler.write_console(string.as_bytes());
+
+                return Ok(Response::Done);
+            }
+            SYS_WRITE => {
+                let mut block = [0u32; 3];
+                memory.read_32(parameter, &mut block)?;
+                let [handle, data, len] = block;
+
+                // The number of bytes which were *not* written is returned.
+                let mut written = 0;
+
+                while written < len {
+                    let chunk_len = ((len - written) as usize).min(TRANSFER_CHUNK_LEN);
+                    let mut buf = vec![0; chunk_len];
+                    memory.read_8(data.wrapping_add(written), &mut buf)?;
+
+                    match self.handler.write(handle, &buf) {
+                        Ok(chunk_written) => {
+                            written += chunk_written as u32;
+
+                            if chunk_written < chunk_len {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            self.set_errno(&e);
+                            break;
+                        }
+                    }
+                }
+
+                len - written
+            }
+            SYS_READ => {
+                let mut block = [0u32; 3];
+                memory.read_32(parameter, &mut block)?;
+                let [handle, data, len] = block;
+
+                // The number of bytes which were *not* read is returned.
+                let mut read = 0;
+
+                while read < len {
+                    let chunk_len = ((len - read) as usize).min(TRANSFER_CHUNK_LEN);
+                    let mut buf = vec![0; chunk_len];
+
+                    match self.handler.read(handle, &mut buf) {
+                        Ok(chunk_read) => {
+                            memory.write_8(data.wrapping_add(read), &buf[..chunk_read])?;
+                            read += chunk_read as u32;
+
+                            // Less data is available right now, like at the end of a line
+                            // of the console.
+                            if chunk_read < chunk_len {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            self.set_errno(&e);
+                            break;
+                        }
+                    }
+                }
+
+                len - read
+            }
+            SYS_READC => {
+                let result = self.handler.read_console().map(u32::from);
+
+                self.result(result)
+            }
+            SYS_ISERROR => {
+                let status = memory.read_word_32(parameter)?;
+
+                ((status as i32) < 0) as u32
+            }
+            SYS_ISTTY => {
+                let handle = memory.read_word_32(parameter)?;
+                let result = self.handler.is_tty(handle).map(u32::from);
+
+                self.result(result)
+            }
+            SYS_SEEK => {
+                let mut block = [0u32; 2];
+                memory.read_32(parameter, &mut block)?;
+                let [handle, position] = block;
+
+                let result = self.handler.seek(handle, position).map(|_| 0);
+
+                self.result(result)
+            }
+            SYS_FLEN => {
+                let handle = memory.read_word_32(parameter)?;
+                let result = self.handler.file_len(handle);
+
+                self.result(result)
+            }
+            SYS_REMOVE => {
+                let mut block = [0u32; 2];
+                memory.read_32(parameter, &mut block)?;
+                let [path, len] = block;
+
+                let path = read_string(memory, path, Some(len))?;
+
+                // On failure, the host error code is returned directly.
+                match self.handler.remove(&path) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        self.set_errno(&e);
+                        self.errno as u32
+                    }
+                }
+            }
+            SYS_CLOCK => self.handler.clock(),
+            SYS_TIME => self.handler.time(),
+            SYS_ERRNO => self.errno as u32,
+            SYS_EXIT | SYS_EXIT_EXTENDED => {
+                if let Some(exit) = read_exit(memory, request)? {
+                    return Ok(Response::Exit(exit));
+                }
+
+                FAILURE
+            }
+            operation => {
+                log::warn!("Unsupported semihosting operation {:#04x}", operation);
+
+                FAILURE
+            }
+        };
+
+        Ok(Response::Value(value))
+    }
+
+    fn result(&mut self, result: io::Result<u32>) -> u32 {
+        result.unwrap_or_else(|e| {
+            self.set_errno(&e);
+            FAILURE
+        })
+    }
+
+    fn set_errno(&mut self, error: &io::Error) {
+        log::debug!("Semihosting operation failed: {}", error);
+
+        self.errno = error.raw_os_error().unwrap_or(EIO);
+    }
+}
+
+/// Completes a request which was serviced without [Semihosting](struct.Semihosting.html),
+/// for example by a debugger.
+///
+/// `value` is written to `r0`/`a0` and the PC is moved past the request, so the core
+/// can be resumed.
+pub fn complete_request(core: &mut Core, value: Option<u32>) -> Result<(), Error> {
+    let registers = core.registers();
+
+    if let Some(value) = value {
+        core.write_core_reg(registers.result_register(0).address, value)?;
+    }
+
+    let instruction_len = match core.architecture() {
+        Architecture::Arm => 2,
+        Architecture::Riscv => 4,
+    };
+
+    let pc = core.read_core_reg(registers.program_counter().address)?;
+    core.write_core_reg(registers.program_counter().address, pc + instruction_len)?;
+
+    Ok(())
+}
+
+/// Reads the exit status of a `SYS_EXIT` or `SYS_EXIT_EXTENDED` request.
+///
+/// Returns `None` for other requests.
+pub fn read_exit(
+    memory: &mut impl MemoryInterface,
+    request: SemihostingRequest,
+) -> Result<Option<Exit>, Error> {
+    let exit = match request.operation {
+        // On 32 bit targets, the reason is passed directly instead of in a parameter block.
+        SYS_EXIT => Exit {
+            reason: request.parameter,
+            subcode: None,
+        },
+        SYS_EXIT_EXTENDED => {
+            let mut block = [0u32; 2];
+            memory.read_32(request.parameter, &mut block)?;
+            let [reason, subcode] = block;
+
+            Exit {
+                reason,
+                subcode: Some(subcode),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(exit))
+}
+
+/// Reads a null terminated string from target memory, without the terminator.
+///
+/// Strings are truncated to 4096 bytes.
+pub fn read_c_string(memory: &mut impl MemoryInterface, address: u32) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; 32];
+
+    while bytes.len() < MAX_STRING_LEN {
+        // Do not read past the end of the aligned chunk, which might be outside of valid memory.
+        let chunk_address = address + bytes.len() as u32;
+        let chunk = &mut chunk[..32 - (chunk_address % 32) as usize];
+        memory.read_8(chunk_address, chunk)?;
+
+        match chunk.iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            None => bytes.extend_from_slice(chunk),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Reads a string from target memory, either with a known length or null terminated.
+fn read_string(
+    memory: &mut impl MemoryInterface,
+    address: u32,
+    len: Option<u32>,
+) -> Result<String, Error> {
+    let bytes = match len {
+        Some(len) => {
+            let mut bytes = vec![0; (len as usize).min(MAX_STRING_LEN)];
+            memory.read_8(address, &mut bytes)?;
+            bytes
+        }
+        None => read_c_string(memory, address)?,
+    };
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Checks if the core is halted at a semihosting request.
+///
+/// Memory which cannot be read is assumed to contain no request.
+pub(crate) fn check_request(core: &mut Core) -> Result<Option<SemihostingRequest>, Error> {
+    let registers = core.registers();
+    let pc = core.read_core_reg(registers.program_counter().address)?;
+
+    let is_request = match core.architecture() {
+        Architecture::Arm => {
+            let mut instruction = [0u8; 2];
+
+            core.read_8(pc, &mut instruction).is_ok()
+                && u16::from_le_bytes(instruction) == arm::assembly::BKPT_SEMIHOSTING
+        }
+        Architecture::Riscv => {
+            // The `ebreak` is surrounded by two instructions, which are not compressed.
+            let mut instructions = [0u8; 12];
+
+            pc >= 4
+                && core.read_8(pc - 4, &mut instructions).is_ok()
+                && is_riscv_request(&instructions)
+        }
+    };
+
+    if !is_request {
+        return Ok(None);
+    }
+
+    let operation = core.read_core_reg(registers.argument_register(0).address)?;
+    let parameter = core.read_core_reg(registers.argument_register(1).address)?;
+
+    log::debug!(
+        "Semihosting request {:#04x} with parameter {:#010x}",
+        operation,
+        parameter
+    );
+
+    Ok(Some(SemihostingRequest {
+        operation,
+        parameter,
+    }))
+}
+
+fn is_riscv_request(instructions: &[u8; 12]) -> bool {
+    let word = |index: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&instructions[index * 4..index * 4 + 4]);
+        u32::from_le_bytes(bytes)
+    };
+
+    word(0) == riscv::assembly::SEMIHOSTING_ENTRY
+        && word(1) == riscv::assembly::EBREAK
+        && word(2) == riscv::assembly::SEMIHOSTING_EXIT
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::mock::MockMemory;
+
+    const RAM_START: u32 = 0x2000_0000;
+    const BLOCK: u32 = 0x2000_0100;
+
+    fn set_block(memory: &mut MockMemory, words: &[u32]) {
+        memory.write_32(BLOCK, words).unwrap();
+    }
+
+    /// Records console output, and provides a single file with fixed content.
+    #[derive(Default)]
+    struct TestHandler {
+        console: Vec<u8>,
+        opened: Vec<(String, OpenMode)>,
+        written: Vec<(u32, Vec<u8>)>,
+    }
+
+    impl SemihostingHandler for TestHandler {
+        fn write_console(&mut self, data: &[u8]) {
+            self.console.extend_from_slice(data);
+        }
+
+        fn open(&mut self, path: &str, mode: OpenMode) -> io::Result<u32> {
+            if path == "missing.txt" {
+                return Err(io::Error::from_raw_os_error(2));
+            }
+
+            self.opened.push((path.to_owned(), mode));
+            Ok(3)
+        }
+
+        fn write(&mut self, handle: u32, data: &[u8]) -> io::Result<usize> {
+            self.written.push((handle, data.to_vec()));
+            Ok(data.len())
+        }
+
+        fn read(&mut self, _handle: u32, buf: &mut [u8]) -> io::Result<usize> {
+            let content = b"hello";
+            let len = buf.len().min(content.len());
+            buf[..len].copy_from_slice(&content[..len]);
+            Ok(len)
+        }
+
+        fn clock(&mut self) -> u32 {
+            42
+        }
+    }
+
+    fn execute(
+        memory: &mut MockMemory,
+        semihosting: &mut Semihosting<TestHandler>,
+        operation: u32,
+        parameter: u32,
+    ) -> Response {
+        semihosting
+            .execute(
+                memory,
+                SemihostingRequest {
+                    operation,
+                    parameter,
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn write0() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        // The string crosses a chunk boundary.
+        memory
+            .write_8(BLOCK + 0x1c, b"Hello, semihosting!\n\0")
+            .unwrap();
+
+        let response = execute(&mut memory, &mut semihosting, SYS_WRITE0, BLOCK + 0x1c);
+
+        assert_eq!(response, Response::Done);
+        assert_eq!(semihosting.handler().console, b"Hello, semihosting!\n");
+    }
+
+    #[test]
+    fn open_and_write() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        memory.write_8(0x2000_0200, b":tt\0").unwrap();
+        set_block(&mut memory, &[0x2000_0200, 4, 3]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_OPEN, BLOCK);
+
+        assert_eq!(response, Response::Value(3));
+        assert_eq!(
+            semihosting.handler().opened,
+            vec![(CONSOLE_PATH.to_owned(), OpenMode::Write)]
+        );
+
+        memory.write_8(0x2000_0200, b"test").unwrap();
+        set_block(&mut memory, &[3, 0x2000_0200, 4]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_WRITE, BLOCK);
+
+        assert_eq!(response, Response::Value(0));
+        assert_eq!(semihosting.handler().written, vec![(3, b"test".to_vec())]);
+    }
+
+    #[test]
+    fn failed_open_sets_errno() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        memory.write_8(0x2000_0200, b"missing.txt").unwrap();
+        set_block(&mut memory, &[0x2000_0200, 0, 11]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_OPEN, BLOCK);
+        assert_eq!(response, Response::Value(FAILURE));
+
+        let response = execute(&mut memory, &mut semihosting, SYS_ERRNO, 0);
+        assert_eq!(response, Response::Value(2));
+    }
+
+    #[test]
+    fn read_returns_remaining_bytes() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        set_block(&mut memory, &[3, 0x2000_0200, 8]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_READ, BLOCK);
+
+        assert_eq!(response, Response::Value(3));
+
+        let mut data = [0u8; 5];
+        memory.read_8(0x2000_0200, &mut data).unwrap();
+        assert_eq!(&data, b"hello");
+    }
+
+    #[test]
+    fn write_is_split_into_chunks() {
+        let mut memory = MockMemory::new(RAM_START, 0x3000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        set_block(&mut memory, &[3, 0x2000_0200, 0x2800]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_WRITE, BLOCK);
+
+        assert_eq!(response, Response::Value(0));
+
+        let chunks: Vec<usize> = semihosting
+            .handler()
+            .written
+            .iter()
+            .map(|(_, data)| data.len())
+            .collect();
+        assert_eq!(chunks, vec![0x1000, 0x1000, 0x800]);
+    }
+
+    #[test]
+    fn read_length_is_not_allocated_at_once() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        // A length of 4 GiB, like from an uninitialized parameter block.
+        set_block(&mut memory, &[3, 0x2000_0200, u32::MAX]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_READ, BLOCK);
+
+        assert_eq!(response, Response::Value(u32::MAX - 5));
+
+        let mut data = [0u8; 5];
+        memory.read_8(0x2000_0200, &mut data).unwrap();
+        assert_eq!(&data, b"hello");
+    }
+
+    #[test]
+    fn unsupported_operations_fail() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        set_block(&mut memory, &[3]);
+
+        let response = execute(&mut memory, &mut semihosting, SYS_FLEN, BLOCK);
+        assert_eq!(response, Response::Value(FAILURE));
+
+        let response = execute(&mut memory, &mut semihosting, 0x15, BLOCK);
+        assert_eq!(response, Response::Value(FAILURE));
+
+        let response = execute(&mut memory, &mut semihosting, SYS_CLOCK, 0);
+        assert_eq!(response, Response::Value(42));
+    }
+
+    #[test]
+    fn exit() {
+        let mut memory = MockMemory::new(RAM_START, 0x1000);
+        let mut semihosting = Semihosting::new(TestHandler::default());
+
+        let response = execute(
+            &mut memory,
+            &mut semihosting,
+            SYS_EXIT,
+            ADP_STOPPED_APPLICATION_EXIT,
+        );
+
+        let exit = match response {
+            Response::Exit(exit) => exit,
+            other => panic!("Unexpected response {:?}", other),
+        };
+        assert!(exit.is_success());
+
+        // `ADP_Stopped_RunTimeErrorUnknown`
+        set_block(&mut memory, &[0x20023, 0]);
+        assert_eq!(
+            execute(&mut memory, &mut semihosting, SYS_EXIT_EXTENDED, BLOCK),
+            Response::Exit(Exit {
+                reason: 0x20023,
+                subcode: Some(0),
+            })
+        );
+
+        set_block(&mut memory, &[ADP_STOPPED_APPLICATION_EXIT, 3]);
+        match execute(&mut memory, &mut semihosting, SYS_EXIT_EXTENDED, BLOCK) {
+            Response::Exit(exit) => assert_eq!(exit.code(), 3),
+            other => panic!("Unexpected response {:?}", other),
+        }
+    }
+
+    #[test]
+    fn riscv_request_sequence() {
+        let mut instructions = [0u8; 12];
+        instructions[0..4].copy_from_slice(&riscv::assembly::SEMIHOSTING_ENTRY.to_le_bytes());
+        instructions[4..8].copy_from_slice(&riscv::assembly::EBREAK.to_le_bytes());
+        instructions[8..12].copy_from_slice(&riscv::assembly::SEMIHOSTING_EXIT.to_le_bytes());
+
+        assert!(is_riscv_request(&instructions));
+
+        instructions[8..12].copy_from_slice(&riscv::assembly::EBREAK.to_le_bytes());
+        assert!(!is_riscv_request(&instructions));
+    }
+}
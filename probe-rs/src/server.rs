@@ -0,0 +1,192 @@
+//! What the debug servers have in common, like the `gdb-server` and the `dap-server`.
+//!
+//! The servers debug the cores of a target all-stop: once one of the running cores halts,
+//! the others are halted as well.
+
+use crate::config::MemoryRegion;
+use crate::{Core, Error, Session};
+use std::fmt;
+
+#[cfg(unix)]
+use std::{
+    fs, io,
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::{Path, PathBuf},
+};
+
+/// How the client connects to a server, GDB to the GDB stub or an editor to the `dap-server`.
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// A TCP socket, listening on `host:port`.
+    Tcp(String),
+    /// A Unix domain socket, listening at the given path.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Stdin and stdout of the process, for clients which start the server themselves,
+    /// like `target remote | gdb-server --pipe`.
+    ///
+    /// Only a single connection is served.
+    Stdio,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            Transport::Unix(path) => write!(f, "{}", path.display()),
+            Transport::Stdio => write!(f, "stdin and stdout"),
+        }
+    }
+}
+
+/// The socket file of a server which listens on a Unix domain socket.
+///
+/// The file stays behind when the listener is closed, and binding to it again fails. So a stale
+/// file is removed before binding, and the file is removed when the server stops listening.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl SocketFile {
+    /// Removes the socket at `path` if no server listens on it anymore.
+    ///
+    /// Other files, and the sockets of running servers, are kept, so that binding to them fails.
+    pub fn remove_stale(path: &Path) -> io::Result<()> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => (),
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        match UnixStream::connect(path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                log::debug!("Removing the stale socket {}", path.display());
+                fs::remove_file(path)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The socket at `path`, which a listener was bound to. It is removed when this is dropped.
+    pub fn new(path: PathBuf) -> Self {
+        SocketFile { path }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("Unable to remove the socket {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The cores which are debugged.
+pub trait Cores {
+    /// The number of cores.
+    fn count(&self) -> usize;
+
+    /// Attaches to core `n`.
+    fn core(&mut self, n: usize) -> Result<Core<'_>, Error>;
+
+    /// The memory regions of the target, which are shared by all cores.
+    fn memory_map(&self) -> &[MemoryRegion];
+}
+
+impl Cores for Session {
+    fn count(&self) -> usize {
+        self.list_cores().len()
+    }
+
+    fn core(&mut self, n: usize) -> Result<Core<'_>, Error> {
+        Session::core(self, n)
+    }
+
+    fn memory_map(&self) -> &[MemoryRegion] {
+        Session::memory_map(self)
+    }
+}
+
+/// Returns the first of the `running` cores which halted.
+///
+/// The others have to be halted with [`halt_others`] then.
+pub fn first_halted<C: Cores + ?Sized>(cores: &mut C, running: &[usize]) -> Option<usize> {
+    running.iter().copied().find(|&n| {
+        match cores.core(n).and_then(|mut core| core.core_halted()) {
+            Ok(halted) => halted,
+            Err(e) => {
+                // The state is polled again, so the error is not fatal.
+                log::debug!("Unable to check if core {} is halted: {}", n, e);
+                false
+            }
+        }
+    })
+}
+
+/// Halts the `running` cores after core `halted` halted.
+pub fn halt_others<C: Cores + ?Sized>(cores: &mut C, running: &[usize], halted: usize) {
+    for &n in running {
+        if n == halted {
+            continue;
+        }
+
+        let result = cores.core(n).and_then(|mut core| {
+            core.halt()?;
+            core.wait_for_core_halted()
+        });
+
+        if let Err(e) = result {
+            log::warn!("Unable to halt core {}: {}", n, e);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::SocketFile;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("probe-rs-{}-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn only_stale_sockets_are_removed() {
+        let path = socket_path("stale");
+
+        // The socket of a closed listener is stale.
+        drop(UnixListener::bind(&path).unwrap());
+        SocketFile::remove_stale(&path).unwrap();
+        assert!(!path.exists());
+
+        // The socket of a running server is kept, so that binding to it fails.
+        let listener = UnixListener::bind(&path).unwrap();
+        SocketFile::remove_stale(&path).unwrap();
+        assert!(UnixListener::bind(&path).is_err());
+
+        // The socket is removed when the server stops listening.
+        drop(SocketFile::new(path.clone()));
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn other_files_are_kept() {
+        let path = socket_path("file");
+
+        fs::write(&path, b"data").unwrap();
+        SocketFile::remove_stale(&path).unwrap();
+        assert!(path.exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
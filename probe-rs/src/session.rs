@@ -5,10 +5,10 @@ use crate::architecture::{
     },
 };
 use crate::config::{
-    ChipInfo, MemoryRegion, RawFlashAlgorithm, RegistryError, Target, TargetSelector,
+    ChipInfo, MemoryRegion, RawFlashAlgorithm, RegistryError, Target, TargetCore, TargetSelector,
 };
 use crate::core::{Architecture, CoreState, SpecificCoreState};
-use crate::{Core, CoreType, Error, Probe};
+use crate::{Core, CoreType, DebugProbeError, Error, Probe};
 
 pub struct Session {
     target: Target,
@@ -37,15 +37,21 @@ impl ArchitectureInterfaceState {
         probe: &'probe mut Probe,
         core: &'probe mut SpecificCoreState,
         core_state: &'probe mut CoreState,
+        target_core: &TargetCore,
+        memory_map: &'probe [MemoryRegion],
     ) -> Result<Core<'probe>, Error> {
         match self {
             ArchitectureInterfaceState::Arm(state) => core.attach_arm(
                 core_state,
                 ArmCommunicationInterface::new(probe, state)?.unwrap(),
+                target_core,
+                memory_map,
             ),
             ArchitectureInterfaceState::Riscv(state) => core.attach_riscv(
                 core_state,
                 RiscvCommunicationInterface::new(probe, state)?.unwrap(),
+                target_core,
+                memory_map,
             ),
         }
     }
@@ -102,34 +108,34 @@ impl Session {
             }
         };
 
-        let data = match target.architecture() {
+        let interface_state = match target.architecture() {
             Architecture::Arm => {
-                let state = ArmCommunicationInterfaceState::new();
-                (
-                    (
-                        SpecificCoreState::from_core_type(target.core_type),
-                        Core::create_state(),
-                    ),
-                    ArchitectureInterfaceState::Arm(state),
-                )
+                ArchitectureInterfaceState::Arm(ArmCommunicationInterfaceState::new())
             }
             Architecture::Riscv => {
-                let state = RiscvCommunicationInterfaceState::new();
-                (
-                    (
-                        SpecificCoreState::from_core_type(target.core_type),
-                        Core::create_state(),
-                    ),
-                    ArchitectureInterfaceState::Riscv(state),
-                )
+                ArchitectureInterfaceState::Riscv(RiscvCommunicationInterfaceState::new())
             }
         };
 
+        // Every core keeps its own state, so they can be debugged independently. The
+        // software breakpoints are shared, as they are placed in the memory of the chip.
+        let memory_state = Core::create_state();
+        let cores = target
+            .cores
+            .iter()
+            .map(|core| {
+                (
+                    SpecificCoreState::from_core_type(core.core_type),
+                    Core::create_state_sharing_memory(&memory_state),
+                )
+            })
+            .collect();
+
         Ok(Self {
             target,
             probe,
-            interface_state: data.1,
-            cores: vec![data.0],
+            interface_state,
+            cores,
         })
     }
 
@@ -161,20 +167,56 @@ impl Session {
             .get_mut(n)
             .ok_or_else(|| Error::CoreNotFound(n))?;
 
-        self.interface_state
-            .attach(&mut self.probe, core, core_state)
+        self.interface_state.attach(
+            &mut self.probe,
+            core,
+            core_state,
+            &self.target.cores[n],
+            &self.target.memory_map,
+        )
+    }
+
+    /// Halts all cores, one after the other.
+    pub fn halt_all(&mut self) -> Result<(), Error> {
+        for n in 0..self.cores.len() {
+            self.core(n)?.halt()?;
+        }
+
+        Ok(())
+    }
+
+    /// Resumes all cores, one after the other.
+    pub fn run_all(&mut self) -> Result<(), Error> {
+        for n in 0..self.cores.len() {
+            self.core(n)?.run()?;
+        }
+
+        Ok(())
     }
 
     /// Returns a list of the flash algotithms on the target.
-    pub(crate) fn flash_algorithms(&self) -> &[RawFlashAlgorithm] {
+    pub fn flash_algorithms(&self) -> &[RawFlashAlgorithm] {
         &self.target.flash_algorithms
     }
 
     /// Returns the memory map of the target.
-    pub(crate) fn memory_map(&self) -> &[MemoryRegion] {
+    pub fn memory_map(&self) -> &[MemoryRegion] {
         &self.target.memory_map
     }
 
+    /// Returns the protocol speed of the probe in kHz.
+    pub fn speed_khz(&self) -> u32 {
+        self.probe.speed_khz()
+    }
+
+    /// Changes the protocol speed of the probe in kHz.
+    ///
+    /// Returns the speed which is used, as the probe may only support some speeds. Fails if the
+    /// probe rejects the speed.
+    pub fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.probe.set_speed_attached(speed_khz)
+    }
+
     /// Return the `Architecture` of the currently connected chip.
     pub fn architecture(&self) -> Architecture {
         match self.interface_state {
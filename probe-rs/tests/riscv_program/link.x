@@ -0,0 +1,15 @@
+/*
+ * The program of the unwinding test of the simulated RISC-V chip. The hart starts at the
+ * beginning of the flash, where the stack pointer is set to the end of the RAM before `main`.
+ * It is built with a nightly compiler, as `program.rs` has no dependencies, not even `core`:
+ *
+ * rustc +nightly --target riscv32i-unknown-none-elf --crate-type bin -C opt-level=0 -g \
+ *     -C overflow-checks=off -C panic=abort -C linker=rust-lld -C linker-flavor=ld.lld \
+ *     -C link-arg=-Tlink.x --remap-path-prefix=$PWD=/src -o program.elf program.rs
+ */
+MEMORY { FLASH : ORIGIN = 0x20000000, LENGTH = 64K RAM : ORIGIN = 0x80000000, LENGTH = 64K }
+ENTRY(main)
+SECTIONS {
+  /* `lui sp, 0x80010` */
+  .text ORIGIN(FLASH) : { LONG(0x80010137); *(.text.main) *(.text .text.*) } > FLASH
+}
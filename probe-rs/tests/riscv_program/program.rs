@@ -0,0 +1,40 @@
+#![feature(no_core, lang_items)]
+#![no_core]
+#![no_main]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+#[lang = "add"]
+pub trait Add<Rhs = Self> {
+    type Output;
+    fn add(self, rhs: Rhs) -> Self::Output;
+}
+
+impl Copy for u32 {}
+
+impl Add for u32 {
+    type Output = u32;
+    fn add(self, rhs: u32) -> u32 {
+        self + rhs
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn main() -> ! {
+    let x: u32 = 5;
+    let y = add(x, 7);
+    let z = add(y, x);
+    loop {}
+}
+
+#[inline(never)]
+fn add(a: u32, b: u32) -> u32 {
+    let sum = a + b;
+    sum
+}